@@ -1,4 +1,3 @@
-use prost_build;
 use sha2::Digest;
 use sha2::Sha256;
 use std::env;
@@ -8,6 +7,7 @@ use std::path::Path;
 
 // use this step to provide paths that are to be used
 // I.E anything provided from the outside in this step will override defaults
+// the root dir set here is only the default, both binaries take --root-dir / SERF_ROOT_DIR at runtime
 // DEFAULTS:
 //  (root path) $HOME/.serf/
 //  (cfg path) $HOME/.serf/cfg/
@@ -16,7 +16,7 @@ use std::path::Path;
 //  (db paths) $HOME/.serf/db/{hashed}/
 //      folder is a sha256 hash of the db name
 //      containing {hashed}.db file
-/**
+/*
 * let test = home_dir()
        .unwrap_or(env::var_os("OUT_DIR").unwrap().into())
        .as_path();
@@ -56,4 +56,6 @@ fn main() {
         .unwrap();
 
     println!("cargo::rerun-if-changed=build.rs");
+    println!("cargo::rerun-if-env-changed=SERF_ROOT_DIR");
+    println!("cargo::rerun-if-changed=src/proto/");
 }
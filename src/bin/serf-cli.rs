@@ -1,11 +1,19 @@
-use std::env;
+use std::{env, io, path::Path, process};
 
-use serf::cli::util::{get_flag_val, DatabaseManager};
-use serf::core::constants::cli;
+use clap::{CommandFactory, Parser};
+use serf::cli::{
+    args::{
+        BackupCommand, CreateCommand, DeleteCommand, DropCommand, InfoCommand, ListCommand,
+        ModifyCommand, ModifyDatabaseCommand, ModifyGroupCommand, ModifyUserCommand, RenameCommand,
+        RestoreCommand, RevokeCommand, SerfCliArgs, SerfCliCommand, ShowCommand,
+    },
+    util::DatabaseManager,
+};
+use serf::core::serf_proto::Error;
 use sqlx::{migrate::MigrateDatabase, Sqlite};
 
 // DEFAULTS:
-//  (root path) $HOME/.serf/
+//  (root path) $HOME/.serf/ or --root-dir / SERF_ROOT_DIR
 //  (cfg path) $HOME/.serf/cfg/
 //  (users db path) $HOME/.serf/cfg/{hashed}/
 //      then {hashed}.db file is the users db
@@ -13,78 +21,278 @@ use sqlx::{migrate::MigrateDatabase, Sqlite};
 //      folder is a sha256 hash of the db name
 //      containing {hashed}.db file
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let database_manager = DatabaseManager::new();
-    if !Sqlite::database_exists(&database_manager.user_db_full_path_string)
-        .await
-        .unwrap_or(false)
-    {
-        database_manager.init().await;
-        println!("INITIAL SETUP");
+async fn main() {
+    let args = SerfCliArgs::parse();
+
+    if let SerfCliCommand::Completions { shell } = args.command {
+        clap_complete::generate(
+            shell,
+            &mut SerfCliArgs::command(),
+            "serf-cli",
+            &mut io::stdout(),
+        );
+        return;
+    }
+
+    if let Err(err) = run(args.command, &args.root_dir).await {
+        eprintln!("Error: {}", err.message);
+        process::exit(1);
     }
+}
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        let cmd_one = args[1].as_str();
-        let cmd_two = args[2].as_str();
+async fn run(command: SerfCliCommand, root_dir: &Path) -> Result<(), Error> {
+    let database_manager = DatabaseManager::new(root_dir);
+    let is_initial_setup = !Sqlite::database_exists(&database_manager.user_db_full_path_string)
+        .await
+        .unwrap_or(false);
 
-        match cmd_one {
-            "create" => {
-                let args_split = args.clone().split_off(3);
+    database_manager.init().await?;
+    if is_initial_setup {
+        println!("INITIAL SETUP");
+    }
 
-                match cmd_two {
-                    "database" => {
-                        let db = get_flag_val::<String>(&args_split, cli::DB_NAME_FLAG).unwrap();
-                        database_manager.create_consumer_database(&db).await;
-                    }
-                    "user" => {
-                        let username =
-                            get_flag_val::<String>(&args_split, cli::USERNAME_FLAG).unwrap();
-                        let password =
-                            get_flag_val::<String>(&args_split, cli::PASSWORD_FLAG).unwrap();
-                        database_manager.create_user(username, password).await;
-                    }
-                    _ => panic!(
-                        "Error: Unknown command {}, supported commands are [database, user]",
-                        cmd_two
-                    ),
+    let audit_entry = command.audit_entry(
+        &env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .unwrap_or(String::from("unknown")),
+    );
+    let res = match command {
+        SerfCliCommand::Create(command) => match command {
+            CreateCommand::Database {
+                database,
+                from,
+                schema_only,
+            } => {
+                database_manager
+                    .create_consumer_database_from(&database.db, from.as_deref(), schema_only)
+                    .await
+            }
+            CreateCommand::User { user, password } => {
+                database_manager
+                    .create_user(user.username, password.password)
+                    .await
+            }
+            CreateCommand::Group(group) => database_manager.create_group(group.group).await,
+            CreateCommand::ApiKey {
+                user,
+                name,
+                expires_at,
+                scope,
+            } => database_manager
+                .create_api_key(user.username, name, expires_at, &scope)
+                .await
+                .map(|api_key| {
+                    println!("{}", api_key);
+                    println!("Store the key now, it can't be shown again. Requests are signed with its sha256 hash.");
+                }),
+            CreateCommand::Role { role, permissions } => {
+                database_manager.create_role(role.name, &permissions).await
+            }
+        },
+        SerfCliCommand::Modify(command) => match command {
+            ModifyCommand::User(command) => match command {
+                ModifyUserCommand::Access {
+                    user,
+                    database,
+                    role,
+                } => {
+                    database_manager
+                        .modify_user_access(user.username, database.db, role)
+                        .await
+                }
+                // --revoke conflicts with --role, leaving it None
+                ModifyUserCommand::Role { user, role, .. } => {
+                    database_manager.modify_user_role(user.username, role).await
+                }
+                ModifyUserCommand::Limits { user, limits } => {
+                    database_manager
+                        .modify_user_limits(
+                            user.username,
+                            limits.query_timeout,
+                            limits.max_rows.map(Some).or(limits.reset_max_rows.then_some(None)),
+                            limits
+                                .max_response_bytes
+                                .map(Some)
+                                .or(limits.reset_max_response_bytes.then_some(None)),
+                            limits.max_databases,
+                        )
+                        .await
+                }
+                ModifyUserCommand::Password {
+                    user,
+                    password,
+                    new_password,
+                } => {
+                    database_manager
+                        .modify_user_password(user.username, password.password, new_password)
+                        .await
+                }
+                ModifyUserCommand::Admin { user, revoke } => {
+                    database_manager
+                        .modify_user_admin(user.username, !revoke)
+                        .await
+                }
+                ModifyUserCommand::Creator { user, revoke } => {
+                    database_manager
+                        .modify_user_creator(user.username, !revoke)
+                        .await
+                }
+                ModifyUserCommand::Rename {
+                    user,
+                    password,
+                    new_username,
+                } => {
+                    database_manager
+                        .modify_user_username(user.username, password.password, new_username)
+                        .await
+                }
+            },
+            ModifyCommand::Database(command) => match command {
+                ModifyDatabaseCommand::Config {
+                    database,
+                    key,
+                    value,
+                } => database_manager.modify_database_config(database.db, key, value),
+            },
+            ModifyCommand::Group(command) => match command {
+                ModifyGroupCommand::AddMember { group, user } => {
+                    database_manager
+                        .modify_group_member(group.group, user.username, true)
+                        .await
+                }
+                ModifyGroupCommand::RemoveMember { group, user } => {
+                    database_manager
+                        .modify_group_member(group.group, user.username, false)
+                        .await
+                }
+                ModifyGroupCommand::Access {
+                    group,
+                    database,
+                    role,
+                } => {
+                    database_manager
+                        .modify_group_access(group.group, database.db, role)
+                        .await
                 }
+            },
+        },
+        SerfCliCommand::Delete(command) => match command {
+            DeleteCommand::User(user) => database_manager.delete_user(user.username).await,
+            DeleteCommand::Role(role) => database_manager.delete_role(role.name).await,
+            DeleteCommand::Group(group) => database_manager.delete_group(group.group).await,
+        },
+        SerfCliCommand::Revoke(command) => match command {
+            RevokeCommand::Access { user, database } => {
+                database_manager
+                    .revoke_user_access(user.username, database.db)
+                    .await
             }
-            "modify" => match cmd_two {
-                "user" => {
-                    let cmd_three = args[3].as_str();
-                    let args_split = args.clone().split_off(4);
-
-                    match cmd_three {
-                        "access" => {
-                            let username =
-                                get_flag_val::<String>(&args_split, cli::USERNAME_FLAG).unwrap();
-                            let database =
-                                get_flag_val::<String>(&args_split, cli::DB_NAME_FLAG).unwrap();
-                            let access_right =
-                                get_flag_val::<u8>(&args_split, cli::ACCESS_RIGHT_FLAG).unwrap();
-
-                            database_manager
-                                .modify_user_access(username, database, access_right)
-                                .await;
-                        }
-                        _ => panic!(
-                            "Error: Unknown command {}, supported commands are [access]",
-                            cmd_three
-                        ),
-                    }
+            RevokeCommand::GroupAccess { group, database } => {
+                database_manager
+                    .revoke_group_access(group.group, database.db)
+                    .await
+            }
+            RevokeCommand::ApiKey { user, name, at } => {
+                database_manager
+                    .revoke_api_key(user.username, name, at)
+                    .await
+            }
+        },
+        SerfCliCommand::List(command) => match command {
+            ListCommand::Users => database_manager.list_users(None).await,
+            ListCommand::Databases => database_manager.list_databases().await,
+            ListCommand::Roles => database_manager.list_roles().await,
+            ListCommand::Groups => database_manager.list_groups().await,
+            ListCommand::ApiKeys(user) => database_manager.list_api_keys(user.username).await,
+        },
+        SerfCliCommand::Show(ShowCommand::User(user)) => {
+            database_manager.list_users(Some(user.username)).await
+        }
+        SerfCliCommand::Drop(DropCommand::Database { database, yes }) => {
+            database_manager.drop_database(database.db, yes).await
+        }
+        SerfCliCommand::Rename(RenameCommand::Database { database, new_db }) => {
+            database_manager.rename_database(database.db, new_db).await
+        }
+        SerfCliCommand::Info(InfoCommand::Database(database)) => {
+            database_manager.database_info(database.db).await
+        }
+        SerfCliCommand::Backup(command) => match command {
+            BackupCommand::Database { database, output } => {
+                database_manager
+                    .backup_database(Some(database.db), &output.output, output.compress)
+                    .await
+            }
+            BackupCommand::Users(output) => {
+                database_manager
+                    .backup_database(None, &output.output, output.compress)
+                    .await
+            }
+        },
+        SerfCliCommand::Restore(command) => match command {
+            RestoreCommand::Database {
+                database,
+                source,
+                yes,
+            } => match (source.input, source.to) {
+                (_, Some(to)) => {
+                    database_manager
+                        .restore_database_to(&database.db, to, yes)
+                        .await
+                }
+                (input, None) => {
+                    database_manager
+                        .restore_database(Some(database.db), &input.unwrap_or_default(), yes)
+                        .await
                 }
-                _ => panic!(
-                    "Error: Unknown command {}, supported commands are [user]",
-                    cmd_two
-                ),
             },
-            _ => panic!(
-                "Error: Unknown command {}, supported commands are [create, modify]",
-                cmd_one
-            ),
+            RestoreCommand::Users(input) => {
+                database_manager
+                    .restore_database(None, &input.input, input.yes)
+                    .await
+            }
+        },
+        SerfCliCommand::Export(export) => {
+            database_manager
+                .export_database(
+                    export.database.db,
+                    export.table,
+                    export.format,
+                    export.output.as_deref(),
+                )
+                .await
+        }
+        SerfCliCommand::Import(import) => {
+            database_manager
+                .import_database(
+                    import.database.db,
+                    import.table,
+                    import.format,
+                    import.input.as_deref(),
+                    import.batch_size,
+                    import.max_errors,
+                )
+                .await
+        }
+        SerfCliCommand::Audit(audit) => {
+            database_manager
+                .list_audit_entries(audit.username, audit.db, audit.from, audit.to, audit.limit)
+                .await
+        }
+        SerfCliCommand::Completions { .. } => Ok(()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        if let Err(err) = database_manager
+            .record_audit_entry(&audit_entry.with_result(&res))
+            .await
+        {
+            eprintln!(
+                "Error: the change wasn't written to the audit log, {}",
+                err.message
+            );
         }
     }
 
-    Ok(())
+    res
 }
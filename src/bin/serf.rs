@@ -1,43 +1,76 @@
-use std::env;
-use std::path::Path;
+use std::io;
 use std::sync::Arc;
 
 use actix_web::{web, App, HttpServer};
-use papaya::HashMap;
-use serf::core::constants::cli;
+use clap::{CommandFactory, Parser};
+use papaya::{HashMap, HashSet};
 use serf::core::state::AppState;
 use serf::{
-    cli::util::get_flag_val,
-    core::util::{async_watch, get_db_users, populate_app_state_users},
+    cli::{
+        args::{AuthMethod, SerfArgs, SerfCommand},
+        util::DatabaseManager,
+    },
+    core::util::{
+        async_archive_wal, async_evict_db_connections, async_prune_audit_log,
+        async_scheduled_backups, async_watch, get_db_users, populate_app_state_users,
+    },
+    web::{
+        auth::{
+            client_certificate_on_connect, tls_server_config, Authenticator, HmacAuthenticator,
+            JwtAuthenticator, MtlsAuthenticator,
+        },
+        replica::{async_replicate, Follower},
+    },
 };
 
-include!(concat!(env!("OUT_DIR"), "/gen.rs"));
-
 const HOST: &str = "127.0.0.1";
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let mut port = cli::DEFAULT_PORT;
-    let mut db_max_conn = cli::DEFAULT_DB_MAX_CONN;
-    let mut db_max_idle_time = cli::DEFAULT_DB_MAX_IDLE_TIME;
-    let mut db_max_lifetime = cli::DEFAULT_DB_MAX_LIFETIME;
-
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        port = get_flag_val::<u16>(&args, cli::PORT_FLAG).unwrap_or(cli::DEFAULT_PORT);
-        db_max_conn =
-            get_flag_val::<u32>(&args, cli::DB_MAX_CONN_FLAG).unwrap_or(cli::DEFAULT_DB_MAX_CONN);
-        db_max_idle_time = get_flag_val::<u64>(&args, cli::DB_MAX_IDLE_TIME_FLAG)
-            .unwrap_or(cli::DEFAULT_DB_MAX_IDLE_TIME);
-        db_max_lifetime = get_flag_val::<u64>(&args, cli::DB_MAX_LIFETIME_FLAG)
-            .unwrap_or(cli::DEFAULT_DB_MAX_LIFETIME);
+    let SerfArgs {
+        command,
+        root_dir,
+        port,
+        db_max_conn,
+        db_max_idle_time,
+        db_max_lifetime,
+        db_max_open,
+        db_pool_max_idle_time,
+        query_timeout,
+        max_rows,
+        max_response_bytes,
+        max_databases,
+        replicate_from,
+        replicate_db,
+        replication_username,
+        replication_password,
+        replication_interval,
+        audit_retention_days,
+        auth,
+        jwks_file,
+        jwt_algorithm,
+        jwt_issuer,
+        jwt_audience,
+        jwt_username_claim,
+        tls_cert,
+        tls_key,
+        tls_client_ca,
+    } = SerfArgs::parse();
+
+    if let Some(SerfCommand::Completions { shell }) = command {
+        clap_complete::generate(shell, &mut SerfArgs::command(), "serf", &mut io::stdout());
+        return Ok(());
     }
 
-    let root_dir = Path::new(ROOT_DIR);
-    let cfg_path = root_dir.join("cfg");
-    let consumer_db_path = root_dir.join("db");
-    let user_db_path = cfg_path.join(USER_DB_HASH);
-    let user_db = format!("{}/{}.db", user_db_path.to_str().unwrap(), USER_DB_HASH);
+    let database_manager = DatabaseManager::new(&root_dir);
+    let user_db = database_manager.user_db_full_path_string.clone();
+    if let Err(err) = database_manager.init().await {
+        panic!("{}", err.message);
+    }
+    let audit_db = match database_manager.connect_audit_db().await {
+        Ok(audit_db) => audit_db,
+        Err(err) => panic!("{}", err.message),
+    };
 
     let app_data = web::Data::new(AppState {
         db_connections: Arc::new(HashMap::new()),
@@ -45,33 +78,131 @@ async fn main() -> std::io::Result<()> {
         db_max_connections: db_max_conn,
         db_max_idle_time,
         db_max_lifetime,
-        db_path: String::from(consumer_db_path.to_str().unwrap()),
+        db_max_open,
+        db_pool_max_idle_time,
+        query_timeout,
+        max_rows,
+        max_response_bytes,
+        max_databases,
+        db_path: String::from(database_manager.consumer_db_base_path.to_str().unwrap()),
+        backup_path: String::from(database_manager.backup_base_path.to_str().unwrap()),
+        archive_path: String::from(database_manager.archive_base_path.to_str().unwrap()),
+        user_db_path: user_db.clone(),
+        restoring_dbs: Arc::new(HashSet::new()),
+        backup_statuses: Arc::new(HashMap::new()),
+        primary: replicate_from.clone(),
+        replication_statuses: Arc::new(HashMap::new()),
+        audit_db: Some(audit_db),
     });
+
+    let authenticator: Arc<dyn Authenticator> = match auth {
+        AuthMethod::Hmac => Arc::new(HmacAuthenticator),
+        AuthMethod::Jwt => match JwtAuthenticator::from_jwks_file(&jwks_file.unwrap_or_default()) {
+            Ok(authenticator) => Arc::new(
+                authenticator
+                    .with_algorithm(jwt_algorithm)
+                    .with_issuer(jwt_issuer)
+                    .with_audience(jwt_audience)
+                    .with_username_claim(jwt_username_claim),
+            ),
+            Err(err) => panic!("{}", err.message),
+        },
+        AuthMethod::Mtls => Arc::new(MtlsAuthenticator),
+    };
+    let tls_config = match (&tls_cert, &tls_key) {
+        (Some(tls_cert), Some(tls_key)) => {
+            match tls_server_config(tls_cert, tls_key, tls_client_ca.as_deref()) {
+                Ok(tls_config) => Some(tls_config),
+                Err(err) => panic!("{}", err.message),
+            }
+        }
+        _ => None,
+    };
+
     let app_data_c = app_data.clone();
+    let app_data_e = app_data.clone();
+    let app_data_b = app_data.clone();
+    let app_data_a = app_data.clone();
+    let app_data_r = app_data.clone();
+    let app_data_p = app_data.clone();
     match get_db_users(&user_db).await {
         Ok(val) => populate_app_state_users(val, &app_data),
         Err(e) => panic!("{e}"),
     };
 
-    let srv = HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(app_data.clone())
+            .app_data(web::Data::from(authenticator.clone()))
             .app_data(web::PayloadConfig::new(100 * 1024 * 1024))
+            .configure(serf::web::controller::init_admin_controller)
+            .configure(serf::web::controller::init_replication_controller)
+            .configure(serf::web::controller::init_export_controller)
             .configure(serf::web::controller::init_db_controller)
             .configure(serf::web::controller::init_health_controller)
     })
-    .bind((HOST, port))
+    .on_connect(client_certificate_on_connect);
+    let srv = match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23((HOST, port), tls_config),
+        None => server.bind((HOST, port)),
+    }
     .unwrap()
     .run();
 
     println!(
-        "SERVER RUNNING @ {}:{}\ndb_max_conn={}\ndb_max_idle_time={}",
-        HOST, port, db_max_conn, db_max_idle_time
+        "SERVER RUNNING @ {}:{}\nroot_dir={}\ndb_max_conn={}\ndb_max_idle_time={}\ndb_max_open={}\ndb_pool_max_idle_time={}\nquery_timeout={}\nmax_rows={}\nmax_response_bytes={}\nmax_databases={}\naudit_retention_days={}\nauth={:?}\ntls={}",
+        HOST,
+        port,
+        root_dir.display(),
+        db_max_conn,
+        db_max_idle_time,
+        db_max_open,
+        db_pool_max_idle_time,
+        query_timeout,
+        max_rows,
+        max_response_bytes,
+        max_databases,
+        audit_retention_days,
+        auth,
+        tls_cert.is_some()
     );
 
     actix_web::rt::spawn(async {
         let _ = async_watch(user_db, app_data_c).await;
     });
 
+    actix_web::rt::spawn(async {
+        async_evict_db_connections(app_data_e).await;
+    });
+
+    actix_web::rt::spawn(async {
+        async_scheduled_backups(app_data_b).await;
+    });
+
+    actix_web::rt::spawn(async {
+        async_archive_wal(app_data_a).await;
+    });
+
+    actix_web::rt::spawn(async move {
+        async_prune_audit_log(app_data_p, audit_retention_days).await;
+    });
+
+    if let Some(primary) = replicate_from {
+        println!(
+            "READ REPLICA OF {} replicating {}",
+            primary,
+            replicate_db.join(", ")
+        );
+        let follower = Follower::new(
+            &primary,
+            &replication_username.unwrap_or_default(),
+            &replication_password.unwrap_or_default(),
+            replicate_db,
+        );
+        actix_web::rt::spawn(async move {
+            async_replicate(app_data_r, follower, replication_interval).await;
+        });
+    }
+
     srv.await
 }
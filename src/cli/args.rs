@@ -0,0 +1,834 @@
+use std::{path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use clap::{
+    builder::{NonEmptyStringValueParser, PossibleValuesParser, TypedValueParser},
+    Args, Parser, Subcommand, ValueEnum,
+};
+use clap_complete::Shell;
+use jsonwebtoken::Algorithm;
+
+use crate::{
+    cli::util::ROOT_DIR,
+    core::{
+        audit::{AuditEntry, AUDIT_SOURCE_CLI},
+        constants::cli,
+        import::IMPORT_BATCH_SIZE,
+        role,
+        serf_proto::{ExportFormat, ImportFormat},
+    },
+};
+
+/// #### Arguments of the serf server binary
+/// \
+/// Every flag can also be given through its environment variable, the flag wins when both are set.
+#[derive(Debug, Parser)]
+#[command(
+    name = "serf",
+    version,
+    about = "SQLite server serving its databases over HTTP"
+)]
+pub struct SerfArgs {
+    #[command(subcommand)]
+    pub command: Option<SerfCommand>,
+
+    /// The serf root dir holding the users db (cfg) and the dbs (db)
+    #[arg(long, env = "SERF_ROOT_DIR", default_value = ROOT_DIR)]
+    pub root_dir: PathBuf,
+
+    /// The network port that the server accepts connections on
+    #[arg(long, env = "SERF_PORT", default_value_t = cli::DEFAULT_PORT)]
+    pub port: u16,
+
+    /// Max number of connections of a db read pool
+    #[arg(long, env = "SERF_DB_MAX_CONN", default_value_t = cli::DEFAULT_DB_MAX_CONN)]
+    pub db_max_conn: u32,
+
+    /// Seconds a connection may stay idle before it is closed
+    #[arg(long, env = "SERF_DB_MAX_IDLE_TIME", default_value_t = cli::DEFAULT_DB_MAX_IDLE_TIME)]
+    pub db_max_idle_time: u64,
+
+    /// Seconds a connection may live before it is closed
+    #[arg(long, env = "SERF_DB_MAX_LIFETIME", default_value_t = cli::DEFAULT_DB_MAX_LIFETIME)]
+    pub db_max_lifetime: u64,
+
+    /// Max number of dbs with an open pool at the same time
+    #[arg(long, env = "SERF_DB_MAX_OPEN", default_value_t = cli::DEFAULT_DB_MAX_OPEN)]
+    pub db_max_open: usize,
+
+    /// Seconds a db pool may go without requests before it is closed
+    #[arg(
+        long,
+        env = "SERF_DB_POOL_MAX_IDLE_TIME",
+        default_value_t = cli::DEFAULT_DB_POOL_MAX_IDLE_TIME
+    )]
+    pub db_pool_max_idle_time: u64,
+
+    /// Milliseconds a fetch or mutation may run for, 0 = no timeout
+    #[arg(long, env = "SERF_QUERY_TIMEOUT", default_value_t = cli::DEFAULT_QUERY_TIMEOUT)]
+    pub query_timeout: u64,
+
+    /// Max number of rows returned by a fetch, 0 = no limit
+    #[arg(long, env = "SERF_MAX_ROWS", default_value_t = cli::DEFAULT_MAX_ROWS)]
+    pub max_rows: u64,
+
+    /// Max size in bytes of the data returned by a fetch, 0 = no limit
+    #[arg(
+        long,
+        env = "SERF_MAX_RESPONSE_BYTES",
+        default_value_t = cli::DEFAULT_MAX_RESPONSE_BYTES
+    )]
+    pub max_response_bytes: u64,
+
+    /// Max number of dbs a user with the creator right may create and own, 0 = no limit
+    #[arg(long, env = "SERF_MAX_DATABASES", default_value_t = cli::DEFAULT_MAX_DATABASES)]
+    pub max_databases: u64,
+
+    /// Run as a read replica of the serf at this url (I.E http://10.0.0.1:8080), writes are rejected
+    #[arg(
+        long,
+        env = "SERF_REPLICATE_FROM",
+        requires_all = ["replicate_db", "replication_username", "replication_password"]
+    )]
+    pub replicate_from: Option<String>,
+
+    /// Name of a db to replicate from the primary, can be given multiple times or comma separated
+    #[arg(
+        long,
+        env = "SERF_REPLICATE_DB",
+        value_delimiter = ',',
+        requires = "replicate_from"
+    )]
+    pub replicate_db: Vec<String>,
+
+    /// User on the primary to replicate as, needs the fetch permission on the replicated dbs
+    #[arg(long, env = "SERF_REPLICATION_USERNAME", requires = "replicate_from")]
+    pub replication_username: Option<String>,
+
+    /// Password of the replication user
+    #[arg(
+        long,
+        env = "SERF_REPLICATION_PASSWORD",
+        hide_env_values = true,
+        requires = "replicate_from"
+    )]
+    pub replication_password: Option<String>,
+
+    /// Milliseconds between polls of the primary for new changes
+    #[arg(
+        long,
+        env = "SERF_REPLICATION_INTERVAL",
+        default_value_t = cli::DEFAULT_REPLICATION_INTERVAL
+    )]
+    pub replication_interval: u64,
+
+    /// Days the entries of the audit log are kept, 0 = forever
+    #[arg(
+        long,
+        env = "SERF_AUDIT_RETENTION_DAYS",
+        default_value_t = cli::DEFAULT_AUDIT_RETENTION_DAYS
+    )]
+    pub audit_retention_days: u64,
+
+    /// How requests are authenticated
+    #[arg(long, env = "SERF_AUTH", value_enum, default_value_t = AuthMethod::Hmac)]
+    pub auth: AuthMethod,
+
+    /// JWKS file with the keys the bearer tokens are verified with
+    #[arg(long, env = "SERF_JWKS_FILE", required_if_eq("auth", "jwt"))]
+    pub jwks_file: Option<PathBuf>,
+
+    /// Algorithm of the JWKS keys that don't set alg, I.E RS256
+    #[arg(long, env = "SERF_JWT_ALGORITHM", value_parser = Algorithm::from_str)]
+    pub jwt_algorithm: Option<Algorithm>,
+
+    /// Issuer (iss) the bearer tokens must have
+    #[arg(long, env = "SERF_JWT_ISSUER")]
+    pub jwt_issuer: Option<String>,
+
+    /// Audience (aud) the bearer tokens must have
+    #[arg(long, env = "SERF_JWT_AUDIENCE")]
+    pub jwt_audience: Option<String>,
+
+    /// Claim of the bearer tokens holding the username
+    #[arg(long, env = "SERF_JWT_USERNAME_CLAIM", default_value = "sub")]
+    pub jwt_username_claim: String,
+
+    /// PEM certificate chain to serve HTTPS with
+    #[arg(
+        long,
+        env = "SERF_TLS_CERT",
+        requires = "tls_key",
+        required_if_eq("auth", "mtls")
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the certificate
+    #[arg(long, env = "SERF_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM of the CAs that issue the client certificates
+    #[arg(
+        long,
+        env = "SERF_TLS_CLIENT_CA",
+        requires = "tls_cert",
+        required_if_eq("auth", "mtls")
+    )]
+    pub tls_client_ca: Option<PathBuf>,
+}
+
+/// #### How the server authenticates requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AuthMethod {
+    /// Requests signed with the user secret or an API key of the user
+    Hmac,
+    /// Signed JWT bearer tokens, verified with the keys of --jwks-file
+    Jwt,
+    /// Client certificates issued by --tls-client-ca, the common name is the username
+    Mtls,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SerfCommand {
+    /// Print a shell completion script
+    Completions { shell: Shell },
+}
+
+/// #### Arguments of the serf-cli binary
+#[derive(Debug, Parser)]
+#[command(
+    name = "serf-cli",
+    version,
+    about = "Manage the databases and users of serf"
+)]
+pub struct SerfCliArgs {
+    #[command(subcommand)]
+    pub command: SerfCliCommand,
+
+    /// The serf root dir holding the users db (cfg) and the dbs (db)
+    #[arg(long, global = true, env = "SERF_ROOT_DIR", default_value = ROOT_DIR)]
+    pub root_dir: PathBuf,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SerfCliCommand {
+    /// Create a database or user
+    #[command(subcommand)]
+    Create(CreateCommand),
+    /// Modify a user or database
+    #[command(subcommand)]
+    Modify(ModifyCommand),
+    /// Delete a user
+    #[command(subcommand)]
+    Delete(DeleteCommand),
+    /// Revoke the access of a user to a database
+    #[command(subcommand)]
+    Revoke(RevokeCommand),
+    /// List users or databases
+    #[command(subcommand)]
+    List(ListCommand),
+    /// Show a user
+    #[command(subcommand)]
+    Show(ShowCommand),
+    /// Drop a database
+    #[command(subcommand)]
+    Drop(DropCommand),
+    /// Rename a database
+    #[command(subcommand)]
+    Rename(RenameCommand),
+    /// Show the page stats and schema of a database
+    #[command(subcommand)]
+    Info(InfoCommand),
+    /// Take a consistent backup of a database or the users db, also while the server is running
+    #[command(subcommand)]
+    Backup(BackupCommand),
+    /// Restore a database or the users db from a backup
+    #[command(subcommand)]
+    Restore(RestoreCommand),
+    /// Export a table of a database as csv, jsonl or sql, or dump the whole database as sql
+    Export(ExportArgs),
+    /// Import rows from csv or jsonl into a table of a database
+    Import(ImportArgs),
+    /// Show the audit log of the changes made to the databases, users and their access
+    Audit(AuditArgs),
+    /// Print a shell completion script
+    Completions { shell: Shell },
+}
+
+impl SerfCliCommand {
+    /// #### The audit log entry of the command, None for commands that don't change anything
+    /// \
+    /// Passwords are never part of the entry.
+    /// \
+    /// __*@param*__ actor: &str (the OS user running serf-cli)
+    pub fn audit_entry(&self, actor: &str) -> Option<AuditEntry> {
+        let entry = |action: &str| AuditEntry::new(AUDIT_SOURCE_CLI, actor, action);
+
+        let entry = match self {
+            SerfCliCommand::Create(command) => match command {
+                CreateCommand::Database { database, from, .. } => match from {
+                    Some(from) => entry(&format!("create database from {}", from)),
+                    None => entry("create database"),
+                }
+                .with_database_name(&database.db),
+                CreateCommand::User { user, .. } => {
+                    entry("create user").with_target_user(&user.username)
+                }
+                CreateCommand::Group(group) => entry(&format!("create group {}", group.group)),
+                CreateCommand::ApiKey { user, name, .. } => {
+                    entry(&format!("create api key {}", name)).with_target_user(&user.username)
+                }
+                CreateCommand::Role { role, .. } => entry(&format!("create role {}", role.name)),
+            },
+            SerfCliCommand::Modify(command) => match command {
+                ModifyCommand::User(command) => match command {
+                    ModifyUserCommand::Access {
+                        user,
+                        database,
+                        role,
+                    } => entry(&format!("grant role {}", role))
+                        .with_target_user(&user.username)
+                        .with_database_name(&database.db),
+                    ModifyUserCommand::Role { user, role, .. } => match role {
+                        Some(role) => entry(&format!("grant global role {}", role)),
+                        None => entry("revoke global role"),
+                    }
+                    .with_target_user(&user.username),
+                    ModifyUserCommand::Limits { user, .. } => {
+                        entry("modify limits").with_target_user(&user.username)
+                    }
+                    ModifyUserCommand::Password { user, .. } => {
+                        entry("modify password").with_target_user(&user.username)
+                    }
+                    ModifyUserCommand::Admin { user, revoke } => entry(if *revoke {
+                        "revoke admin"
+                    } else {
+                        "grant admin"
+                    })
+                    .with_target_user(&user.username),
+                    ModifyUserCommand::Creator { user, revoke } => entry(if *revoke {
+                        "revoke creator"
+                    } else {
+                        "grant creator"
+                    })
+                    .with_target_user(&user.username),
+                    ModifyUserCommand::Rename {
+                        user, new_username, ..
+                    } => entry(&format!("rename user to {}", new_username))
+                        .with_target_user(&user.username),
+                },
+                ModifyCommand::Database(ModifyDatabaseCommand::Config {
+                    database,
+                    key,
+                    value,
+                }) => entry(&format!("modify config {}={}", key, value))
+                    .with_database_name(&database.db),
+                ModifyCommand::Group(command) => match command {
+                    ModifyGroupCommand::AddMember { group, user } => {
+                        entry(&format!("add member to group {}", group.group))
+                            .with_target_user(&user.username)
+                    }
+                    ModifyGroupCommand::RemoveMember { group, user } => {
+                        entry(&format!("remove member from group {}", group.group))
+                            .with_target_user(&user.username)
+                    }
+                    ModifyGroupCommand::Access {
+                        group,
+                        database,
+                        role,
+                    } => entry(&format!("grant role {} to group {}", role, group.group))
+                        .with_database_name(&database.db),
+                },
+            },
+            SerfCliCommand::Delete(command) => match command {
+                DeleteCommand::User(user) => entry("delete user").with_target_user(&user.username),
+                DeleteCommand::Role(role) => entry(&format!("delete role {}", role.name)),
+                DeleteCommand::Group(group) => entry(&format!("delete group {}", group.group)),
+            },
+            SerfCliCommand::Revoke(command) => match command {
+                RevokeCommand::Access { user, database } => entry("revoke access")
+                    .with_target_user(&user.username)
+                    .with_database_name(&database.db),
+                RevokeCommand::GroupAccess { group, database } => {
+                    entry(&format!("revoke access of group {}", group.group))
+                        .with_database_name(&database.db)
+                }
+                RevokeCommand::ApiKey { user, name, .. } => {
+                    entry(&format!("revoke api key {}", name)).with_target_user(&user.username)
+                }
+            },
+            SerfCliCommand::Drop(DropCommand::Database { database, .. }) => {
+                entry("drop database").with_database_name(&database.db)
+            }
+            SerfCliCommand::Rename(RenameCommand::Database { database, new_db }) => {
+                entry(&format!("rename database to {}", new_db)).with_database_name(&database.db)
+            }
+            SerfCliCommand::Restore(command) => match command {
+                RestoreCommand::Database { database, .. } => {
+                    entry("restore database").with_database_name(&database.db)
+                }
+                RestoreCommand::Users(_) => entry("restore users"),
+            },
+            SerfCliCommand::Import(import) => entry(&format!("import into {}", import.table))
+                .with_database_name(&import.database.db),
+            SerfCliCommand::List(_)
+            | SerfCliCommand::Show(_)
+            | SerfCliCommand::Info(_)
+            | SerfCliCommand::Backup(_)
+            | SerfCliCommand::Export(_)
+            | SerfCliCommand::Audit(_)
+            | SerfCliCommand::Completions { .. } => return None,
+        };
+
+        Some(entry)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CreateCommand {
+    /// Create a database, stored as <root_dir>/db/<db_hash>/<db_hash>.db
+    Database {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        /// Create the database as a copy of this database, I.E a migrated template
+        #[arg(long, value_parser = NonEmptyStringValueParser::new())]
+        from: Option<String>,
+        /// Copy the schema and tracked migrations of the template but none of its rows
+        #[arg(long, requires = "from")]
+        schema_only: bool,
+    },
+    /// Create a user
+    User {
+        #[command(flatten)]
+        user: UserArgs,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Create a group of users that can be given roles on databases together
+    Group(GroupArgs),
+    /// Create an API key for a user, the key is printed once
+    ApiKey {
+        #[command(flatten)]
+        user: UserArgs,
+        /// Name of the key, sent in header 2 of the requests signed with the key
+        #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+        name: String,
+        /// The key can't be used after this time (RFC 3339)
+        #[arg(long)]
+        expires_at: Option<DateTime<Utc>>,
+        /// Permissions the key is limited to, all permissions of the user when not given
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_parser = PossibleValuesParser::new(role::PERMISSIONS.map(|(name, _)| name))
+        )]
+        scope: Vec<String>,
+    },
+    /// Create a custom role with a set of permissions
+    Role {
+        #[command(flatten)]
+        role: RoleArgs,
+        /// fetch, mutate, migrate and admin (backup, restore and the /admin endpoints)
+        #[arg(
+            short,
+            long,
+            required = true,
+            value_delimiter = ',',
+            value_parser = PossibleValuesParser::new(role::PERMISSIONS.map(|(name, _)| name))
+        )]
+        permissions: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ModifyCommand {
+    /// Modify a user
+    #[command(subcommand)]
+    User(ModifyUserCommand),
+    /// Modify a database
+    #[command(subcommand)]
+    Database(ModifyDatabaseCommand),
+    /// Modify a group
+    #[command(subcommand)]
+    Group(ModifyGroupCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ModifyUserCommand {
+    /// Give a user access to a database
+    Access {
+        #[command(flatten)]
+        user: UserArgs,
+        #[command(flatten)]
+        database: DatabaseArgs,
+        /// reader, writer, migrator, admin or a custom role, replacing the role on the database
+        #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+        role: String,
+    },
+    /// Give a user a role on every database, on top of its roles per database
+    Role {
+        #[command(flatten)]
+        user: UserArgs,
+        #[arg(
+            short,
+            long,
+            required_unless_present = "revoke",
+            value_parser = NonEmptyStringValueParser::new()
+        )]
+        role: Option<String>,
+        /// Take the global role away instead
+        #[arg(long, conflicts_with = "role")]
+        revoke: bool,
+    },
+    /// Override the server wide limits for a user, 0 resets a limit to the server default
+    Limits {
+        #[command(flatten)]
+        user: UserArgs,
+        #[command(flatten)]
+        limits: UserLimitArgs,
+    },
+    /// Change the password of a user
+    Password {
+        #[command(flatten)]
+        user: UserArgs,
+        #[command(flatten)]
+        password: PasswordArgs,
+        #[arg(long, value_parser = NonEmptyStringValueParser::new())]
+        new_password: String,
+    },
+    /// Give a user the admin global role, allowing it to use the /admin endpoints
+    Admin {
+        #[command(flatten)]
+        user: UserArgs,
+        /// Take the admin global role away instead
+        #[arg(long)]
+        revoke: bool,
+    },
+    /// Give a user the creator right, allowing it to create dbs through the protocol
+    Creator {
+        #[command(flatten)]
+        user: UserArgs,
+        /// Take the creator right away instead, the dbs it created are kept
+        #[arg(long)]
+        revoke: bool,
+    },
+    /// Change the username of a user
+    Rename {
+        #[command(flatten)]
+        user: UserArgs,
+        #[command(flatten)]
+        password: PasswordArgs,
+        #[arg(long, value_parser = NonEmptyStringValueParser::new())]
+        new_username: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ModifyDatabaseCommand {
+    /// Change a connection setting of a database
+    Config {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+        key: String,
+        /// Read as JSON when possible (numbers, booleans, null) and as a string otherwise
+        #[arg(short, long, allow_negative_numbers = true)]
+        value: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ModifyGroupCommand {
+    /// Add a user to a group, giving it the roles of the group
+    AddMember {
+        #[command(flatten)]
+        group: GroupArgs,
+        #[command(flatten)]
+        user: UserArgs,
+    },
+    /// Remove a user from a group
+    RemoveMember {
+        #[command(flatten)]
+        group: GroupArgs,
+        #[command(flatten)]
+        user: UserArgs,
+    },
+    /// Give every member of a group a role on a database
+    Access {
+        #[command(flatten)]
+        group: GroupArgs,
+        #[command(flatten)]
+        database: DatabaseArgs,
+        /// reader, writer, migrator, admin or a custom role, replacing the role on the database
+        #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+        role: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DeleteCommand {
+    /// Delete a user together with all of its database access
+    User(UserArgs),
+    /// Delete a custom role that isn't given to any user or group
+    Role(RoleArgs),
+    /// Delete a group, its members lose the roles of the group
+    Group(GroupArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RevokeCommand {
+    /// Revoke the access of a user to a database
+    Access {
+        #[command(flatten)]
+        user: UserArgs,
+        #[command(flatten)]
+        database: DatabaseArgs,
+    },
+    /// Revoke the access of a group to a database
+    GroupAccess {
+        #[command(flatten)]
+        group: GroupArgs,
+        #[command(flatten)]
+        database: DatabaseArgs,
+    },
+    /// Revoke an API key of a user
+    ApiKey {
+        #[command(flatten)]
+        user: UserArgs,
+        #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+        name: String,
+        /// Let the key expire at this time (RFC 3339) instead of deleting it right away
+        #[arg(long)]
+        at: Option<DateTime<Utc>>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ListCommand {
+    /// List all users with their limits and database access
+    Users,
+    /// List all databases with their size and users
+    Databases,
+    /// List all roles with their permissions
+    Roles,
+    /// List all groups with their members and database access
+    Groups,
+    /// List the API keys of a user
+    ApiKeys(UserArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ShowCommand {
+    /// Show a user with its limits and database access
+    User(UserArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DropCommand {
+    /// Delete a database together with all user access to it
+    Database {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        /// Skip the confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RenameCommand {
+    /// Rename a database, moving all user access along with it
+    Database {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[arg(long, value_parser = NonEmptyStringValueParser::new())]
+        new_db: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum InfoCommand {
+    /// Show the page stats and schema of a database
+    Database(DatabaseArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupCommand {
+    /// Back up a database
+    Database {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[command(flatten)]
+        output: BackupOutputArgs,
+    },
+    /// Back up the users db
+    Users(BackupOutputArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RestoreCommand {
+    /// Replace a database with a backup, or with its WAL archive at a point in time,
+    /// the server should not be serving the database (use the /admin/restore endpoint for that)
+    Database {
+        #[command(flatten)]
+        database: DatabaseArgs,
+        #[command(flatten)]
+        source: RestoreSourceArgs,
+        /// Skip the confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Replace the users db with a backup, the server should be stopped
+    Users(RestoreInputArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct BackupOutputArgs {
+    /// File to write the backup to, the manifest is written next to it as <output>.manifest.json
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Gzip the backup
+    #[arg(short = 'z', long)]
+    pub compress: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreInputArgs {
+    /// Backup file to restore, its manifest is expected next to it as <input>.manifest.json
+    #[arg(short, long)]
+    pub input: PathBuf,
+    /// Skip the confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub struct RestoreSourceArgs {
+    /// Backup file to restore, its manifest is expected next to it as <input>.manifest.json
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+    /// Restore the database as it was at this time (RFC 3339) from its WAL archive
+    #[arg(long)]
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+    /// Table to export, leave out for a SQL dump of the whole database
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub table: Option<String>,
+    /// csv or jsonl need a table, defaults to csv with a table and to sql without
+    #[arg(
+        short,
+        long,
+        value_parser = PossibleValuesParser::new(["csv", "jsonl", "sql"])
+            .map(|format| ExportFormat::from_str_name(&format.to_uppercase()).unwrap_or_default())
+    )]
+    pub format: Option<ExportFormat>,
+    /// File to write the export to, stdout when left out
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub table: String,
+    /// csv needs a header line with the column names, jsonl holds one JSON object per line
+    #[arg(
+        short,
+        long,
+        default_value = "csv",
+        value_parser = PossibleValuesParser::new(["csv", "jsonl"]).map(|format| {
+            ImportFormat::from_str_name(&format!("IMPORT_FORMAT_{}", format.to_uppercase()))
+                .unwrap_or_default()
+        })
+    )]
+    pub format: ImportFormat,
+    /// File to import, stdin when left out
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+    /// Number of failed rows tolerated before the import is aborted
+    #[arg(long, default_value_t = 0)]
+    pub max_errors: u64,
+    /// Number of rows inserted per transaction
+    #[arg(long, default_value_t = IMPORT_BATCH_SIZE)]
+    pub batch_size: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// Changes made by or to this user
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub username: Option<String>,
+    /// Changes made to this database
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub db: Option<String>,
+    /// Changes made at or after this time (RFC 3339)
+    #[arg(long)]
+    pub from: Option<DateTime<Utc>>,
+    /// Changes made at or before this time (RFC 3339)
+    #[arg(long)]
+    pub to: Option<DateTime<Utc>>,
+    /// Max number of entries to show, 0 = no limit
+    #[arg(long, default_value_t = 0)]
+    pub limit: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct UserArgs {
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub username: String,
+}
+
+#[derive(Debug, Args)]
+pub struct PasswordArgs {
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub password: String,
+}
+
+#[derive(Debug, Args)]
+pub struct GroupArgs {
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub group: String,
+}
+
+#[derive(Debug, Args)]
+pub struct RoleArgs {
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct DatabaseArgs {
+    #[arg(short, long, value_parser = NonEmptyStringValueParser::new())]
+    pub db: String,
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = true)]
+pub struct UserLimitArgs {
+    /// Milliseconds
+    #[arg(long)]
+    pub query_timeout: Option<u64>,
+    /// 0 = unlimited
+    #[arg(long)]
+    pub max_rows: Option<u64>,
+    /// Goes back to the server max rows
+    #[arg(long, conflicts_with = "max_rows")]
+    pub reset_max_rows: bool,
+    /// Bytes, 0 = unlimited
+    #[arg(long)]
+    pub max_response_bytes: Option<u64>,
+    /// Goes back to the server max response bytes
+    #[arg(long, conflicts_with = "max_response_bytes")]
+    pub reset_max_response_bytes: bool,
+    /// Number of dbs the user may create as a creator
+    #[arg(long)]
+    pub max_databases: Option<u64>,
+}
@@ -1,35 +1,92 @@
 use std::{
-    fs,
+    fs, io,
     path::{Path, PathBuf},
     str::FromStr,
 };
 
+use chrono::{DateTime, Utc};
 use regex::Regex;
+use serde_json::Value as JsonValue;
 use sha2::{Digest, Sha256};
 use sqlx::{
     migrate::MigrateDatabase,
+    sqlite::SqliteRow,
     sqlite::{SqliteAutoVacuum, SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
-    Sqlite, SqlitePool,
+    ConnectOptions, Connection, Row, Sqlite, SqliteConnection, SqlitePool,
 };
 
 use crate::core::{
+    audit::{self, AuditEntry, AuditFilter},
+    backup::{self, USER_DB_BACKUP_DIR},
+    config::DatabaseConfig,
     constants::queries,
-    db::{execute_query, AppliedQuery},
-    serf_proto::{query_arg, QueryArg},
+    db::{execute_query, fetch_query, quote_identifier, AppliedQuery},
+    error::{
+        undefined_error, ResourceNotExistError, SerfError, UndefinedError, UserNotAllowedError,
+        UserNotExistError,
+    },
+    export, import, replication,
+    role::{self, Role},
+    serf_proto::{
+        query_arg, DatabaseListing, DatabaseUser, Error, ExportFormat, ImportFormat, QueryArg,
+        UserAccess, UserListing,
+    },
+    wal_archive,
 };
 
 include!(concat!(env!("OUT_DIR"), "/gen.rs"));
 
+const DATABASE_NAME_PATTERN: &str = r"^[a-zA-Z0-9_-]+$";
+
+/// #### An API key of a user, without the key itself
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ApiKeyListing {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// None = all permissions of the user
+    pub scope: Option<u8>,
+}
+
+/// #### A group of users with the roles it has on dbs, as (db name, role)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Group {
+    pub name: String,
+    pub members: Vec<String>,
+    pub access: Vec<(String, String)>,
+}
+
+/// #### Manages the users db and the consumer dbs on disk
+/// \
+/// Every change to the users db is done in a transaction, any error returned from a method
+/// means that the transaction was rolled back.
 pub struct DatabaseManager {
+    pub archive_base_path: PathBuf,
+    pub audit_db_base_path: PathBuf,
+    pub audit_db_full_path_string: String,
+    pub backup_base_path: PathBuf,
     pub consumer_db_base_path: PathBuf,
     pub user_db_base_path: PathBuf,
     pub user_db_full_path_string: String,
 }
 
+impl Default for DatabaseManager {
+    fn default() -> Self {
+        Self::new(Path::new(ROOT_DIR))
+    }
+}
+
 impl DatabaseManager {
-    pub fn new() -> DatabaseManager {
-        let root_dir = Path::new(ROOT_DIR);
+    /// #### Paths of the users db and the consumer dbs under the given serf root dir
+    /// \
+    /// __*@param*__ root_dir: &Path (I.E the --root-dir flag, by default ROOT_DIR set at build time)
+    pub fn new(root_dir: &Path) -> DatabaseManager {
         let cfg_path = root_dir.join("cfg");
+        let archive_base_path = root_dir.join("archive");
+        let audit_db_base_path = root_dir.join("audit");
+        let audit_db_full_path_string =
+            format!("{}/audit.db", audit_db_base_path.to_str().unwrap());
+        let backup_base_path = root_dir.join("backup");
         let consumer_db_base_path = root_dir.join("db");
         let user_db_base_path = cfg_path.join(USER_DB_HASH);
         let user_db_full_path_string = format!(
@@ -39,6 +96,10 @@ impl DatabaseManager {
         );
 
         DatabaseManager {
+            archive_base_path,
+            audit_db_base_path,
+            audit_db_full_path_string,
+            backup_base_path,
             consumer_db_base_path,
             user_db_base_path,
             user_db_full_path_string,
@@ -62,192 +123,2049 @@ impl DatabaseManager {
         Ok(())
     }
 
-    pub async fn init(&self) {
-        if !self.user_db_base_path.exists() {
-            let _ = fs::create_dir_all(&self.user_db_base_path);
-        }
+    async fn connect_user_db(&self) -> Result<SqlitePool, Error> {
+        SqlitePool::connect(&format!("sqlite:{}", self.user_db_full_path_string))
+            .await
+            .map_err(undefined_error)
+    }
 
-        if !self.consumer_db_base_path.exists() {
-            let _ = fs::create_dir_all(&self.consumer_db_base_path);
-        }
+    pub async fn init(&self) -> Result<(), Error> {
+        fs::create_dir_all(&self.user_db_base_path).map_err(undefined_error)?;
+        fs::create_dir_all(&self.consumer_db_base_path).map_err(undefined_error)?;
+        fs::create_dir_all(&self.audit_db_base_path).map_err(undefined_error)?;
 
-        match Self::create_database(&self.user_db_full_path_string).await {
-            Ok(_) => {
-                let pool =
-                    SqlitePool::connect(&format!("sqlite:{}", self.user_db_full_path_string))
-                        .await
-                        .unwrap();
-                let mut transaction = pool.begin().await.unwrap();
+        Self::create_database(&self.user_db_full_path_string)
+            .await
+            .map_err(undefined_error)?;
 
-                // CREATE users
-                match execute_query(
-                    AppliedQuery::new(queries::CREATE_USERS_TABLE),
-                    &mut *transaction,
-                )
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+        let user_db_version = sqlx::query_scalar::<_, i64>(queries::GET_USER_DB_VERSION)
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(undefined_error)? as usize;
+
+        // bring the users db schema up to date, I.E a users db created by an older
+        // version of serf gets the tables / columns that were added since
+        for migration in queries::USER_DB_MIGRATIONS.iter().skip(user_db_version) {
+            execute_query(AppliedQuery::new(migration), &mut *transaction)
                 .await
-                {
-                    Ok(_) => {
-                        // CREATE database accesses
-                        // access_right can contain, or at least handle 1, 2 or 3 as value
-                        // all other values will be seen as non-functioning
-                        match execute_query(
-                            AppliedQuery::new(queries::CREATE_USERS_DATABASE_ACCESS_TABLE),
-                            &mut *transaction,
-                        )
-                        .await
-                        {
-                            Ok(_) => {
-                                let _ = transaction.commit().await;
-                            }
-                            Err(err) => {
-                                let _ = transaction.rollback().await;
-                                panic!("Error: {}", err);
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        let _ = transaction.rollback().await;
-                        panic!("Error: {}", err);
-                    }
+                .map_err(undefined_error)?;
+        }
+
+        execute_query(
+            AppliedQuery::new(&format!(
+                "PRAGMA user_version = {};",
+                queries::USER_DB_MIGRATIONS.len()
+            )),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)?;
+        pool.close().await;
+
+        Ok(())
+    }
+
+    pub async fn create_consumer_database(&self, db_name: &str) -> Result<(), Error> {
+        self.create_consumer_database_from(db_name, None, false)
+            .await
+    }
+
+    /// #### Creates a db, as a copy of the __template_name__ db when given
+    /// \
+    /// The template is copied with VACUUM INTO, which reads a consistent snapshot of it, so a template
+    /// that is being served can be copied. Its schema and tracked migrations (__migrations_tracker_t__) are
+    /// always copied, so the new db is as migrated as the template, its rows only without __schema_only__.
+    /// The copy starts out with the default db config, no replication log and no access.
+    pub async fn create_consumer_database_from(
+        &self,
+        db_name: &str,
+        template_name: Option<&str>,
+        schema_only: bool,
+    ) -> Result<(), Error> {
+        validate_database_name(db_name)?;
+
+        let db_name_hash = base16ct::lower::encode_string(&Sha256::digest(db_name.as_bytes()));
+        let consumer_db_full_path = self.consumer_db_base_path.join(&db_name_hash);
+        let consumer_db = format!(
+            "{}/{}.db",
+            consumer_db_full_path.to_str().unwrap(),
+            db_name_hash
+        );
+
+        if Sqlite::database_exists(&consumer_db).await.unwrap_or(false) {
+            return Err(UndefinedError::with_message("Database already exists"));
+        }
+
+        let template_db_file = match template_name {
+            Some(template_name) => {
+                let template_name_hash =
+                    base16ct::lower::encode_string(&Sha256::digest(template_name.as_bytes()));
+                let template_db_file = self
+                    .consumer_db_base_path
+                    .join(&template_name_hash)
+                    .join(format!("{}.db", template_name_hash));
+                if !template_db_file.exists() {
+                    return Err(ResourceNotExistError::with_message(
+                        "Template database doesn't exist",
+                    ));
                 }
+
+                Some(template_db_file)
+            }
+            None => None,
+        };
+
+        fs::create_dir_all(&consumer_db_full_path).map_err(undefined_error)?;
+        let res = async {
+            if let Some(template_db_file) = &template_db_file {
+                copy_database(template_db_file, Path::new(&consumer_db), schema_only).await?;
             }
-            Err(err) => panic!("Error: {}", err),
+
+            Self::create_database(&consumer_db)
+                .await
+                .map_err(undefined_error)?;
+            // reminder of the actual db name should it be forgotten
+            fs::write(consumer_db_full_path.join(db_name), &db_name_hash)
+                .map_err(undefined_error)?;
+            DatabaseConfig::default().save(&consumer_db_full_path)
+        }
+        .await;
+        if let Err(e) = res {
+            let _ = fs::remove_dir_all(&consumer_db_full_path);
+            return Err(e);
         }
+
+        match template_name {
+            Some(template_name) => println!(
+                "Successfully created db {} as {} from {}{}",
+                db_name,
+                db_name_hash,
+                template_name,
+                if schema_only { " (schema only)" } else { "" }
+            ),
+            None => println!("Successfully created db {} as {}", db_name, db_name_hash),
+        }
+
+        Ok(())
     }
 
-    pub async fn create_consumer_database(&self, db_name: &str) {
-        if !db_name.eq("") {
-            let regex = Regex::new(r"^[a-zA-Z0-9_-]+$").unwrap();
-            if !regex.is_match(db_name) {
-                panic!("Error: Database name format must follow either one or a combination of the patterns [a-z, A-Z, 0-9, _, -]");
-            }
+    /// #### Creates a db on behalf of a creator, who becomes its owner with the writer role
+    /// \
+    /// __*@param*__ max_databases: u64 (the number of dbs the user may own, 0 = no limit)
+    pub async fn create_owned_database(
+        &self,
+        username: String,
+        db_name: &str,
+        template_name: Option<&str>,
+        schema_only: bool,
+        max_databases: u64,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+        let db_name_hash = base16ct::lower::encode_string(&Sha256::digest(db_name.as_bytes()));
 
-            let db_name_hash = base16ct::lower::encode_string(&Sha256::digest(db_name.as_bytes()));
-            let consumer_db_full_path_string = format!(
-                "{}/{}",
-                self.consumer_db_base_path.to_str().unwrap(),
-                db_name_hash
-            );
-            let consumer_db_full_path = Path::new(&consumer_db_full_path_string);
-            let consumer_db = format!("{}/{}.db", consumer_db_full_path_string, db_name_hash);
-
-            if !consumer_db_full_path.exists() {
-                let _ = fs::create_dir_all(&consumer_db_full_path);
-            }
-
-            if !Sqlite::database_exists(&consumer_db).await.unwrap_or(false) {
-                match Self::create_database(&consumer_db).await {
-                    Ok(_) => {
-                        println!("Successfully created db {} as {}", db_name, db_name_hash);
-                        let _ = fs::write(
-                            format!("{}/{}", consumer_db_full_path_string, db_name),
-                            db_name_hash,
-                        );
-                    }
-                    Err(err) => panic!("Error: {}", err),
-                }
-            } else {
-                panic!("Error: Database already exists");
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        let owned_databases = sqlx::query_scalar::<_, i64>(queries::COUNT_USER_OWNED_DATABASES)
+            .bind(&username_hash)
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(undefined_error)? as u64;
+        if max_databases > 0 && owned_databases >= max_databases {
+            return Err(UserNotAllowedError::with_message(&format!(
+                "User already owns the max number of dbs ({})",
+                max_databases
+            )));
+        }
+
+        self.create_consumer_database_from(db_name, template_name, schema_only)
+            .await?;
+        let res = execute_query(
+            AppliedQuery::new(queries::INSERT_OWNER_DATABASE_ACCESS).with_args(&[
+                QueryArg::new(query_arg::Value::String(db_name.to_string())),
+                QueryArg::new(query_arg::Value::String(db_name_hash.clone())),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error);
+
+        match res {
+            Ok(_) => transaction.commit().await.map_err(undefined_error),
+            Err(e) => {
+                let _ = fs::remove_dir_all(self.consumer_db_base_path.join(&db_name_hash));
+                Err(e)
             }
         }
     }
 
-    pub async fn create_user(&self, username: String, password: String) {
-        if !username.eq("") && !password.eq("") {
-            let username_hash =
-                base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
-            let username_password_hash = base16ct::lower::encode_string(&Sha256::digest(
-                format!("{}{}", username, password).as_bytes(),
+    pub async fn create_user(&self, username: String, password: String) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+        let username_password_hash = base16ct::lower::encode_string(&Sha256::digest(
+            format!("{}{}", username, password).as_bytes(),
+        ));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        execute_query(
+            AppliedQuery::new(queries::INSERT_USER).with_args(&[
+                QueryArg::new(query_arg::Value::String(username)),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+                QueryArg::new(query_arg::Value::String(username_password_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### Gives the user the role on the db, replacing the role it had on the db
+    /// \
+    /// __*@param*__ role: String (I.E reader, writer, migrator, admin or a custom role)
+    pub async fn modify_user_access(
+        &self,
+        username: String,
+        database_name: String,
+        role: String,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+        let database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        get_role(&role, &mut transaction).await?;
+
+        execute_query(
+            AppliedQuery::new(queries::UPSERT_USER_DATABASE_ACCESS).with_args(&[
+                QueryArg::new(query_arg::Value::String(database_name)),
+                QueryArg::new(query_arg::Value::String(database_name_hash)),
+                QueryArg::new(query_arg::Value::String(role)),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### Gives the user a global role, applying to every db on top of its roles per db
+    /// \
+    /// __*@param*__ role: Option\<String\> (None takes the global role away)
+    pub async fn modify_user_role(
+        &self,
+        username: String,
+        role: Option<String>,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        if let Some(role) = &role {
+            get_role(role, &mut transaction).await?;
+        }
+
+        let res = execute_query(
+            AppliedQuery::new(queries::UPDATE_USER_ROLE).with_args(&[
+                QueryArg::new(query_arg::Value::String(role.unwrap_or_default())),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(UserNotExistError::default());
+        }
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### Creates a custom role with the given permissions
+    /// \
+    /// __*@param*__ permissions: &\[String\] (I.E ["fetch", "migrate"], see role::PERMISSIONS)
+    pub async fn create_role(&self, name: String, permissions: &[String]) -> Result<(), Error> {
+        let permissions = role::permissions_from_names(permissions)?;
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        execute_query(
+            AppliedQuery::new(queries::INSERT_ROLE).with_args(&[
+                QueryArg::new(query_arg::Value::String(name)),
+                QueryArg::new(query_arg::Value::Int(permissions as i64)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### Deletes a custom role
+    /// \
+    /// Built-in roles and roles that are still given to a user can't be deleted.
+    pub async fn delete_role(&self, name: String) -> Result<(), Error> {
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        if get_role(&name, &mut transaction).await?.is_builtin {
+            return Err(UserNotAllowedError::with_message(
+                "Built-in roles can't be deleted",
             ));
+        }
 
-            let pool = SqlitePool::connect(&format!("sqlite:{}", self.user_db_full_path_string))
-                .await
-                .unwrap();
-            let mut transaction = pool.begin().await.unwrap();
-            let _ = match execute_query(
-                AppliedQuery::new(queries::INSERT_USER).with_args(&vec![
-                    QueryArg::new(query_arg::Value::String(username)),
-                    QueryArg::new(query_arg::Value::String(username_hash)),
-                    QueryArg::new(query_arg::Value::String(username_password_hash)),
-                ]),
-                &mut *transaction,
-            )
+        let assignments = fetch_query(
+            AppliedQuery::new(queries::COUNT_ROLE_ASSIGNMENTS)
+                .with_args(&[QueryArg::new(query_arg::Value::String(name.clone()))]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?
+        .first()
+        .map_or(0, |row| row.get::<i64, _>(0));
+
+        if assignments > 0 {
+            return Err(UserNotAllowedError::with_message(&format!(
+                "Role {} is still given to users {} times",
+                name, assignments
+            )));
+        }
+
+        execute_query(
+            AppliedQuery::new(queries::DELETE_ROLE)
+                .with_args(&[QueryArg::new(query_arg::Value::String(name))]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### Every role with its permissions
+    /// \
+    /// __*@returns*__ Result\<Vec\<Role\>, Error\> (ordered by name)
+    pub async fn get_roles(&self) -> Result<Vec<Role>, Error> {
+        let pool = self.connect_user_db().await?;
+        let rows = fetch_query(AppliedQuery::new(queries::GET_ROLES), &pool)
             .await
-            {
-                Ok(_) => transaction.commit().await,
-                Err(err) => {
-                    let _ = transaction.rollback().await;
-                    panic!("Error: {}", err);
-                }
-            };
-        } else {
-            panic!("Error: Must provide username and password with flags [-u, -p]");
+            .map_err(undefined_error)?;
+
+        pool.close().await;
+
+        Ok(rows.iter().map(role_from_row).collect())
+    }
+
+    /// #### Prints every role with its permissions
+    pub async fn list_roles(&self) -> Result<(), Error> {
+        for role in self.get_roles().await? {
+            println!(
+                "{}{}: {}",
+                role.name,
+                if role.is_builtin { " [built-in]" } else { "" },
+                role::permissions_display(role.permissions)
+            );
+        }
+
+        Ok(())
+    }
+
+    pub async fn create_group(&self, name: String) -> Result<(), Error> {
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        execute_query(
+            AppliedQuery::new(queries::INSERT_GROUP)
+                .with_args(&[QueryArg::new(query_arg::Value::String(name))]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### Deletes a group, its members lose the roles they had through the group
+    pub async fn delete_group(&self, name: String) -> Result<(), Error> {
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        // groups_users and groups_database_access entries are removed through ON DELETE CASCADE
+        let res = execute_query(
+            AppliedQuery::new(queries::DELETE_GROUP)
+                .with_args(&[QueryArg::new(query_arg::Value::String(name.clone()))]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(group_not_exist_error(&name));
         }
+
+        transaction.commit().await.map_err(undefined_error)
     }
 
-    pub async fn modify_user_access(
+    /// #### Adds the user to the group or removes it from the group
+    /// \
+    /// __*@param*__ is_member: bool (false removes the user from the group)
+    pub async fn modify_group_member(
         &self,
+        group: String,
         username: String,
+        is_member: bool,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        get_group(&group, &mut transaction).await?;
+        get_user(&username_hash, &mut transaction).await?;
+
+        execute_query(
+            AppliedQuery::new(if is_member {
+                queries::INSERT_GROUP_USER
+            } else {
+                queries::DELETE_GROUP_USER
+            })
+            .with_args(&[
+                QueryArg::new(query_arg::Value::String(group)),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### Gives the group the role on the db, replacing the role it had on the db
+    /// \
+    /// Every member of the group gets the role on the db, combined with the roles it has of its own.
+    pub async fn modify_group_access(
+        &self,
+        group: String,
         database_name: String,
-        access_right: u8,
-    ) {
-        if !username.eq("") && !database_name.eq("") && access_right != 0 {
-            let username_hash =
-                base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
-            let database_name_hash =
-                base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
-
-            let pool = SqlitePool::connect(&format!("sqlite:{}", self.user_db_full_path_string))
-                .await
-                .unwrap();
-            let mut transaction = pool.begin().await.unwrap();
-
-            let _ = match execute_query(
-                AppliedQuery::new(queries::UPSERT_USER_DATABASE_ACCESS).with_args(&vec![
-                    QueryArg::new(query_arg::Value::String(database_name)),
-                    QueryArg::new(query_arg::Value::String(database_name_hash)),
-                    QueryArg::new(query_arg::Value::Int(access_right as i64)),
-                    QueryArg::new(query_arg::Value::String(username_hash)),
-                ]),
-                &mut *transaction,
-            )
+        role: String,
+    ) -> Result<(), Error> {
+        let database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        get_group(&group, &mut transaction).await?;
+        get_role(&role, &mut transaction).await?;
+
+        execute_query(
+            AppliedQuery::new(queries::UPSERT_GROUP_DATABASE_ACCESS).with_args(&[
+                QueryArg::new(query_arg::Value::String(database_name)),
+                QueryArg::new(query_arg::Value::String(database_name_hash)),
+                QueryArg::new(query_arg::Value::String(role)),
+                QueryArg::new(query_arg::Value::String(group)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    pub async fn revoke_group_access(
+        &self,
+        group: String,
+        database_name: String,
+    ) -> Result<(), Error> {
+        let database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        let res = execute_query(
+            AppliedQuery::new(queries::DELETE_GROUP_DATABASE_ACCESS).with_args(&[
+                QueryArg::new(query_arg::Value::String(group.clone())),
+                QueryArg::new(query_arg::Value::String(database_name_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(ResourceNotExistError::with_message(&format!(
+                "Group {} has no access to db {}",
+                group, database_name
+            )));
+        }
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### Every group with its members and the roles it has on dbs
+    /// \
+    /// __*@returns*__ Result\<Vec\<Group\>, Error\> (ordered by name)
+    pub async fn get_groups(&self) -> Result<Vec<Group>, Error> {
+        let pool = self.connect_user_db().await?;
+        let members = fetch_query(AppliedQuery::new(queries::GET_GROUPS_WITH_MEMBERS), &pool)
             .await
+            .map_err(undefined_error)?;
+        let access = fetch_query(AppliedQuery::new(queries::GET_GROUPS_WITH_ACCESS), &pool)
+            .await
+            .map_err(undefined_error)?;
+        pool.close().await;
+
+        let mut groups: Vec<Group> = vec![];
+        for row in members {
+            let name: String = row.get("name");
+            if groups.last().map(|group| &group.name) != Some(&name) {
+                groups.push(Group {
+                    name,
+                    members: vec![],
+                    access: vec![],
+                });
+            }
+
+            if let (Some(username), Some(group)) =
+                (row.get::<Option<String>, _>("username"), groups.last_mut())
             {
-                Ok(_) => {
-                    let _ = transaction.commit().await;
-                }
-                Err(err) => {
-                    let _ = transaction.rollback().await;
-                    panic!("Error: {}", err);
+                group.members.push(username);
+            }
+        }
+
+        for row in access {
+            let name: String = row.get("name");
+            if let Some(group) = groups.iter_mut().find(|group| group.name == name) {
+                group.access.push((row.get("database"), row.get("role")));
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// #### Prints every group with its members and the roles it has on dbs
+    pub async fn list_groups(&self) -> Result<(), Error> {
+        for group in self.get_groups().await? {
+            println!(
+                "{} (members: {})",
+                group.name,
+                if group.members.is_empty() {
+                    "none".to_string()
+                } else {
+                    group.members.join(", ")
                 }
-            };
-        } else {
-            panic!("Error: Must provide username, database and access right(1-3) with flags [-u, -db, -a]");
+            );
+
+            if group.access.is_empty() {
+                println!("    no database access");
+            }
+
+            for (database, role) in group.access {
+                println!("    {}: {}", database, role);
+            }
         }
+
+        Ok(())
     }
-}
 
-pub fn get_flag_val<'a, T>(args: &'a Vec<String>, flag: &'a str) -> Option<T>
-where
-    T: std::str::FromStr,
-{
-    let mut res = None;
+    /// #### Creates an API key for the user, which requests can be signed with instead of the password
+    /// \
+    /// The key is only returned here, the users db holds the secret requests made with the key are signed with,
+    /// the sha256 hex of the key, sent together with the key name in header 2.
+    /// \
+    /// __*@param*__ scope: &\[String\] (permissions the key is limited to, empty = all permissions of the user)
+    /// \
+    /// __*@returns*__ Result\<String, Error\> (the key)
+    pub async fn create_api_key(
+        &self,
+        username: String,
+        name: String,
+        expires_at: Option<DateTime<Utc>>,
+        scope: &[String],
+    ) -> Result<String, Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+        let scope = role::permissions_from_names(scope)?;
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        get_user(&username_hash, &mut transaction).await?;
+
+        let api_key: String = fetch_query(
+            AppliedQuery::new(queries::GENERATE_API_KEY),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?
+        .first()
+        .map(|row| row.get(0))
+        .ok_or_else(|| UndefinedError::with_message("Could not generate a key"))?;
+
+        execute_query(
+            AppliedQuery::new(queries::INSERT_API_KEY).with_args(&[
+                QueryArg::new(query_arg::Value::String(name)),
+                QueryArg::new(query_arg::Value::String(base16ct::lower::encode_string(
+                    &Sha256::digest(api_key.as_bytes()),
+                ))),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+                QueryArg::new(query_arg::Value::Int(
+                    expires_at.map_or(0, |expires_at| expires_at.timestamp()),
+                )),
+                QueryArg::new(query_arg::Value::Int(scope as i64)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)?;
+
+        Ok(api_key)
+    }
+
+    /// #### Revokes an API key of the user, right away or at the given time
+    /// \
+    /// __*@param*__ at: Option\<DateTime\<Utc\>\> (the key expires at this time instead of being deleted,
+    /// I.E to keep the old key working until every consumer uses a new key)
+    pub async fn revoke_api_key(
+        &self,
+        username: String,
+        name: String,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        let mut query_args = vec![
+            QueryArg::new(query_arg::Value::String(username_hash)),
+            QueryArg::new(query_arg::Value::String(name.clone())),
+        ];
+        let applied_query = match at {
+            Some(at) => {
+                query_args.insert(0, QueryArg::new(query_arg::Value::Int(at.timestamp())));
+                AppliedQuery::new(queries::UPDATE_API_KEY_EXPIRES_AT).with_args(&query_args)
+            }
+            None => AppliedQuery::new(queries::DELETE_API_KEY).with_args(&query_args),
+        };
+
+        let res = execute_query(applied_query, &mut *transaction)
+            .await
+            .map_err(undefined_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(ResourceNotExistError::with_message(&format!(
+                "API key {} of user {} doesn't exist",
+                name, username
+            )));
+        }
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### The API keys of the user, without the keys themselves
+    /// \
+    /// __*@returns*__ Result\<Vec\<ApiKeyListing\>, Error\> (ordered by name)
+    pub async fn get_api_keys(&self, username: String) -> Result<Vec<ApiKeyListing>, Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut conn = pool.acquire().await.map_err(undefined_error)?;
+
+        get_user(&username_hash, &mut conn).await?;
+
+        let rows = fetch_query(
+            AppliedQuery::new(queries::GET_USER_API_KEYS)
+                .with_args(&[QueryArg::new(query_arg::Value::String(username_hash))]),
+            &mut *conn,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        drop(conn);
+        pool.close().await;
 
-    for i in 0..args.len() - 1 {
-        let args_flag = &args[i];
-        let args_flag_val = &args[i + 1];
+        Ok(rows
+            .iter()
+            .map(|row| ApiKeyListing {
+                name: row.get("name"),
+                created_at: DateTime::from_timestamp(row.get("created_at"), 0).unwrap_or_default(),
+                expires_at: row
+                    .get::<Option<i64>, _>("expires_at")
+                    .and_then(|expires_at| DateTime::from_timestamp(expires_at, 0)),
+                scope: row.get::<Option<i64>, _>("scope").map(|scope| scope as u8),
+            })
+            .collect())
+    }
 
-        if args_flag == flag {
-            if !args_flag_val.starts_with("-") {
-                if let Ok(parsed_val) = args_flag_val.parse::<T>() {
-                    res = Some(parsed_val);
-                    break;
+    /// #### Prints the API keys of the user, without the keys themselves
+    pub async fn list_api_keys(&self, username: String) -> Result<(), Error> {
+        let now = Utc::now();
+        for api_key in self.get_api_keys(username).await? {
+            println!(
+                "{} (created: {}, expires: {}, scope: {}){}",
+                api_key.name,
+                api_key.created_at.to_rfc3339(),
+                api_key
+                    .expires_at
+                    .map_or("never".to_string(), |expires_at| expires_at.to_rfc3339()),
+                api_key
+                    .scope
+                    .map_or("all".to_string(), role::permissions_display),
+                if api_key
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at < now)
+                {
+                    " [expired]"
                 } else {
-                    panic!("Flag value cannot be parsed");
+                    ""
                 }
-            }
+            );
+        }
+
+        Ok(())
+    }
+
+    /// #### Opens the audit db, kept apart from the users db so that recording a change doesn't reload the users
+    pub async fn connect_audit_db(&self) -> Result<SqlitePool, Error> {
+        audit::open_audit_db(&self.audit_db_full_path_string)
+            .await
+            .map_err(undefined_error)
+    }
+
+    pub async fn record_audit_entry(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let pool = self.connect_audit_db().await?;
+        audit::record(&pool, entry).await.map_err(undefined_error)?;
+        pool.close().await;
+
+        Ok(())
+    }
+
+    /// #### Entries of the audit log, filtered by user, db name and time range
+    pub async fn get_audit_entries(
+        &self,
+        username: Option<String>,
+        db_name: Option<String>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: u64,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        let filter = AuditFilter {
+            username,
+            database: db_name
+                .map(|db_name| base16ct::lower::encode_string(&Sha256::digest(db_name.as_bytes()))),
+            from,
+            to,
+            limit,
         };
+
+        let pool = self.connect_audit_db().await?;
+        let entries = audit::get_entries(&pool, &filter)
+            .await
+            .map_err(undefined_error)?;
+        pool.close().await;
+
+        Ok(entries)
     }
 
-    res
+    pub async fn list_audit_entries(
+        &self,
+        username: Option<String>,
+        db_name: Option<String>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: u64,
+    ) -> Result<(), Error> {
+        for entry in self
+            .get_audit_entries(username, db_name, from, to, limit)
+            .await?
+        {
+            println!(
+                "{} [{}] {} {}{}{}{}{}{} => {}",
+                entry.created_at.to_rfc3339(),
+                entry.source,
+                entry.username,
+                entry.action,
+                entry
+                    .target_user
+                    .map_or(String::new(), |target_user| format!(
+                        " user={}",
+                        target_user
+                    )),
+                entry
+                    .database
+                    .map_or(String::new(), |database| format!(" db={}", database)),
+                entry
+                    .query
+                    .map_or(String::new(), |query| format!(" query={:?}", query)),
+                entry
+                    .args_digest
+                    .map_or(String::new(), |args_digest| format!(
+                        " args={}",
+                        args_digest
+                    )),
+                entry
+                    .rows_affected
+                    .map_or(String::new(), |rows_affected| format!(
+                        " rows={}",
+                        rows_affected
+                    )),
+                entry.outcome
+            );
+        }
+
+        Ok(())
+    }
+
+    /// #### Overrides the server wide limits for a user
+    /// \
+    /// __*@param*__ query_timeout, max_databases: Option\<u64\> (None = unchanged, 0 = server default)
+    /// \
+    /// __*@param*__ max_rows, max_response_bytes: Option\<Option\<u64\>\> (None = unchanged, Some(None) = server default, Some(Some(0)) = unlimited)
+    pub async fn modify_user_limits(
+        &self,
+        username: String,
+        query_timeout: Option<u64>,
+        max_rows: Option<Option<u64>>,
+        max_response_bytes: Option<Option<u64>>,
+        max_databases: Option<u64>,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+        let limits = [
+            (queries::UPDATE_USER_QUERY_TIMEOUT, query_timeout),
+            (queries::UPDATE_USER_MAX_DATABASES, max_databases),
+        ];
+        let fetch_limits = [
+            (
+                queries::UPDATE_USER_MAX_ROWS,
+                queries::RESET_USER_MAX_ROWS,
+                max_rows,
+            ),
+            (
+                queries::UPDATE_USER_MAX_RESPONSE_BYTES,
+                queries::RESET_USER_MAX_RESPONSE_BYTES,
+                max_response_bytes,
+            ),
+        ];
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        for (query, limit) in limits {
+            if let Some(limit) = limit {
+                execute_query(
+                    AppliedQuery::new(query).with_args(&[
+                        QueryArg::new(query_arg::Value::Int(limit as i64)),
+                        QueryArg::new(query_arg::Value::String(username_hash.clone())),
+                    ]),
+                    &mut *transaction,
+                )
+                .await
+                .map_err(undefined_error)?;
+            }
+        }
+
+        for (query, reset_query, limit) in fetch_limits {
+            let (query, args) = match limit {
+                Some(Some(limit)) => (
+                    query,
+                    vec![
+                        QueryArg::new(query_arg::Value::Int(limit as i64)),
+                        QueryArg::new(query_arg::Value::String(username_hash.clone())),
+                    ],
+                ),
+                Some(None) => (
+                    reset_query,
+                    vec![QueryArg::new(query_arg::Value::String(
+                        username_hash.clone(),
+                    ))],
+                ),
+                None => continue,
+            };
+
+            execute_query(AppliedQuery::new(query).with_args(&args), &mut *transaction)
+                .await
+                .map_err(undefined_error)?;
+        }
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    pub async fn modify_user_admin(&self, username: String, is_admin: bool) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        let res = execute_query(
+            AppliedQuery::new(queries::UPDATE_USER_ADMIN_ROLE).with_args(&[
+                QueryArg::new(query_arg::Value::Int(is_admin as i64)),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(UserNotExistError::default());
+        }
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    pub async fn modify_user_creator(
+        &self,
+        username: String,
+        is_creator: bool,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        let res = execute_query(
+            AppliedQuery::new(queries::UPDATE_USER_IS_CREATOR).with_args(&[
+                QueryArg::new(query_arg::Value::Int(is_creator as i64)),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(UserNotExistError::default());
+        }
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    pub async fn modify_user_password(
+        &self,
+        username: String,
+        password: String,
+        new_password: String,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+        let username_password_hash = base16ct::lower::encode_string(&Sha256::digest(
+            format!("{}{}", username, password).as_bytes(),
+        ));
+        let new_username_password_hash = base16ct::lower::encode_string(&Sha256::digest(
+            format!("{}{}", username, new_password).as_bytes(),
+        ));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        verify_user_password(&mut transaction, &username_hash, &username_password_hash).await?;
+        execute_query(
+            AppliedQuery::new(queries::UPDATE_USER_PASSWORD_HASH).with_args(&[
+                QueryArg::new(query_arg::Value::String(new_username_password_hash)),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// The password is needed since the password hash the server signs and verifies with
+    /// is derived from both the username and the password.
+    pub async fn modify_user_username(
+        &self,
+        username: String,
+        password: String,
+        new_username: String,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+        let username_password_hash = base16ct::lower::encode_string(&Sha256::digest(
+            format!("{}{}", username, password).as_bytes(),
+        ));
+        let new_username_hash =
+            base16ct::lower::encode_string(&Sha256::digest(new_username.as_bytes()));
+        let new_username_password_hash = base16ct::lower::encode_string(&Sha256::digest(
+            format!("{}{}", new_username, password).as_bytes(),
+        ));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        verify_user_password(&mut transaction, &username_hash, &username_password_hash).await?;
+        // users_database_access follows the new username_hash through ON UPDATE CASCADE
+        execute_query(
+            AppliedQuery::new(queries::UPDATE_USER_USERNAME).with_args(&[
+                QueryArg::new(query_arg::Value::String(new_username)),
+                QueryArg::new(query_arg::Value::String(new_username_hash)),
+                QueryArg::new(query_arg::Value::String(new_username_password_hash)),
+                QueryArg::new(query_arg::Value::String(username_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    pub async fn delete_user(&self, username: String) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        // users_database_access entries are removed through ON DELETE CASCADE
+        let res = execute_query(
+            AppliedQuery::new(queries::DELETE_USER)
+                .with_args(&[QueryArg::new(query_arg::Value::String(username_hash))]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(UserNotExistError::default());
+        }
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    pub async fn revoke_user_access(
+        &self,
+        username: String,
+        database_name: String,
+    ) -> Result<(), Error> {
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()));
+        let database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        let res = execute_query(
+            AppliedQuery::new(queries::DELETE_USER_DATABASE_ACCESS).with_args(&[
+                QueryArg::new(query_arg::Value::String(username_hash)),
+                QueryArg::new(query_arg::Value::String(database_name_hash)),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        if res.rows_affected() == 0 {
+            return Err(UndefinedError::with_message(
+                "User has no access to the database",
+            ));
+        }
+
+        transaction.commit().await.map_err(undefined_error)
+    }
+
+    /// #### The users together with the databases they can access and their roles
+    /// \
+    /// __*@param*__ username: Option\<&str\> (None = all users) \
+    /// __*@returns*__ Result\<Vec\<UserListing\>, Error\> (ordered by username, limits are 0 or None when not overridden)
+    pub async fn get_users(&self, username: Option<&str>) -> Result<Vec<UserListing>, Error> {
+        let query_args = username
+            .iter()
+            .map(|username| {
+                QueryArg::new(query_arg::Value::String(base16ct::lower::encode_string(
+                    &Sha256::digest(username.as_bytes()),
+                )))
+            })
+            .collect::<Vec<_>>();
+        let applied_query = match username {
+            Some(_) => AppliedQuery::new(queries::GET_USER_WITH_ACCESS).with_args(&query_args),
+            None => AppliedQuery::new(queries::GET_USERS_WITH_ACCESS),
+        };
+
+        let pool = self.connect_user_db().await?;
+        let rows = fetch_query(applied_query, &pool)
+            .await
+            .map_err(undefined_error)?;
+        let roles = fetch_query(AppliedQuery::new(queries::GET_ROLES), &pool)
+            .await
+            .map_err(undefined_error)?
+            .iter()
+            .map(|row| {
+                (
+                    row.get::<String, _>("name"),
+                    row.get::<i64, _>("permissions") as u8,
+                )
+            })
+            .collect::<std::collections::HashMap<_, _>>();
+        pool.close().await;
+
+        if let (Some(username), true) = (username, rows.is_empty()) {
+            return Err(UserNotExistError::with_message(&format!(
+                "User {} doesn't exist",
+                username
+            )));
+        }
+
+        let mut users: Vec<UserListing> = vec![];
+        for row in rows {
+            let row_username: String = row.get("username");
+            if users.last().map(|user| &user.username) != Some(&row_username) {
+                users.push(UserListing {
+                    username: row_username,
+                    is_admin: row.get("is_admin"),
+                    is_creator: row.get("is_creator"),
+                    query_timeout: row.get::<Option<i64>, _>("query_timeout").unwrap_or(0) as u64,
+                    max_rows: row
+                        .get::<Option<i64>, _>("max_rows")
+                        .map(|max_rows| max_rows as u64),
+                    max_response_bytes: row
+                        .get::<Option<i64>, _>("max_response_bytes")
+                        .map(|max_response_bytes| max_response_bytes as u64),
+                    max_databases: row.get::<Option<i64>, _>("max_databases").unwrap_or(0) as u64,
+                    role: row
+                        .get::<Option<String>, _>("global_role")
+                        .unwrap_or_default(),
+                    groups: serde_json::from_str(row.get("groups")).unwrap_or_default(),
+                    access: vec![],
+                });
+            }
+
+            if let (Some(database), Some(database_hash), Some(role), Some(user)) = (
+                row.get::<Option<String>, _>("database"),
+                row.get::<Option<String>, _>("database_hash"),
+                row.get::<Option<String>, _>("role"),
+                users.last_mut(),
+            ) {
+                user.access.push(UserAccess {
+                    database,
+                    database_hash,
+                    permissions: roles.get(&role).copied().unwrap_or(0) as u32,
+                    role,
+                    is_owner: row.get("is_owner"),
+                });
+            }
+        }
+
+        Ok(users)
+    }
+
+    /// #### Prints the users together with the databases they can access and their roles
+    /// \
+    /// __*@param*__ username: Option\<String\> (None = all users)
+    pub async fn list_users(&self, username: Option<String>) -> Result<(), Error> {
+        for user in self.get_users(username.as_deref()).await? {
+            println!(
+                "{}{}{} (query_timeout: {}, max_rows: {}, max_response_bytes: {}, max_databases: {})",
+                user.username,
+                if user.is_admin { " [admin]" } else { "" },
+                if user.is_creator { " [creator]" } else { "" },
+                limit_display(user.query_timeout),
+                fetch_limit_display(user.max_rows),
+                fetch_limit_display(user.max_response_bytes),
+                limit_display(user.max_databases),
+            );
+
+            if user.access.is_empty() && user.role.is_empty() {
+                println!("    no database access");
+            }
+
+            if !user.role.is_empty() {
+                println!("    all databases: {}", user.role);
+            }
+
+            if !user.groups.is_empty() {
+                println!("    groups: {}", user.groups.join(", "));
+            }
+
+            for access in user.access {
+                println!(
+                    "    {}: {} ({}){}",
+                    access.database,
+                    access.role,
+                    role::permissions_display(access.permissions as u8),
+                    if access.is_owner { " [owner]" } else { "" }
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn modify_database_config(
+        &self,
+        database_name: String,
+        key: String,
+        value: String,
+    ) -> Result<(), Error> {
+        let database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+        let consumer_db_full_path = self.consumer_db_base_path.join(database_name_hash);
+
+        if !consumer_db_full_path.exists() {
+            return Err(ResourceNotExistError::with_message(
+                "Database doesn't exist",
+            ));
+        }
+
+        let db_config = DatabaseConfig::load(&consumer_db_full_path)?;
+
+        // values are read as JSON when possible (numbers, booleans, null)
+        // and as plain strings otherwise, I.E -v 5000, -v true, -v null or -v NORMAL
+        let mut db_config_json = serde_json::to_value(db_config).map_err(undefined_error)?;
+        db_config_json[&key] = serde_json::from_str(&value).unwrap_or(JsonValue::String(value));
+
+        let db_config =
+            serde_json::from_value::<DatabaseConfig>(db_config_json).map_err(undefined_error)?;
+        if let Some(backup_config) = &db_config.backup {
+            backup_config.next_run(&chrono::Utc::now())?;
+        }
+
+        db_config.save(&consumer_db_full_path)
+    }
+
+    /// #### Every db with its name, hash, size, WAL size and the users that can access it
+    /// \
+    /// __*@returns*__ Result\<Vec\<DatabaseListing\>, Error\> (ordered by name, dbs without a name file first)
+    pub async fn get_databases(&self) -> Result<Vec<DatabaseListing>, Error> {
+        let mut databases = fs::read_dir(&self.consumer_db_base_path)
+            .map_err(undefined_error)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| {
+                let db_name_hash = entry.file_name().to_string_lossy().to_string();
+                let db_name = read_database_name(&entry.path(), &db_name_hash);
+                (db_name, db_name_hash)
+            })
+            .collect::<Vec<_>>();
+        databases.sort();
+
+        let pool = self.connect_user_db().await?;
+        let mut database_listings = vec![];
+
+        for (db_name, db_name_hash) in databases {
+            let consumer_db_full_path = self.consumer_db_base_path.join(&db_name_hash);
+            let rows = fetch_query(
+                AppliedQuery::new(queries::GET_DATABASE_USERS).with_args(&[QueryArg::new(
+                    query_arg::Value::String(db_name_hash.clone()),
+                )]),
+                &pool,
+            )
+            .await
+            .map_err(undefined_error)?;
+
+            database_listings.push(DatabaseListing {
+                name: db_name.unwrap_or_default(),
+                size: file_size(&consumer_db_full_path.join(format!("{}.db", db_name_hash))),
+                wal_size: file_size(
+                    &consumer_db_full_path.join(format!("{}.db-wal", db_name_hash)),
+                ),
+                hash: db_name_hash,
+                users: rows
+                    .iter()
+                    .map(|row| DatabaseUser {
+                        username: row.get("username"),
+                        role: row.get::<Option<String>, _>("role").unwrap_or_default(),
+                    })
+                    .collect(),
+            });
+        }
+
+        pool.close().await;
+
+        Ok(database_listings)
+    }
+
+    /// #### Prints every db with its name, hash, size, WAL size and the users that can access it
+    pub async fn list_databases(&self) -> Result<(), Error> {
+        for database in self.get_databases().await? {
+            println!(
+                "{} ({}) size: {} bytes, wal size: {} bytes",
+                if database.name.is_empty() {
+                    "<unknown name>"
+                } else {
+                    &database.name
+                },
+                database.hash,
+                database.size,
+                database.wal_size
+            );
+
+            if database.users.is_empty() {
+                println!("    no users");
+            }
+
+            for user in database.users {
+                println!("    {}: {}", user.username, user.role);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// #### Removes the db directory and all access to the db
+    /// \
+    /// Asks for the db name to be typed again unless __confirmed__ is true.
+    /// The directory is moved aside before the access is removed and only deleted once that is
+    /// committed, so a failed drop leaves both the db and its access in place.
+    /// The server should not be serving the db while it is dropped.
+    pub async fn drop_database(&self, database_name: String, confirmed: bool) -> Result<(), Error> {
+        let database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+        let consumer_db_full_path = self.consumer_db_base_path.join(&database_name_hash);
+
+        if !consumer_db_full_path.exists() {
+            return Err(ResourceNotExistError::with_message(
+                "Database doesn't exist",
+            ));
+        }
+
+        if !confirmed {
+            confirm_database_name(
+                &format!(
+                    "This will permanently delete db {} ({}), type the db name to confirm:",
+                    database_name, database_name_hash
+                ),
+                &database_name,
+            )?;
+        }
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        for query in [
+            queries::DELETE_DATABASE_ACCESS,
+            queries::DELETE_GROUPS_DATABASE_ACCESS,
+        ] {
+            execute_query(
+                AppliedQuery::new(query).with_args(&[QueryArg::new(query_arg::Value::String(
+                    database_name_hash.clone(),
+                ))]),
+                &mut *transaction,
+            )
+            .await
+            .map_err(undefined_error)?;
+        }
+
+        let dropped_db_full_path = self
+            .consumer_db_base_path
+            .join(format!("{}.dropped", database_name_hash));
+        fs::rename(&consumer_db_full_path, &dropped_db_full_path).map_err(undefined_error)?;
+        if let Err(err) = transaction.commit().await {
+            let _ = fs::rename(&dropped_db_full_path, &consumer_db_full_path);
+            return Err(undefined_error(err));
+        }
+        fs::remove_dir_all(&dropped_db_full_path).map_err(|err| {
+            UndefinedError::with_message(&format!(
+                "Dropped db {} but could not delete {}: {}",
+                database_name,
+                dropped_db_full_path.display(),
+                err
+            ))
+        })?;
+
+        println!(
+            "Successfully dropped db {} ({})",
+            database_name, database_name_hash
+        );
+
+        Ok(())
+    }
+
+    /// #### Renames a db, moving it to the directory of the new db name hash and moving its access along
+    /// \
+    /// The db is moved back when the access can't be moved along.
+    /// The server should not be serving the db while it is renamed.
+    pub async fn rename_database(
+        &self,
+        database_name: String,
+        new_database_name: String,
+    ) -> Result<(), Error> {
+        validate_database_name(&new_database_name)?;
+
+        let database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+        let new_database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(new_database_name.as_bytes()));
+        let consumer_db_full_path = self.consumer_db_base_path.join(&database_name_hash);
+        let new_consumer_db_full_path = self.consumer_db_base_path.join(&new_database_name_hash);
+
+        if !consumer_db_full_path.exists() {
+            return Err(ResourceNotExistError::with_message(
+                "Database doesn't exist",
+            ));
+        }
+
+        if new_consumer_db_full_path.exists() {
+            return Err(UndefinedError::with_message(&format!(
+                "Database {} already exists",
+                new_database_name
+            )));
+        }
+
+        let pool = self.connect_user_db().await?;
+        let mut transaction = pool.begin().await.map_err(undefined_error)?;
+
+        for query in [
+            queries::UPDATE_DATABASE_ACCESS_DATABASE,
+            queries::UPDATE_GROUPS_DATABASE_ACCESS_DATABASE,
+        ] {
+            execute_query(
+                AppliedQuery::new(query).with_args(&[
+                    QueryArg::new(query_arg::Value::String(new_database_name.clone())),
+                    QueryArg::new(query_arg::Value::String(new_database_name_hash.clone())),
+                    QueryArg::new(query_arg::Value::String(database_name_hash.clone())),
+                ]),
+                &mut *transaction,
+            )
+            .await
+            .map_err(undefined_error)?;
+        }
+
+        move_database_dir(
+            &consumer_db_full_path,
+            &new_consumer_db_full_path,
+            &database_name_hash,
+            &new_database_name_hash,
+        )?;
+
+        let res = match fs::write(
+            new_consumer_db_full_path.join(&new_database_name),
+            &new_database_name_hash,
+        ) {
+            Ok(_) => transaction.commit().await.map_err(undefined_error),
+            Err(err) => Err(undefined_error(err)),
+        };
+        if let Err(err) = res {
+            let _ = fs::remove_file(new_consumer_db_full_path.join(&new_database_name));
+            let _ = move_database_dir(
+                &new_consumer_db_full_path,
+                &consumer_db_full_path,
+                &new_database_name_hash,
+                &database_name_hash,
+            );
+            return Err(err);
+        }
+        let _ = fs::remove_file(new_consumer_db_full_path.join(&database_name));
+
+        println!(
+            "Successfully renamed db {} to {} as {}",
+            database_name, new_database_name, new_database_name_hash
+        );
+
+        Ok(())
+    }
+
+    /// #### Prints the page stats and schema of a db
+    pub async fn database_info(&self, database_name: String) -> Result<(), Error> {
+        let database_name_hash =
+            base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+        let consumer_db = self
+            .consumer_db_base_path
+            .join(&database_name_hash)
+            .join(format!("{}.db", database_name_hash));
+
+        if !consumer_db.exists() {
+            return Err(ResourceNotExistError::with_message(
+                "Database doesn't exist",
+            ));
+        }
+
+        let pool = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(&consumer_db)
+                .read_only(true),
+        )
+        .await
+        .map_err(undefined_error)?;
+
+        let stats = fetch_query(AppliedQuery::new(queries::GET_DATABASE_STATS), &pool)
+            .await
+            .map_err(undefined_error)?
+            .pop()
+            .ok_or_else(|| UndefinedError::with_message("Could not read database stats"))?;
+        let schema = fetch_query(AppliedQuery::new(queries::GET_DATABASE_SCHEMA), &pool)
+            .await
+            .map_err(undefined_error)?;
+        pool.close().await;
+
+        let page_size: i64 = stats.get("page_size");
+        let page_count: i64 = stats.get("page_count");
+
+        println!("{} ({})", database_name, database_name_hash);
+        println!("    page size: {} bytes", page_size);
+        println!("    page count: {}", page_count);
+        println!(
+            "    freelist count: {}",
+            stats.get::<i64, _>("freelist_count")
+        );
+        println!("    size: {} bytes", page_size * page_count);
+        println!(
+            "    journal mode: {}",
+            stats.get::<String, _>("journal_mode")
+        );
+        println!("    user version: {}", stats.get::<i64, _>("user_version"));
+
+        println!("schema:");
+        for row in schema {
+            println!(
+                "    {} {}: {}",
+                row.get::<String, _>("type"),
+                row.get::<String, _>("name"),
+                row.get::<String, _>("sql")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// #### Backs up a db, or the users db when __database_name__ is None
+    /// \
+    /// Safe to run while the server is serving the db, see core::backup::backup_database.
+    pub async fn backup_database(
+        &self,
+        database_name: Option<String>,
+        output: &Path,
+        compress: bool,
+    ) -> Result<(), Error> {
+        let (db_file, database) = self.backup_target(database_name.as_deref());
+        let manifest = backup::backup_database(&db_file, output, &database, compress).await?;
+
+        println!(
+            "Successfully backed up {} to {} ({} bytes, sha256 {})",
+            database_name.as_deref().unwrap_or("the users db"),
+            output.display(),
+            manifest.size,
+            manifest.sha256
+        );
+
+        Ok(())
+    }
+
+    /// #### Replaces a db, or the users db when __database_name__ is None, with a backup
+    /// \
+    /// The backup is checked against its manifest and integrity checked before the db is replaced.
+    /// Asks for the db name (or users) to be typed again unless __confirmed__ is true.
+    /// The server should not be serving the db while it is restored, use the /admin/restore endpoint for that.
+    pub async fn restore_database(
+        &self,
+        database_name: Option<String>,
+        input: &Path,
+        confirmed: bool,
+    ) -> Result<(), Error> {
+        let (db_file, database) = self.backup_target(database_name.as_deref());
+        let display_name = database_name.as_deref().unwrap_or("users");
+
+        if !db_file.exists() {
+            return Err(ResourceNotExistError::with_message(
+                "Database doesn't exist",
+            ));
+        }
+
+        let manifest = backup::verify_backup(input).await?;
+        if manifest.database != database {
+            println!(
+                "NOTE: the backup was taken of {}, restoring it as {}",
+                manifest.database, database
+            );
+        }
+
+        if !confirmed {
+            confirm_database_name(
+                &format!(
+                    "This will replace db {} with the backup from {}, type the db name to confirm:",
+                    display_name, manifest.created_at
+                ),
+                display_name,
+            )?;
+        }
+
+        backup::restore_database(input, &db_file).await?;
+
+        println!(
+            "Successfully restored {} from {}",
+            display_name,
+            input.display()
+        );
+
+        Ok(())
+    }
+
+    /// #### Replaces a db with its state at __to__, rebuilt from its WAL archive
+    /// \
+    /// The archive is written by the server for dbs that have wal_archive configured, see archive_base_path.
+    /// Asks for the db name to be typed again unless __confirmed__ is true.
+    /// The server should not be serving the db while it is restored.
+    pub async fn restore_database_to(
+        &self,
+        database_name: &str,
+        to: DateTime<Utc>,
+        confirmed: bool,
+    ) -> Result<(), Error> {
+        let (db_file, database) = self.backup_target(Some(database_name));
+
+        if !db_file.exists() {
+            return Err(ResourceNotExistError::with_message(
+                "Database doesn't exist",
+            ));
+        }
+
+        if !confirmed {
+            confirm_database_name(
+                &format!(
+                    "This will replace db {} with its state at {} from the WAL archive, type the db name to confirm:",
+                    database_name,
+                    to.to_rfc3339()
+                ),
+                database_name,
+            )?;
+        }
+
+        let restored_to =
+            wal_archive::restore_wal_archive(&self.archive_base_path.join(database), to, &db_file)
+                .await?;
+
+        println!(
+            "Successfully restored {} to {} (last archived change at or before {})",
+            database_name,
+            to.to_rfc3339(),
+            restored_to.to_rfc3339()
+        );
+
+        Ok(())
+    }
+
+    /// #### Exports a table of a db as csv, jsonl or sql, or dumps the whole db as sql when __table__ is None
+    /// \
+    /// Safe to run while the server is serving the db, the db is read in a single read transaction.
+    /// \
+    /// __*@param*__ format: Option\<ExportFormat\> (defaults to csv with a table and to sql without) \
+    /// __*@param*__ output: Option\<&Path\> (stdout when None)
+    pub async fn export_database(
+        &self,
+        database_name: String,
+        table: Option<String>,
+        format: Option<ExportFormat>,
+        output: Option<&Path>,
+    ) -> Result<(), Error> {
+        let (db_file, _) = self.backup_target(Some(&database_name));
+        let format = format.unwrap_or(match table {
+            Some(_) => ExportFormat::Csv,
+            None => ExportFormat::Sql,
+        });
+
+        if !db_file.exists() {
+            return Err(ResourceNotExistError::with_message(
+                "Database doesn't exist",
+            ));
+        }
+
+        let mut conn = SqliteConnectOptions::new()
+            .filename(&db_file)
+            .read_only(true)
+            .connect()
+            .await
+            .map_err(undefined_error)?;
+
+        let res = match output {
+            Some(output) => {
+                let mut writer = tokio::io::BufWriter::new(
+                    tokio::fs::File::create(output)
+                        .await
+                        .map_err(undefined_error)?,
+                );
+                let res = export::export(&mut conn, table.as_deref(), format, &mut writer).await;
+                if res.is_err() {
+                    let _ = fs::remove_file(output);
+                }
+
+                res
+            }
+            None => {
+                let mut writer = tokio::io::BufWriter::new(tokio::io::stdout());
+                export::export(&mut conn, table.as_deref(), format, &mut writer).await
+            }
+        };
+        let _ = conn.close().await;
+        let rows = res?;
+
+        let message = format!(
+            "Successfully exported {} rows of {} as {}",
+            rows,
+            match &table {
+                Some(table) => format!("{}.{}", database_name, table),
+                None => database_name.clone(),
+            },
+            format.as_str_name().to_lowercase()
+        );
+        match output {
+            Some(output) => println!("{} to {}", message, output.display()),
+            // stdout holds the export
+            None => eprintln!("{}", message),
+        }
+
+        Ok(())
+    }
+
+    /// #### Imports rows from csv or jsonl into a table of a db, see core::import::import
+    /// \
+    /// Safe to run while the server is serving the db. The rows are recorded in the replication log
+    /// when the db has replication configured, so followers pick them up like any mutation.
+    /// \
+    /// __*@param*__ input: Option\<&Path\> (stdin when None)
+    pub async fn import_database(
+        &self,
+        database_name: String,
+        table: String,
+        format: ImportFormat,
+        input: Option<&Path>,
+        batch_size: u64,
+        max_errors: u64,
+    ) -> Result<(), Error> {
+        let (db_file, database_name_hash) = self.backup_target(Some(&database_name));
+
+        if !db_file.exists() {
+            return Err(ResourceNotExistError::with_message(
+                "Database doesn't exist",
+            ));
+        }
+
+        let db_config =
+            DatabaseConfig::load(&self.consumer_db_base_path.join(&database_name_hash))?;
+        let mut conn = db_config
+            .apply(SqliteConnectOptions::new().filename(&db_file))
+            .journal_mode(SqliteJournalMode::Wal)
+            .connect()
+            .await
+            .map_err(undefined_error)?;
+        if db_config.replication.is_some() {
+            replication::create_log(&mut conn)
+                .await
+                .map_err(undefined_error)?;
+        }
+
+        let res = match input {
+            Some(input) => {
+                let reader = tokio::io::BufReader::new(
+                    tokio::fs::File::open(input)
+                        .await
+                        .map_err(undefined_error)?,
+                );
+                import::import(
+                    &mut conn,
+                    &table,
+                    format,
+                    reader,
+                    batch_size,
+                    max_errors,
+                    db_config.replication.as_ref(),
+                )
+                .await
+            }
+            None => {
+                let reader = tokio::io::BufReader::new(tokio::io::stdin());
+                import::import(
+                    &mut conn,
+                    &table,
+                    format,
+                    reader,
+                    batch_size,
+                    max_errors,
+                    db_config.replication.as_ref(),
+                )
+                .await
+            }
+        };
+        let _ = conn.close().await;
+        let report = res?;
+
+        for error in &report.errors {
+            println!("line {}: {}", error.line, error.message);
+        }
+
+        if report.aborted {
+            return Err(UndefinedError::with_message(&format!(
+                "Import aborted after {} failed rows, {} rows were imported into {}.{}",
+                report.failed, report.imported, database_name, table
+            )));
+        }
+
+        println!(
+            "Successfully imported {} rows into {}.{}, {} rows failed",
+            report.imported, database_name, table, report.failed
+        );
+
+        Ok(())
+    }
+
+    /// the db file and the name it is known by in backup manifests (db name hash or cfg)
+    fn backup_target(&self, database_name: Option<&str>) -> (PathBuf, String) {
+        match database_name {
+            Some(database_name) => {
+                let database_name_hash =
+                    base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+                let db_file = self
+                    .consumer_db_base_path
+                    .join(&database_name_hash)
+                    .join(format!("{}.db", database_name_hash));
+
+                (db_file, database_name_hash)
+            }
+            None => (
+                PathBuf::from(&self.user_db_full_path_string),
+                USER_DB_BACKUP_DIR.to_string(),
+            ),
+        }
+    }
+}
+
+/// #### Copies a consistent snapshot of the db at __source__ into a new db file at __target__
+/// \
+/// The replication log of the source isn't copied, and with __schema_only__ the rows of every table
+/// are left behind except for the tracked migrations.
+async fn copy_database(source: &Path, target: &Path, schema_only: bool) -> Result<(), Error> {
+    let mut conn = SqliteConnectOptions::new()
+        .filename(source)
+        .read_only(true)
+        .connect()
+        .await
+        .map_err(undefined_error)?;
+    let res = sqlx::query("VACUUM INTO ?;")
+        .bind(target.to_string_lossy())
+        .execute(&mut conn)
+        .await
+        .map_err(undefined_error);
+    let _ = conn.close().await;
+    res?;
+
+    let mut conn = SqliteConnectOptions::new()
+        .filename(target)
+        .foreign_keys(false)
+        .connect()
+        .await
+        .map_err(undefined_error)?;
+    let res = clear_database_copy(&mut conn, schema_only).await;
+    let _ = conn.close().await;
+
+    res
+}
+
+async fn clear_database_copy(conn: &mut SqliteConnection, schema_only: bool) -> Result<(), Error> {
+    execute_query(
+        AppliedQuery::new(queries::DROP_REPLICATION_LOG_TABLE),
+        &mut *conn,
+    )
+    .await
+    .map_err(undefined_error)?;
+
+    if !schema_only {
+        return Ok(());
+    }
+
+    let tables = sqlx::query_scalar::<_, String>(queries::GET_CLONE_DATA_TABLES)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(undefined_error)?;
+    for table in tables {
+        execute_query(
+            AppliedQuery::new(&format!("DELETE FROM {};", quote_identifier(&table))),
+            &mut *conn,
+        )
+        .await
+        .map_err(undefined_error)?;
+    }
+
+    let has_sqlite_sequence = sqlx::query_scalar::<_, i64>(queries::HAS_SQLITE_SEQUENCE_TABLE)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(undefined_error)?;
+    if has_sqlite_sequence > 0 {
+        execute_query(
+            AppliedQuery::new("DELETE FROM sqlite_sequence;"),
+            &mut *conn,
+        )
+        .await
+        .map_err(undefined_error)?;
+    }
+
+    // gives the pages of the left behind rows back
+    execute_query(AppliedQuery::new("VACUUM;"), &mut *conn)
+        .await
+        .map_err(undefined_error)
+        .map(|_| ())
+}
+
+fn confirm_database_name(prompt: &str, database_name: &str) -> Result<(), Error> {
+    println!("{}", prompt);
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).map_err(undefined_error)?;
+
+    if input.trim() != database_name {
+        return Err(UndefinedError::with_message(
+            "Confirmation did not match the database name, aborting",
+        ));
+    }
+
+    Ok(())
+}
+
+/// #### Moves a db directory and renames the db file (and its -wal / -shm files) after the new db name hash
+/// \
+/// Whatever was moved is moved back when one of the renames fails.
+fn move_database_dir(
+    db_dir: &Path,
+    new_db_dir: &Path,
+    db_name_hash: &str,
+    new_db_name_hash: &str,
+) -> Result<(), Error> {
+    fs::rename(db_dir, new_db_dir).map_err(undefined_error)?;
+
+    let mut renamed = vec![];
+    for suffix in ["db", "db-wal", "db-shm"] {
+        let db_file = new_db_dir.join(format!("{}.{}", db_name_hash, suffix));
+        if !db_file.exists() {
+            continue;
+        }
+
+        let new_db_file = new_db_dir.join(format!("{}.{}", new_db_name_hash, suffix));
+        if let Err(err) = fs::rename(&db_file, &new_db_file) {
+            for (db_file, new_db_file) in renamed.iter().rev() {
+                let _ = fs::rename(new_db_file, db_file);
+            }
+            let _ = fs::rename(new_db_dir, db_dir);
+
+            return Err(undefined_error(err));
+        }
+        renamed.push((db_file, new_db_file));
+    }
+
+    Ok(())
+}
+
+fn validate_database_name(db_name: &str) -> Result<(), Error> {
+    if Regex::new(DATABASE_NAME_PATTERN).unwrap().is_match(db_name) {
+        Ok(())
+    } else {
+        Err(UndefinedError::with_message("Database name format must follow either one or a combination of the patterns [a-z, A-Z, 0-9, _, -]"))
+    }
+}
+
+async fn verify_user_password(
+    conn: &mut SqliteConnection,
+    username_hash: &str,
+    username_password_hash: &str,
+) -> Result<(), Error> {
+    match sqlx::query_scalar::<_, String>(queries::GET_USER_PASSWORD_HASH)
+        .bind(username_hash)
+        .fetch_optional(conn)
+        .await
+    {
+        Ok(Some(current_hash)) if current_hash == username_password_hash => Ok(()),
+        Ok(Some(_)) => Err(UndefinedError::with_message("Password is incorrect")),
+        Ok(None) => Err(UserNotExistError::default()),
+        Err(err) => Err(undefined_error(err)),
+    }
+}
+
+/// the db name is kept as the name of the reminder file in the db directory, see create_consumer_database
+fn read_database_name(consumer_db_full_path: &Path, db_name_hash: &str) -> Option<String> {
+    fs::read_dir(consumer_db_full_path)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .find(|file_name| {
+            base16ct::lower::encode_string(&Sha256::digest(file_name.as_bytes())) == db_name_hash
+        })
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map_or(0, |metadata| metadata.len())
+}
+
+async fn get_role(name: &str, conn: &mut SqliteConnection) -> Result<Role, Error> {
+    fetch_query(
+        AppliedQuery::new(queries::GET_ROLE)
+            .with_args(&[QueryArg::new(query_arg::Value::String(name.to_string()))]),
+        conn,
+    )
+    .await
+    .map_err(undefined_error)?
+    .first()
+    .map(role_from_row)
+    .ok_or_else(|| ResourceNotExistError::with_message(&format!("Role {} doesn't exist", name)))
+}
+
+async fn get_user(username_hash: &str, conn: &mut SqliteConnection) -> Result<(), Error> {
+    let user = fetch_query(
+        AppliedQuery::new(queries::GET_USER).with_args(&[QueryArg::new(query_arg::Value::String(
+            username_hash.to_string(),
+        ))]),
+        conn,
+    )
+    .await
+    .map_err(undefined_error)?;
+
+    match user.is_empty() {
+        true => Err(UserNotExistError::default()),
+        false => Ok(()),
+    }
+}
+
+async fn get_group(name: &str, conn: &mut SqliteConnection) -> Result<(), Error> {
+    let group = fetch_query(
+        AppliedQuery::new(queries::GET_GROUP)
+            .with_args(&[QueryArg::new(query_arg::Value::String(name.to_string()))]),
+        conn,
+    )
+    .await
+    .map_err(undefined_error)?;
+
+    match group.is_empty() {
+        true => Err(group_not_exist_error(name)),
+        false => Ok(()),
+    }
+}
+
+fn group_not_exist_error(name: &str) -> Error {
+    ResourceNotExistError::with_message(&format!("Group {} doesn't exist", name))
+}
+
+fn role_from_row(row: &SqliteRow) -> Role {
+    Role {
+        name: row.get("name"),
+        permissions: row.get::<i64, _>("permissions") as u8,
+        is_builtin: row.get("is_builtin"),
+    }
+}
+
+fn limit_display(limit: u64) -> String {
+    match limit {
+        0 => "server default".to_string(),
+        limit => limit.to_string(),
+    }
+}
+
+fn fetch_limit_display(limit: Option<u64>) -> String {
+    match limit {
+        None => "server default".to_string(),
+        Some(0) => "unlimited".to_string(),
+        Some(limit) => limit.to_string(),
+    }
 }
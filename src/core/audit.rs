@@ -0,0 +1,249 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode},
+    Row, SqlitePool,
+};
+
+use super::{
+    constants::queries,
+    db::{execute_query, fetch_query, AppliedQuery},
+    serf_proto::{query_arg, Error, QueryArg},
+};
+
+/// Changes made to the dbs through the protocol (mutations, migrations, imports and created dbs)
+pub const AUDIT_SOURCE_PROTOCOL: &str = "protocol";
+/// Changes made through the /admin endpoints
+pub const AUDIT_SOURCE_ADMIN: &str = "admin";
+/// Changes made with serf-cli, the username is the OS user running it
+pub const AUDIT_SOURCE_CLI: &str = "cli";
+pub const AUDIT_OUTCOME_OK: &str = "ok";
+
+/// #### A change recorded in the audit log
+/// \
+/// username is the user that made the change, target_user the user the change was made to (I.E the user
+/// that was granted access) and database the db hash. rows_affected holds the rows changed by a mutation
+/// or the rows imported by an import, outcome is "ok" or the error the change failed with.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AuditEntry {
+    pub created_at: DateTime<Utc>,
+    pub source: String,
+    pub username: String,
+    pub action: String,
+    pub target_user: Option<String>,
+    pub database: Option<String>,
+    pub query: Option<String>,
+    /// sha256 hex of the query args, the args themselves aren't recorded
+    pub args_digest: Option<String>,
+    pub rows_affected: Option<u64>,
+    pub outcome: String,
+}
+
+impl AuditEntry {
+    pub fn new(source: &str, username: &str, action: &str) -> Self {
+        AuditEntry {
+            created_at: Utc::now(),
+            source: source.to_string(),
+            username: username.to_string(),
+            action: action.to_string(),
+            target_user: None,
+            database: None,
+            query: None,
+            args_digest: None,
+            rows_affected: None,
+            outcome: AUDIT_OUTCOME_OK.to_string(),
+        }
+    }
+
+    pub fn with_target_user(self, target_user: &str) -> Self {
+        AuditEntry {
+            target_user: Some(target_user.to_string()),
+            ..self
+        }
+    }
+
+    /// __*@param*__ database: &str (the db name, recorded as its hash)
+    pub fn with_database_name(self, database: &str) -> Self {
+        self.with_database_hash(&database_name_hash(database))
+    }
+
+    pub fn with_database_hash(self, database: &str) -> Self {
+        AuditEntry {
+            database: Some(database.to_string()),
+            ..self
+        }
+    }
+
+    pub fn with_query(self, query: &str, args_digest: Option<String>) -> Self {
+        AuditEntry {
+            query: Some(query.to_string()),
+            args_digest,
+            ..self
+        }
+    }
+
+    pub fn with_rows_affected(self, rows_affected: u64) -> Self {
+        AuditEntry {
+            rows_affected: Some(rows_affected),
+            ..self
+        }
+    }
+
+    /// Sets the outcome to the error of a failed change
+    pub fn with_error(self, error: &Error) -> Self {
+        AuditEntry {
+            outcome: error.message.clone(),
+            ..self
+        }
+    }
+
+    pub fn with_result<T>(self, res: &Result<T, Error>) -> Self {
+        match res {
+            Ok(_) => self,
+            Err(e) => self.with_error(e),
+        }
+    }
+}
+
+/// #### Entries of the audit log to find
+/// \
+/// username matches the user that made a change or the user it was made to, database is a db hash.
+/// The time range is inclusive, a limit of 0 = no limit.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct AuditFilter {
+    pub username: Option<String>,
+    pub database: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: u64,
+}
+
+fn database_name_hash(database_name: &str) -> String {
+    base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()))
+}
+
+/// #### The digest of the args of a query, None when there are none
+pub fn args_digest(parts: &[QueryArg]) -> Option<String> {
+    if parts.is_empty() {
+        return None;
+    }
+
+    let mut hasher = Sha256::new();
+    parts
+        .iter()
+        .for_each(|part| hasher.update(part.encode_length_delimited_to_vec()));
+
+    Some(base16ct::lower::encode_string(&hasher.finalize()))
+}
+
+/// #### Opens the audit db, creating it with the audit log table when missing
+pub async fn open_audit_db(audit_db: &str) -> Result<SqlitePool, sqlx::Error> {
+    let connect_options = SqliteConnectOptions::from_str(&format!("sqlite:{}", audit_db))?
+        .journal_mode(SqliteJournalMode::Wal)
+        .create_if_missing(true);
+    let pool = SqlitePool::connect_with(connect_options).await?;
+
+    execute_query(AppliedQuery::new(queries::CREATE_AUDIT_LOG_TABLE), &pool).await?;
+
+    Ok(pool)
+}
+
+pub async fn record(pool: &SqlitePool, entry: &AuditEntry) -> Result<(), sqlx::Error> {
+    let text = |value: &str| QueryArg::new(query_arg::Value::String(value.to_string()));
+
+    execute_query(
+        AppliedQuery::new(queries::INSERT_AUDIT_ENTRY).with_args(&[
+            QueryArg::new(query_arg::Value::Int(entry.created_at.timestamp_millis())),
+            text(&entry.source),
+            text(&entry.username),
+            text(&entry.action),
+            text(entry.target_user.as_deref().unwrap_or_default()),
+            text(entry.database.as_deref().unwrap_or_default()),
+            text(entry.query.as_deref().unwrap_or_default()),
+            text(entry.args_digest.as_deref().unwrap_or_default()),
+            QueryArg::new(query_arg::Value::Int(
+                entry
+                    .rows_affected
+                    .map_or(-1, |rows_affected| rows_affected as i64),
+            )),
+            text(&entry.outcome),
+        ]),
+        pool,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// #### The entries of the audit log matching the filter, oldest first
+pub async fn get_entries(
+    pool: &SqlitePool,
+    filter: &AuditFilter,
+) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    let rows = fetch_query(
+        AppliedQuery::new(queries::GET_AUDIT_ENTRIES).with_args(&[
+            QueryArg::new(query_arg::Value::String(
+                filter.username.clone().unwrap_or_default(),
+            )),
+            QueryArg::new(query_arg::Value::String(
+                filter.database.clone().unwrap_or_default(),
+            )),
+            QueryArg::new(query_arg::Value::Int(
+                filter.from.map_or(0, |from| from.timestamp_millis()),
+            )),
+            QueryArg::new(query_arg::Value::Int(
+                filter.to.map_or(i64::MAX, |to| to.timestamp_millis()),
+            )),
+            QueryArg::new(query_arg::Value::Int(match filter.limit {
+                0 => -1,
+                limit => limit as i64,
+            })),
+        ]),
+        pool,
+    )
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| AuditEntry {
+            created_at: DateTime::from_timestamp_millis(row.get::<i64, _>("created_at"))
+                .unwrap_or_default(),
+            source: row.get("source"),
+            username: row.get("username"),
+            action: row.get("action"),
+            target_user: row.get("target_user"),
+            database: row.get("database"),
+            query: row.get("query"),
+            args_digest: row.get("args_digest"),
+            rows_affected: row
+                .get::<Option<i64>, _>("rows_affected")
+                .map(|rows_affected| rows_affected as u64),
+            outcome: row.get("outcome"),
+        })
+        .collect())
+}
+
+/// #### Deletes the entries older than the retention
+/// \
+/// __*@param*__ retention_days: u64 (0 = entries are kept forever)
+/// \
+/// __*@returns*__ Result\<u64, sqlx::Error\> (number of deleted entries)
+pub async fn prune(pool: &SqlitePool, retention_days: u64) -> Result<u64, sqlx::Error> {
+    if retention_days == 0 {
+        return Ok(0);
+    }
+
+    let prune_before = Utc::now() - chrono::Duration::days(retention_days as i64);
+    let res = execute_query(
+        AppliedQuery::new(queries::PRUNE_AUDIT_LOG).with_args(&[QueryArg::new(
+            query_arg::Value::Int(prune_before.timestamp_millis()),
+        )]),
+        pool,
+    )
+    .await?;
+
+    Ok(res.rows_affected())
+}
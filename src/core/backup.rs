@@ -0,0 +1,333 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDateTime;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, Connection};
+
+use super::{
+    error::{undefined_error, ResourceNotExistError, SerfError, UndefinedError},
+    replication,
+    serf_proto::Error,
+};
+
+pub const BACKUP_MANIFEST_SUFFIX: &str = "manifest.json";
+pub(crate) const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+/// Name of the backup dir of the users db, consumer dbs use their db name hash
+pub const USER_DB_BACKUP_DIR: &str = "cfg";
+
+/// Written next to every backup as __\<backup_file\>.manifest.json__ and checked before a restore.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// db name hash, or cfg for the users db
+    pub database: String,
+    pub file: String,
+    /// RFC 3339
+    pub created_at: String,
+    pub compressed: bool,
+    /// bytes of the backup file
+    pub size: u64,
+    /// sha256 hex of the backup file
+    pub sha256: String,
+}
+
+/// #### Takes a consistent copy of a db that may be written to while the backup runs
+/// \
+/// The copy is made with VACUUM INTO over a read-only connection, so it only contains committed
+/// transactions (WAL included) and does not block the writer. The copy is then gzipped when
+/// __compress__ is true and its manifest is written next to it.
+/// \
+/// __*@param*__ db_file: &Path (I.E \<root_dir\>/db/\<db_hash\>/\<db_hash\>.db) \
+/// __*@param*__ backup_file: &Path \
+/// __*@param*__ database: &str (db name hash or cfg, stored in the manifest) \
+/// __*@param*__ compress: bool \
+/// \
+/// __*@returns*__ Result\<BackupManifest, Error\>
+pub async fn backup_database(
+    db_file: &Path,
+    backup_file: &Path,
+    database: &str,
+    compress: bool,
+) -> Result<BackupManifest, Error> {
+    if !db_file.exists() {
+        return Err(ResourceNotExistError::with_message(
+            "Database doesn't exist",
+        ));
+    }
+
+    if backup_file.exists() {
+        return Err(UndefinedError::with_message(&format!(
+            "Backup file {} already exists",
+            backup_file.display()
+        )));
+    }
+
+    if let Some(backup_dir) = backup_file.parent() {
+        fs::create_dir_all(backup_dir).map_err(undefined_error)?;
+    }
+
+    let vacuum_file = with_suffix(backup_file, "tmp");
+    vacuum_into(db_file, &vacuum_file).await?;
+
+    let backup_file_c = backup_file.to_path_buf();
+    let res = tokio::task::spawn_blocking(move || {
+        if compress {
+            let res = gzip_file(&vacuum_file, &backup_file_c);
+            let _ = fs::remove_file(&vacuum_file);
+            res?;
+        } else {
+            fs::rename(&vacuum_file, &backup_file_c)?;
+        }
+
+        sha256_file(&backup_file_c)
+    })
+    .await
+    .map_err(undefined_error)?;
+    let (size, sha256) = res.map_err(undefined_error)?;
+
+    let manifest = BackupManifest {
+        database: database.to_string(),
+        file: file_name(backup_file),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        compressed: compress,
+        size,
+        sha256,
+    };
+    let content = serde_json::to_string_pretty(&manifest).map_err(undefined_error)?;
+    fs::write(manifest_path(backup_file), content).map_err(undefined_error)?;
+
+    Ok(manifest)
+}
+
+/// #### Reads the manifest of a backup and checks the backup file against it
+pub async fn verify_backup(backup_file: &Path) -> Result<BackupManifest, Error> {
+    let manifest_file = manifest_path(backup_file);
+    if !backup_file.exists() || !manifest_file.exists() {
+        return Err(ResourceNotExistError::with_message(
+            "Backup or its manifest doesn't exist",
+        ));
+    }
+
+    let content = fs::read_to_string(&manifest_file).map_err(undefined_error)?;
+    let manifest: BackupManifest = serde_json::from_str(&content).map_err(undefined_error)?;
+
+    let backup_file_c = backup_file.to_path_buf();
+    let (size, sha256) = tokio::task::spawn_blocking(move || sha256_file(&backup_file_c))
+        .await
+        .map_err(undefined_error)?
+        .map_err(undefined_error)?;
+
+    if size != manifest.size || sha256 != manifest.sha256 {
+        return Err(UndefinedError::with_message(
+            "Backup checksum does not match its manifest",
+        ));
+    }
+
+    Ok(manifest)
+}
+
+/// #### Verifies a backup and swaps it in as the given db file
+/// \
+/// The backup is unpacked next to the db file and integrity checked before it replaces the db,
+/// so a failed restore leaves the db as it was. Nothing may have the db open during the swap,
+/// which is why the server closes the pools of the db first.
+/// \
+/// __*@returns*__ Result\<BackupManifest, Error\> (the manifest of the restored backup)
+pub async fn restore_database(backup_file: &Path, db_file: &Path) -> Result<BackupManifest, Error> {
+    let manifest = verify_backup(backup_file).await?;
+    let restore_file = with_suffix(db_file, "restore");
+
+    let backup_file_c = backup_file.to_path_buf();
+    let restore_file_c = restore_file.clone();
+    let compressed = manifest.compressed;
+    tokio::task::spawn_blocking(move || {
+        if compressed {
+            gunzip_file(&backup_file_c, &restore_file_c)
+        } else {
+            fs::copy(&backup_file_c, &restore_file_c).map(|_| ())
+        }
+    })
+    .await
+    .map_err(undefined_error)?
+    .map_err(undefined_error)?;
+
+    let res = match check_integrity(&restore_file).await {
+        Ok(_) => replication::resync_followers(db_file, &restore_file).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        let _ = fs::remove_file(&restore_file);
+        return Err(e);
+    }
+    swap_db_file(&restore_file, db_file)?;
+
+    Ok(manifest)
+}
+
+/// #### Timestamped file name of a new backup, I.E 20250101T120000123Z.db.gz
+pub fn backup_file_name(compress: bool) -> String {
+    format!(
+        "{}.db{}",
+        chrono::Utc::now().format(BACKUP_TIMESTAMP_FORMAT),
+        if compress { ".gz" } else { "" }
+    )
+}
+
+/// #### Removes the timestamped backups in a backup dir that fall outside of the retention
+/// \
+/// The latest backup of each of the latest __keep_hourly__ hours and of each of the latest
+/// __keep_daily__ days is kept, and the latest backup is always kept. Files not named like
+/// backup_file_name are left alone.
+/// \
+/// __*@returns*__ Result\<Vec\<String\>, Error\> (the file names of the removed backups)
+pub fn prune_backups(
+    backup_dir: &Path,
+    keep_hourly: u32,
+    keep_daily: u32,
+) -> Result<Vec<String>, Error> {
+    let mut backups = fs::read_dir(backup_dir)
+        .map_err(undefined_error)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter_map(|file| backup_timestamp(&file).map(|timestamp| (timestamp, file)))
+        .collect::<Vec<_>>();
+    backups.sort_by(|a, b| b.cmp(a));
+
+    let mut hours = HashSet::new();
+    let mut days = HashSet::new();
+    let mut removed = vec![];
+    for (i, (timestamp, file)) in backups.into_iter().enumerate() {
+        // going from the latest backup back, the first backup seen of an hour / day is its latest
+        let is_latest_of_hour = hours.insert(timestamp.format("%Y%m%d%H").to_string());
+        let is_latest_of_day = days.insert(timestamp.format("%Y%m%d").to_string());
+        let keep = i == 0
+            || (is_latest_of_hour && hours.len() <= keep_hourly as usize)
+            || (is_latest_of_day && days.len() <= keep_daily as usize);
+
+        if !keep {
+            let backup_file = backup_dir.join(&file);
+            fs::remove_file(&backup_file).map_err(undefined_error)?;
+            let _ = fs::remove_file(manifest_path(&backup_file));
+            removed.push(file);
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Backup files are addressed by name only, within the backup dir of their db.
+pub fn validate_backup_file_name(file: &str) -> Result<(), Error> {
+    if file.is_empty() || file.contains(['/', '\\']) || file.contains("..") {
+        return Err(ResourceNotExistError::with_message("Backup doesn't exist"));
+    }
+
+    Ok(())
+}
+
+pub fn manifest_path(backup_file: &Path) -> PathBuf {
+    with_suffix(backup_file, BACKUP_MANIFEST_SUFFIX)
+}
+
+/// #### Copies a db with VACUUM INTO over a read-only connection, removing the copy if it fails
+pub(crate) async fn vacuum_into(db_file: &Path, dest: &Path) -> Result<(), Error> {
+    let mut conn = SqliteConnectOptions::new()
+        .filename(db_file)
+        .read_only(true)
+        .connect()
+        .await
+        .map_err(undefined_error)?;
+    let res = sqlx::query("VACUUM INTO ?;")
+        .bind(dest.to_string_lossy().to_string())
+        .execute(&mut conn)
+        .await;
+    let _ = conn.close().await;
+
+    if let Err(e) = res {
+        let _ = fs::remove_file(dest);
+        return Err(undefined_error(e));
+    }
+
+    Ok(())
+}
+
+/// #### Replaces __db_file__ with __new_file__, nothing may have the db open
+pub(crate) fn swap_db_file(new_file: &Path, db_file: &Path) -> Result<(), Error> {
+    // the -wal / -shm files belong to the db being replaced
+    for suffix in ["wal", "shm"] {
+        let _ = fs::remove_file(with_db_suffix(db_file, suffix));
+        let _ = fs::remove_file(with_db_suffix(new_file, suffix));
+    }
+
+    fs::rename(new_file, db_file).map_err(undefined_error)
+}
+
+pub(crate) async fn check_integrity(db_file: &Path) -> Result<(), Error> {
+    let mut conn = SqliteConnectOptions::new()
+        .filename(db_file)
+        .read_only(true)
+        .connect()
+        .await
+        .map_err(undefined_error)?;
+    let res = sqlx::query_scalar::<_, String>("PRAGMA integrity_check;")
+        .fetch_one(&mut conn)
+        .await;
+    let _ = conn.close().await;
+
+    match res {
+        Ok(check) if check == "ok" => Ok(()),
+        Ok(check) => Err(UndefinedError::with_message(&format!(
+            "Backup failed the integrity check: {}",
+            check
+        ))),
+        Err(e) => Err(undefined_error(e)),
+    }
+}
+
+pub(crate) fn gzip_file(src: &Path, dest: &Path) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(src)?);
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(dest)?), Compression::default());
+    io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?.into_inner()?.sync_all()
+}
+
+pub(crate) fn gunzip_file(src: &Path, dest: &Path) -> io::Result<()> {
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(src)?));
+    let mut writer = BufWriter::new(File::create(dest)?);
+    io::copy(&mut decoder, &mut writer)?;
+    writer.into_inner()?.sync_all()
+}
+
+fn sha256_file(path: &Path) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+
+    Ok((size, base16ct::lower::encode_string(&hasher.finalize())))
+}
+
+fn backup_timestamp(file: &str) -> Option<NaiveDateTime> {
+    let timestamp = file
+        .strip_suffix(".db")
+        .or_else(|| file.strip_suffix(".db.gz"))?;
+
+    NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT).ok()
+}
+
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.to_string_lossy(), suffix))
+}
+
+// SQLite names them <db_file>-wal / <db_file>-shm
+pub(crate) fn with_db_suffix(path: &Path, suffix: &str) -> PathBuf {
+    PathBuf::from(format!("{}-{}", path.to_string_lossy(), suffix))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().to_string())
+}
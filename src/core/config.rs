@@ -0,0 +1,221 @@
+use std::{fs, path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteSynchronous};
+
+use super::{
+    error::{SerfError, UndefinedError},
+    serf_proto::Error,
+};
+
+pub const DATABASE_CONFIG_FILE: &str = "config.json";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl From<Synchronous> for SqliteSynchronous {
+    fn from(synchronous: Synchronous) -> Self {
+        match synchronous {
+            Synchronous::Off => SqliteSynchronous::Off,
+            Synchronous::Normal => SqliteSynchronous::Normal,
+            Synchronous::Full => SqliteSynchronous::Full,
+            Synchronous::Extra => SqliteSynchronous::Extra,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TempStore {
+    Default,
+    File,
+    Memory,
+}
+
+impl TempStore {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TempStore::Default => "DEFAULT",
+            TempStore::File => "FILE",
+            TempStore::Memory => "MEMORY",
+        }
+    }
+}
+
+/// Per database connection settings, stored as __config.json__ next to the db file
+/// I.E ```<root_dir>/db/<db_hash>/config.json``` and applied to every pool opened for the db.
+/// Settings left out of the file fall back to the defaults below.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// milliseconds
+    pub busy_timeout: u64,
+    pub synchronous: Synchronous,
+    /// PRAGMA cache_size, negative values are in KiB and positive values in pages
+    pub cache_size: Option<i64>,
+    /// PRAGMA mmap_size, bytes
+    pub mmap_size: Option<u64>,
+    pub foreign_keys: bool,
+    pub temp_store: Option<TempStore>,
+    /// scheduled backups of the db, none = no scheduled backups
+    pub backup: Option<BackupScheduleConfig>,
+    /// continuous archiving of the WAL of the db, none = no archiving
+    pub wal_archive: Option<WalArchiveConfig>,
+    /// serve the changes of the db to followers, none = not replicated
+    pub replication: Option<ReplicationConfig>,
+}
+
+/// Backups taken by the server on a cron schedule, written as timestamped files to
+/// ```<root_dir>/backup/<db_hash>/``` and pruned by the keep_* retention rules after every run.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupScheduleConfig {
+    /// cron expression in UTC, I.E "0 * * * *" = at the start of every hour
+    pub schedule: String,
+    pub compress: bool,
+    /// keep the latest backup of each of the latest __keep_hourly__ hours that have a backup
+    pub keep_hourly: u32,
+    /// keep the latest backup of each of the latest __keep_daily__ days that have a backup
+    pub keep_daily: u32,
+}
+
+impl Default for BackupScheduleConfig {
+    fn default() -> Self {
+        BackupScheduleConfig {
+            schedule: String::from("0 * * * *"),
+            compress: true,
+            keep_hourly: 24,
+            keep_daily: 7,
+        }
+    }
+}
+
+/// The server copies the WAL of the db to ```<root_dir>/archive/<db_hash>/``` every __interval__
+/// seconds and checkpoints it itself afterwards (autocheckpointing is turned off for the db), so that
+/// the db can be rebuilt as of any archived point in time with ```serf-cli restore database --to```.
+/// Every __snapshot_interval__ seconds a new generation is started from a fresh snapshot of the db.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalArchiveConfig {
+    /// seconds
+    pub interval: u64,
+    /// seconds
+    pub snapshot_interval: u64,
+    /// number of generations (snapshot + archived WAL) kept
+    pub keep_generations: u32,
+}
+
+impl Default for WalArchiveConfig {
+    fn default() -> Self {
+        WalArchiveConfig {
+            interval: 10,
+            snapshot_interval: 86400,
+            keep_generations: 2,
+        }
+    }
+}
+
+/// Every mutation and migration of the db is recorded in its __replication_log_t__ table in the same
+/// transaction, from where followers fetch and replay them. Only the latest __keep_changes__ changes
+/// are kept, a follower that falls further behind starts over from a snapshot of the db.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplicationConfig {
+    pub keep_changes: u64,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        ReplicationConfig {
+            keep_changes: 100000,
+        }
+    }
+}
+
+impl BackupScheduleConfig {
+    /// #### The first time after __after__ that the schedule is due
+    pub fn next_run(&self, after: &DateTime<Utc>) -> Result<DateTime<Utc>, Error> {
+        Cron::new(&self.schedule)
+            .parse()
+            .and_then(|cron| cron.find_next_occurrence(after, false))
+            .map_err(|e| {
+                UndefinedError::with_message(&format!(
+                    "Invalid backup schedule {}: {}",
+                    self.schedule, e
+                ))
+            })
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            busy_timeout: 5000,
+            synchronous: Synchronous::Full,
+            cache_size: None,
+            mmap_size: None,
+            foreign_keys: true,
+            temp_store: None,
+            backup: None,
+            wal_archive: None,
+            replication: None,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// #### Reads the db config file in the given db directory
+    /// \
+    /// A missing file gives the default config.
+    pub fn load(db_dir: &Path) -> Result<DatabaseConfig, Error> {
+        let config_path = db_dir.join(DATABASE_CONFIG_FILE);
+        if !config_path.exists() {
+            return Ok(DatabaseConfig::default());
+        }
+
+        let content = fs::read_to_string(&config_path)
+            .map_err(|e| UndefinedError::with_message(&e.to_string()))?;
+        serde_json::from_str(&content).map_err(|e| UndefinedError::with_message(&e.to_string()))
+    }
+
+    pub fn save(&self, db_dir: &Path) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| UndefinedError::with_message(&e.to_string()))?;
+        fs::write(db_dir.join(DATABASE_CONFIG_FILE), content)
+            .map_err(|e| UndefinedError::with_message(&e.to_string()))
+    }
+
+    pub fn apply(&self, connect_options: SqliteConnectOptions) -> SqliteConnectOptions {
+        let mut connect_options = connect_options
+            .busy_timeout(Duration::from_millis(self.busy_timeout))
+            .synchronous(self.synchronous.into())
+            .foreign_keys(self.foreign_keys);
+
+        if let Some(cache_size) = self.cache_size {
+            connect_options = connect_options.pragma("cache_size", cache_size.to_string());
+        }
+
+        if let Some(mmap_size) = self.mmap_size {
+            connect_options = connect_options.pragma("mmap_size", mmap_size.to_string());
+        }
+
+        if let Some(temp_store) = self.temp_store {
+            connect_options = connect_options.pragma("temp_store", temp_store.as_str());
+        }
+
+        // the WAL archiver does the checkpointing so that no frames are checkpointed before they are archived
+        if self.wal_archive.is_some() {
+            connect_options = connect_options.pragma("wal_autocheckpoint", "0");
+        }
+
+        connect_options
+    }
+}
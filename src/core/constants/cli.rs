@@ -2,11 +2,11 @@ pub const DEFAULT_PORT: u16 = 8080;
 pub const DEFAULT_DB_MAX_CONN: u32 = 12;
 pub const DEFAULT_DB_MAX_IDLE_TIME: u64 = 3600;
 pub const DEFAULT_DB_MAX_LIFETIME: u64 = 86400;
-pub const PORT_FLAG: &str = "--port";
-pub const DB_MAX_CONN_FLAG: &str = "--db-max-conn";
-pub const DB_MAX_IDLE_TIME_FLAG: &str = "--db-max-idle-time";
-pub const DB_MAX_LIFETIME_FLAG: &str = "--db-max-lifetime";
-pub const DB_NAME_FLAG: &str = "-db";
-pub const USERNAME_FLAG: &str = "-u";
-pub const PASSWORD_FLAG: &str = "-p";
-pub const ACCESS_RIGHT_FLAG: &str = "-ar";
+pub const DEFAULT_DB_MAX_OPEN: usize = 100;
+pub const DEFAULT_DB_POOL_MAX_IDLE_TIME: u64 = 3600;
+pub const DEFAULT_QUERY_TIMEOUT: u64 = 0;
+pub const DEFAULT_MAX_ROWS: u64 = 0;
+pub const DEFAULT_MAX_RESPONSE_BYTES: u64 = 0;
+pub const DEFAULT_MAX_DATABASES: u64 = 10;
+pub const DEFAULT_REPLICATION_INTERVAL: u64 = 1000;
+pub const DEFAULT_AUDIT_RETENTION_DAYS: u64 = 0;
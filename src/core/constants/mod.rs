@@ -1,2 +1,2 @@
-pub mod queries;
 pub mod cli;
+pub mod queries;
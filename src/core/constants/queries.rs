@@ -1,14 +1,44 @@
+// a row per user, databases holds the permissions of the user per db hash with the permissions of its direct
+// and group grants on a db combined (bit or of the role::FETCH, MUTATE, MIGRATE and ADMIN bits)
 pub const GET_USERS_AND_ACCESS: &str = r#"
     SELECT 
         u.username,
         u.username_password_hash,
         u.username_hash,
-        json_array(
-            json_object(
-                database_hash, access_right
+        u.query_timeout,
+        u.max_rows,
+        u.max_response_bytes,
+        u.is_creator,
+        u.max_databases,
+        COALESCE(gr.permissions, 0) AS permissions,
+        (
+            SELECT json_group_object(
+                k.name,
+                json_object('secret', k.secret, 'expires_at', k.expires_at, 'scope', k.scope)
             )
-        ) as databases
-    FROM users u INNER JOIN users_database_access USING(username_hash);
+            FROM api_keys k WHERE k.username_hash = u.username_hash
+        ) AS api_keys,
+        json_group_object(a.database_hash, a.permissions)
+            FILTER (WHERE a.database_hash IS NOT NULL) AS databases
+    FROM users u
+        LEFT JOIN (
+            SELECT
+                g.username_hash,
+                g.database_hash,
+                MAX(g.permissions & 1) | MAX(g.permissions & 2) | MAX(g.permissions & 4) | MAX(g.permissions & 8) AS permissions
+            FROM (
+                SELECT uda.username_hash, uda.database_hash, COALESCE(r.permissions, 0) AS permissions
+                FROM users_database_access uda LEFT JOIN roles r ON r.name = uda.role
+                UNION ALL
+                SELECT gu.username_hash, gda.database_hash, COALESCE(r.permissions, 0) AS permissions
+                FROM groups_users gu
+                    INNER JOIN groups_database_access gda USING(group_name)
+                    LEFT JOIN roles r ON r.name = gda.role
+            ) g
+            GROUP BY g.username_hash, g.database_hash
+        ) a ON a.username_hash = u.username_hash
+        LEFT JOIN roles gr ON gr.name = u.role
+    GROUP BY u.id;
 "#;
 
 pub const CREATE_USERS_TABLE: &str = r#"
@@ -20,6 +50,7 @@ pub const CREATE_USERS_TABLE: &str = r#"
     );
 "#;
 
+// access_right is replaced by the role column, see UPDATE_USERS_DATABASE_ACCESS_ROLE_FROM_ACCESS_RIGHT
 pub const CREATE_USERS_DATABASE_ACCESS_TABLE: &str = r#"
     CREATE TABLE IF NOT EXISTS users_database_access (
         id INTEGER PRIMARY KEY,
@@ -35,6 +66,138 @@ pub const CREATE_USERS_DATABASE_ACCESS_TABLE: &str = r#"
     );
 "#;
 
+pub const ALTER_USERS_ADD_QUERY_TIMEOUT: &str = r#"
+    ALTER TABLE users ADD COLUMN query_timeout INTEGER;
+"#;
+
+pub const ALTER_USERS_ADD_MAX_ROWS: &str = r#"
+    ALTER TABLE users ADD COLUMN max_rows INTEGER;
+"#;
+
+pub const ALTER_USERS_ADD_MAX_RESPONSE_BYTES: &str = r#"
+    ALTER TABLE users ADD COLUMN max_response_bytes INTEGER;
+"#;
+
+// creators can create dbs through the protocol, becoming the owner of them
+pub const ALTER_USERS_ADD_IS_CREATOR: &str = r#"
+    ALTER TABLE users ADD COLUMN is_creator INTEGER NOT NULL DEFAULT 0;
+"#;
+
+pub const ALTER_USERS_ADD_MAX_DATABASES: &str = r#"
+    ALTER TABLE users ADD COLUMN max_databases INTEGER;
+"#;
+
+pub const ALTER_USERS_DATABASE_ACCESS_ADD_IS_OWNER: &str = r#"
+    ALTER TABLE users_database_access ADD COLUMN is_owner INTEGER NOT NULL DEFAULT 0;
+"#;
+
+// the built-in roles of role::BUILTIN_ROLES, permissions being bits of fetch = 1, mutate = 2, migrate = 4 and admin = 8
+pub const CREATE_ROLES_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS roles (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        permissions INTEGER NOT NULL,
+        is_builtin INTEGER NOT NULL DEFAULT 0
+    );
+    INSERT OR IGNORE INTO roles(name, permissions, is_builtin) VALUES
+        ('reader', 1, 1),
+        ('writer', 3, 1),
+        ('migrator', 5, 1),
+        ('admin', 15, 1);
+"#;
+
+pub const ALTER_USERS_DATABASE_ACCESS_ADD_ROLE: &str = r#"
+    ALTER TABLE users_database_access ADD COLUMN role TEXT REFERENCES roles (name) ON UPDATE CASCADE;
+"#;
+
+// access_right 2 and 3 could fetch, mutate and migrate, kept as the custom read_write role
+pub const UPDATE_USERS_DATABASE_ACCESS_ROLE_FROM_ACCESS_RIGHT: &str = r#"
+    INSERT INTO roles(name, permissions)
+        SELECT 'read_write', 7 WHERE EXISTS (SELECT 1 FROM users_database_access WHERE access_right >= 2);
+    UPDATE users_database_access SET role = CASE WHEN access_right >= 2 THEN 'read_write' ELSE 'reader' END;
+"#;
+
+// a global role applies to every db, on top of the roles per db
+pub const ALTER_USERS_ADD_ROLE: &str = r#"
+    ALTER TABLE users ADD COLUMN role TEXT REFERENCES roles (name) ON UPDATE CASCADE;
+"#;
+
+// members of a group get the roles the group has on dbs, on top of their own
+pub const CREATE_GROUPS_TABLES: &str = r#"
+    CREATE TABLE IF NOT EXISTS groups (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS groups_users (
+        id INTEGER PRIMARY KEY,
+        group_name TEXT NOT NULL,
+        username_hash TEXT NOT NULL,
+        UNIQUE(group_name,username_hash)
+        FOREIGN KEY (group_name)
+        REFERENCES groups (name)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+        FOREIGN KEY (username_hash)
+        REFERENCES users (username_hash)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+    );
+    CREATE TABLE IF NOT EXISTS groups_database_access (
+        id INTEGER PRIMARY KEY,
+        database TEXT NOT NULL,
+        database_hash TEXT NOT NULL,
+        group_name TEXT NOT NULL,
+        role TEXT NOT NULL REFERENCES roles (name) ON UPDATE CASCADE,
+        UNIQUE(database_hash,group_name)
+        FOREIGN KEY (group_name)
+        REFERENCES groups (name)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+    );
+"#;
+
+// the key itself is only shown when it is created, requests made with it are signed with its sha256 hex,
+// so secret is the signing secret of the key (the same as username_password_hash is for the password)
+pub const CREATE_API_KEYS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS api_keys (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        secret TEXT NOT NULL,
+        username_hash TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        scope INTEGER,
+        UNIQUE(username_hash,name)
+        FOREIGN KEY (username_hash)
+        REFERENCES users (username_hash)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+    );
+"#;
+
+/// Schema of the users db, applied in order on init starting from the users db PRAGMA user_version.
+/// Only ever append to this list.
+pub const USER_DB_MIGRATIONS: &[&str] = &[
+    CREATE_USERS_TABLE,
+    CREATE_USERS_DATABASE_ACCESS_TABLE,
+    ALTER_USERS_ADD_QUERY_TIMEOUT,
+    ALTER_USERS_ADD_MAX_ROWS,
+    ALTER_USERS_ADD_MAX_RESPONSE_BYTES,
+    ALTER_USERS_ADD_IS_CREATOR,
+    ALTER_USERS_ADD_MAX_DATABASES,
+    ALTER_USERS_DATABASE_ACCESS_ADD_IS_OWNER,
+    CREATE_ROLES_TABLE,
+    ALTER_USERS_DATABASE_ACCESS_ADD_ROLE,
+    UPDATE_USERS_DATABASE_ACCESS_ROLE_FROM_ACCESS_RIGHT,
+    ALTER_USERS_ADD_ROLE,
+    CREATE_GROUPS_TABLES,
+    CREATE_API_KEYS_TABLE,
+];
+
+pub const GET_USER_DB_VERSION: &str = "PRAGMA user_version;";
+// changes whenever another connection commits to the db, only comparable on the same connection
+pub const GET_DATA_VERSION: &str = "PRAGMA data_version;";
+
 pub const INSERT_USER: &str = r#"
     INSERT OR IGNORE INTO users(
         username,
@@ -47,17 +210,302 @@ pub const UPSERT_USER_DATABASE_ACCESS: &str = r#"
     INSERT OR IGNORE INTO users_database_access(
         database,
         database_hash,
-        access_right,
+        role,
         username_hash
     ) VALUES(?, ?, ?, ?)
     ON CONFLICT(
         database_hash,
         username_hash
     ) DO UPDATE SET
-        access_right = excluded.access_right
+        role = excluded.role
     WHERE excluded.database_hash=users_database_access.database_hash AND excluded.username_hash=users_database_access.username_hash;
 "#;
 
+pub const UPDATE_USER_QUERY_TIMEOUT: &str = r#"
+    UPDATE users SET query_timeout = NULLIF(?, 0) WHERE username_hash = ?;
+"#;
+
+// 0 is stored as is and means unlimited, the reset queries below go back to the server default
+pub const UPDATE_USER_MAX_ROWS: &str = r#"
+    UPDATE users SET max_rows = ? WHERE username_hash = ?;
+"#;
+
+pub const UPDATE_USER_MAX_RESPONSE_BYTES: &str = r#"
+    UPDATE users SET max_response_bytes = ? WHERE username_hash = ?;
+"#;
+
+pub const RESET_USER_MAX_ROWS: &str = r#"
+    UPDATE users SET max_rows = NULL WHERE username_hash = ?;
+"#;
+
+pub const RESET_USER_MAX_RESPONSE_BYTES: &str = r#"
+    UPDATE users SET max_response_bytes = NULL WHERE username_hash = ?;
+"#;
+
+// granting admin replaces the global role of the user, revoking it only clears the admin role
+pub const UPDATE_USER_ADMIN_ROLE: &str = r#"
+    UPDATE users SET role = CASE WHEN ? THEN 'admin' WHEN role = 'admin' THEN NULL ELSE role END
+    WHERE username_hash = ?;
+"#;
+
+pub const UPDATE_USER_IS_CREATOR: &str = r#"
+    UPDATE users SET is_creator = ? WHERE username_hash = ?;
+"#;
+
+pub const UPDATE_USER_MAX_DATABASES: &str = r#"
+    UPDATE users SET max_databases = NULLIF(?, 0) WHERE username_hash = ?;
+"#;
+
+// owners get the writer role (fetch and mutate), migrations and admin have to be granted
+pub const INSERT_OWNER_DATABASE_ACCESS: &str = r#"
+    INSERT INTO users_database_access(
+        database,
+        database_hash,
+        role,
+        username_hash,
+        is_owner
+    ) VALUES(?, ?, 'writer', ?, 1);
+"#;
+
+pub const COUNT_USER_OWNED_DATABASES: &str = r#"
+    SELECT COUNT(*) FROM users_database_access WHERE username_hash = ? AND is_owner = 1;
+"#;
+
+pub const UPDATE_USER_PASSWORD_HASH: &str = r#"
+    UPDATE users SET username_password_hash = ? WHERE username_hash = ?;
+"#;
+
+pub const UPDATE_USER_USERNAME: &str = r#"
+    UPDATE users SET
+        username = ?,
+        username_hash = ?,
+        username_password_hash = ?
+    WHERE username_hash = ?;
+"#;
+
+pub const GET_USER_PASSWORD_HASH: &str = r#"
+    SELECT username_password_hash FROM users WHERE username_hash = ?;
+"#;
+
+pub const DELETE_USER: &str = r#"
+    DELETE FROM users WHERE username_hash = ?;
+"#;
+
+pub const DELETE_USER_DATABASE_ACCESS: &str = r#"
+    DELETE FROM users_database_access WHERE username_hash = ? AND database_hash = ?;
+"#;
+
+pub const GET_USERS_WITH_ACCESS: &str = r#"
+    SELECT
+        u.username,
+        u.query_timeout,
+        u.max_rows,
+        u.max_response_bytes,
+        COALESCE(gr.permissions & 8, 0) != 0 AS is_admin,
+        u.is_creator,
+        u.max_databases,
+        u.role AS global_role,
+        (
+            SELECT json_group_array(group_name)
+            FROM (SELECT group_name FROM groups_users gu WHERE gu.username_hash = u.username_hash ORDER BY group_name)
+        ) AS groups,
+        uda.database,
+        uda.database_hash,
+        uda.role,
+        uda.is_owner
+    FROM users u
+        LEFT JOIN users_database_access uda USING(username_hash)
+        LEFT JOIN roles gr ON gr.name = u.role
+    ORDER BY u.username, uda.database;
+"#;
+
+pub const GET_USER_WITH_ACCESS: &str = r#"
+    SELECT
+        u.username,
+        u.query_timeout,
+        u.max_rows,
+        u.max_response_bytes,
+        COALESCE(gr.permissions & 8, 0) != 0 AS is_admin,
+        u.is_creator,
+        u.max_databases,
+        u.role AS global_role,
+        (
+            SELECT json_group_array(group_name)
+            FROM (SELECT group_name FROM groups_users gu WHERE gu.username_hash = u.username_hash ORDER BY group_name)
+        ) AS groups,
+        uda.database,
+        uda.database_hash,
+        uda.role,
+        uda.is_owner
+    FROM users u
+        LEFT JOIN users_database_access uda USING(username_hash)
+        LEFT JOIN roles gr ON gr.name = u.role
+    WHERE u.username_hash = ?
+    ORDER BY uda.database;
+"#;
+
+pub const GET_DATABASE_USERS: &str = r#"
+    SELECT u.username, uda.role
+    FROM users_database_access uda INNER JOIN users u USING(username_hash)
+    WHERE uda.database_hash = ?
+    ORDER BY u.username;
+"#;
+
+pub const UPDATE_USER_ROLE: &str = r#"
+    UPDATE users SET role = NULLIF(?, '') WHERE username_hash = ?;
+"#;
+
+pub const GET_ROLE: &str = r#"
+    SELECT name, permissions, is_builtin FROM roles WHERE name = ?;
+"#;
+
+pub const GET_ROLES: &str = r#"
+    SELECT name, permissions, is_builtin FROM roles ORDER BY name;
+"#;
+
+pub const INSERT_ROLE: &str = r#"
+    INSERT INTO roles(name, permissions) VALUES(?, ?);
+"#;
+
+pub const COUNT_ROLE_ASSIGNMENTS: &str = r#"
+    SELECT
+        (SELECT COUNT(*) FROM users WHERE role = ?1)
+        + (SELECT COUNT(*) FROM users_database_access WHERE role = ?1)
+        + (SELECT COUNT(*) FROM groups_database_access WHERE role = ?1);
+"#;
+
+pub const DELETE_ROLE: &str = r#"
+    DELETE FROM roles WHERE name = ? AND is_builtin = 0;
+"#;
+
+pub const INSERT_GROUP: &str = r#"
+    INSERT INTO groups(name) VALUES(?);
+"#;
+
+pub const DELETE_GROUP: &str = r#"
+    DELETE FROM groups WHERE name = ?;
+"#;
+
+pub const INSERT_GROUP_USER: &str = r#"
+    INSERT OR IGNORE INTO groups_users(group_name, username_hash) VALUES(?, ?);
+"#;
+
+pub const DELETE_GROUP_USER: &str = r#"
+    DELETE FROM groups_users WHERE group_name = ? AND username_hash = ?;
+"#;
+
+pub const GET_GROUP: &str = r#"
+    SELECT name FROM groups WHERE name = ?;
+"#;
+
+pub const GET_USER: &str = r#"
+    SELECT username FROM users WHERE username_hash = ?;
+"#;
+
+pub const UPSERT_GROUP_DATABASE_ACCESS: &str = r#"
+    INSERT INTO groups_database_access(
+        database,
+        database_hash,
+        role,
+        group_name
+    ) VALUES(?, ?, ?, ?)
+    ON CONFLICT(
+        database_hash,
+        group_name
+    ) DO UPDATE SET
+        role = excluded.role;
+"#;
+
+pub const DELETE_GROUP_DATABASE_ACCESS: &str = r#"
+    DELETE FROM groups_database_access WHERE group_name = ? AND database_hash = ?;
+"#;
+
+pub const GET_GROUPS_WITH_MEMBERS: &str = r#"
+    SELECT g.name, u.username
+    FROM groups g
+        LEFT JOIN groups_users gu ON gu.group_name = g.name
+        LEFT JOIN users u USING(username_hash)
+    ORDER BY g.name, u.username;
+"#;
+
+pub const GET_GROUPS_WITH_ACCESS: &str = r#"
+    SELECT g.name, gda.database, gda.role
+    FROM groups g INNER JOIN groups_database_access gda ON gda.group_name = g.name
+    ORDER BY g.name, gda.database;
+"#;
+
+pub const DELETE_GROUPS_DATABASE_ACCESS: &str = r#"
+    DELETE FROM groups_database_access WHERE database_hash = ?;
+"#;
+
+pub const UPDATE_GROUPS_DATABASE_ACCESS_DATABASE: &str = r#"
+    UPDATE groups_database_access SET database = ?, database_hash = ? WHERE database_hash = ?;
+"#;
+
+pub const GENERATE_API_KEY: &str = r#"
+    SELECT lower(hex(randomblob(32)));
+"#;
+
+pub const INSERT_API_KEY: &str = r#"
+    INSERT INTO api_keys(
+        name,
+        secret,
+        username_hash,
+        created_at,
+        expires_at,
+        scope
+    ) VALUES(?, ?, ?, unixepoch(), NULLIF(?, 0), NULLIF(?, 0));
+"#;
+
+pub const UPDATE_API_KEY_EXPIRES_AT: &str = r#"
+    UPDATE api_keys SET expires_at = ? WHERE username_hash = ? AND name = ?;
+"#;
+
+pub const DELETE_API_KEY: &str = r#"
+    DELETE FROM api_keys WHERE username_hash = ? AND name = ?;
+"#;
+
+pub const GET_USER_API_KEYS: &str = r#"
+    SELECT name, created_at, expires_at, scope FROM api_keys WHERE username_hash = ? ORDER BY name;
+"#;
+
+pub const DELETE_DATABASE_ACCESS: &str = r#"
+    DELETE FROM users_database_access WHERE database_hash = ?;
+"#;
+
+pub const UPDATE_DATABASE_ACCESS_DATABASE: &str = r#"
+    UPDATE users_database_access SET database = ?, database_hash = ? WHERE database_hash = ?;
+"#;
+
+pub const GET_DATABASE_STATS: &str = r#"
+    SELECT
+        page_size,
+        page_count,
+        freelist_count,
+        journal_mode,
+        user_version
+    FROM pragma_page_size(), pragma_page_count(), pragma_freelist_count(), pragma_journal_mode(), pragma_user_version();
+"#;
+
+pub const GET_DATABASE_SCHEMA: &str = r#"
+    SELECT type, name, sql FROM sqlite_schema
+    WHERE name NOT LIKE 'sqlite_%' AND sql IS NOT NULL
+    ORDER BY type DESC, name;
+"#;
+
+// tables first so that indexes, triggers and views are created after the tables they depend on,
+// ?1 = NULL for the schema of the whole db
+pub const GET_EXPORT_SCHEMA: &str = r#"
+    SELECT type, name, sql FROM sqlite_schema
+    WHERE name NOT LIKE 'sqlite_%' AND name != '__replication_log_t__' AND sql IS NOT NULL
+        AND (?1 IS NULL OR tbl_name = ?1)
+    ORDER BY type != 'table', rowid;
+"#;
+
+pub const GET_TABLE_COLUMNS: &str = r#"
+    SELECT name, type FROM pragma_table_info(?);
+"#;
+
 pub const CREATE_MIGRATIONS_TABLE: &str = r#"
     CREATE TABLE IF NOT EXISTS __migrations_tracker_t__ (
         id INTEGER PRIMARY KEY,
@@ -73,3 +521,144 @@ pub const INSERT_MIGRATION: &str = r#"
         query
     ) VALUES(?, ?);
 "#;
+
+pub const CREATE_REPLICATION_LOG_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS __replication_log_t__ (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        sub INTEGER NOT NULL,
+        dat BLOB NOT NULL
+    );
+"#;
+
+pub const DROP_REPLICATION_LOG_TABLE: &str = r#"
+    DROP TABLE IF EXISTS __replication_log_t__;
+"#;
+
+// tables and virtual tables (their shadow tables follow along), the tracked migrations are kept
+pub const GET_CLONE_DATA_TABLES: &str = r#"
+    SELECT name FROM pragma_table_list
+    WHERE schema = 'main' AND type IN ('table', 'virtual')
+        AND name NOT LIKE 'sqlite_%' AND name != '__migrations_tracker_t__';
+"#;
+
+pub const HAS_SQLITE_SEQUENCE_TABLE: &str = r#"
+    SELECT COUNT(*) FROM sqlite_schema WHERE type = 'table' AND name = 'sqlite_sequence';
+"#;
+
+pub const HAS_REPLICATION_LOG_TABLE: &str = r#"
+    SELECT COUNT(*) FROM sqlite_schema WHERE type = 'table' AND name = '__replication_log_t__';
+"#;
+
+pub const INSERT_REPLICATION_CHANGE: &str = r#"
+    INSERT INTO __replication_log_t__(
+        sub,
+        dat
+    ) VALUES(?, ?);
+"#;
+
+pub const INSERT_REPLICATED_CHANGE: &str = r#"
+    INSERT INTO __replication_log_t__(
+        id,
+        sub,
+        dat
+    ) VALUES(?, ?, ?);
+"#;
+
+pub const PRUNE_REPLICATION_LOG: &str = r#"
+    DELETE FROM __replication_log_t__ WHERE id <= ?;
+"#;
+
+pub const GET_REPLICATION_CHANGES: &str = r#"
+    SELECT id, sub, dat FROM __replication_log_t__
+    WHERE id > ?
+    ORDER BY id
+    LIMIT ?;
+"#;
+
+// the last id handed out rather than MAX(id), so that it holds when the log is empty or pruned
+pub const GET_REPLICATION_LAST_ID: &str = r#"
+    SELECT COALESCE(
+        (SELECT seq FROM sqlite_sequence WHERE name = '__replication_log_t__'),
+        0
+    );
+"#;
+
+pub const SEED_REPLICATION_LAST_ID: &str = r#"
+    INSERT INTO sqlite_sequence(name, seq)
+    SELECT '__replication_log_t__', ?
+    WHERE NOT EXISTS (SELECT 1 FROM sqlite_sequence WHERE name = '__replication_log_t__');
+"#;
+
+pub const SET_REPLICATION_LAST_ID: &str = r#"
+    UPDATE sqlite_sequence SET seq = ? WHERE name = '__replication_log_t__';
+"#;
+
+pub const CLEAR_REPLICATION_LOG: &str = r#"
+    DELETE FROM __replication_log_t__;
+"#;
+
+// the audit log lives in its own db (see core::audit) so that recording changes doesn't touch the users db,
+// rows are only ever inserted, and deleted once they are past the retention
+pub const CREATE_AUDIT_LOG_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS audit_log (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        created_at INTEGER NOT NULL,
+        source TEXT NOT NULL,
+        username TEXT NOT NULL,
+        action TEXT NOT NULL,
+        target_user TEXT,
+        database TEXT,
+        query TEXT,
+        args_digest TEXT,
+        rows_affected INTEGER,
+        outcome TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log (created_at);
+    CREATE INDEX IF NOT EXISTS idx_audit_log_username ON audit_log (username);
+    CREATE INDEX IF NOT EXISTS idx_audit_log_database ON audit_log (database);
+    CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+    BEGIN
+        SELECT RAISE(ABORT, 'The audit log is append only');
+    END;
+"#;
+
+pub const INSERT_AUDIT_ENTRY: &str = r#"
+    INSERT INTO audit_log(
+        created_at,
+        source,
+        username,
+        action,
+        target_user,
+        database,
+        query,
+        args_digest,
+        rows_affected,
+        outcome
+    ) VALUES(?, ?, ?, ?, NULLIF(?, ''), NULLIF(?, ''), NULLIF(?, ''), NULLIF(?, ''), NULLIF(?, -1), ?);
+"#;
+
+// ?1 username (matching the user that made the change or the user it was made to, '' = any user),
+// ?2 db hash ('' = any db), ?3 and ?4 the time range in millis, ?5 the max number of entries (-1 = no limit)
+pub const GET_AUDIT_ENTRIES: &str = r#"
+    SELECT
+        created_at,
+        source,
+        username,
+        action,
+        target_user,
+        database,
+        query,
+        args_digest,
+        rows_affected,
+        outcome
+    FROM audit_log
+    WHERE (?1 = '' OR username = ?1 OR target_user = ?1)
+        AND (?2 = '' OR database = ?2)
+        AND created_at BETWEEN ?3 AND ?4
+    ORDER BY id
+    LIMIT ?5;
+"#;
+
+pub const PRUNE_AUDIT_LOG: &str = r#"
+    DELETE FROM audit_log WHERE created_at < ?;
+"#;
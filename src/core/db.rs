@@ -1,8 +1,14 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{future::BoxFuture, TryStreamExt};
 use serde_json::{json, Map as JsonMap, Value as JsonValue};
+use sqlx::pool::PoolConnectionMetadata;
 use sqlx::query::Query;
 use sqlx::sqlite::{SqliteQueryResult, SqliteRow};
 use sqlx::{Column, Executor, Row};
-use sqlx::{Database, Sqlite, SqlitePool, TypeInfo};
+use sqlx::{Database, Sqlite, SqliteConnection, TypeInfo};
 
 use crate::core::serf_proto::{query_arg, QueryArg};
 
@@ -27,13 +33,16 @@ impl<'a> AppliedQuery<'a> {
 /// #### Does a sqlx fetch_all call which returns an awaitable Future
 /// \
 /// __*@param*__ q: AppliedQuery<'a> \
-/// __*@param*__ db: &'a SqlitePool \
+/// __*@param*__ db: T (I.E &'a SqlitePool or &'a mut SqliteConnection) \
 /// \
 /// __*@returns*__ Result\<Vec\<sqlx::sqlite::SqliteRow\>, sqlx::error::Error\>
-pub async fn fetch_query<'a>(
+pub async fn fetch_query<'a, T>(
     q: AppliedQuery<'a>,
-    db: &'a SqlitePool,
-) -> Result<Vec<SqliteRow>, sqlx::error::Error> {
+    db: T,
+) -> Result<Vec<SqliteRow>, sqlx::error::Error>
+where
+    T: Executor<'a, Database = Sqlite>,
+{
     apply_query(sqlx::query(q.query), q.args)
         .fetch_all(db)
         .await
@@ -97,33 +106,169 @@ pub fn map_sqliterow_col_to_json_value<'a>(
     }
 }
 
-pub async fn fetch_all_as_json<'a>(
+pub fn map_sqliterow_to_json_object(row: &SqliteRow) -> JsonValue {
+    let mut json_row = JsonMap::new();
+
+    for column in row.columns() {
+        let column_name = column.name();
+
+        json_row.insert(
+            column_name.to_string(),
+            map_sqliterow_col_to_json_value(row, column_name, TypeInfo::name(column.type_info())),
+        );
+    }
+
+    JsonValue::Object(json_row)
+}
+
+pub async fn fetch_all_as_json<'a, T>(
     q: AppliedQuery<'a>,
-    db: &'a SqlitePool,
-) -> Result<JsonValue, sqlx::error::Error> {
+    db: T,
+) -> Result<JsonValue, sqlx::error::Error>
+where
+    T: Executor<'a, Database = Sqlite>,
+{
     let rows = fetch_query(q, db).await?;
-    let mut json_array = JsonValue::Array(vec![]);
-
-    if let JsonValue::Array(ref mut arr) = json_array {
-        for row in rows {
-            let mut json_row = JsonMap::new();
-
-            for column in row.columns() {
-                let column_name = column.name();
-
-                json_row.insert(
-                    column_name.to_string(),
-                    map_sqliterow_col_to_json_value(
-                        &row,
-                        column_name,
-                        TypeInfo::name(column.type_info()),
-                    ),
-                );
-            }
-
-            arr.push(JsonValue::Object(json_row));
+
+    Ok(JsonValue::Array(
+        rows.iter().map(map_sqliterow_to_json_object).collect(),
+    ))
+}
+
+/// #### Fetches the rows of a query as a serialized JSON array, one row at a time
+/// \
+/// Stops reading rows once __max_rows__ rows have been read or once the next row would make the JSON
+/// larger than __max_bytes__ (0 = no limit). The returned bool is true when rows were left out.
+/// \
+/// __*@returns*__ Result\<(Vec\<u8\>, bool), sqlx::error::Error\>
+pub async fn fetch_as_json_bytes<'a, T>(
+    q: AppliedQuery<'a>,
+    db: T,
+    max_rows: u64,
+    max_bytes: u64,
+) -> Result<(Vec<u8>, bool), sqlx::error::Error>
+where
+    T: Executor<'a, Database = Sqlite>,
+{
+    let mut rows = apply_query(sqlx::query(q.query), q.args).fetch(db);
+    let mut json_bytes = vec![b'['];
+    let mut row_count = 0;
+    let mut truncated = false;
+
+    while let Some(row) = rows.try_next().await? {
+        if max_rows > 0 && row_count >= max_rows {
+            truncated = true;
+            break;
         }
+
+        let json_row_bytes = serde_json::to_vec(&map_sqliterow_to_json_object(&row))
+            .map_err(|e| sqlx::error::Error::Decode(Box::new(e)))?;
+        let separator_len = if row_count > 0 { 1 } else { 0 };
+        // + 1 for the closing bracket
+        let json_bytes_len = json_bytes.len() + separator_len + json_row_bytes.len() + 1;
+        if max_bytes > 0 && json_bytes_len as u64 > max_bytes {
+            truncated = true;
+            break;
+        }
+
+        if row_count > 0 {
+            json_bytes.push(b',');
+        }
+        json_bytes.extend(json_row_bytes);
+        row_count += 1;
+    }
+    json_bytes.push(b']');
+
+    Ok((json_bytes, truncated))
+}
+
+const QUERY_INTERRUPT_RUNNING: u8 = 0;
+const QUERY_INTERRUPT_CANCELLED: u8 = 1;
+const QUERY_INTERRUPT_DONE: u8 = 2;
+// number of SQLite VM instructions between each check of the interrupt state
+const QUERY_INTERRUPT_CHECK_OPS: i32 = 1000;
+
+/// #### Interrupts the statement running on a connection
+/// \
+/// Installs a SQLite progress handler on the connection which interrupts the running statement
+/// once the timeout (millis, 0 = no timeout) has passed, or once the QueryInterrupt is dropped
+/// without having been finished, I.E when the request future was dropped because the client went away.
+/// \
+/// The handler stays on the connection after the query, pools have to remove it before handing the
+/// connection out again with clear_query_interrupt. Otherwise an interrupt that was cancelled after its
+/// query was done (I.E the request was dropped during the commit) would interrupt the next query.
+pub struct QueryInterrupt {
+    state: Arc<AtomicU8>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl QueryInterrupt {
+    pub async fn set(conn: &mut SqliteConnection, timeout: u64) -> Result<Self, sqlx::Error> {
+        let state = Arc::new(AtomicU8::new(QUERY_INTERRUPT_RUNNING));
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let deadline = (timeout > 0).then(|| Instant::now() + Duration::from_millis(timeout));
+
+        let handler_state = Arc::clone(&state);
+        let handler_timed_out = Arc::clone(&timed_out);
+        conn.lock_handle()
+            .await?
+            .set_progress_handler(QUERY_INTERRUPT_CHECK_OPS, move || {
+                match handler_state.load(Ordering::Relaxed) {
+                    QUERY_INTERRUPT_RUNNING => match deadline {
+                        Some(deadline) if Instant::now() >= deadline => {
+                            handler_timed_out.store(true, Ordering::Relaxed);
+                            handler_state.store(QUERY_INTERRUPT_DONE, Ordering::Relaxed);
+                            false
+                        }
+                        _ => true,
+                    },
+                    QUERY_INTERRUPT_CANCELLED => {
+                        handler_state.store(QUERY_INTERRUPT_DONE, Ordering::Relaxed);
+                        false
+                    }
+                    _ => true,
+                }
+            });
+
+        Ok(QueryInterrupt { state, timed_out })
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Relaxed)
     }
 
-    Ok(json_array)
+    /// Disarms the interrupt, must be called once the query (and its commit / rollback) is done.
+    pub fn finish(&self) {
+        self.state.store(QUERY_INTERRUPT_DONE, Ordering::Relaxed);
+    }
+}
+
+/// #### Quotes a table or column name for use in a query, doubling any quotes inside it
+pub fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// #### Removes the progress handler of a QueryInterrupt from a pooled connection
+/// \
+/// Passed to SqlitePoolOptions::before_acquire of the pools that queries with a QueryInterrupt run on.
+pub fn clear_query_interrupt(
+    conn: &mut SqliteConnection,
+    _: PoolConnectionMetadata,
+) -> BoxFuture<'_, Result<bool, sqlx::Error>> {
+    Box::pin(async move {
+        conn.lock_handle().await?.remove_progress_handler();
+
+        Ok(true)
+    })
+}
+
+impl Drop for QueryInterrupt {
+    fn drop(&mut self) {
+        let _ = self.state.compare_exchange(
+            QUERY_INTERRUPT_RUNNING,
+            QUERY_INTERRUPT_CANCELLED,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
 }
@@ -11,6 +11,8 @@ pub const HEADER_MISSING: &str = "Request is missing a required header";
 pub const HEADER_MALFORMED: &str = "Request header value is malformed";
 pub const RESOURCE_NOT_EXIST: &str = "Resource doesn't exist";
 pub const PROTOPACKAGE: &str = "Proto package verification or signing error";
+pub const TIMEOUT: &str = "Query timed out";
+pub const NOT_PRIMARY: &str = "Server is a read replica, writes go to the primary";
 
 pub trait SerfError<'a> {
     fn default() -> Error;
@@ -25,6 +27,8 @@ pub struct HeaderMissingError;
 pub struct HeaderMalformedError;
 pub struct ResourceNotExistError;
 pub struct ProtoPackageError;
+pub struct TimeoutError;
+pub struct NotPrimaryError;
 
 impl Error {
     pub fn new(message: &str, kind: ErrorKind) -> Self {
@@ -115,16 +119,51 @@ impl<'a> SerfError<'a> for ProtoPackageError {
     }
 }
 
+impl<'a> SerfError<'a> for TimeoutError {
+    fn default() -> Error {
+        Error::new(TIMEOUT, ErrorKind::Timeout)
+    }
+
+    fn with_message(message: &'a str) -> Error {
+        Error::new(message, ErrorKind::Timeout)
+    }
+}
+
+impl<'a> SerfError<'a> for NotPrimaryError {
+    fn default() -> Error {
+        Error::new(NOT_PRIMARY, ErrorKind::NotPrimary)
+    }
+
+    fn with_message(message: &'a str) -> Error {
+        Error::new(message, ErrorKind::NotPrimary)
+    }
+}
+
 impl ProtoPackageError {
     pub fn signing_error(message: &str) -> Error {
         ProtoPackageError::with_message(&format!("{}: {}", "SIGN", message))
     }
-    
+
     pub fn verification_error(message: &str) -> Error {
         ProtoPackageError::with_message(&format!("{}: {}", "VERIFY", message))
     }
 }
 
+/// #### Maps a sqlx error to a DatabaseError
+/// \
+/// The message of the db is used when there is one, the whole sqlx error otherwise.
+pub fn database_error(e: sqlx::Error) -> Error {
+    match e.as_database_error() {
+        Some(db_error) => DatabaseError::with_message(db_error.message()),
+        None => DatabaseError::with_message(&e.to_string()),
+    }
+}
+
+/// #### Maps any error to an UndefinedError with its message, for use with map_err
+pub fn undefined_error(err: impl ToString) -> Error {
+    UndefinedError::with_message(&err.to_string())
+}
+
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
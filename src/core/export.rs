@@ -0,0 +1,279 @@
+use futures::TryStreamExt;
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use sqlx::{
+    sqlite::SqliteRow, Column, Executor, Row, SqliteConnection, Statement, TypeInfo, ValueRef,
+};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use super::{
+    constants::queries,
+    db::{map_sqliterow_col_to_json_value, quote_identifier},
+    error::{database_error, undefined_error, ResourceNotExistError, SerfError, UndefinedError},
+    serf_proto::{Error, ExportFormat},
+};
+
+/// #### Streams a table of a db, or a SQL dump of the whole db, to __writer__
+/// \
+/// Rows are read and written one at a time, so a table of any size can be exported.
+/// Everything is read in a single read transaction, a dump is a consistent snapshot of the db.
+/// \
+/// Values are mapped by their stored type through map_sqliterow_col_to_json_value. Blobs are written as
+/// lower case hex in csv, as arrays of bytes in jsonl (like fetch) and as X'..' literals in sql.
+/// NULL is an empty csv field while an empty string or blob is quoted, so they stay apart on import.
+/// \
+/// __*@param*__ table: Option\<&str\> (None for a dump of the whole db, only with ExportFormat::Sql) \
+/// __*@returns*__ Result\<u64, Error\> (the number of exported rows)
+pub async fn export<W>(
+    conn: &mut SqliteConnection,
+    table: Option<&str>,
+    format: ExportFormat,
+    writer: &mut W,
+) -> Result<u64, Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    check_export(conn, table, format).await?;
+
+    execute(conn, "BEGIN").await?;
+    let res = match format {
+        ExportFormat::Sql => export_sql(conn, table, writer).await,
+        format => export_table(conn, table.unwrap_or_default(), format, writer).await,
+    };
+    let _ = execute(conn, "ROLLBACK").await;
+
+    let rows = res?;
+    writer.flush().await.map_err(undefined_error)?;
+
+    Ok(rows)
+}
+
+/// #### Checks that __table__ can be exported as __format__ before anything is written
+/// \
+/// A table is required for csv and jsonl, and has to exist when given.
+pub async fn check_export(
+    conn: &mut SqliteConnection,
+    table: Option<&str>,
+    format: ExportFormat,
+) -> Result<(), Error> {
+    let table = match table {
+        Some(table) => table,
+        None if format == ExportFormat::Sql => return Ok(()),
+        None => {
+            return Err(UndefinedError::with_message(
+                "A table is required for csv and jsonl exports",
+            ))
+        }
+    };
+
+    let schema = get_schema(conn, Some(table)).await?;
+    if !schema
+        .iter()
+        .any(|(object_type, ..)| object_type == "table")
+    {
+        return Err(ResourceNotExistError::with_message("Table doesn't exist"));
+    }
+
+    Ok(())
+}
+
+async fn export_table<W>(
+    conn: &mut SqliteConnection,
+    table: &str,
+    format: ExportFormat,
+    writer: &mut W,
+) -> Result<u64, Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let query = format!("SELECT * FROM {};", quote_identifier(table));
+    if format == ExportFormat::Csv {
+        // from the statement rather than the first row, so that an empty table still gets its header
+        let header = (&mut *conn)
+            .prepare(&query)
+            .await
+            .map_err(database_error)?
+            .columns()
+            .iter()
+            .map(|column| csv_field(column.name()))
+            .collect::<Vec<_>>();
+        write_line(writer, &header.join(",")).await?;
+    }
+
+    let mut rows = sqlx::query(&query).fetch(&mut *conn);
+    let mut row_count = 0;
+
+    while let Some(row) = rows.try_next().await.map_err(database_error)? {
+        let line = match format {
+            ExportFormat::Jsonl => {
+                let json_row = row
+                    .columns()
+                    .iter()
+                    .map(|column| column.name().to_string())
+                    .zip(map_row_values(&row))
+                    .collect::<JsonMap<_, _>>();
+                let mut line =
+                    serde_json::to_string(&JsonValue::Object(json_row)).map_err(undefined_error)?;
+                line.push('\n');
+                line
+            }
+            _ => {
+                let fields = map_row_values(&row)
+                    .iter()
+                    .map(|value| match value {
+                        JsonValue::Null => String::new(),
+                        JsonValue::String(value) => csv_field(value),
+                        JsonValue::Array(_) => csv_field(&hex(value)),
+                        value => value.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                format!("{}\n", fields.join(","))
+            }
+        };
+
+        writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(undefined_error)?;
+        row_count += 1;
+    }
+
+    Ok(row_count)
+}
+
+async fn export_sql<W>(
+    conn: &mut SqliteConnection,
+    table: Option<&str>,
+    writer: &mut W,
+) -> Result<u64, Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let schema = get_schema(conn, table).await?;
+
+    write_line(writer, "PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;").await?;
+    let mut row_count = 0;
+
+    for (object_type, name, sql) in &schema {
+        write_line(writer, &format!("{};", sql)).await?;
+        // virtual tables are filled through their shadow tables
+        if object_type != "table" || sql.starts_with("CREATE VIRTUAL") {
+            continue;
+        }
+
+        let query = format!("SELECT * FROM {};", quote_identifier(name));
+        let mut rows = sqlx::query(&query).fetch(&mut *conn);
+
+        while let Some(row) = rows.try_next().await.map_err(database_error)? {
+            let columns = row
+                .columns()
+                .iter()
+                .map(|column| quote_identifier(column.name()))
+                .collect::<Vec<_>>();
+            let values = map_row_values(&row)
+                .iter()
+                .map(|value| match value {
+                    JsonValue::Null => String::from("NULL"),
+                    JsonValue::String(value) => format!("'{}'", value.replace('\'', "''")),
+                    JsonValue::Array(_) => format!("X'{}'", hex(value)),
+                    value => value.to_string(),
+                })
+                .collect::<Vec<_>>();
+
+            write_line(
+                writer,
+                &format!(
+                    "INSERT INTO {} ({}) VALUES({});",
+                    quote_identifier(name),
+                    columns.join(","),
+                    values.join(",")
+                ),
+            )
+            .await?;
+            row_count += 1;
+        }
+    }
+
+    write_line(writer, "COMMIT;").await?;
+
+    Ok(row_count)
+}
+
+/// __*@returns*__ Result\<Vec\<(String, String, String)\>, Error\> (type, name and sql of the schema objects)
+async fn get_schema(
+    conn: &mut SqliteConnection,
+    table: Option<&str>,
+) -> Result<Vec<(String, String, String)>, Error> {
+    let rows = sqlx::query(queries::GET_EXPORT_SCHEMA)
+        .bind(table)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(database_error)?;
+
+    Ok(rows
+        .iter()
+        .map(|row| (row.get("type"), row.get("name"), row.get("sql")))
+        .collect())
+}
+
+// maps by the type of the stored value rather than the declared type of the column,
+// which may be anything (or nothing) for tables that aren't STRICT
+fn map_row_values(row: &SqliteRow) -> Vec<JsonValue> {
+    row.columns()
+        .iter()
+        .map(|column| {
+            let value_type = row
+                .try_get_raw(column.ordinal())
+                .ok()
+                .filter(|value| !value.is_null())
+                .map(|value| value.type_info().into_owned());
+
+            match &value_type {
+                Some(value_type) => {
+                    map_sqliterow_col_to_json_value(row, column.name(), value_type.name())
+                }
+                None => JsonValue::Null,
+            }
+        })
+        .collect()
+}
+
+// an empty string is quoted, an unquoted empty field is NULL
+fn csv_field(value: &str) -> String {
+    if value.is_empty() || value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn hex(bytes: &JsonValue) -> String {
+    let bytes = bytes
+        .as_array()
+        .map(|bytes| {
+            bytes
+                .iter()
+                .filter_map(|byte| byte.as_u64().map(|byte| byte as u8))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    base16ct::lower::encode_string(&bytes)
+}
+
+async fn write_line<W>(writer: &mut W, line: &str) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer
+        .write_all(format!("{}\n", line).as_bytes())
+        .await
+        .map_err(undefined_error)
+}
+
+async fn execute(conn: &mut SqliteConnection, query: &str) -> Result<(), Error> {
+    sqlx::query(query)
+        .execute(&mut *conn)
+        .await
+        .map(|_| ())
+        .map_err(database_error)
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use serde_json::Value as JsonValue;
+use sqlx::{Connection, Row, SqliteConnection};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use super::{
+    config::ReplicationConfig,
+    constants::queries,
+    db::{execute_query, quote_identifier, AppliedQuery},
+    error::{database_error, undefined_error, ResourceNotExistError, SerfError, UndefinedError},
+    replication::log_change,
+    serf_proto::{query_arg, Error, ImportFormat, ImportRowError, QueryArg, QueryRequest, Sub},
+};
+
+/// Default number of rows inserted per transaction
+pub const IMPORT_BATCH_SIZE: u64 = 1000;
+
+/// The outcome of an import, __imported__ only counts committed rows
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ImportReport {
+    pub imported: u64,
+    pub failed: u64,
+    /// the errors of the failed rows, in line order
+    pub errors: Vec<ImportRowError>,
+    /// more than max_errors rows failed, the rows of the batch in progress were rolled back
+    pub aborted: bool,
+}
+
+/// #### Inserts the rows read from __reader__ into __table__ in batches of __batch_size__ rows per transaction
+/// \
+/// Columns are mapped by name, from the header line of a csv or the keys of a JSON object, columns that
+/// are left out get their default value. An unquoted empty csv field and a JSON null are inserted as NULL,
+/// and csv fields or JSON strings of hex going into a BLOB column are decoded, so an export imports as is.
+/// \
+/// A row that can't be parsed or inserted is reported and skipped, until more than __max_errors__ rows
+/// failed. Every inserted row is recorded in the replication log when __replication__ is set.
+/// \
+/// __*@returns*__ Result\<ImportReport, Error\> (Err when nothing could be imported, I.E the table doesn't exist)
+pub async fn import<R>(
+    conn: &mut SqliteConnection,
+    table: &str,
+    format: ImportFormat,
+    reader: R,
+    batch_size: u64,
+    max_errors: u64,
+    replication: Option<&ReplicationConfig>,
+) -> Result<ImportReport, Error>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let column_types = sqlx::query(queries::GET_TABLE_COLUMNS)
+        .bind(table)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(|row| (row.get::<String, _>("name"), row.get::<String, _>("type")))
+        .collect::<HashMap<_, _>>();
+    if column_types.is_empty() {
+        return Err(ResourceNotExistError::with_message("Table doesn't exist"));
+    }
+
+    let mut lines = reader.lines();
+    let mut line_number = 0;
+    let mut csv_columns = None;
+    let mut report = ImportReport::default();
+    let mut batch_rows = 0;
+    let mut transaction = conn.begin().await.map_err(database_error)?;
+
+    loop {
+        let record = match next_record(&mut lines, &mut line_number, format).await? {
+            Some(record) => record,
+            None => break,
+        };
+        let (record_line, record) = record;
+
+        let row = match format {
+            ImportFormat::Csv => match &csv_columns {
+                None => {
+                    let columns = parse_csv_record(&record)
+                        .map_err(|e| UndefinedError::with_message(&format!("Header: {}", e)))?
+                        .into_iter()
+                        .map(|column| column.unwrap_or_default())
+                        .collect::<Vec<_>>();
+                    if let Some(column) = columns
+                        .iter()
+                        .find(|column| !column_types.contains_key(*column))
+                    {
+                        return Err(UndefinedError::with_message(&format!(
+                            "Column {} doesn't exist in table {}",
+                            column, table
+                        )));
+                    }
+
+                    csv_columns = Some(columns);
+                    continue;
+                }
+                Some(columns) => csv_row(columns, &record, &column_types),
+            },
+            ImportFormat::Jsonl => jsonl_row(&record, &column_types),
+        };
+
+        let res = match row {
+            Ok(row) => insert_row(&mut transaction, table, row, replication).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(_) => batch_rows += 1,
+            Err(message) => {
+                report.failed += 1;
+                report.errors.push(ImportRowError {
+                    line: record_line,
+                    message,
+                });
+
+                if report.failed > max_errors {
+                    report.aborted = true;
+                    transaction.rollback().await.map_err(database_error)?;
+                    return Ok(report);
+                }
+            }
+        }
+
+        if batch_rows >= batch_size.max(1) {
+            transaction.commit().await.map_err(database_error)?;
+            report.imported += batch_rows;
+            batch_rows = 0;
+            transaction = conn.begin().await.map_err(database_error)?;
+        }
+    }
+
+    transaction.commit().await.map_err(database_error)?;
+    report.imported += batch_rows;
+
+    Ok(report)
+}
+
+/// __*@returns*__ Result\<Option\<(u64, String)\>, Error\> (the line the record starts on and the record, None at the end)
+async fn next_record<R>(
+    lines: &mut tokio::io::Lines<R>,
+    line_number: &mut u64,
+    format: ImportFormat,
+) -> Result<Option<(u64, String)>, Error>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut record: Option<(u64, String)> = None;
+
+    while let Some(line) = lines.next_line().await.map_err(undefined_error)? {
+        *line_number += 1;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+
+        record = match record {
+            // a quoted csv field spanning lines
+            Some((record_line, record)) => Some((record_line, format!("{}\n{}", record, line))),
+            None if line.trim().is_empty() => continue,
+            None => Some((*line_number, line.to_string())),
+        };
+
+        // quotes are always paired in a complete csv record, escaped ones included
+        let is_complete = match &record {
+            Some((_, record)) => {
+                format != ImportFormat::Csv || record.matches('"').count() % 2 == 0
+            }
+            None => false,
+        };
+        if is_complete {
+            break;
+        }
+    }
+
+    Ok(record)
+}
+
+type ImportRow = Vec<(String, Option<query_arg::Value>)>;
+
+fn csv_row(
+    columns: &[String],
+    record: &str,
+    column_types: &HashMap<String, String>,
+) -> Result<ImportRow, String> {
+    let fields = parse_csv_record(record)?;
+    if fields.len() != columns.len() {
+        return Err(format!(
+            "Expected {} fields but got {}",
+            columns.len(),
+            fields.len()
+        ));
+    }
+
+    Ok(columns
+        .iter()
+        .zip(fields)
+        .map(|(column, field)| {
+            let value = field.map(|field| match decode_blob(column_types, column, &field) {
+                Some(blob) => query_arg::Value::Blob(blob),
+                None => query_arg::Value::String(field),
+            });
+
+            (column.clone(), value)
+        })
+        .collect())
+}
+
+fn jsonl_row(record: &str, column_types: &HashMap<String, String>) -> Result<ImportRow, String> {
+    let object = match serde_json::from_str::<JsonValue>(record) {
+        Ok(JsonValue::Object(object)) => object,
+        Ok(_) => return Err(String::from("Line is not a JSON object")),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    object
+        .into_iter()
+        .map(|(column, value)| {
+            if !column_types.contains_key(&column) {
+                return Err(format!("Column {} doesn't exist", column));
+            }
+
+            let value = match value {
+                JsonValue::Null => None,
+                JsonValue::Bool(value) => Some(query_arg::Value::Int(value as i64)),
+                JsonValue::Number(number) => Some(match number.as_i64() {
+                    Some(value) => query_arg::Value::Int(value),
+                    None => query_arg::Value::Float(number.as_f64().unwrap_or_default()),
+                }),
+                JsonValue::String(value) => {
+                    Some(match decode_blob(column_types, &column, &value) {
+                        Some(blob) => query_arg::Value::Blob(blob),
+                        None => query_arg::Value::String(value),
+                    })
+                }
+                // blobs are exported as arrays of bytes
+                JsonValue::Array(values)
+                    if values
+                        .iter()
+                        .all(|value| value.as_u64().is_some_and(|byte| byte <= 255)) =>
+                {
+                    Some(query_arg::Value::Blob(
+                        values
+                            .iter()
+                            .filter_map(|value| value.as_u64().map(|byte| byte as u8))
+                            .collect(),
+                    ))
+                }
+                value => Some(query_arg::Value::String(value.to_string())),
+            };
+
+            Ok((column, value))
+        })
+        .collect()
+}
+
+// NULL values are written into the statement, query args are always bound
+async fn insert_row(
+    conn: &mut SqliteConnection,
+    table: &str,
+    row: ImportRow,
+    replication: Option<&ReplicationConfig>,
+) -> Result<(), String> {
+    if row.is_empty() {
+        return Err(String::from("Row has no columns"));
+    }
+
+    let columns = row
+        .iter()
+        .map(|(column, _)| quote_identifier(column))
+        .collect::<Vec<_>>();
+    let placeholders = row
+        .iter()
+        .map(|(_, value)| if value.is_some() { "?" } else { "NULL" })
+        .collect::<Vec<_>>();
+    let query_request = QueryRequest {
+        query: format!(
+            "INSERT INTO {} ({}) VALUES({});",
+            quote_identifier(table),
+            columns.join(","),
+            placeholders.join(",")
+        ),
+        parts: row
+            .into_iter()
+            .filter_map(|(_, value)| value.map(QueryArg::new))
+            .collect(),
+        timeout: 0,
+    };
+
+    execute_query(
+        AppliedQuery::new(&query_request.query).with_args(&query_request.parts),
+        &mut *conn,
+    )
+    .await
+    .map_err(|e| database_error(e).message)?;
+
+    if let Some(replication) = replication {
+        log_change(
+            conn,
+            Sub::Mutate,
+            prost::Message::encode_to_vec(&query_request),
+            replication.keep_changes,
+        )
+        .await
+        .map_err(|e| database_error(e).message)?;
+    }
+
+    Ok(())
+}
+
+/// #### Splits a csv record into its fields
+/// \
+/// __*@returns*__ Result\<Vec\<Option\<String\>\>, String\> (None for an unquoted empty field)
+fn parse_csv_record(record: &str) -> Result<Vec<Option<String>>, String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut chars = record.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() && !quoted => {
+                quoted = true;
+                in_quotes = true;
+            }
+            (',', false) => {
+                fields.push(
+                    Some(std::mem::take(&mut field)).filter(|field| quoted || !field.is_empty()),
+                );
+                quoted = false;
+            }
+            ('"', false) => return Err(String::from("Unexpected quote in an unquoted field")),
+            (c, _) if quoted && !in_quotes => {
+                return Err(format!("Unexpected {} after a quoted field", c))
+            }
+            (c, _) => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(String::from("Unterminated quoted field"));
+    }
+    fields.push(Some(field).filter(|field| quoted || !field.is_empty()));
+
+    Ok(fields)
+}
+
+fn decode_blob(
+    column_types: &HashMap<String, String>,
+    column: &str,
+    value: &str,
+) -> Option<Vec<u8>> {
+    let is_blob = column_types
+        .get(column)
+        .is_some_and(|column_type| column_type.eq_ignore_ascii_case("BLOB"));
+
+    if is_blob {
+        base16ct::mixed::decode_vec(value).ok()
+    } else {
+        None
+    }
+}
@@ -1,8 +1,16 @@
+pub mod audit;
+pub mod backup;
+pub mod config;
+pub mod constants;
 pub mod db;
+pub mod error;
+pub mod export;
+pub mod import;
+pub mod replication;
+pub mod role;
 pub mod state;
 pub mod util;
-pub mod constants;
-pub mod error;
+pub mod wal_archive;
 pub mod serf_proto {
     include!(concat!(env!("OUT_DIR"), "/serf_proto.rs"));
 }
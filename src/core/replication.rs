@@ -0,0 +1,531 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use prost::Message;
+use sqlx::{
+    sqlite::SqliteConnectOptions, ConnectOptions, Connection, Row, SqliteConnection, SqlitePool,
+};
+
+use super::{
+    backup::{check_integrity, vacuum_into, with_suffix},
+    constants::queries,
+    db::{execute_query, fetch_query, AppliedQuery},
+    error::{database_error, undefined_error, DatabaseError, SerfError, UndefinedError},
+    serf_proto::{
+        query_arg, Error, MigrationRequest, QueryArg, QueryRequest, ReplicationChange, Sub,
+    },
+};
+
+/// Max number of changes in a single replication response
+pub const REPLICATION_MAX_CHANGES: u64 = 1000;
+
+// functions that give a different result on every call or connection
+const NON_DETERMINISTIC_FUNCTIONS: [&str; 5] = [
+    "random",
+    "randomblob",
+    "changes",
+    "total_changes",
+    "last_insert_rowid",
+];
+const CURRENT_TIME_KEYWORDS: [&str; 3] = ["current_timestamp", "current_date", "current_time"];
+const DATE_TIME_FUNCTIONS: [&str; 7] = [
+    "date",
+    "time",
+    "datetime",
+    "julianday",
+    "unixepoch",
+    "strftime",
+    "timediff",
+];
+const ROWID_ALIASES: [&str; 3] = ["rowid", "_rowid_", "oid"];
+
+static SNAPSHOT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The changes after a follower's last applied change, as served by the primary
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReplicationChanges {
+    pub changes: Vec<ReplicationChange>,
+    /// id of the latest change on the primary
+    pub last_id: u64,
+    /// the changes right after the follower's last applied change were pruned, or the follower
+    /// is ahead of the primary (I.E the primary was restored), either way it needs a snapshot
+    pub resync: bool,
+}
+
+/// #### Creates the replication log of a db when missing
+/// \
+/// The ids of a new log start at the current time in microseconds, so that they are past the ids of any
+/// earlier log of a db by the same name (I.E one that was dropped and created again, or cloned and given
+/// replication). Followers of the earlier log are then asked to resync, see get_changes.
+pub async fn create_log(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    execute_query(
+        AppliedQuery::new(queries::CREATE_REPLICATION_LOG_TABLE),
+        &mut *conn,
+    )
+    .await?;
+    execute_query(
+        AppliedQuery::new(queries::SEED_REPLICATION_LAST_ID).with_args(&[QueryArg::new(
+            query_arg::Value::Int(Utc::now().timestamp_micros()),
+        )]),
+        &mut *conn,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// #### Checks that a mutation or migration gives the same result when a follower replays it
+/// \
+/// Changes are replicated as the statements themselves, so a statement that reads the current time,
+/// a random value or connection state, or refers to the implicit rowid (which isn't guaranteed to
+/// survive the VACUUM INTO of a snapshot), would leave the followers with different data.
+/// Those values have to be passed as args instead. The same goes for column defaults, so migrations
+/// adding a default like CURRENT_TIMESTAMP are rejected as well.
+pub fn check_deterministic(query: &str, args: &[QueryArg]) -> Result<(), Error> {
+    let tokens = sql_tokens(query);
+    let mut uses_now = args.iter().any(|arg| {
+        matches!(&arg.value, Some(query_arg::Value::String(value)) if value.eq_ignore_ascii_case("now"))
+    });
+    let mut date_time_function = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let called = tokens.get(i + 1) == Some(&SqlToken::Punct('('));
+        match token {
+            SqlToken::Ident(ident)
+                if called && NON_DETERMINISTIC_FUNCTIONS.contains(&ident.as_str()) =>
+            {
+                return Err(non_deterministic_error(&format!("{}()", ident)));
+            }
+            SqlToken::Ident(ident) if CURRENT_TIME_KEYWORDS.contains(&ident.as_str()) => {
+                return Err(non_deterministic_error(&ident.to_uppercase()));
+            }
+            SqlToken::Ident(ident) if ROWID_ALIASES.contains(&ident.as_str()) => {
+                return Err(non_deterministic_error(ident));
+            }
+            SqlToken::Ident(ident) if called && DATE_TIME_FUNCTIONS.contains(&ident.as_str()) => {
+                // without a time value the functions use the current time, strftime takes a format first
+                let time_value_args =
+                    call_args(&tokens[i + 1..]).saturating_sub(usize::from(ident == "strftime"));
+                if time_value_args == 0 {
+                    return Err(non_deterministic_error(&format!("{}()", ident)));
+                }
+                date_time_function.get_or_insert(ident);
+            }
+            SqlToken::Str(value) if value.eq_ignore_ascii_case("now") => uses_now = true,
+            _ => {}
+        }
+    }
+
+    match date_time_function {
+        Some(ident) if uses_now => Err(non_deterministic_error(&format!("{}('now')", ident))),
+        _ => Ok(()),
+    }
+}
+
+/// #### Makes the followers of a db resync once __restore_file__ replaces __db_file__
+/// \
+/// A restore takes the db back to an older state without going through the replication log, so the
+/// followers can't tell the restored changes apart from the ones they applied before. The log of the
+/// restored copy is emptied and its last id moved past the ids of both logs, so that every follower is
+/// asked to resync from a snapshot (see get_changes). Nothing is done when neither has a replication log.
+pub async fn resync_followers(db_file: &Path, restore_file: &Path) -> Result<(), Error> {
+    let replaced_last_id = if db_file.exists() {
+        let mut conn = SqliteConnectOptions::new()
+            .filename(db_file)
+            .read_only(true)
+            .connect()
+            .await
+            .map_err(undefined_error)?;
+        let replaced_last_id = applied_id(&mut conn).await;
+        let _ = conn.close().await;
+
+        replaced_last_id?
+    } else {
+        None
+    };
+
+    let mut conn = SqliteConnectOptions::new()
+        .filename(restore_file)
+        .connect()
+        .await
+        .map_err(undefined_error)?;
+    let res = async {
+        let restored_last_id = applied_id(&mut conn).await?;
+        if replaced_last_id.is_none() && restored_last_id.is_none() {
+            return Ok(());
+        }
+
+        let last_id = replaced_last_id
+            .max(restored_last_id)
+            .unwrap_or_default()
+            .max(Utc::now().timestamp_micros() as u64)
+            + 1;
+        let mut transaction = conn.begin().await.map_err(database_error)?;
+        create_log(&mut transaction).await.map_err(database_error)?;
+        execute_query(
+            AppliedQuery::new(queries::CLEAR_REPLICATION_LOG),
+            &mut *transaction,
+        )
+        .await
+        .map_err(database_error)?;
+        execute_query(
+            AppliedQuery::new(queries::SET_REPLICATION_LAST_ID)
+                .with_args(&[QueryArg::new(query_arg::Value::Int(last_id as i64))]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(database_error)?;
+
+        transaction.commit().await.map_err(database_error)
+    }
+    .await;
+    let _ = conn.close().await;
+
+    res
+}
+
+/// #### Records a mutation or migration in the replication log of the db
+/// \
+/// Must run in the transaction of the change itself, so that a change is logged if and only if it
+/// was committed. Changes older than the latest __keep_changes__ are pruned.
+/// \
+/// __*@param*__ sub: Sub (Sub::Mutate or Sub::Migrate) \
+/// __*@param*__ dat: Vec\<u8\> (the encoded QueryRequest or MigrationRequest)
+pub async fn log_change(
+    conn: &mut SqliteConnection,
+    sub: Sub,
+    dat: Vec<u8>,
+    keep_changes: u64,
+) -> Result<(), sqlx::Error> {
+    let res = execute_query(
+        AppliedQuery::new(queries::INSERT_REPLICATION_CHANGE).with_args(&[
+            QueryArg::new(query_arg::Value::Int(sub as i64)),
+            QueryArg::new(query_arg::Value::Blob(dat)),
+        ]),
+        &mut *conn,
+    )
+    .await?;
+
+    let prune_to_id = res.last_insert_rowid() - keep_changes.max(1) as i64;
+    if prune_to_id > 0 {
+        execute_query(
+            AppliedQuery::new(queries::PRUNE_REPLICATION_LOG)
+                .with_args(&[QueryArg::new(query_arg::Value::Int(prune_to_id))]),
+            &mut *conn,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// #### Reads the changes logged after __after_id__, at most REPLICATION_MAX_CHANGES of them
+pub async fn get_changes(db: &SqlitePool, after_id: u64) -> Result<ReplicationChanges, Error> {
+    let last_id = last_id(db).await.map_err(database_error)?;
+    let changes = fetch_query(
+        AppliedQuery::new(queries::GET_REPLICATION_CHANGES).with_args(&[
+            QueryArg::new(query_arg::Value::Int(after_id as i64)),
+            QueryArg::new(query_arg::Value::Int(REPLICATION_MAX_CHANGES as i64)),
+        ]),
+        db,
+    )
+    .await
+    .map_err(database_error)?
+    .iter()
+    .map(|row| {
+        let mut change = ReplicationChange {
+            id: row.get::<i64, _>("id") as u64,
+            sub: 0,
+            dat: row.get("dat"),
+        };
+        change.set_sub(Sub::try_from(row.get::<i32, _>("sub")).unwrap_or(Sub::Data));
+
+        change
+    })
+    .collect::<Vec<_>>();
+
+    // ids have no gaps other than the pruned ones, the change right after __after_id__ must be there
+    let resync = match changes.first() {
+        Some(change) => change.id != after_id + 1,
+        None => last_id != after_id,
+    };
+
+    Ok(ReplicationChanges {
+        changes: if resync { vec![] } else { changes },
+        last_id,
+        resync,
+    })
+}
+
+/// #### Replays changes fetched from the primary in a single transaction
+/// \
+/// The changes are logged in the replication log of the follower with their primary ids,
+/// so the follower's last applied change is always the latest change in its log.
+/// \
+/// __*@returns*__ Result\<Option\<u64\>, Error\> (the id of the last applied change, None when there were no changes)
+pub async fn apply_changes(
+    conn: &mut SqliteConnection,
+    changes: &[ReplicationChange],
+) -> Result<Option<u64>, Error> {
+    let mut transaction = conn.begin().await.map_err(database_error)?;
+
+    for change in changes {
+        match change.sub() {
+            Sub::Mutate => {
+                let query_request =
+                    QueryRequest::decode(change.dat.as_slice()).map_err(undefined_error)?;
+                execute_query(
+                    AppliedQuery::new(&query_request.query).with_args(&query_request.parts),
+                    &mut *transaction,
+                )
+                .await
+                .map_err(database_error)?;
+            }
+            Sub::Migrate => {
+                let migration =
+                    MigrationRequest::decode(change.dat.as_slice()).map_err(undefined_error)?;
+                execute_query(
+                    AppliedQuery::new(queries::CREATE_MIGRATIONS_TABLE),
+                    &mut *transaction,
+                )
+                .await
+                .map_err(database_error)?;
+                execute_query(
+                    AppliedQuery::new(queries::INSERT_MIGRATION).with_args(&[
+                        QueryArg::new(query_arg::Value::String(migration.name)),
+                        QueryArg::new(query_arg::Value::String(migration.query.clone())),
+                    ]),
+                    &mut *transaction,
+                )
+                .await
+                .map_err(database_error)?;
+                execute_query(AppliedQuery::new(&migration.query), &mut *transaction)
+                    .await
+                    .map_err(database_error)?;
+            }
+            sub => {
+                return Err(UndefinedError::with_message(&format!(
+                    "Change {} has an unknown subject {}",
+                    change.id,
+                    sub.as_str_name()
+                )))
+            }
+        }
+
+        execute_query(
+            AppliedQuery::new(queries::INSERT_REPLICATED_CHANGE).with_args(&[
+                QueryArg::new(query_arg::Value::Int(change.id as i64)),
+                QueryArg::new(query_arg::Value::Int(change.sub as i64)),
+                QueryArg::new(query_arg::Value::Blob(change.dat.clone())),
+            ]),
+            &mut *transaction,
+        )
+        .await
+        .map_err(database_error)?;
+    }
+
+    transaction.commit().await.map_err(database_error)?;
+
+    Ok(changes.last().map(|change| change.id))
+}
+
+/// #### The id of the latest change in the replication log of a db
+/// \
+/// __*@returns*__ Result\<Option\<u64\>, Error\> (None when the db has no replication log, I.E it isn't a replica)
+pub async fn applied_id(conn: &mut SqliteConnection) -> Result<Option<u64>, Error> {
+    let has_log = sqlx::query_scalar::<_, i64>(queries::HAS_REPLICATION_LOG_TABLE)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(database_error)?;
+
+    if has_log == 0 {
+        return Ok(None);
+    }
+
+    last_id(&mut *conn).await.map(Some).map_err(database_error)
+}
+
+/// #### Takes a gzipped copy of a replicated db, log included, to start a follower from
+pub async fn snapshot(db_file: &Path) -> Result<Vec<u8>, Error> {
+    let snapshot_file = with_suffix(
+        db_file,
+        &format!(
+            "snapshot{}",
+            SNAPSHOT_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+    vacuum_into(db_file, &snapshot_file).await?;
+
+    let snapshot_file_c = snapshot_file.clone();
+    let res = tokio::task::spawn_blocking(move || {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        io::copy(&mut File::open(&snapshot_file_c)?, &mut encoder)?;
+        encoder.finish()
+    })
+    .await
+    .map_err(undefined_error);
+    let _ = fs::remove_file(&snapshot_file);
+
+    res?.map_err(undefined_error)
+}
+
+/// #### Unpacks a snapshot from the primary next to __db_file__ and checks it
+/// \
+/// __*@returns*__ Result\<(PathBuf, u64), Error\> (the unpacked file, to be swapped in with the db closed, and the id of its latest change)
+pub async fn unpack_snapshot(snapshot: Vec<u8>, db_file: &Path) -> Result<(PathBuf, u64), Error> {
+    let restore_file = with_suffix(db_file, "restore");
+    let restore_file_c = restore_file.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut writer = BufWriter::new(File::create(&restore_file_c)?);
+        io::copy(&mut GzDecoder::new(snapshot.as_slice()), &mut writer)?;
+        writer.into_inner()?.sync_all()
+    })
+    .await
+    .map_err(undefined_error)?
+    .map_err(undefined_error)?;
+
+    let res = async {
+        check_integrity(&restore_file).await?;
+
+        let mut conn = SqliteConnectOptions::new()
+            .filename(&restore_file)
+            .read_only(true)
+            .connect()
+            .await
+            .map_err(undefined_error)?;
+        let applied_id = applied_id(&mut conn).await;
+        let _ = conn.close().await;
+
+        applied_id?.ok_or_else(|| {
+            UndefinedError::with_message("Snapshot of the primary has no replication log")
+        })
+    }
+    .await;
+
+    match res {
+        Ok(applied_id) => Ok((restore_file, applied_id)),
+        Err(e) => {
+            let _ = fs::remove_file(&restore_file);
+            Err(e)
+        }
+    }
+}
+
+async fn last_id<'a, T>(db: T) -> Result<u64, sqlx::Error>
+where
+    T: sqlx::Executor<'a, Database = sqlx::Sqlite>,
+{
+    sqlx::query_scalar::<_, i64>(queries::GET_REPLICATION_LAST_ID)
+        .fetch_one(db)
+        .await
+        .map(|last_id| last_id as u64)
+}
+
+#[derive(Debug, PartialEq)]
+enum SqlToken {
+    Ident(String),
+    Str(String),
+    Punct(char),
+}
+
+/// The identifiers (lowercased), string literals and punctuation of a statement, comments and
+/// quoted identifiers are left out
+fn sql_tokens(query: &str) -> Vec<SqlToken> {
+    let mut tokens = vec![];
+    let mut chars = query.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let mut value = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\'' if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            value.push(c);
+                        }
+                        '\'' => break,
+                        _ => value.push(c),
+                    }
+                }
+                tokens.push(SqlToken::Str(value));
+            }
+            '"' | '`' | '[' => {
+                let end = if c == '[' { ']' } else { c };
+                for c in chars.by_ref() {
+                    if c == end {
+                        break;
+                    }
+                }
+                tokens.push(SqlToken::Punct('"'));
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = c.to_ascii_lowercase().to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '$') {
+                        break;
+                    }
+                    ident.push(c.to_ascii_lowercase());
+                    chars.next();
+                }
+                tokens.push(SqlToken::Ident(ident));
+            }
+            c if c.is_whitespace() => {}
+            c => tokens.push(SqlToken::Punct(c)),
+        }
+    }
+
+    tokens
+}
+
+/// The number of args of the call whose opening parenthesis __tokens__ start with
+fn call_args(tokens: &[SqlToken]) -> usize {
+    let mut depth = 0;
+    let mut args = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            SqlToken::Punct('(') => depth += 1,
+            SqlToken::Punct(')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return if i == 1 { 0 } else { args + 1 };
+                }
+            }
+            SqlToken::Punct(',') if depth == 1 => args += 1,
+            _ => {}
+        }
+    }
+
+    args + 1
+}
+
+fn non_deterministic_error(expression: &str) -> Error {
+    DatabaseError::with_message(&format!(
+        "{} gives a different result on the followers of a replicated db, pass its value as an arg instead",
+        expression
+    ))
+}
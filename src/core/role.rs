@@ -0,0 +1,74 @@
+use super::{
+    error::{SerfError, UndefinedError},
+    serf_proto::Error,
+};
+
+/// Fetches, exports and replication of a db
+pub const FETCH: u8 = 1;
+/// Mutations and imports
+pub const MUTATE: u8 = 2;
+pub const MIGRATE: u8 = 4;
+/// The /admin endpoints, backup and restore of the db when the role is assigned per db
+pub const ADMIN: u8 = 8;
+pub const ALL: u8 = FETCH | MUTATE | MIGRATE | ADMIN;
+
+/// Names of the permissions, as given to serf-cli create role
+pub const PERMISSIONS: [(&str, u8); 4] = [
+    ("fetch", FETCH),
+    ("mutate", MUTATE),
+    ("migrate", MIGRATE),
+    ("admin", ADMIN),
+];
+
+pub const READER: &str = "reader";
+pub const WRITER: &str = "writer";
+pub const MIGRATOR: &str = "migrator";
+pub const ADMIN_ROLE: &str = "admin";
+
+/// The roles created with the users db, they can't be deleted
+pub const BUILTIN_ROLES: [(&str, u8); 4] = [
+    (READER, FETCH),
+    (WRITER, FETCH | MUTATE),
+    (MIGRATOR, FETCH | MIGRATE),
+    (ADMIN_ROLE, ALL),
+];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Role {
+    pub name: String,
+    pub permissions: u8,
+    pub is_builtin: bool,
+}
+
+/// #### Permissions from their names, I.E ["fetch", "migrate"]
+/// \
+/// __*@returns*__ Result\<u8, Error\> (the permission bits)
+pub fn permissions_from_names<S: AsRef<str>>(names: &[S]) -> Result<u8, Error> {
+    names.iter().try_fold(0, |permissions, name| {
+        match PERMISSIONS
+            .iter()
+            .find(|(permission_name, _)| permission_name.eq_ignore_ascii_case(name.as_ref()))
+        {
+            Some((_, permission)) => Ok(permissions | permission),
+            None => Err(UndefinedError::with_message(&format!(
+                "Unknown permission {}, must be one of fetch, mutate, migrate, admin",
+                name.as_ref()
+            ))),
+        }
+    })
+}
+
+/// #### The names of the permission bits, I.E fetch+migrate
+pub fn permissions_display(permissions: u8) -> String {
+    let names = PERMISSIONS
+        .iter()
+        .filter(|(_, permission)| permissions & permission != 0)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+
+    if names.is_empty() {
+        String::from("none")
+    } else {
+        names.join("+")
+    }
+}
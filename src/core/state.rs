@@ -1,11 +1,25 @@
-use std::sync::Arc;
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use papaya::{Guard, HashMap};
-use serde::Deserialize;
+use papaya::{Guard, HashMap, HashSet, LocalGuard};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::SqlitePool;
 
-pub type DatabaseConnections = Arc<HashMap<Arc<str>, SqlitePool>>;
+use super::config::ReplicationConfig;
+
+// millis
+const DB_CLOSE_POLL_INTERVAL: u64 = 50;
+
+pub type DatabaseConnections = Arc<HashMap<Arc<str>, DatabaseConnection>>;
 pub type Users = Arc<HashMap<Arc<str>, User>>;
+pub type BackupStatuses = Arc<HashMap<Arc<str>, BackupStatus>>;
+pub type ReplicationStatuses = Arc<HashMap<Arc<str>, ReplicationStatus>>;
 
 #[derive(Debug)]
 pub struct AppState {
@@ -14,11 +28,158 @@ pub struct AppState {
     pub db_max_connections: u32,
     pub db_max_idle_time: u64,
     pub db_max_lifetime: u64,
+    pub db_max_open: usize,
+    pub db_pool_max_idle_time: u64,
+    /// milliseconds, 0 = no timeout
+    pub query_timeout: u64,
+    /// 0 = no limit
+    pub max_rows: u64,
+    /// 0 = no limit
+    pub max_response_bytes: u64,
+    /// dbs a creator may own, 0 = no limit
+    pub max_databases: u64,
     pub db_path: String,
+    pub backup_path: String,
+    /// WAL archives of the dbs that have wal_archive configured, I.E \<root_dir\>/archive
+    pub archive_path: String,
+    pub user_db_path: String,
+    /// dbs being restored from a backup, no pools are opened for them until the restore is done
+    pub restoring_dbs: Arc<HashSet<Arc<str>>>,
+    /// status of the scheduled backups per db, served by /health/backups
+    pub backup_statuses: BackupStatuses,
+    /// url of the primary when the server runs as a read replica, writes are rejected then
+    pub primary: Option<String>,
+    /// status of the replicated dbs per db when the server runs as a read replica, served by /health/replication
+    pub replication_statuses: ReplicationStatuses,
+    /// the audit log the mutations, migrations and admin changes are recorded in, nothing is recorded when unset
+    pub audit_db: Option<SqlitePool>,
+}
+
+/// Times are RFC 3339, last_* are unset until the first scheduled backup of the db has run.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize)]
+pub struct BackupStatus {
+    pub last_run_at: Option<String>,
+    /// backup file name of the last successful run
+    pub last_file: Option<String>,
+    /// error of the last run, unset when it succeeded
+    pub last_error: Option<String>,
+    pub next_run_at: Option<String>,
+}
+
+/// Times are RFC 3339, lag is the number of changes the follower was behind the primary after the last sync.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize)]
+pub struct ReplicationStatus {
+    /// id of the last change applied from the primary
+    pub applied_id: u64,
+    /// id of the latest change on the primary as of the last sync
+    pub primary_last_id: u64,
+    pub lag: u64,
+    pub last_synced_at: Option<String>,
+    /// error of the last sync, unset when it succeeded
+    pub last_error: Option<String>,
+}
+
+/// The pools of a single database. All mutations and migrations go through the
+/// single connection `writer` pool while fetches are served by the read-only `reader` pool.
+/// `last_accessed` (millis) is what the eviction of idle / least recently used pools is based on.
+/// `replication` is set when the db had replication configured as the pools were opened.
+#[derive(Debug, Clone)]
+pub struct DatabaseConnection {
+    pub reader: SqlitePool,
+    pub writer: SqlitePool,
+    pub replication: Option<ReplicationConfig>,
+    last_accessed: Arc<AtomicI64>,
+    /// requests currently holding the pools, see DatabaseConnection::lease
+    leases: Arc<AtomicUsize>,
+    closing: Arc<AtomicBool>,
+}
+
+/// #### The pools of a db held by a request
+/// \
+/// The pools aren't closed while a lease on them exists, dropping the lease releases it.
+#[derive(Debug)]
+pub struct DatabaseConnectionLease(DatabaseConnection);
+
+impl Deref for DatabaseConnectionLease {
+    type Target = DatabaseConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for DatabaseConnectionLease {
+    fn drop(&mut self) {
+        self.0.leases.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl DatabaseConnection {
+    pub fn new(reader: SqlitePool, writer: SqlitePool) -> Self {
+        DatabaseConnection {
+            reader,
+            writer,
+            replication: None,
+            last_accessed: Arc::new(AtomicI64::new(chrono::Utc::now().timestamp_millis())),
+            leases: Arc::new(AtomicUsize::new(0)),
+            closing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_replication(self, replication: Option<ReplicationConfig>) -> Self {
+        DatabaseConnection {
+            replication,
+            ..self
+        }
+    }
+
+    pub fn touch(&self) {
+        self.last_accessed
+            .store(chrono::Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub fn last_accessed(&self) -> i64 {
+        self.last_accessed.load(Ordering::Relaxed)
+    }
+
+    /// #### Holds the pools for a request so that they aren't closed under it
+    /// \
+    /// __*@returns*__ Option\<DatabaseConnectionLease\> (None when the pools are being closed, I.E they
+    /// were evicted after being looked up, new pools have to be opened then)
+    pub fn lease(&self) -> Option<DatabaseConnectionLease> {
+        // the lease is taken before checking closing so that close sees it in the count
+        self.leases.fetch_add(1, Ordering::SeqCst);
+        if self.closing.load(Ordering::SeqCst) {
+            self.leases.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        Some(DatabaseConnectionLease(self.clone()))
+    }
+
+    pub fn is_in_use(&self) -> bool {
+        self.leases.load(Ordering::SeqCst) > 0
+            || [&self.reader, &self.writer]
+                .iter()
+                .any(|pool| pool.size() as usize > pool.num_idle())
+    }
+
+    /// #### Closes the pools once no request holds a lease on them
+    /// \
+    /// No new leases are given out once this is called.
+    pub async fn close(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+        while self.leases.load(Ordering::SeqCst) > 0 {
+            actix_web::rt::time::sleep(Duration::from_millis(DB_CLOSE_POLL_INTERVAL)).await;
+        }
+
+        self.writer.close().await;
+        self.reader.close().await;
+    }
 }
 
 impl AppState {
-    pub fn users_guard(&self) -> impl Guard + '_ {
+    pub fn users_guard(&self) -> LocalGuard<'_> {
         self.users.guard()
     }
 
@@ -38,19 +199,71 @@ impl AppState {
         &self,
         db_name: &str,
         guard: &'guard impl Guard,
-    ) -> Option<&'guard SqlitePool> {
-        Arc::clone(&self.db_connections).get(db_name, guard)
+    ) -> Option<&'guard DatabaseConnection> {
+        Arc::clone(&self.db_connections)
+            .get(db_name, guard)
+            .inspect(|db_connection| db_connection.touch())
     }
 
-    pub fn insert_db_connection<'guard>(
+    pub fn remove_db_connection(
         &self,
         db_name: &str,
-        db_connection: SqlitePool,
-        guard: &'guard impl Guard,
+        guard: &impl Guard,
+    ) -> Option<DatabaseConnection> {
+        Arc::clone(&self.db_connections)
+            .remove(db_name, guard)
+            .cloned()
+    }
+
+    pub fn is_restoring_db(&self, db_name: &str) -> bool {
+        self.restoring_dbs.pin().contains(db_name)
+    }
+
+    pub fn insert_db_connection(
+        &self,
+        db_name: &str,
+        db_connection: DatabaseConnection,
+        guard: &impl Guard,
     ) {
-        let db_connections: Arc<HashMap<Arc<str>, SqlitePool>> = Arc::clone(&self.db_connections);
+        let db_connections: Arc<HashMap<Arc<str>, DatabaseConnection>> =
+            Arc::clone(&self.db_connections);
         db_connections.insert(Arc::from(db_name), db_connection, guard);
     }
+
+    /// Removes every pool that has been idle for longer than `db_pool_max_idle_time`,
+    /// then the least recently used pools until at most `db_max_open` remain.
+    /// Pools that are leased or have connections checked out are never evicted.
+    ///
+    /// The evicted pools are returned so that the caller can close them.
+    pub fn evict_db_connections(
+        &self,
+        now_millis: i64,
+        guard: &impl Guard,
+    ) -> Vec<(Arc<str>, DatabaseConnection)> {
+        let db_connections = Arc::clone(&self.db_connections);
+        let max_idle_millis = (self.db_pool_max_idle_time * 1000) as i64;
+        let mut evicted = vec![];
+        let mut candidates: Vec<(Arc<str>, i64)> = db_connections
+            .iter(guard)
+            .filter(|(_, db_connection)| !db_connection.is_in_use())
+            .map(|(db_name, db_connection)| (db_name.clone(), db_connection.last_accessed()))
+            .collect();
+        candidates.sort_by_key(|(_, last_accessed)| *last_accessed);
+
+        let mut open = db_connections.len();
+        for (db_name, last_accessed) in candidates {
+            if open <= self.db_max_open && now_millis - last_accessed <= max_idle_millis {
+                break;
+            }
+
+            if let Some(db_connection) = db_connections.remove(&db_name, guard) {
+                evicted.push((db_name, db_connection.clone()));
+                open -= 1;
+            }
+        }
+
+        evicted
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
@@ -58,19 +271,63 @@ pub struct User {
     pub username: String,
     pub username_hash: String,
     pub username_password_hash: String,
+    /// milliseconds, overrides the server query timeout when set
+    pub query_timeout: Option<u64>,
+    /// overrides the server max rows when set, 0 = unlimited
+    pub max_rows: Option<u64>,
+    /// overrides the server max response bytes when set, 0 = unlimited
+    pub max_response_bytes: Option<u64>,
+    /// can create dbs through the protocol, getting the writer role on them
+    #[serde(default, deserialize_with = "deserialize_bool_from_int")]
+    pub is_creator: bool,
+    /// overrides the server max dbs a creator may own when set
+    pub max_databases: Option<u64>,
+    /// permissions of the global role, applying to every db (see core::role)
+    #[serde(default)]
+    pub permissions: u8,
+    /// permissions of the role per db hash
+    #[serde(skip)]
+    pub db_permissions: HashMap<Arc<str>, u8>,
+    /// API keys by name, requests can be signed with them instead of the username_password_hash
     #[serde(skip)]
-    pub db_access_rights: HashMap<Arc<str>, u8>,
+    pub api_keys: HashMap<Arc<str>, ApiKey>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+pub struct ApiKey {
+    /// the secret requests made with the key are signed with (sha256 hex of the key), it can sign requests
+    /// the same as the key itself
+    pub secret: String,
+    /// unix timestamp (seconds), the key can't be used after it when set
+    pub expires_at: Option<u64>,
+    /// bits of role::FETCH, MUTATE, MIGRATE and ADMIN the key is limited to, all permissions of the user when not set
+    pub scope: Option<u8>,
 }
 
 impl User {
     pub fn guard(&self) -> impl Guard + '_ {
-        self.db_access_rights.guard()
+        self.db_permissions.guard()
     }
 
-    pub fn get_access_right(&self, db_name: &str) -> u8 {
-        match self.db_access_rights.pin().get(db_name) {
-            Some(ar) => *ar,
-            None => 0,
+    /// #### The permissions of the global role together with the role of the db
+    /// \
+    /// __*@returns*__ u8 (bits of role::FETCH, MUTATE, MIGRATE and ADMIN)
+    pub fn get_permissions(&self, db_name: &str) -> u8 {
+        match self.db_permissions.pin().get(db_name) {
+            Some(permissions) => self.permissions | *permissions,
+            None => self.permissions,
         }
     }
+
+    pub fn has_permission(&self, db_name: &str, permission: u8) -> bool {
+        self.get_permissions(db_name) & permission == permission
+    }
+}
+
+// SQLite has no bool type, the users db stores flags as 0 / 1
+fn deserialize_bool_from_int<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(i64::deserialize(deserializer)? != 0)
 }
@@ -1,47 +1,107 @@
 use core::str;
-use std::path::Path;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use actix_web::web;
+use chrono::{DateTime, Utc};
 use futures::{
     channel::mpsc::{channel, Receiver},
     SinkExt, StreamExt,
 };
-use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
-use papaya::{Guard, HashMap};
+use notify::{
+    event::ModifyKind, Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use papaya::Guard;
 use serde_json::Value as JsonValue;
-use sqlx::sqlite::SqlitePoolOptions;
-use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Connection, SqliteConnection, SqlitePool};
 
 use super::{
+    audit::{self, AuditEntry},
+    backup::{self, BackupManifest, USER_DB_BACKUP_DIR},
+    config::DatabaseConfig,
     constants::queries,
-    db::{fetch_all_as_json, AppliedQuery},
-    error::{ResourceNotExistError, SerfError},
+    db::{clear_query_interrupt, fetch_all_as_json, AppliedQuery},
+    error::{ResourceNotExistError, SerfError, UndefinedError},
+    replication,
     serf_proto::Error,
-    state::{AppState, User},
+    state::{ApiKey, AppState, DatabaseConnection, DatabaseConnectionLease, User},
+    wal_archive::{self, WalArchiveState},
 };
 
+const DB_EVICTION_INTERVAL: u64 = 60;
+const BACKUP_SCHEDULE_INTERVAL: u64 = 30;
+const WAL_ARCHIVE_INTERVAL: u64 = 5;
+const AUDIT_PRUNE_INTERVAL: u64 = 3600;
+// millis
+const USERS_RELOAD_DEBOUNCE: u64 = 200;
+
+/// #### Opens the writer and reader pools of a database
+/// \
+/// The writer pool holds a single connection so that writes are queued up in the pool
+/// instead of competing for the database lock, while the reader pool is read-only
+/// and can hold up to __max_connections__ connections. Both use WAL journaling
+/// and get the pragmas of the given __db_config__ applied.
 pub async fn create_db_connection(
     connection_string: &str,
+    db_config: &DatabaseConfig,
     max_connections: u32,
     max_idle_time: u64,
     max_lifetime: u64,
-) -> Result<SqlitePool, Error> {
+) -> Result<DatabaseConnection, Error> {
     let error_msg = "Database does not exist";
     if connection_string.contains("..") {
         return Err(ResourceNotExistError::with_message(error_msg));
     }
 
+    let connect_options = match SqliteConnectOptions::from_str(connection_string) {
+        Ok(options) => db_config.apply(options),
+        Err(_) => return Err(ResourceNotExistError::with_message(error_msg)),
+    };
+
+    let writer = match SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(Duration::from_secs(max_idle_time))
+        .max_lifetime(Duration::from_secs(max_lifetime))
+        .before_acquire(clear_query_interrupt)
+        .connect_with(connect_options.clone().journal_mode(SqliteJournalMode::Wal))
+        .await
+    {
+        Ok(pool) => pool,
+        Err(_) => return Err(ResourceNotExistError::with_message(error_msg)),
+    };
+
     match SqlitePoolOptions::new()
         .max_connections(max_connections)
         .idle_timeout(Duration::from_secs(max_idle_time))
         .max_lifetime(Duration::from_secs(max_lifetime))
-        .connect(connection_string)
+        .before_acquire(clear_query_interrupt)
+        .connect_with(connect_options.read_only(true))
         .await
     {
-        Ok(pool) => Ok(pool),
-        Err(_) => Err(ResourceNotExistError::with_message(error_msg)),
+        Ok(reader) => {
+            if db_config.replication.is_some() {
+                let res = match writer.acquire().await {
+                    Ok(mut conn) => replication::create_log(&mut conn).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    writer.close().await;
+                    reader.close().await;
+                    return Err(UndefinedError::with_message(&e.to_string()));
+                }
+            }
+
+            Ok(DatabaseConnection::new(reader, writer)
+                .with_replication(db_config.replication.clone()))
+        }
+        Err(_) => {
+            writer.close().await;
+            Err(ResourceNotExistError::with_message(error_msg))
+        }
     }
 }
 
@@ -49,18 +109,40 @@ pub async fn get_or_insert_db_connection<'a>(
     data: &'a web::Data<AppState>,
     db_name: &'a str,
     db_connections_guard: &'a impl Guard,
-) -> Result<&'a SqlitePool, Error> {
-    match data.get_db_connection(db_name, db_connections_guard) {
+) -> Result<DatabaseConnectionLease, Error> {
+    match data
+        .get_db_connection(db_name, db_connections_guard)
+        .and_then(|connection| connection.lease())
+    {
         Some(connection) => Ok(connection),
         None => {
+            if data.is_restoring_db(db_name) {
+                return Err(ResourceNotExistError::with_message(
+                    "Database is being restored",
+                ));
+            }
+
             // ToDo: replace with real logs some day
             println!(
                 "Database connection not open, trying to open for {}",
                 db_name
             );
 
+            let db_dir = Path::new(&data.db_path).join(db_name);
+            let db_config = match DatabaseConfig::load(&db_dir) {
+                Ok(db_config) => db_config,
+                Err(e) => {
+                    eprintln!(
+                        "Database config for {} could not be read: {}",
+                        db_name, e.message
+                    );
+                    DatabaseConfig::default()
+                }
+            };
+
             match create_db_connection(
                 &format!("sqlite:{}/{}/{}.db", data.db_path, db_name, db_name),
+                &db_config,
                 data.db_max_connections,
                 data.db_max_idle_time,
                 data.db_max_lifetime,
@@ -68,22 +150,386 @@ pub async fn get_or_insert_db_connection<'a>(
             .await
             {
                 Ok(conn) => {
+                    // a restore may have started while the pools were being opened
+                    if data.is_restoring_db(db_name) {
+                        conn.close().await;
+                        return Err(ResourceNotExistError::with_message(
+                            "Database is being restored",
+                        ));
+                    }
+
                     // ToDo: replace with real logs some day
                     println!("Database connection opened for {}", db_name);
-                    data.insert_db_connection(&db_name, conn, db_connections_guard);
+                    // leased before it is inserted so that the eviction below can't pick it
+                    let lease = conn.lease().unwrap();
+                    data.insert_db_connection(db_name, conn, db_connections_guard);
+                    close_db_connections(data.evict_db_connections(
+                        chrono::Utc::now().timestamp_millis(),
+                        db_connections_guard,
+                    ));
+
+                    Ok(lease)
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+}
+
+pub fn close_db_connections(evicted: Vec<(Arc<str>, DatabaseConnection)>) {
+    for (db_name, db_connection) in evicted {
+        tokio::spawn(async move {
+            db_connection.close().await;
+            // ToDo: replace with real logs some day
+            println!("Database connection closed for {}", db_name);
+        });
+    }
+}
+
+pub async fn async_evict_db_connections(app_data: web::Data<AppState>) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(
+        DB_EVICTION_INTERVAL.min(app_data.db_pool_max_idle_time.max(1)),
+    ));
+
+    loop {
+        interval.tick().await;
+        let db_connections_guard = app_data.db_connections_guard();
+        close_db_connections(
+            app_data
+                .evict_db_connections(chrono::Utc::now().timestamp_millis(), &db_connections_guard),
+        );
+    }
+}
+
+/// #### Backs up a db into the server backup dir, I.E \<root_dir\>/backup/\<db_hash\>/\<timestamp\>.db
+/// \
+/// __*@param*__ database: &str (db name hash, empty = the users db) \
+/// __*@param*__ compress: bool
+pub async fn backup_db(
+    data: &web::Data<AppState>,
+    database: &str,
+    compress: bool,
+) -> Result<BackupManifest, Error> {
+    let (db_file, backup_dir_name) = if database.is_empty() {
+        (PathBuf::from(&data.user_db_path), USER_DB_BACKUP_DIR)
+    } else {
+        (consumer_db_file(data, database)?, database)
+    };
+    let backup_file = Path::new(&data.backup_path)
+        .join(backup_dir_name)
+        .join(backup::backup_file_name(compress));
+
+    backup::backup_database(&db_file, &backup_file, backup_dir_name, compress).await
+}
+
+/// #### Restores a db from a backup in the server backup dir
+/// \
+/// The pools of the db are closed and no new ones are opened until the backup has been swapped in,
+/// requests for the db in the meantime get a ResourceNotExist error.
+/// The users db can not be restored while the server runs, use serf-cli for that with the server stopped.
+pub async fn restore_db(
+    data: &web::Data<AppState>,
+    database: &str,
+    file: &str,
+) -> Result<BackupManifest, Error> {
+    if database.is_empty() {
+        return Err(UndefinedError::with_message(
+            "The users db can only be restored with serf-cli while the server is stopped",
+        ));
+    }
+
+    backup::validate_backup_file_name(file)?;
+    let db_file = consumer_db_file(data, database)?;
+    let backup_file = Path::new(&data.backup_path).join(database).join(file);
+
+    with_db_closed(
+        data,
+        database,
+        backup::restore_database(&backup_file, &db_file),
+    )
+    .await
+}
+
+/// #### Closes the pools of a db and keeps new ones from being opened while __replace__ runs
+/// \
+/// For anything that swaps out the db file, requests for the db in the meantime get a
+/// ResourceNotExist error. The pools are reopened on the next request for the db.
+pub async fn with_db_closed<T>(
+    data: &web::Data<AppState>,
+    database: &str,
+    replace: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    if !data.restoring_dbs.pin().insert(Arc::from(database)) {
+        return Err(UndefinedError::with_message(
+            "Database is already being restored",
+        ));
+    }
+    let _restoring = RestoringDb { data, database };
+
+    let db_connection = {
+        let db_connections_guard = data.db_connections_guard();
+        data.remove_db_connection(database, &db_connections_guard)
+    };
+    if let Some(db_connection) = db_connection {
+        // waits for the connections in use to be released
+        db_connection.close().await;
+        // ToDo: replace with real logs some day
+        println!("Database connection closed for {} to replace it", database);
+    }
+
+    replace.await
+}
+
+/// Unmarks a db as being restored when dropped, so that a cancelled or panicked restore
+/// doesn't leave the db unavailable
+struct RestoringDb<'a> {
+    data: &'a web::Data<AppState>,
+    database: &'a str,
+}
+
+impl Drop for RestoringDb<'_> {
+    fn drop(&mut self) {
+        self.data.restoring_dbs.pin().remove(self.database);
+    }
+}
+
+pub async fn async_scheduled_backups(app_data: web::Data<AppState>) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(BACKUP_SCHEDULE_INTERVAL));
+    let mut next_runs = std::collections::HashMap::new();
+
+    loop {
+        interval.tick().await;
+        run_scheduled_backups(&app_data, &mut next_runs, chrono::Utc::now()).await;
+    }
+}
+
+/// #### Backs up every db whose scheduled backup is due at __now__ and prunes its backups
+/// \
+/// The backup config of every db is read on every call so that schedule changes apply without
+/// a restart. A db seen for the first time, or with a changed schedule, is first due at the next
+/// time its schedule matches after __now__. __next_runs__ holds the next run and schedule per db
+/// between calls.
+pub async fn run_scheduled_backups(
+    app_data: &web::Data<AppState>,
+    next_runs: &mut std::collections::HashMap<String, (DateTime<Utc>, String)>,
+    now: DateTime<Utc>,
+) {
+    let db_names = match std::fs::read_dir(&app_data.db_path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            eprintln!("Scheduled backups could not list the dbs: {}", e);
+            return;
+        }
+    };
+    let backup_statuses = Arc::clone(&app_data.backup_statuses);
+    let backup_statuses_pin = backup_statuses.pin();
+
+    next_runs.retain(|db_name, _| db_names.contains(db_name));
+    for db_name in db_names {
+        let backup_config = match DatabaseConfig::load(&Path::new(&app_data.db_path).join(&db_name))
+        {
+            Ok(DatabaseConfig {
+                backup: Some(backup_config),
+                ..
+            }) => backup_config,
+            _ => {
+                next_runs.remove(&db_name);
+                backup_statuses_pin.remove(db_name.as_str());
+                continue;
+            }
+        };
+
+        let mut status = backup_statuses_pin
+            .get(db_name.as_str())
+            .cloned()
+            .unwrap_or_default();
+        let next_run = match next_runs.get(&db_name) {
+            Some((next_run, schedule)) if *schedule == backup_config.schedule => Some(*next_run),
+            _ => None,
+        };
+        let due = next_run.is_some_and(|next_run| next_run <= now);
+
+        if due {
+            status.last_run_at = Some(now.to_rfc3339());
+            match backup_db(app_data, &db_name, backup_config.compress).await {
+                Ok(manifest) => {
+                    status.last_file = Some(manifest.file);
+                    status.last_error = None;
+                    if let Err(e) = backup::prune_backups(
+                        &Path::new(&app_data.backup_path).join(&db_name),
+                        backup_config.keep_hourly,
+                        backup_config.keep_daily,
+                    ) {
+                        eprintln!(
+                            "Scheduled backup pruning failed for {}: {}",
+                            db_name, e.message
+                        );
+                    }
                 }
                 Err(e) => {
-                    return Err(e);
+                    eprintln!("Scheduled backup failed for {}: {}", db_name, e.message);
+                    status.last_error = Some(e.message);
                 }
-            };
+            }
+        }
 
-            Ok(data
-                .get_db_connection(db_name, db_connections_guard)
-                .unwrap())
+        if due || next_run.is_none() {
+            match backup_config.next_run(&now) {
+                Ok(next_run) => {
+                    status.next_run_at = Some(next_run.to_rfc3339());
+                    next_runs.insert(db_name.clone(), (next_run, backup_config.schedule));
+                }
+                Err(e) => {
+                    eprintln!("Backup schedule failed for {}: {}", db_name, e.message);
+                    status.next_run_at = None;
+                    status.last_error = Some(e.message);
+                    next_runs.remove(&db_name);
+                }
+            }
         }
+
+        backup_statuses_pin.insert(Arc::from(db_name.as_str()), status);
+    }
+}
+
+pub async fn async_archive_wal(app_data: web::Data<AppState>) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(WAL_ARCHIVE_INTERVAL));
+    let mut archive_states = std::collections::HashMap::new();
+
+    loop {
+        interval.tick().await;
+        run_wal_archive(&app_data, &mut archive_states, chrono::Utc::now()).await;
+    }
+}
+
+/// #### Records a change in the audit log of the server, when it has one
+/// \
+/// The entry is written from a spawned task, so a change that has been made is recorded even when
+/// the request is dropped before its response is sent. A failure to record is logged, the change
+/// itself has already been made by then.
+pub fn record_audit_entry(app_data: &web::Data<AppState>, entry: AuditEntry) {
+    if let Some(audit_db) = app_data.audit_db.clone() {
+        actix_web::rt::spawn(async move {
+            if let Err(e) = audit::record(&audit_db, &entry).await {
+                eprintln!("audit log: {}", e);
+            }
+        });
     }
 }
 
+/// #### Deletes the audit log entries older than __retention_days__ once an hour
+/// \
+/// __*@param*__ retention_days: u64 (0 = entries are kept forever)
+pub async fn async_prune_audit_log(app_data: web::Data<AppState>, retention_days: u64) {
+    let Some(audit_db) = app_data.audit_db.clone() else {
+        return;
+    };
+    if retention_days == 0 {
+        return;
+    }
+
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(AUDIT_PRUNE_INTERVAL));
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = audit::prune(&audit_db, retention_days).await {
+            eprintln!("audit log: {}", e);
+        }
+    }
+}
+
+/// #### Archives the WAL of every open db that has wal_archive configured and is due at __now__
+/// \
+/// Only dbs with open pools are archived, a closed db has no WAL to archive. A db is due when its
+/// last archive run is at least its wal_archive interval before __now__. __archive_states__ holds
+/// the time of the last run and the archive state per db between calls.
+pub async fn run_wal_archive(
+    app_data: &web::Data<AppState>,
+    archive_states: &mut std::collections::HashMap<
+        String,
+        (DateTime<Utc>, Option<WalArchiveState>),
+    >,
+    now: DateTime<Utc>,
+) {
+    // cloned without touching the pools, archiving shouldn't keep idle pools from being evicted
+    let db_connections = app_data
+        .db_connections
+        .pin()
+        .iter()
+        .map(|(db_name, db_connection)| (db_name.to_string(), db_connection.clone()))
+        .collect::<Vec<_>>();
+
+    for (db_name, db_connection) in db_connections {
+        let db_dir = Path::new(&app_data.db_path).join(&db_name);
+        let wal_archive_config = match DatabaseConfig::load(&db_dir) {
+            Ok(DatabaseConfig {
+                wal_archive: Some(wal_archive_config),
+                ..
+            }) => wal_archive_config,
+            _ => {
+                archive_states.remove(&db_name);
+                continue;
+            }
+        };
+
+        let (last_run, state) = archive_states.remove(&db_name).unwrap_or((now, None));
+        if state.is_some() && (now - last_run).num_seconds() < wal_archive_config.interval as i64 {
+            archive_states.insert(db_name, (last_run, state));
+            continue;
+        }
+
+        let mut conn = match db_connection.writer.acquire().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!(
+                    "WAL archive could not acquire the writer of {}: {}",
+                    db_name, e
+                );
+                archive_states.insert(db_name, (last_run, state));
+                continue;
+            }
+        };
+        let state = match wal_archive::archive_wal(
+            &mut conn,
+            &db_dir.join(format!("{}.db", db_name)),
+            &Path::new(&app_data.archive_path).join(&db_name),
+            state,
+            &wal_archive_config,
+            now,
+        )
+        .await
+        {
+            Ok(state) => state,
+            Err(e) => {
+                eprintln!("WAL archive failed for {}: {}", db_name, e.message);
+                // whatever was archived last may be incomplete, start over with a new generation
+                None
+            }
+        };
+
+        archive_states.insert(db_name, (now, state));
+    }
+}
+
+/// #### Path of the db file of a consumer db, the db must exist
+pub fn consumer_db_file(data: &web::Data<AppState>, database: &str) -> Result<PathBuf, Error> {
+    let db_file = Path::new(&data.db_path)
+        .join(database)
+        .join(format!("{}.db", database));
+
+    if !database.chars().all(|c| c.is_ascii_alphanumeric()) || !db_file.exists() {
+        return Err(ResourceNotExistError::with_message(
+            "Database does not exist",
+        ));
+    }
+
+    Ok(db_file)
+}
+
 pub async fn get_db_users(user_db: &str) -> Result<JsonValue, sqlx::error::Error> {
     let pool = SqlitePool::connect(&format!("sqlite:{}", user_db)).await?;
     let users = fetch_all_as_json(AppliedQuery::new(queries::GET_USERS_AND_ACCESS), &pool).await?;
@@ -93,43 +539,72 @@ pub async fn get_db_users(user_db: &str) -> Result<JsonValue, sqlx::error::Error
     Ok(users)
 }
 
-pub fn populate_app_state_users(db_users: JsonValue, app_data: &web::Data<AppState>) {
-    let app_state_users = Arc::clone(&app_data.users);
-    let app_state_users_pin = app_state_users.pin();
-
-    if app_state_users_pin.len() > 0 {
-        app_state_users_pin.clear();
-    }
+/// #### Builds the users of the rows of GET_USERS_AND_ACCESS, keyed by username hash
+/// \
+/// There is a row per user, holding its API keys by name and its permissions per db hash.
+pub fn build_users(db_users: &JsonValue) -> std::collections::HashMap<Arc<str>, User> {
+    let mut users = std::collections::HashMap::<Arc<str>, User>::new();
 
     if let Some(arr) = db_users.as_array() {
         arr.iter().for_each(|x| {
             let user: User = serde_json::from_value(x.clone()).unwrap();
-            let databases = x.get("databases").unwrap();
-            let db_access_rights = HashMap::new();
-            let db_access_rights_pin = db_access_rights.pin();
-
-            if databases.is_array() {
-                databases.as_array().unwrap().iter().for_each(|obj| {
-                    serde_json::from_value::<std::collections::HashMap<Arc<str>, u8>>(obj.clone())
-                        .unwrap()
-                        .iter()
-                        .for_each(|(k, v)| {
-                            db_access_rights_pin.insert(k.clone(), *v);
-                        });
+
+            if let Some(api_keys) = x.get("api_keys").and_then(JsonValue::as_object) {
+                let api_keys_pin = user.api_keys.pin();
+                api_keys.iter().for_each(|(name, api_key)| {
+                    api_keys_pin.insert(
+                        Arc::from(name.as_str()),
+                        serde_json::from_value::<ApiKey>(api_key.clone()).unwrap(),
+                    );
                 });
             }
 
-            app_state_users_pin.insert(
-                Arc::from(user.username_hash.as_str()),
-                User {
-                    username: user.username,
-                    username_hash: user.username_hash,
-                    username_password_hash: user.username_password_hash,
-                    db_access_rights: db_access_rights.clone(),
-                },
-            );
+            if let Some(databases) = x.get("databases").and_then(JsonValue::as_object) {
+                let db_permissions_pin = user.db_permissions.pin();
+                databases.iter().for_each(|(database_hash, permissions)| {
+                    db_permissions_pin.insert(
+                        Arc::from(database_hash.as_str()),
+                        serde_json::from_value::<u8>(permissions.clone()).unwrap(),
+                    );
+                });
+            }
+
+            users.insert(Arc::from(user.username_hash.as_str()), user);
         });
     }
+
+    users
+}
+
+/// #### Updates the users of the app state to the users of the users db
+/// \
+/// The users are fully built before they are swapped in one by one, only users that changed are replaced
+/// and users that are gone are removed. A user is never missing from the app state while the users are updated.
+/// \
+/// __*@returns*__ usize (number of users that were added, replaced or removed)
+pub fn populate_app_state_users(db_users: JsonValue, app_data: &web::Data<AppState>) -> usize {
+    let users = build_users(&db_users);
+    let app_state_users = Arc::clone(&app_data.users);
+    let mut app_state_users_pin = app_state_users.pin();
+    let mut changes = 0;
+
+    app_state_users_pin.retain(|username_hash, _| {
+        let is_kept = users.contains_key(username_hash);
+        if !is_kept {
+            changes += 1;
+        }
+
+        is_kept
+    });
+
+    users.into_iter().for_each(|(username_hash, user)| {
+        if app_state_users_pin.get(&username_hash) != Some(&user) {
+            app_state_users_pin.insert(username_hash, user);
+            changes += 1;
+        }
+    });
+
+    changes
 }
 
 fn async_watcher() -> notify::Result<(RecommendedWatcher, Receiver<notify::Result<Event>>)> {
@@ -146,28 +621,100 @@ fn async_watcher() -> notify::Result<(RecommendedWatcher, Receiver<notify::Resul
     Ok((watcher, rx))
 }
 
+/// #### Keeps the users of the app state up to date with the users db
+/// \
+/// Events are debounced for USERS_RELOAD_DEBOUNCE millis, after which the users are only reloaded when the
+/// data_version of the users db moved, I.E another connection committed to it since the last reload.
+/// The cfg dir is watched for writes to the users db and its WAL, the shared memory file is left out as
+/// it is written by reads as well. When the users db file is replaced (I.E restored from a backup) the
+/// connection would keep reading the old file, so it is opened again and the users are reloaded.
 pub async fn async_watch(
     file_path_string: String,
     app_data: web::Data<AppState>,
 ) -> notify::Result<()> {
     let (mut watcher, mut rx) = async_watcher()?;
+    let file_path = Path::new(&file_path_string);
+    let wal_file_path = PathBuf::from(format!("{}-wal", file_path_string));
+    let connect = || async {
+        SqliteConnection::connect(&format!("sqlite:{}", file_path_string))
+            .await
+            .map_err(|e| notify::Error::generic(&e.to_string()))
+    };
+    let mut conn = connect().await?;
+    let mut data_version = None;
 
     watcher.watch(
-        Path::new(&file_path_string).as_ref(),
+        file_path.parent().unwrap_or(Path::new("")),
         RecursiveMode::NonRecursive,
     )?;
 
+    // (is a write to the users db or its WAL, replaces the users db file)
+    let users_db_event = |res: notify::Result<Event>| match res {
+        Ok(ev) if !ev.kind.is_access() => {
+            let is_replaced = (ev.kind.is_create()
+                || ev.kind.is_remove()
+                || matches!(ev.kind, EventKind::Modify(ModifyKind::Name(_))))
+                && ev.paths.iter().any(|path| path == file_path);
+            let is_written = ev
+                .paths
+                .iter()
+                .any(|path| path == file_path || *path == wal_file_path);
+
+            (is_written, is_replaced)
+        }
+        Ok(_) => (false, false),
+        Err(e) => {
+            eprintln!("watch error: {:?}", e);
+            (false, false)
+        }
+    };
+
     while let Some(res) = rx.next().await {
-        match res {
-            Ok(ev) => {
-                println!("{ev:?}");
-                match get_db_users(&file_path_string).await {
-                    Ok(val) => populate_app_state_users(val, &app_data),
-                    Err(e) => eprintln!("watch error: {:?}", e),
-                };
+        let (is_written, mut is_replaced) = users_db_event(res);
+        if !is_written {
+            continue;
+        }
+
+        actix_web::rt::time::sleep(Duration::from_millis(USERS_RELOAD_DEBOUNCE)).await;
+        while let Ok(Some(res)) = rx.try_next() {
+            is_replaced |= users_db_event(res).1;
+        }
+
+        if is_replaced {
+            match connect().await {
+                Ok(new_conn) => {
+                    let _ = std::mem::replace(&mut conn, new_conn).close().await;
+                    data_version = None;
+                }
+                Err(e) => {
+                    eprintln!("watch error: {:?}", e);
+                    continue;
+                }
             }
-            Err(e) => eprintln!("watch error: {:?}", e),
         }
+
+        let current_data_version = match sqlx::query_scalar::<_, i64>(queries::GET_DATA_VERSION)
+            .fetch_one(&mut conn)
+            .await
+        {
+            Ok(current_data_version) => current_data_version,
+            Err(e) => {
+                eprintln!("watch error: {:?}", e);
+                continue;
+            }
+        };
+        if data_version == Some(current_data_version) {
+            continue;
+        }
+
+        match get_db_users(&file_path_string).await {
+            Ok(val) => {
+                let changes = populate_app_state_users(val, &app_data);
+                data_version = Some(current_data_version);
+                println!("users reloaded, {} changed", changes);
+            }
+            Err(e) => eprintln!("watch error: {:?}", e),
+        };
     }
 
     Ok(())
@@ -0,0 +1,336 @@
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, Connection, SqliteConnection};
+
+use super::{
+    backup::{
+        check_integrity, gunzip_file, gzip_file, swap_db_file, with_db_suffix, with_suffix,
+        BACKUP_TIMESTAMP_FORMAT,
+    },
+    config::WalArchiveConfig,
+    error::{undefined_error, ResourceNotExistError, SerfError, UndefinedError},
+    replication,
+    serf_proto::Error,
+};
+
+const WAL_HEADER_SIZE: u64 = 32;
+const SNAPSHOT_FILE: &str = "snapshot.db.gz";
+const SEGMENTS_DIR: &str = "wal";
+
+/// #### What the WAL archiver knows about the archive of a db
+/// \
+/// A generation is a snapshot of the db file taken right after a full checkpoint, followed by the
+/// segments of the WAL archived since. Each segment is a gzipped copy of the WAL file from its
+/// start, named ```<timestamp>-<salt>.wal.gz``` after the salt of the WAL header, so a later segment
+/// with the same salt holds everything an earlier one does.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WalArchiveState {
+    pub generation: String,
+    pub snapshot_at: DateTime<Utc>,
+    /// salt of the WAL last archived, none when the WAL was truncated after it
+    pub salt: Option<String>,
+    pub archived_len: u64,
+    /// modification time of the db file after the last checkpoint done by the archiver,
+    /// if it changed since something else checkpointed WAL frames that may not have been archived
+    pub db_modified: Option<SystemTime>,
+}
+
+/// #### Archives the WAL of a db, starting a new generation when needed
+/// \
+/// Must be called with the writer connection of the db, so that nothing is written while the WAL
+/// is copied. A new generation is started when there is no __state__, when the snapshot is older
+/// than the snapshot_interval, or when the WAL or db file changed in a way that means frames may
+/// have been checkpointed without being archived (I.E the pool was closed, which checkpoints the WAL).
+/// \
+/// __*@returns*__ Result\<Option\<WalArchiveState\>, Error\> (the new state, None while no generation could be started)
+pub async fn archive_wal(
+    conn: &mut SqliteConnection,
+    db_file: &Path,
+    archive_dir: &Path,
+    state: Option<WalArchiveState>,
+    wal_archive_config: &WalArchiveConfig,
+    now: DateTime<Utc>,
+) -> Result<Option<WalArchiveState>, Error> {
+    let wal_file = with_db_suffix(db_file, "wal");
+    let wal_header = read_wal_header(&wal_file).map_err(undefined_error)?;
+    let db_modified = modified(db_file);
+
+    let mut state = match state {
+        Some(state)
+            if (now - state.snapshot_at).num_seconds()
+                < wal_archive_config.snapshot_interval as i64
+                && state.db_modified == db_modified
+                && match (&state.salt, &wal_header) {
+                    (None, _) => true,
+                    (Some(_), None) => false,
+                    (Some(salt), Some((wal_salt, wal_len))) => {
+                        salt == wal_salt && *wal_len >= state.archived_len
+                    }
+                } =>
+        {
+            state
+        }
+        state => {
+            return match start_generation(conn, db_file, archive_dir, now).await? {
+                Some(state) => {
+                    prune_generations(archive_dir, wal_archive_config.keep_generations)?;
+                    Ok(Some(state))
+                }
+                // not every frame could be checkpointed, try again next time
+                None => Ok(state),
+            };
+        }
+    };
+
+    if let Some((wal_salt, wal_len)) = wal_header {
+        if wal_len > WAL_HEADER_SIZE
+            && (state.salt.as_ref() != Some(&wal_salt) || wal_len > state.archived_len)
+        {
+            let segments_dir = archive_dir.join(&state.generation).join(SEGMENTS_DIR);
+            fs::create_dir_all(&segments_dir).map_err(undefined_error)?;
+            let segment_file = segments_dir.join(format!(
+                "{}-{}.wal.gz",
+                now.format(BACKUP_TIMESTAMP_FORMAT),
+                wal_salt
+            ));
+            write_gzipped(&wal_file, &segment_file).await?;
+
+            state.salt = Some(wal_salt);
+            state.archived_len = wal_len;
+            if checkpoint(conn).await? {
+                state.salt = None;
+                state.archived_len = 0;
+            }
+            state.db_modified = modified(db_file);
+        }
+    }
+
+    Ok(Some(state))
+}
+
+/// #### Rebuilds a db as it was at __to__ from its WAL archive
+/// \
+/// Takes the latest generation started at or before __to__, unpacks its snapshot and replays the
+/// segments archived at or before __to__ on it. The result is integrity checked before it is
+/// swapped in as __db_file__, nothing may have the db open during the swap.
+/// \
+/// __*@returns*__ Result\<DateTime\<Utc\>, Error\> (the time of the last replayed segment, or of the snapshot)
+pub async fn restore_wal_archive(
+    archive_dir: &Path,
+    to: DateTime<Utc>,
+    db_file: &Path,
+) -> Result<DateTime<Utc>, Error> {
+    let generation = list_timestamped(archive_dir, |name| Some(name))
+        .into_iter()
+        .rfind(|(timestamp, _)| *timestamp <= to)
+        .ok_or_else(|| {
+            ResourceNotExistError::with_message(&format!(
+                "No snapshot in the WAL archive at or before {}",
+                to.to_rfc3339()
+            ))
+        })?;
+    let generation_dir = archive_dir.join(&generation.1);
+
+    // per salt only the latest segment at or before __to__ is needed
+    let mut segments: Vec<(DateTime<Utc>, String, String)> = vec![];
+    for (timestamp, file) in list_timestamped(&generation_dir.join(SEGMENTS_DIR), |name| {
+        name.strip_suffix(".wal.gz")?
+            .split_once('-')
+            .map(|(ts, _)| ts)
+    }) {
+        if timestamp > to {
+            break;
+        }
+
+        let salt = file
+            .strip_suffix(".wal.gz")
+            .and_then(|name| name.split_once('-'))
+            .map_or_else(String::new, |(_, salt)| salt.to_string());
+        match segments.last_mut() {
+            Some(last) if last.2 == salt => *last = (timestamp, file, salt),
+            _ => segments.push((timestamp, file, salt)),
+        }
+    }
+
+    let restore_file = with_suffix(db_file, "restore");
+    let restore_wal_file = with_db_suffix(&restore_file, "wal");
+    let res = async {
+        gunzip(&generation_dir.join(SNAPSHOT_FILE), &restore_file).await?;
+        for (_, file, _) in &segments {
+            let _ = fs::remove_file(with_db_suffix(&restore_file, "shm"));
+            gunzip(
+                &generation_dir.join(SEGMENTS_DIR).join(file),
+                &restore_wal_file,
+            )
+            .await?;
+
+            let mut conn = SqliteConnectOptions::new()
+                .filename(&restore_file)
+                .connect()
+                .await
+                .map_err(undefined_error)?;
+            let checkpointed = checkpoint(&mut conn).await;
+            let _ = conn.close().await;
+
+            if !checkpointed? {
+                return Err(UndefinedError::with_message(&format!(
+                    "WAL segment {} could not be replayed",
+                    file
+                )));
+            }
+        }
+
+        check_integrity(&restore_file).await?;
+        replication::resync_followers(db_file, &restore_file).await
+    }
+    .await;
+
+    if let Err(e) = res {
+        for file in [
+            restore_file.clone(),
+            restore_wal_file,
+            with_db_suffix(&restore_file, "shm"),
+        ] {
+            let _ = fs::remove_file(file);
+        }
+        return Err(e);
+    }
+
+    swap_db_file(&restore_file, db_file)?;
+
+    Ok(segments
+        .last()
+        .map_or(generation.0, |(timestamp, _, _)| *timestamp))
+}
+
+async fn start_generation(
+    conn: &mut SqliteConnection,
+    db_file: &Path,
+    archive_dir: &Path,
+    now: DateTime<Utc>,
+) -> Result<Option<WalArchiveState>, Error> {
+    if !checkpoint(conn).await? {
+        return Ok(None);
+    }
+
+    // with the WAL truncated and the writer held the db file doesn't change while it is copied
+    let generation = now.format(BACKUP_TIMESTAMP_FORMAT).to_string();
+    let generation_dir = archive_dir.join(&generation);
+    fs::create_dir_all(&generation_dir).map_err(undefined_error)?;
+    write_gzipped(db_file, &generation_dir.join(SNAPSHOT_FILE)).await?;
+
+    Ok(Some(WalArchiveState {
+        generation,
+        snapshot_at: now,
+        salt: None,
+        archived_len: 0,
+        db_modified: modified(db_file),
+    }))
+}
+
+fn prune_generations(archive_dir: &Path, keep_generations: u32) -> Result<(), Error> {
+    let generations = list_timestamped(archive_dir, |name| Some(name));
+    let remove_count = generations
+        .len()
+        .saturating_sub(keep_generations.max(1) as usize);
+
+    for (_, generation) in generations.into_iter().take(remove_count) {
+        fs::remove_dir_all(archive_dir.join(generation)).map_err(undefined_error)?;
+    }
+
+    Ok(())
+}
+
+/// #### Runs a TRUNCATE checkpoint
+/// \
+/// __*@returns*__ Result\<bool, Error\> (true when every frame was checkpointed and the WAL truncated)
+async fn checkpoint(conn: &mut SqliteConnection) -> Result<bool, Error> {
+    let busy = sqlx::query_scalar::<_, i64>("PRAGMA wal_checkpoint(TRUNCATE);")
+        .fetch_one(conn)
+        .await
+        .map_err(undefined_error)?;
+
+    Ok(busy == 0)
+}
+
+/// the salt (hex) of the WAL header and the length of the WAL file, None when there is no WAL
+fn read_wal_header(wal_file: &Path) -> std::io::Result<Option<(String, u64)>> {
+    let mut file = match File::open(wal_file) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let wal_len = file.metadata()?.len();
+    if wal_len < WAL_HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let mut header = [0u8; WAL_HEADER_SIZE as usize];
+    file.read_exact(&mut header)?;
+
+    // salt-1 and salt-2 change every time the WAL is restarted
+    Ok(Some((
+        base16ct::lower::encode_string(&header[16..24]),
+        wal_len,
+    )))
+}
+
+/// the entries of a dir whose timestamp (as returned by __timestamp__) parses, in timestamp order
+fn list_timestamped(
+    dir: &Path,
+    timestamp: impl Fn(&str) -> Option<&str>,
+) -> Vec<(DateTime<Utc>, String)> {
+    let mut entries = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter_map(|name| {
+                    let parsed = timestamp(&name).and_then(|timestamp| {
+                        NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT).ok()
+                    });
+                    parsed.map(|parsed| (parsed.and_utc(), name))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    entries.sort();
+
+    entries
+}
+
+async fn write_gzipped(src: &Path, dest: &Path) -> Result<(), Error> {
+    let src = src.to_path_buf();
+    let dest = dest.to_path_buf();
+    let tmp = with_suffix(&dest, "tmp");
+
+    tokio::task::spawn_blocking(move || {
+        gzip_file(&src, &tmp)?;
+        fs::rename(&tmp, &dest)
+    })
+    .await
+    .map_err(undefined_error)?
+    .map_err(undefined_error)
+}
+
+async fn gunzip(src: &Path, dest: &Path) -> Result<(), Error> {
+    let src: PathBuf = src.to_path_buf();
+    let dest: PathBuf = dest.to_path_buf();
+
+    tokio::task::spawn_blocking(move || gunzip_file(&src, &dest))
+        .await
+        .map_err(undefined_error)?
+        .map_err(undefined_error)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
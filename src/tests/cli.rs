@@ -0,0 +1,526 @@
+#[allow(non_snake_case)]
+pub mod args {
+    use std::path::Path;
+
+    use clap::{error::ErrorKind, CommandFactory, Parser};
+    use sha2::{Digest, Sha256};
+
+    use crate::{
+        cli::args::{
+            BackupCommand, CreateCommand, ModifyCommand, ModifyDatabaseCommand, ModifyUserCommand,
+            RestoreCommand, SerfArgs, SerfCliArgs, SerfCliCommand,
+        },
+        core::{
+            constants::cli,
+            import::IMPORT_BATCH_SIZE,
+            serf_proto::{ExportFormat, ImportFormat},
+        },
+    };
+
+    #[test]
+    fn test_serf_cli_args__command_tree_is_valid() {
+        SerfCliArgs::command().debug_assert();
+    }
+
+    #[test]
+    fn test_serf_args__command_tree_is_valid() {
+        SerfArgs::command().debug_assert();
+    }
+
+    #[test]
+    fn test_serf_args__defaults() {
+        let args = SerfArgs::try_parse_from(["serf", "--port", "9090"]).unwrap();
+
+        assert_eq!(args.port, 9090);
+        assert_eq!(args.db_max_conn, cli::DEFAULT_DB_MAX_CONN);
+        assert_eq!(args.db_max_open, cli::DEFAULT_DB_MAX_OPEN);
+        assert_eq!(args.query_timeout, cli::DEFAULT_QUERY_TIMEOUT);
+        assert_eq!(args.max_databases, cli::DEFAULT_MAX_DATABASES);
+        assert!(args.command.is_none());
+    }
+
+    #[test]
+    fn test_serf_args__replica_parsed() {
+        let args = SerfArgs::try_parse_from([
+            "serf",
+            "--replicate-from",
+            "http://10.0.0.1:8080",
+            "--replicate-db",
+            "db1,db2",
+            "--replication-username",
+            "replicator",
+            "--replication-password",
+            "secret",
+        ])
+        .unwrap();
+
+        assert_eq!(args.replicate_from.as_deref(), Some("http://10.0.0.1:8080"));
+        assert_eq!(args.replicate_db, vec!["db1", "db2"]);
+        assert_eq!(args.replication_username.as_deref(), Some("replicator"));
+        assert_eq!(args.replication_interval, cli::DEFAULT_REPLICATION_INTERVAL);
+    }
+
+    #[test]
+    fn test_serf_args__replica_requires_dbs_and_user() {
+        let res = SerfArgs::try_parse_from([
+            "serf",
+            "--replicate-from",
+            "http://10.0.0.1:8080",
+            "--replicate-db",
+            "db1",
+        ]);
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_serf_cli_args__modify_user_limits_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "modify",
+            "user",
+            "limits",
+            "-u",
+            "rikardbq",
+            "--max-rows",
+            "1000",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Modify(ModifyCommand::User(ModifyUserCommand::Limits {
+                user,
+                limits,
+            })) => {
+                assert_eq!(user.username, "rikardbq");
+                assert_eq!(limits.query_timeout, None);
+                assert_eq!(limits.max_rows, Some(1000));
+                assert_eq!(limits.max_response_bytes, None);
+                assert_eq!(limits.max_databases, None);
+            }
+            _ => panic!("Should be modify user limits"),
+        }
+    }
+
+    #[test]
+    fn test_serf_cli_args__create_database_from_template_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "create",
+            "database",
+            "-d",
+            "tenant_db",
+            "--from",
+            "template_db",
+            "--schema-only",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Create(CreateCommand::Database {
+                database,
+                from,
+                schema_only,
+            }) => {
+                assert_eq!(database.db, "tenant_db");
+                assert_eq!(from.as_deref(), Some("template_db"));
+                assert!(schema_only);
+            }
+            _ => panic!("Should be create database"),
+        }
+
+        let err = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "create",
+            "database",
+            "-d",
+            "tenant_db",
+            "--schema-only",
+        ])
+        .expect_err("Should require --from");
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn test_serf_cli_args__modify_user_creator_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli", "modify", "user", "creator", "-u", "rikardbq", "--revoke",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Modify(ModifyCommand::User(ModifyUserCommand::Creator {
+                user,
+                revoke,
+            })) => {
+                assert_eq!(user.username, "rikardbq");
+                assert!(revoke);
+            }
+            _ => panic!("Should be modify user creator"),
+        }
+    }
+
+    #[test]
+    fn test_serf_cli_args__modify_user_limits_requires_a_limit() {
+        let err =
+            SerfCliArgs::try_parse_from(["serf-cli", "modify", "user", "limits", "-u", "rikardbq"])
+                .expect_err("Should require at least one limit");
+
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn test_serf_cli_args__modify_user_limits_reset_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "modify",
+            "user",
+            "limits",
+            "-u",
+            "rikardbq",
+            "--reset-max-rows",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Modify(ModifyCommand::User(ModifyUserCommand::Limits {
+                limits,
+                ..
+            })) => {
+                assert_eq!(limits.max_rows, None);
+                assert!(limits.reset_max_rows);
+                assert!(!limits.reset_max_response_bytes);
+            }
+            _ => panic!("Should be modify user limits"),
+        }
+
+        let err = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "modify",
+            "user",
+            "limits",
+            "-u",
+            "rikardbq",
+            "--max-rows",
+            "0",
+            "--reset-max-rows",
+        ])
+        .expect_err("Should not set and reset max rows together");
+
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_serf_cli_args__access_requires_role() {
+        let err = SerfCliArgs::try_parse_from([
+            "serf-cli", "modify", "user", "access", "-u", "rikardbq", "-d", "test_db",
+        ])
+        .expect_err("Should require a role");
+
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn test_serf_cli_args__create_role_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "create",
+            "role",
+            "-n",
+            "deployer",
+            "-p",
+            "fetch,migrate",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Create(CreateCommand::Role { role, permissions }) => {
+                assert_eq!(role.name, "deployer");
+                assert_eq!(permissions, vec!["fetch", "migrate"]);
+            }
+            _ => panic!("Should be create role"),
+        }
+    }
+
+    #[test]
+    fn test_serf_cli_args__create_role_unknown_permission() {
+        let err = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "create",
+            "role",
+            "-n",
+            "deployer",
+            "-p",
+            "fetch,write",
+        ])
+        .expect_err("Should reject the write permission");
+
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn test_serf_cli_args__create_api_key_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "create",
+            "api-key",
+            "-u",
+            "test_user",
+            "-n",
+            "ci",
+            "--expires-at",
+            "2030-01-01T00:00:00Z",
+            "--scope",
+            "fetch,mutate",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Create(CreateCommand::ApiKey {
+                user,
+                name,
+                expires_at,
+                scope,
+            }) => {
+                assert_eq!(user.username, "test_user");
+                assert_eq!(name, "ci");
+                assert_eq!(
+                    expires_at.map(|expires_at| expires_at.timestamp()),
+                    Some(1893456000)
+                );
+                assert_eq!(scope, vec!["fetch", "mutate"]);
+            }
+            _ => panic!("Should be create api-key"),
+        }
+    }
+
+    #[test]
+    fn test_serf_cli_args__audit_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "audit",
+            "-u",
+            "test_user",
+            "-d",
+            "test_db",
+            "--from",
+            "2030-01-01T00:00:00Z",
+            "--limit",
+            "10",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Audit(audit) => {
+                assert_eq!(audit.username.as_deref(), Some("test_user"));
+                assert_eq!(audit.db.as_deref(), Some("test_db"));
+                assert_eq!(audit.from.map(|from| from.timestamp()), Some(1893456000));
+                assert_eq!(audit.to, None);
+                assert_eq!(audit.limit, 10);
+            }
+            _ => panic!("Should be audit"),
+        }
+    }
+
+    #[test]
+    fn test_serf_cli_command__audit_entry_of_changes_only() {
+        let audit_entry = |args: &[&str]| {
+            SerfCliArgs::try_parse_from([&["serf-cli"], args].concat())
+                .unwrap()
+                .command
+                .audit_entry("operator")
+        };
+
+        let grant = audit_entry(&[
+            "modify",
+            "user",
+            "access",
+            "-u",
+            "test_user",
+            "-d",
+            "test_db",
+            "-r",
+            "writer",
+        ])
+        .expect("Should audit a grant");
+        let create_user = audit_entry(&["create", "user", "-u", "test_user", "-p", "secret"])
+            .expect("Should audit a created user");
+
+        assert_eq!(grant.source, "cli");
+        assert_eq!(grant.username, "operator");
+        assert_eq!(grant.action, "grant role writer");
+        assert_eq!(grant.target_user.as_deref(), Some("test_user"));
+        assert_eq!(
+            grant.database,
+            Some(base16ct::lower::encode_string(&Sha256::digest(
+                "test_db".as_bytes()
+            )))
+        );
+        assert!(!format!("{:?}", create_user).contains("secret"));
+        assert!(audit_entry(&["list", "users"]).is_none());
+        assert!(audit_entry(&["audit", "-u", "test_user"]).is_none());
+    }
+
+    #[test]
+    fn test_serf_cli_args__empty_username_rejected() {
+        let err = SerfCliArgs::try_parse_from(["serf-cli", "delete", "user", "-u", ""])
+            .expect_err("Should reject an empty username");
+
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn test_serf_cli_args__database_config_value_can_be_negative() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "modify",
+            "database",
+            "config",
+            "-d",
+            "test_db",
+            "-k",
+            "cache_size",
+            "-v",
+            "-4000",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Modify(ModifyCommand::Database(ModifyDatabaseCommand::Config {
+                value,
+                ..
+            })) => assert_eq!(value, "-4000"),
+            _ => panic!("Should be modify database config"),
+        }
+    }
+
+    #[test]
+    fn test_serf_cli_args__backup_database_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "backup",
+            "database",
+            "-d",
+            "test_db",
+            "-o",
+            "test_db.bak",
+            "-z",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Backup(BackupCommand::Database { database, output }) => {
+                assert_eq!(database.db, "test_db");
+                assert_eq!(output.output.to_str(), Some("test_db.bak"));
+                assert!(output.compress);
+            }
+            _ => panic!("Should be backup database"),
+        }
+    }
+
+    #[test]
+    fn test_serf_cli_args__export_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "export",
+            "-d",
+            "test_db",
+            "-t",
+            "test_table",
+            "-f",
+            "jsonl",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Export(export) => {
+                assert_eq!(export.database.db, "test_db");
+                assert_eq!(export.table.as_deref(), Some("test_table"));
+                assert_eq!(export.format, Some(ExportFormat::Jsonl));
+                assert_eq!(export.output, None);
+            }
+            _ => panic!("Should be export"),
+        }
+
+        let err = SerfCliArgs::try_parse_from(["serf-cli", "export", "-d", "test_db", "-f", "xml"])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn test_serf_cli_args__import_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "import",
+            "-d",
+            "test_db",
+            "-t",
+            "test_table",
+            "-f",
+            "jsonl",
+            "-i",
+            "rows.jsonl",
+            "--max-errors",
+            "10",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Import(import) => {
+                assert_eq!(import.database.db, "test_db");
+                assert_eq!(import.table, "test_table");
+                assert_eq!(import.format, ImportFormat::Jsonl);
+                assert_eq!(import.input.as_deref(), Some(Path::new("rows.jsonl")));
+                assert_eq!(import.max_errors, 10);
+                assert_eq!(import.batch_size, IMPORT_BATCH_SIZE);
+            }
+            _ => panic!("Should be import"),
+        }
+
+        assert!(SerfCliArgs::try_parse_from(["serf-cli", "import", "-d", "test_db"]).is_err());
+    }
+
+    #[test]
+    fn test_serf_cli_args__restore_database_to_parsed() {
+        let args = SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "restore",
+            "database",
+            "-d",
+            "test_db",
+            "--to",
+            "2025-01-01T12:00:00Z",
+        ])
+        .unwrap();
+
+        match args.command {
+            SerfCliCommand::Restore(RestoreCommand::Database {
+                database,
+                source,
+                yes,
+            }) => {
+                assert_eq!(database.db, "test_db");
+                assert_eq!(source.input, None);
+                assert_eq!(
+                    source.to.map(|to| to.to_rfc3339()).as_deref(),
+                    Some("2025-01-01T12:00:00+00:00")
+                );
+                assert!(!yes);
+            }
+            _ => panic!("Should be restore database"),
+        }
+
+        assert!(SerfCliArgs::try_parse_from([
+            "serf-cli",
+            "restore",
+            "database",
+            "-d",
+            "test_db",
+            "-i",
+            "test_db.bak",
+            "--to",
+            "2025-01-01T12:00:00Z",
+        ])
+        .is_err());
+    }
+}
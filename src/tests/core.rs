@@ -50,9 +50,14 @@ pub mod db {
 pub mod state {
     use std::{any::Any, sync::Arc};
 
+    use actix_web::web;
+    use serde_json::json;
+
     use crate::core::{
+        config::DatabaseConfig,
+        role,
         state::{AppState, User},
-        util::create_db_connection,
+        util::{create_db_connection, populate_app_state_users},
     };
 
     #[test]
@@ -61,13 +66,27 @@ pub mod state {
             username: "test_user".to_string(),
             username_hash: "test_user_hash".to_string(),
             username_password_hash: "some_other_hash".to_string(),
-            db_access_rights: papaya::HashMap::new(),
+            query_timeout: None,
+            max_rows: None,
+            max_response_bytes: None,
+            is_creator: false,
+            max_databases: None,
+            permissions: 0,
+            db_permissions: papaya::HashMap::new(),
+            api_keys: papaya::HashMap::new(),
         };
         let expected_user2 = User {
             username: "test_user2".to_string(),
             username_hash: "test_user2_hash".to_string(),
             username_password_hash: "some_other_hash".to_string(),
-            db_access_rights: papaya::HashMap::new(),
+            query_timeout: None,
+            max_rows: None,
+            max_response_bytes: None,
+            is_creator: false,
+            max_databases: None,
+            permissions: 0,
+            db_permissions: papaya::HashMap::new(),
+            api_keys: papaya::HashMap::new(),
         };
 
         let app_state = AppState {
@@ -76,7 +95,21 @@ pub mod state {
             db_max_connections: 32,
             db_max_idle_time: 3600,
             db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
             db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
         };
         let users_guard = app_state.users_guard();
         let users = app_state.users.pin();
@@ -86,7 +119,14 @@ pub mod state {
                 username: "test_user".to_string(),
                 username_hash: "test_user_hash".to_string(),
                 username_password_hash: "some_other_hash".to_string(),
-                db_access_rights: papaya::HashMap::new(),
+                query_timeout: None,
+                max_rows: None,
+                max_response_bytes: None,
+                is_creator: false,
+                max_databases: None,
+                permissions: 0,
+                db_permissions: papaya::HashMap::new(),
+                api_keys: papaya::HashMap::new(),
             },
         );
         users.insert(
@@ -95,7 +135,14 @@ pub mod state {
                 username: "test_user2".to_string(),
                 username_hash: "test_user2_hash".to_string(),
                 username_password_hash: "some_other_hash".to_string(),
-                db_access_rights: papaya::HashMap::new(),
+                query_timeout: None,
+                max_rows: None,
+                max_response_bytes: None,
+                is_creator: false,
+                max_databases: None,
+                permissions: 0,
+                db_permissions: papaya::HashMap::new(),
+                api_keys: papaya::HashMap::new(),
             },
         );
 
@@ -116,7 +163,21 @@ pub mod state {
             db_max_connections: 32,
             db_max_idle_time: 3600,
             db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
             db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
         };
         let users_guard = app_state.users_guard();
 
@@ -128,49 +189,238 @@ pub mod state {
     }
 
     #[test]
-    fn test_app_state__get_user_db_access_rights_exists() {
-        let expected_user1_access_right = 3u8;
-        let expected_user2_access_right = 2u8;
+    fn test_app_state__get_user_db_permissions_exists() {
+        let expected_user1_permissions = role::FETCH | role::MUTATE;
+        let expected_user2_permissions = role::FETCH | role::MIGRATE;
 
         let user1 = User {
             username: "test_user".to_string(),
             username_hash: "test_user_hash".to_string(),
             username_password_hash: "some_other_hash".to_string(),
-            db_access_rights: papaya::HashMap::new(),
+            query_timeout: None,
+            max_rows: None,
+            max_response_bytes: None,
+            is_creator: false,
+            max_databases: None,
+            permissions: 0,
+            db_permissions: papaya::HashMap::new(),
+            api_keys: papaya::HashMap::new(),
         };
-        let user1_access_rights = &user1.db_access_rights.pin();
-        user1_access_rights.insert(Arc::from("test_db_name"), 3);
+        let user1_db_permissions = &user1.db_permissions.pin();
+        user1_db_permissions.insert(Arc::from("test_db_name"), role::FETCH | role::MUTATE);
 
         let user2 = User {
             username: "test_user2".to_string(),
             username_hash: "test_user2_hash".to_string(),
             username_password_hash: "some_other_hash".to_string(),
-            db_access_rights: papaya::HashMap::new(),
+            query_timeout: None,
+            max_rows: None,
+            max_response_bytes: None,
+            is_creator: false,
+            max_databases: None,
+            permissions: role::FETCH,
+            db_permissions: papaya::HashMap::new(),
+            api_keys: papaya::HashMap::new(),
         };
-        let user2_access_rights = &user2.db_access_rights.pin();
-        user2_access_rights.insert(Arc::from("test_db_name"), 2);
-
-        let user1_access_right = user1.get_access_right("test_db_name");
-        let user2_access_right = user2.get_access_right("test_db_name");
-
-        assert_eq!(user1_access_right, expected_user1_access_right);
-        assert_eq!(user2_access_right, expected_user2_access_right);
+        let user2_db_permissions = &user2.db_permissions.pin();
+        user2_db_permissions.insert(Arc::from("test_db_name"), role::MIGRATE);
+
+        let user1_permissions = user1.get_permissions("test_db_name");
+        let user2_permissions = user2.get_permissions("test_db_name");
+
+        assert_eq!(user1_permissions, expected_user1_permissions);
+        assert_eq!(user2_permissions, expected_user2_permissions);
+        assert!(!user1.has_permission("test_db_name", role::MIGRATE));
+        assert!(user2.has_permission("test_db_name", role::MIGRATE));
+        assert!(!user2.has_permission("test_db_name", role::MUTATE));
+        assert_eq!(user2.get_permissions("other_db_name"), role::FETCH);
     }
 
     #[test]
-    fn test_app_state__get_user_db_access_rights_not_exists() {
-        let expected_user_access_right = 0u8;
+    fn test_app_state__get_user_db_permissions_not_exists() {
+        let expected_user_permissions = 0u8;
 
         let user = User {
             username: "test_user".to_string(),
             username_hash: "test_user_hash".to_string(),
             username_password_hash: "some_other_hash".to_string(),
-            db_access_rights: papaya::HashMap::new(),
+            query_timeout: None,
+            max_rows: None,
+            max_response_bytes: None,
+            is_creator: false,
+            max_databases: None,
+            permissions: 0,
+            db_permissions: papaya::HashMap::new(),
+            api_keys: papaya::HashMap::new(),
+        };
+
+        let user_permissions = user.get_permissions("test_db_name");
+
+        assert_eq!(user_permissions, expected_user_permissions);
+    }
+
+    #[test]
+    fn test_populate_app_state_users__users_with_zero_one_and_many_grants() {
+        let app_state = web::Data::new(AppState {
+            db_connections: Arc::new(papaya::HashMap::new()),
+            users: Arc::new(papaya::HashMap::new()),
+            db_max_connections: 32,
+            db_max_idle_time: 3600,
+            db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
+            db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
+        });
+        let db_user_row = |username: &str, databases: serde_json::Value| {
+            json!({
+                "username": username,
+                "username_hash": format!("{}_hash", username),
+                "username_password_hash": "some_other_hash",
+                "query_timeout": null,
+                "max_rows": null,
+                "max_response_bytes": null,
+                "is_creator": 0,
+                "max_databases": null,
+                "permissions": 0,
+                "databases": databases,
+            })
         };
 
-        let user_access_right = user.get_access_right("test_db_name");
+        populate_app_state_users(
+            json!([
+                db_user_row("test_user", json!({})),
+                db_user_row("test_user2", json!({ "test_db_name": role::FETCH })),
+                db_user_row(
+                    "test_user3",
+                    json!({
+                        "test_db_name": role::FETCH | role::MIGRATE,
+                        "test_db_name_too": role::FETCH | role::MUTATE
+                    })
+                ),
+            ]),
+            &app_state,
+        );
 
-        assert_eq!(user_access_right, expected_user_access_right);
+        let users_guard = app_state.users_guard();
+        let user = app_state.get_user("test_user_hash", &users_guard).unwrap();
+        let user2 = app_state.get_user("test_user2_hash", &users_guard).unwrap();
+        let user3 = app_state.get_user("test_user3_hash", &users_guard).unwrap();
+
+        assert_eq!(app_state.users.len(), 3);
+        assert_eq!(user.db_permissions.len(), 0);
+        assert_eq!(user.get_permissions("test_db_name"), 0);
+        assert_eq!(user2.db_permissions.len(), 1);
+        assert_eq!(user2.get_permissions("test_db_name"), role::FETCH);
+        assert_eq!(user3.db_permissions.len(), 2);
+        assert_eq!(
+            user3.get_permissions("test_db_name"),
+            role::FETCH | role::MIGRATE
+        );
+        assert_eq!(
+            user3.get_permissions("test_db_name_too"),
+            role::FETCH | role::MUTATE
+        );
+    }
+
+    #[test]
+    fn test_populate_app_state_users__updates_only_changed_users() {
+        let app_state = web::Data::new(AppState {
+            db_connections: Arc::new(papaya::HashMap::new()),
+            users: Arc::new(papaya::HashMap::new()),
+            db_max_connections: 32,
+            db_max_idle_time: 3600,
+            db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
+            db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
+        });
+        let db_user_row = |username: &str, databases: serde_json::Value| {
+            json!({
+                "username": username,
+                "username_hash": format!("{}_hash", username),
+                "username_password_hash": "some_other_hash",
+                "query_timeout": null,
+                "max_rows": null,
+                "max_response_bytes": null,
+                "is_creator": 0,
+                "max_databases": null,
+                "permissions": 0,
+                "databases": databases,
+            })
+        };
+
+        let changes = populate_app_state_users(
+            json!([
+                db_user_row("test_user", json!({ "test_db_name": role::FETCH })),
+                db_user_row("test_user2", json!({ "test_db_name": role::FETCH })),
+                db_user_row("test_user3", json!({ "test_db_name": role::FETCH })),
+            ]),
+            &app_state,
+        );
+        assert_eq!(changes, 3);
+
+        let users_guard = app_state.users_guard();
+        let user = app_state.get_user("test_user_hash", &users_guard).unwrap();
+
+        // test_user2 gets another role, test_user3 is deleted
+        let changes = populate_app_state_users(
+            json!([
+                db_user_row("test_user", json!({ "test_db_name": role::FETCH })),
+                db_user_row("test_user2", json!({ "test_db_name": role::ALL })),
+            ]),
+            &app_state,
+        );
+
+        assert_eq!(changes, 2);
+        assert_eq!(app_state.users.len(), 2);
+        assert!(std::ptr::eq(
+            user,
+            app_state.get_user("test_user_hash", &users_guard).unwrap()
+        ));
+        assert_eq!(
+            app_state
+                .get_user("test_user2_hash", &users_guard)
+                .unwrap()
+                .get_permissions("test_db_name"),
+            role::ALL
+        );
+        assert!(app_state
+            .get_user("test_user3_hash", &users_guard)
+            .is_none());
+        assert_eq!(
+            populate_app_state_users(
+                json!([
+                    db_user_row("test_user", json!({ "test_db_name": role::FETCH })),
+                    db_user_row("test_user2", json!({ "test_db_name": role::ALL })),
+                ]),
+                &app_state,
+            ),
+            0
+        );
     }
 
     #[tokio::test]
@@ -181,11 +431,26 @@ pub mod state {
             db_max_connections: 32,
             db_max_idle_time: 3600,
             db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
             db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
         };
         let db_connections_guard = app_state.db_connections_guard();
         let created_db_connection = create_db_connection(
             "sqlite::memory:",
+            &DatabaseConfig::default(),
             app_state.db_max_connections,
             app_state.db_max_idle_time,
             app_state.db_max_lifetime,
@@ -198,7 +463,6 @@ pub mod state {
             created_db_connection.unwrap(),
             &db_connections_guard,
         );
-        assert!(true);
     }
 
     #[tokio::test]
@@ -209,11 +473,26 @@ pub mod state {
             db_max_connections: 32,
             db_max_idle_time: 3600,
             db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
             db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
         };
         let db_connections_guard = app_state.db_connections_guard();
         let created_db_connection = create_db_connection(
             "sqlite::memory:",
+            &DatabaseConfig::default(),
             app_state.db_max_connections,
             app_state.db_max_idle_time,
             app_state.db_max_lifetime,
@@ -227,7 +506,6 @@ pub mod state {
             unwrapped_created_db_connection.clone(),
             &db_connections_guard,
         );
-        assert!(true);
 
         let db_connection = app_state.get_db_connection("test_db_name", &db_connections_guard);
         assert!(db_connection.is_some());
@@ -236,5 +514,366 @@ pub mod state {
             unwrapped_db_connection.type_id(),
             unwrapped_created_db_connection.type_id()
         );
+        assert_eq!(
+            unwrapped_db_connection
+                .writer
+                .options()
+                .get_max_connections(),
+            1
+        );
+        assert_eq!(
+            unwrapped_db_connection
+                .reader
+                .options()
+                .get_max_connections(),
+            app_state.db_max_connections
+        );
+    }
+
+    #[tokio::test]
+    async fn test_app_state__evict_idle_db_connections() {
+        let app_state = AppState {
+            db_connections: Arc::new(papaya::HashMap::new()),
+            users: Arc::new(papaya::HashMap::new()),
+            db_max_connections: 32,
+            db_max_idle_time: 3600,
+            db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 60,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
+            db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
+        };
+        let db_connections_guard = app_state.db_connections_guard();
+        for db_name in ["test_db_name1", "test_db_name2"] {
+            let created_db_connection = create_db_connection(
+                "sqlite::memory:",
+                &DatabaseConfig::default(),
+                1,
+                3600,
+                86400,
+            )
+            .await
+            .unwrap();
+            app_state.insert_db_connection(db_name, created_db_connection, &db_connections_guard);
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let evicted_now = app_state.evict_db_connections(now, &db_connections_guard);
+        assert!(evicted_now.is_empty());
+
+        let evicted_later = app_state.evict_db_connections(now + 61 * 1000, &db_connections_guard);
+        assert_eq!(evicted_later.len(), 2);
+        assert!(app_state
+            .get_db_connection("test_db_name1", &db_connections_guard)
+            .is_none());
+        assert!(app_state
+            .get_db_connection("test_db_name2", &db_connections_guard)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_app_state__evict_least_recently_used_db_connections() {
+        let app_state = AppState {
+            db_connections: Arc::new(papaya::HashMap::new()),
+            users: Arc::new(papaya::HashMap::new()),
+            db_max_connections: 32,
+            db_max_idle_time: 3600,
+            db_max_lifetime: 86400,
+            db_max_open: 2,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
+            db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
+        };
+        let db_connections_guard = app_state.db_connections_guard();
+        for db_name in ["test_db_name1", "test_db_name2", "test_db_name3"] {
+            let created_db_connection = create_db_connection(
+                "sqlite::memory:",
+                &DatabaseConfig::default(),
+                1,
+                3600,
+                86400,
+            )
+            .await
+            .unwrap();
+            app_state.insert_db_connection(db_name, created_db_connection, &db_connections_guard);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        // touching the first db makes the second one the least recently used
+        assert!(app_state
+            .get_db_connection("test_db_name1", &db_connections_guard)
+            .is_some());
+
+        let evicted = app_state
+            .evict_db_connections(chrono::Utc::now().timestamp_millis(), &db_connections_guard);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(&*evicted[0].0, "test_db_name2");
+        assert_eq!(app_state.db_connections.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_app_state__leased_db_connection_is_not_evicted_or_closed() {
+        let app_state = AppState {
+            db_connections: Arc::new(papaya::HashMap::new()),
+            users: Arc::new(papaya::HashMap::new()),
+            db_max_connections: 32,
+            db_max_idle_time: 3600,
+            db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 60,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
+            db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
+        };
+        let db_connections_guard = app_state.db_connections_guard();
+        let created_db_connection = create_db_connection(
+            "sqlite::memory:",
+            &DatabaseConfig::default(),
+            1,
+            3600,
+            86400,
+        )
+        .await
+        .unwrap();
+        app_state.insert_db_connection(
+            "test_db_name",
+            created_db_connection,
+            &db_connections_guard,
+        );
+
+        // a lease taken before a connection is acquired keeps the pools from being evicted
+        let lease = app_state
+            .get_db_connection("test_db_name", &db_connections_guard)
+            .unwrap()
+            .lease()
+            .unwrap();
+        let now = chrono::Utc::now().timestamp_millis();
+        assert!(app_state
+            .evict_db_connections(now + 61 * 1000, &db_connections_guard)
+            .is_empty());
+
+        // and closing the pools waits for the lease to be released
+        let removed = app_state
+            .remove_db_connection("test_db_name", &db_connections_guard)
+            .unwrap();
+        let closing = tokio::spawn(async move { removed.close().await });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!closing.is_finished());
+        assert!(lease.lease().is_none());
+        assert!(sqlx::query("SELECT 1;")
+            .execute(&lease.reader)
+            .await
+            .is_ok());
+
+        drop(lease);
+        closing.await.unwrap();
+    }
+}
+
+#[allow(non_snake_case)]
+pub mod config {
+    use std::fs;
+
+    use sqlx::Row;
+
+    use crate::core::{
+        config::{
+            BackupScheduleConfig, DatabaseConfig, Synchronous, TempStore, DATABASE_CONFIG_FILE,
+        },
+        util::create_db_connection,
+    };
+
+    #[test]
+    fn test_database_config__load_missing_file_gives_default() {
+        let db_dir = std::env::temp_dir().join("serf_test_database_config__missing");
+        let _ = fs::remove_dir_all(&db_dir);
+
+        let db_config = DatabaseConfig::load(&db_dir);
+
+        assert!(db_config.is_ok());
+        assert_eq!(db_config.unwrap(), DatabaseConfig::default());
+    }
+
+    #[test]
+    fn test_database_config__load_partial_file_and_save() {
+        let expected_db_config = DatabaseConfig {
+            busy_timeout: 10000,
+            synchronous: Synchronous::Normal,
+            temp_store: Some(TempStore::Memory),
+            ..DatabaseConfig::default()
+        };
+
+        let db_dir = std::env::temp_dir().join("serf_test_database_config__partial");
+        let _ = fs::create_dir_all(&db_dir);
+        fs::write(
+            db_dir.join(DATABASE_CONFIG_FILE),
+            r#"{ "busy_timeout": 10000, "synchronous": "NORMAL", "temp_store": "MEMORY" }"#,
+        )
+        .unwrap();
+
+        let db_config = DatabaseConfig::load(&db_dir);
+        assert!(db_config.is_ok());
+        assert_eq!(db_config.unwrap(), expected_db_config);
+
+        let mut saved_db_config = expected_db_config.clone();
+        saved_db_config.foreign_keys = false;
+        assert!(saved_db_config.save(&db_dir).is_ok());
+        assert_eq!(DatabaseConfig::load(&db_dir).unwrap(), saved_db_config);
+
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    #[test]
+    fn test_database_config__load_unknown_key_fail() {
+        let db_dir = std::env::temp_dir().join("serf_test_database_config__unknown_key");
+        let _ = fs::create_dir_all(&db_dir);
+        fs::write(
+            db_dir.join(DATABASE_CONFIG_FILE),
+            r#"{ "not_a_pragma": 1 }"#,
+        )
+        .unwrap();
+
+        let db_config = DatabaseConfig::load(&db_dir);
+
+        assert!(db_config.is_err());
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_config__applied_to_created_db_connection() {
+        let db_config = DatabaseConfig {
+            cache_size: Some(-4000),
+            foreign_keys: false,
+            temp_store: Some(TempStore::Memory),
+            ..DatabaseConfig::default()
+        };
+
+        let db_connection = create_db_connection("sqlite::memory:", &db_config, 1, 3600, 86400)
+            .await
+            .unwrap();
+
+        for pool in [&db_connection.reader, &db_connection.writer] {
+            let cache_size = sqlx::query("PRAGMA cache_size;").fetch_one(pool).await;
+            let foreign_keys = sqlx::query("PRAGMA foreign_keys;").fetch_one(pool).await;
+            let temp_store = sqlx::query("PRAGMA temp_store;").fetch_one(pool).await;
+
+            assert_eq!(cache_size.unwrap().get::<i64, _>(0), -4000);
+            assert_eq!(foreign_keys.unwrap().get::<i64, _>(0), 0);
+            assert_eq!(temp_store.unwrap().get::<i64, _>(0), 2);
+        }
+    }
+
+    #[test]
+    fn test_backup_schedule_config__next_run() {
+        let now = chrono::DateTime::parse_from_rfc3339("2025-01-01T12:30:00Z")
+            .unwrap()
+            .to_utc();
+        let backup_config = BackupScheduleConfig::default();
+
+        assert_eq!(
+            backup_config.next_run(&now).unwrap().to_rfc3339(),
+            "2025-01-01T13:00:00+00:00"
+        );
+
+        let invalid_backup_config = BackupScheduleConfig {
+            schedule: String::from("not a schedule"),
+            ..BackupScheduleConfig::default()
+        };
+        assert!(invalid_backup_config.next_run(&now).is_err());
+    }
+}
+
+#[allow(non_snake_case)]
+pub mod role {
+    use crate::core::role::{self, permissions_display, permissions_from_names};
+
+    #[test]
+    fn test_permissions_from_names__combines_permissions() {
+        let permissions = permissions_from_names(&["fetch", "MIGRATE"]).unwrap();
+
+        assert_eq!(permissions, role::FETCH | role::MIGRATE);
+        assert_eq!(permissions_display(permissions), "fetch+migrate");
+        assert_eq!(permissions_display(0), "none");
+    }
+
+    #[test]
+    fn test_permissions_from_names__unknown_permission() {
+        assert!(permissions_from_names(&["fetch", "write"]).is_err());
+    }
+}
+
+#[allow(non_snake_case)]
+pub mod replication {
+    use crate::core::{
+        replication::check_deterministic,
+        serf_proto::{query_arg, QueryArg},
+    };
+
+    #[test]
+    fn test_check_deterministic__rejects_non_deterministic_statements() {
+        for query in [
+            "INSERT INTO t(a) VALUES(random());",
+            "INSERT INTO t(a) VALUES(RandomBlob(16));",
+            "UPDATE t SET updated = CURRENT_TIMESTAMP;",
+            "INSERT INTO t(a) VALUES(datetime('now'));",
+            "INSERT INTO t(a) VALUES(unixepoch());",
+            "INSERT INTO t(a) VALUES(strftime('%s'));",
+            "UPDATE t SET a = 1 WHERE rowid = 2;",
+            "INSERT INTO t(a) VALUES(last_insert_rowid());",
+            "ALTER TABLE t ADD COLUMN created TEXT DEFAULT CURRENT_DATE;",
+        ] {
+            assert!(check_deterministic(query, &[]).is_err(), "{}", query);
+        }
+
+        let now_arg = [QueryArg::new(query_arg::Value::String("NOW".to_string()))];
+        assert!(check_deterministic("INSERT INTO t(a) VALUES(date(?));", &now_arg).is_err());
+    }
+
+    #[test]
+    fn test_check_deterministic__allows_values_passed_as_args() {
+        let time_arg = [QueryArg::new(query_arg::Value::String(
+            "2025-01-01 12:00:00".to_string(),
+        ))];
+
+        for query in [
+            "INSERT INTO t(a) VALUES(datetime(?));",
+            "INSERT INTO t(a) VALUES(strftime('%s', ?));",
+            "INSERT INTO t(\"random\", [rowid_like]) VALUES('now or never', 'random()');",
+            "-- random()\nUPDATE t SET a = 1 WHERE id = ?; /* CURRENT_TIMESTAMP */",
+        ] {
+            assert!(check_deterministic(query, &time_arg).is_ok(), "{}", query);
+        }
     }
 }
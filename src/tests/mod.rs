@@ -4,5 +4,6 @@ pub mod test_utils {
     }
 }
 
+pub mod cli;
 pub mod core;
 pub mod web;
@@ -5,14 +5,20 @@ pub mod util {
 
     use crate::{
         core::{
-            error::{HeaderMalformedError, HeaderMissingError, SerfError, UndefinedError},
-            serf_proto::{claims::Dat, Claims, Iss, MigrationRequest, QueryRequest, Sub},
+            audit::AUDIT_SOURCE_PROTOCOL,
+            error::{
+                DatabaseError, HeaderMalformedError, HeaderMissingError, SerfError, UndefinedError,
+            },
+            serf_proto::{
+                claims::Dat, query_arg, Claims, ImportRequest, Iss, MigrationRequest,
+                MutationResponse, QueryArg, QueryRequest, Sub,
+            },
         },
         web::{
-            proto::ProtoPackage,
+            proto::{encode_proto, ProtoPackage},
             util::{
                 check_content_type, extract_headers, get_header_value, get_proto_package_result,
-                MockRequestHandler,
+                protocol_audit_entry, with_audit_outcome, HandledRequest, MockRequestHandler,
             },
         },
     };
@@ -125,11 +131,46 @@ pub mod util {
         assert_eq!(result.unwrap(), expected_proto_package);
     }
 
+    #[tokio::test]
+    async fn test_get_proto_package_result__calls_handle_import() {
+        let expected_proto_package = ProtoPackage {
+            data: vec![1, 2, 3],
+            signature: "any".to_string(),
+        };
+
+        let mut mock_handler = MockRequestHandler::new();
+        mock_handler
+            .expect_handle_import()
+            .times(1)
+            .with(predicate::always())
+            .returning(|_| {
+                let res = Ok(ProtoPackage {
+                    data: vec![1, 2, 3],
+                    signature: "any".to_string(),
+                });
+
+                Box::pin(async move { res })
+            });
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: 1,
+            exp: 2,
+            sub: Sub::Import.into(),
+            dat: Some(Dat::ImportRequest(ImportRequest::default())),
+        };
+
+        let result = get_proto_package_result(claims, &mock_handler).await;
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), expected_proto_package);
+    }
+
     #[tokio::test]
     async fn test_get_proto_package_result__query_request_handle_incorrect_subject() {
         let expected_error = UndefinedError::default();
 
-        let mut mock_handler = MockRequestHandler::new();
+        let mut mock_handler = MockRequestHandler::<ProtoPackage>::new();
         mock_handler.expect_handle_fetch().times(0);
         mock_handler.expect_handle_mutate().times(0);
 
@@ -154,7 +195,7 @@ pub mod util {
     async fn test_get_proto_package_result__migration_request_handle_incorrect_subject() {
         let expected_error = UndefinedError::default();
 
-        let mut mock_handler = MockRequestHandler::new();
+        let mut mock_handler = MockRequestHandler::<ProtoPackage>::new();
         mock_handler.expect_handle_migrate().times(0);
 
         let claims = Claims {
@@ -333,22 +374,110 @@ pub mod util {
             expected_error
         );
     }
+
+    #[test]
+    fn test_protocol_audit_entry__changes_only() {
+        let claims = |sub: Sub, dat: Dat| Claims {
+            iss: Iss::Client.into(),
+            iat: 1,
+            exp: 2,
+            sub: sub.into(),
+            dat: Some(dat),
+        };
+        let query_request = QueryRequest {
+            query: "UPDATE t SET a = ?;".to_string(),
+            parts: vec![QueryArg::new(query_arg::Value::Int(1))],
+            timeout: 0,
+        };
+
+        let mutation = protocol_audit_entry(
+            "test_user",
+            "0123abcd",
+            &claims(Sub::Mutate, Dat::QueryRequest(query_request.clone())),
+        )
+        .expect("Should audit a mutation");
+        let fetch = protocol_audit_entry(
+            "test_user",
+            "0123abcd",
+            &claims(Sub::Fetch, Dat::QueryRequest(query_request)),
+        );
+        let migration = protocol_audit_entry(
+            "test_user",
+            "0123abcd",
+            &claims(
+                Sub::Migrate,
+                Dat::MigrationRequest(MigrationRequest {
+                    name: "0001_init".to_string(),
+                    query: "CREATE TABLE t(a);".to_string(),
+                }),
+            ),
+        )
+        .expect("Should audit a migration");
+
+        assert_eq!(mutation.source, AUDIT_SOURCE_PROTOCOL);
+        assert_eq!(mutation.username, "test_user");
+        assert_eq!(mutation.database.as_deref(), Some("0123abcd"));
+        assert_eq!(mutation.query.as_deref(), Some("UPDATE t SET a = ?;"));
+        assert_eq!(mutation.args_digest.map(|digest| digest.len()), Some(64));
+        assert!(fetch.is_none());
+        assert_eq!(migration.action, "migrate 0001_init");
+        assert_eq!(migration.args_digest, None);
+    }
+
+    #[test]
+    fn test_with_audit_outcome__rows_affected_or_error() {
+        let entry = protocol_audit_entry(
+            "test_user",
+            "0123abcd",
+            &Claims {
+                iss: Iss::Client.into(),
+                iat: 1,
+                exp: 2,
+                sub: Sub::Mutate.into(),
+                dat: Some(Dat::QueryRequest(QueryRequest::default())),
+            },
+        )
+        .unwrap();
+
+        let proto_package =
+            || encode_proto(MutationResponse::as_dat(3, 7), Sub::Data, "secret").unwrap();
+        let ok = with_audit_outcome(
+            entry.clone(),
+            &Ok(HandledRequest::new(proto_package()).with_rows_affected(3)),
+        );
+        let aborted = with_audit_outcome(
+            entry.clone(),
+            &Ok(HandledRequest::new(proto_package())
+                .with_rows_affected(1)
+                .with_outcome("aborted after 2 failed rows")),
+        );
+        let err = with_audit_outcome(entry, &Err(DatabaseError::with_message("no such table: t")));
+
+        assert_eq!(ok.rows_affected, Some(3));
+        assert_eq!(ok.outcome, "ok");
+        assert_eq!(aborted.rows_affected, Some(1));
+        assert_eq!(aborted.outcome, "aborted after 2 failed rows");
+        assert_eq!(err.rows_affected, None);
+        assert_eq!(err.outcome, "no such table: t");
+    }
 }
 
 #[allow(non_snake_case)]
 #[cfg(test)]
 pub mod proto {
-    use std::any::Any;
+    use std::{any::Any, sync::Arc};
 
     use prost::Message;
 
     use crate::{
         core::{
             error::ProtoPackageError,
+            role,
             serf_proto::{Claims, Iss, QueryRequest, Request, Sub},
+            state::{ApiKey, User},
         },
         tests::test_utils::constants::TEST_NOW_TIMESTAMP,
-        web::proto::{generate_signature, ProtoPackage, ProtoPackageVerifier},
+        web::proto::{decode_proto, generate_signature, ProtoPackage, ProtoPackageVerifier},
     };
 
     #[test]
@@ -554,8 +683,7 @@ pub mod proto {
             error: None,
         };
 
-        let mut buf = Vec::new();
-        buf.reserve(request.encoded_len());
+        let mut buf = Vec::with_capacity(request.encoded_len());
 
         let encode_res = request.encode(&mut buf);
         assert!(encode_res.is_ok());
@@ -566,7 +694,7 @@ pub mod proto {
         let request_res = ProtoPackageVerifier::builder()
             .with_issuer(issuer)
             .with_secret(secret)
-            .with_signature(&signature)
+            .with_signature(signature)
             .TEST_with_now_timestamp(TEST_NOW_TIMESTAMP)
             .build()
             .verify(&buf);
@@ -599,8 +727,7 @@ pub mod proto {
             error: None,
         };
 
-        let mut buf = Vec::new();
-        buf.reserve(request.encoded_len());
+        let mut buf = Vec::with_capacity(request.encoded_len());
 
         let encode_res = request.encode(&mut buf);
         assert!(encode_res.is_ok());
@@ -611,7 +738,7 @@ pub mod proto {
         let request_res = ProtoPackageVerifier::builder()
             .with_issuer(issuer)
             .with_secret(secret)
-            .with_signature(&signature)
+            .with_signature(signature)
             .TEST_with_now_timestamp(TEST_NOW_TIMESTAMP)
             .build()
             .verify(&buf);
@@ -641,8 +768,7 @@ pub mod proto {
             error: None,
         };
 
-        let mut buf = Vec::new();
-        buf.reserve(request.encoded_len());
+        let mut buf = Vec::with_capacity(request.encoded_len());
 
         let encode_res = request.encode(&mut buf);
         assert!(encode_res.is_ok());
@@ -653,7 +779,7 @@ pub mod proto {
         let request_res = ProtoPackageVerifier::builder()
             .with_issuer(issuer)
             .with_secret(secret)
-            .with_signature(&signature)
+            .with_signature(signature)
             .TEST_with_now_timestamp(TEST_NOW_TIMESTAMP)
             .build()
             .verify(&buf);
@@ -686,8 +812,7 @@ pub mod proto {
             error: None,
         };
 
-        let mut buf = Vec::new();
-        buf.reserve(request.encoded_len());
+        let mut buf = Vec::with_capacity(request.encoded_len());
 
         let encode_res = request.encode(&mut buf);
         assert!(encode_res.is_ok());
@@ -698,7 +823,7 @@ pub mod proto {
         let request_res = ProtoPackageVerifier::builder()
             .with_issuer(issuer)
             .with_secret(secret)
-            .with_signature(&signature)
+            .with_signature(signature)
             .TEST_with_now_timestamp(TEST_NOW_TIMESTAMP)
             .build()
             .verify(&buf);
@@ -719,8 +844,7 @@ pub mod proto {
             error: None,
         };
 
-        let mut buf = Vec::new();
-        buf.reserve(request.encoded_len());
+        let mut buf = Vec::with_capacity(request.encoded_len());
 
         let encode_res = request.encode(&mut buf);
         assert!(encode_res.is_ok());
@@ -732,7 +856,7 @@ pub mod proto {
         let request_res = ProtoPackageVerifier::builder()
             .with_issuer(issuer)
             .with_secret(secret)
-            .with_signature(&signature)
+            .with_signature(signature)
             .build()
             .verify(&buf);
 
@@ -743,6 +867,114 @@ pub mod proto {
         );
     }
 
+    fn test_user_with_api_key(api_key: ApiKey) -> User {
+        let user = User {
+            username: "test_user".to_string(),
+            username_hash: "test_user_hash".to_string(),
+            username_password_hash: "test_hash".to_string(),
+            query_timeout: None,
+            max_rows: None,
+            max_response_bytes: None,
+            is_creator: false,
+            max_databases: None,
+            permissions: 0,
+            db_permissions: papaya::HashMap::new(),
+            api_keys: papaya::HashMap::new(),
+        };
+        user.db_permissions
+            .pin()
+            .insert(Arc::from("test_db_name"), role::FETCH | role::MUTATE);
+        user.api_keys.pin().insert(Arc::from("test_key"), api_key);
+
+        user
+    }
+
+    fn test_client_proto_package(secret: &str) -> ProtoPackage {
+        ProtoPackage::builder()
+            .with_data(QueryRequest::as_dat(
+                "SELECT * FROM test_data_table;".to_string(),
+                vec![],
+            ))
+            .with_subject(Sub::Fetch)
+            .with_issuer(Iss::Client)
+            .sign(secret)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decode_proto__signed_with_api_key() {
+        let user = test_user_with_api_key(ApiKey {
+            secret: "test_key_secret".to_string(),
+            expires_at: Some(chrono::Utc::now().timestamp() as u64 + 60),
+            scope: Some(role::FETCH),
+        });
+        let proto_package = test_client_proto_package("test_key_secret");
+
+        let (_, request_key) = decode_proto(
+            &proto_package.data,
+            &user,
+            Some("test_key"),
+            &proto_package.signature,
+        )
+        .unwrap();
+
+        assert_eq!(request_key.secret, "test_key_secret");
+        assert_eq!(
+            request_key.get_permissions(&user, "test_db_name"),
+            role::FETCH
+        );
+        assert!(decode_proto(&proto_package.data, &user, None, &proto_package.signature).is_err());
+    }
+
+    #[test]
+    fn test_decode_proto__signed_with_user_secret() {
+        let user = test_user_with_api_key(ApiKey {
+            secret: "test_key_secret".to_string(),
+            expires_at: None,
+            scope: None,
+        });
+        let proto_package = test_client_proto_package("test_hash");
+
+        let (_, request_key) =
+            decode_proto(&proto_package.data, &user, None, &proto_package.signature).unwrap();
+
+        assert_eq!(request_key.secret, "test_hash");
+        assert_eq!(
+            request_key.get_permissions(&user, "test_db_name"),
+            role::FETCH | role::MUTATE
+        );
+    }
+
+    #[test]
+    fn test_decode_proto__api_key_unknown_or_expired() {
+        let user = test_user_with_api_key(ApiKey {
+            secret: "test_key_secret".to_string(),
+            expires_at: Some(chrono::Utc::now().timestamp() as u64 - 60),
+            scope: None,
+        });
+        let proto_package = test_client_proto_package("test_key_secret");
+
+        for (key_id, expected_error) in [
+            (
+                "test_key",
+                ProtoPackageError::verification_error("key expired"),
+            ),
+            (
+                "no_key",
+                ProtoPackageError::verification_error("unknown key"),
+            ),
+        ] {
+            let res = decode_proto(
+                &proto_package.data,
+                &user,
+                Some(key_id),
+                &proto_package.signature,
+            );
+
+            assert_eq!(res.expect_err("Should not verify"), expected_error);
+        }
+    }
+
     #[test]
     fn test_generate_signature__ensure_same() {
         // HMAC<SHA256> with secret: "test_secret"
@@ -757,3 +989,467 @@ pub mod proto {
         assert_eq!(signature2, expected_signature);
     }
 }
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+pub mod auth {
+    use std::sync::Arc;
+
+    use actix_web::{body::MessageBody, http::StatusCode, test::TestRequest, web};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+
+    use crate::{
+        core::{
+            error::ProtoPackageError,
+            serf_proto::{Iss, QueryRequest, Sub},
+            state::{ApiKey, AppState, User},
+        },
+        web::{
+            auth::{authenticate, Authenticator, JwtAuthenticator, MtlsAuthenticator},
+            proto::{generate_signature, ProtoPackage},
+        },
+    };
+
+    // self-signed, subject O=serf, CN=test_user
+    const TEST_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIBnDCCAUOgAwIBAgIUKNtJP+AfYxlKM4apXEP4ouyUYm4wCgYIKoZIzj0EAwIw
+IzENMAsGA1UECgwEc2VyZjESMBAGA1UEAwwJdGVzdF91c2VyMCAXDTI2MTAxOTEw
+MTY1MloYDzIxMjYwOTI1MTAxNjUyWjAjMQ0wCwYDVQQKDARzZXJmMRIwEAYDVQQD
+DAl0ZXN0X3VzZXIwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAAQ2wboYub0QJDkE
+jhCxgeDSdifg2VV/YjW27A7RS8CiA5fUo/ib+nL1qop40QeWpqt2NnQfgiHDQTsh
+IioZjIUno1MwUTAdBgNVHQ4EFgQUJXzzBazGHGkwYArs7plJTwj7s3UwHwYDVR0j
+BBgwFoAUJXzzBazGHGkwYArs7plJTwj7s3UwDwYDVR0TAQH/BAUwAwEB/zAKBggq
+hkjOPQQDAgNHADBEAiBX4gC+4uncvBxSj0Id1ZE+kk1tQVw7WRAlrSAZA0ev+AIg
+e7xoaXv7n9+Togqul0cTs2kUcCLnS+hJawJHwOM+S1M=
+-----END CERTIFICATE-----
+";
+
+    fn test_app_state() -> AppState {
+        let app_state = AppState {
+            db_connections: Arc::new(papaya::HashMap::new()),
+            users: Arc::new(papaya::HashMap::new()),
+            db_max_connections: 32,
+            db_max_idle_time: 3600,
+            db_max_lifetime: 86400,
+            db_max_open: 100,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
+            db_path: String::from("testing_path"),
+            backup_path: String::from("testing_backup_path"),
+            archive_path: String::from("testing_archive_path"),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(papaya::HashSet::new()),
+            backup_statuses: Arc::new(papaya::HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(papaya::HashMap::new()),
+            audit_db: None,
+        };
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(b"test_user"));
+        app_state.users.pin().insert(
+            Arc::from(username_hash.as_str()),
+            User {
+                username: "test_user".to_string(),
+                username_hash,
+                username_password_hash: "test_hash".to_string(),
+                query_timeout: None,
+                max_rows: None,
+                max_response_bytes: None,
+                is_creator: false,
+                max_databases: None,
+                permissions: 0,
+                db_permissions: papaya::HashMap::new(),
+                api_keys: papaya::HashMap::new(),
+            },
+        );
+
+        app_state
+    }
+
+    fn test_client_proto_package(secret: &str) -> ProtoPackage {
+        ProtoPackage::builder()
+            .with_data(QueryRequest::as_dat(
+                "SELECT * FROM test_data_table;".to_string(),
+                vec![],
+            ))
+            .with_subject(Sub::Fetch)
+            .with_issuer(Iss::Client)
+            .sign(secret)
+            .unwrap()
+    }
+
+    fn test_jwt_authenticator() -> JwtAuthenticator {
+        // the secret is "test_jwt_secret"
+        let jwks = json!({
+            "keys": [{ "kty": "oct", "kid": "test_kid", "alg": "HS256", "k": "dGVzdF9qd3Rfc2VjcmV0" }]
+        });
+
+        JwtAuthenticator::new(serde_json::from_value(jwks).unwrap())
+            .with_issuer(Some("test_issuer".to_string()))
+    }
+
+    fn test_token(kid: &str, claims: serde_json::Value) -> String {
+        let header = Header {
+            kid: Some(kid.to_string()),
+            ..Header::default()
+        };
+
+        jsonwebtoken::encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(b"test_jwt_secret"),
+        )
+        .unwrap()
+    }
+
+    fn test_claims(iss: &str, exp: i64) -> serde_json::Value {
+        json!({ "sub": "test_user", "iss": iss, "exp": exp })
+    }
+
+    #[test]
+    fn test_authenticate__hmac_by_default() {
+        let app_state = test_app_state();
+        let proto_package = test_client_proto_package("test_hash");
+        let req = TestRequest::default()
+            .insert_header(("content-type", "application/protobuf"))
+            .insert_header((
+                "0",
+                base16ct::lower::encode_string(&Sha256::digest(b"test_user")),
+            ))
+            .insert_header(("1", proto_package.signature))
+            .to_http_request();
+
+        let users_guard = app_state.users_guard();
+        let authenticated =
+            authenticate(&req, &app_state, &proto_package.data, &users_guard).unwrap();
+
+        assert_eq!(authenticated.user.username, "test_user");
+        assert_eq!(authenticated.request_key.secret, "test_hash");
+    }
+
+    #[test]
+    fn test_authenticate__hmac_unknown_user() {
+        let app_state = test_app_state();
+        let proto_package = test_client_proto_package("test_hash");
+        let req = TestRequest::default()
+            .insert_header(("content-type", "application/protobuf"))
+            .insert_header(("0", "unknown_user_hash"))
+            .insert_header(("1", proto_package.signature))
+            .to_http_request();
+
+        let users_guard = app_state.users_guard();
+        let res = authenticate(&req, &app_state, &proto_package.data, &users_guard);
+
+        assert_eq!(
+            res.err().expect("Should be unauthorized").status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn test_authenticate__hmac_api_key_error_signed_with_key_secret() {
+        let app_state = test_app_state();
+        let username_hash = base16ct::lower::encode_string(&Sha256::digest(b"test_user"));
+        app_state
+            .users
+            .pin()
+            .get(username_hash.as_str())
+            .unwrap()
+            .api_keys
+            .pin()
+            .insert(
+                Arc::from("test_key"),
+                ApiKey {
+                    secret: "test_key_secret".to_string(),
+                    expires_at: None,
+                    scope: None,
+                },
+            );
+        let proto_package = test_client_proto_package("not_the_key_secret");
+        let request = |key_id: &str| {
+            TestRequest::default()
+                .insert_header(("content-type", "application/protobuf"))
+                .insert_header(("0", username_hash.clone()))
+                .insert_header(("1", proto_package.signature.clone()))
+                .insert_header(("2", key_id))
+                .to_http_request()
+        };
+
+        let users_guard = app_state.users_guard();
+        let res = authenticate(
+            &request("test_key"),
+            &app_state,
+            &proto_package.data,
+            &users_guard,
+        )
+        .err()
+        .expect("Should fail verification");
+        let signature = res
+            .headers()
+            .get("0")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = res.into_body().try_into_bytes().unwrap();
+
+        assert_eq!(signature, generate_signature(&body, b"test_key_secret"));
+        assert_eq!(
+            authenticate(
+                &request("no_key"),
+                &app_state,
+                &proto_package.data,
+                &users_guard
+            )
+            .err()
+            .expect("Should be unauthorized")
+            .status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn test_authenticate__jwt_bearer_token() {
+        let app_state = test_app_state();
+        let authenticator: Arc<dyn Authenticator> = Arc::new(test_jwt_authenticator());
+        let token = test_token(
+            "test_kid",
+            test_claims("test_issuer", chrono::Utc::now().timestamp() + 60),
+        );
+        // the body of a request authenticated by a token isn't signed
+        let proto_package = test_client_proto_package("any");
+        let req = TestRequest::default()
+            .app_data(web::Data::from(authenticator))
+            .insert_header(("content-type", "application/protobuf"))
+            .insert_header(("authorization", format!("Bearer {}", token)))
+            .to_http_request();
+
+        let users_guard = app_state.users_guard();
+        let authenticated =
+            authenticate(&req, &app_state, &proto_package.data, &users_guard).unwrap();
+
+        assert_eq!(authenticated.user.username, "test_user");
+        assert_eq!(authenticated.request_key.secret, token);
+    }
+
+    #[test]
+    fn test_authenticate__jwt_missing_or_unknown_user() {
+        let app_state = test_app_state();
+        let authenticator: Arc<dyn Authenticator> = Arc::new(test_jwt_authenticator());
+        let token = test_token(
+            "test_kid",
+            json!({
+                "sub": "unknown_user",
+                "iss": "test_issuer",
+                "exp": chrono::Utc::now().timestamp() + 60
+            }),
+        );
+        let proto_package = test_client_proto_package("any");
+        let users_guard = app_state.users_guard();
+
+        let missing_token_req = TestRequest::default()
+            .app_data(web::Data::from(authenticator.clone()))
+            .insert_header(("content-type", "application/protobuf"))
+            .to_http_request();
+        let unknown_user_req = TestRequest::default()
+            .app_data(web::Data::from(authenticator))
+            .insert_header(("content-type", "application/protobuf"))
+            .insert_header(("authorization", format!("Bearer {}", token)))
+            .to_http_request();
+
+        assert_eq!(
+            authenticate(
+                &missing_token_req,
+                &app_state,
+                &proto_package.data,
+                &users_guard
+            )
+            .err()
+            .expect("Should be missing the token")
+            .status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            authenticate(
+                &unknown_user_req,
+                &app_state,
+                &proto_package.data,
+                &users_guard
+            )
+            .err()
+            .expect("Should be unauthorized")
+            .status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn test_jwt_authenticator__verify_token() {
+        let authenticator = test_jwt_authenticator();
+        let exp = chrono::Utc::now().timestamp() + 60;
+
+        assert_eq!(
+            authenticator
+                .verify_token(&test_token("test_kid", test_claims("test_issuer", exp)))
+                .unwrap(),
+            "test_user"
+        );
+        assert!(authenticator
+            .verify_token(&test_token("test_kid", test_claims("other_issuer", exp)))
+            .is_err());
+        assert!(authenticator
+            .verify_token(&test_token("other_kid", test_claims("test_issuer", exp)))
+            .is_err());
+        assert!(authenticator
+            .verify_token(&test_token(
+                "test_kid",
+                test_claims("test_issuer", chrono::Utc::now().timestamp() - 3600)
+            ))
+            .is_err());
+        assert!(authenticator
+            .verify_token(&test_token(
+                "test_kid",
+                json!({ "iss": "test_issuer", "exp": exp })
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_jwt_authenticator__algorithm_pinned_to_key() {
+        let claims = test_claims("test_issuer", chrono::Utc::now().timestamp() + 60);
+        let token = |alg: Algorithm| {
+            let header = Header {
+                kid: Some("test_kid".to_string()),
+                ..Header::new(alg)
+            };
+            jsonwebtoken::encode(
+                &header,
+                &claims,
+                &EncodingKey::from_secret(b"test_jwt_secret"),
+            )
+            .unwrap()
+        };
+        // the same key without alg
+        let jwks = json!({
+            "keys": [{ "kty": "oct", "kid": "test_kid", "k": "dGVzdF9qd3Rfc2VjcmV0" }]
+        });
+        let authenticator_without_alg =
+            JwtAuthenticator::new(serde_json::from_value(jwks).unwrap());
+
+        assert_eq!(
+            test_jwt_authenticator()
+                .verify_token(&token(Algorithm::HS384))
+                .expect_err("Should not match the key algorithm"),
+            ProtoPackageError::verification_error(
+                "token algorithm HS384 doesn't match the key algorithm HS256"
+            )
+        );
+        assert!(authenticator_without_alg
+            .verify_token(&token(Algorithm::HS256))
+            .is_err());
+        assert_eq!(
+            authenticator_without_alg
+                .with_algorithm(Some(Algorithm::HS256))
+                .verify_token(&token(Algorithm::HS256))
+                .unwrap(),
+            "test_user"
+        );
+    }
+
+    #[test]
+    fn test_mtls_authenticator__certificate_username() {
+        let certificate = rustls_pemfile::certs(&mut TEST_CERTIFICATE.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            MtlsAuthenticator::certificate_username(&certificate).unwrap(),
+            "test_user"
+        );
+        assert!(MtlsAuthenticator::certificate_username(b"not a certificate").is_err());
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+pub mod health {
+    use crate::{
+        core::state::{BackupStatus, ReplicationStatus},
+        web::controller::health::{BackupsHealth, ReplicationHealth},
+    };
+
+    #[test]
+    fn test_backups_health__from_statuses() {
+        let statuses = [
+            BackupStatus {
+                last_run_at: Some("2026-10-19T10:00:00+00:00".to_string()),
+                last_file: Some("backup_1.db".to_string()),
+                last_error: None,
+                next_run_at: Some("2026-10-19T12:00:00+00:00".to_string()),
+            },
+            BackupStatus {
+                last_run_at: Some("2026-10-19T11:00:00+00:00".to_string()),
+                last_file: None,
+                last_error: Some("disk full at /var/serf/backup".to_string()),
+                next_run_at: Some("2026-10-19T11:30:00+00:00".to_string()),
+            },
+            BackupStatus::default(),
+        ];
+
+        assert_eq!(
+            BackupsHealth::from_statuses(statuses.iter()),
+            BackupsHealth {
+                ok: 2,
+                failed: 1,
+                last_run_at: Some("2026-10-19T11:00:00+00:00".to_string()),
+                next_run_at: Some("2026-10-19T11:30:00+00:00".to_string()),
+            }
+        );
+        assert_eq!(
+            BackupsHealth::from_statuses([].iter()),
+            BackupsHealth::default()
+        );
+    }
+
+    #[test]
+    fn test_replication_health__from_statuses() {
+        let statuses = [
+            ReplicationStatus {
+                applied_id: 10,
+                primary_last_id: 12,
+                lag: 2,
+                last_synced_at: Some("2026-10-19T10:00:00+00:00".to_string()),
+                last_error: None,
+            },
+            ReplicationStatus {
+                applied_id: 5,
+                primary_last_id: 5,
+                lag: 0,
+                last_synced_at: Some("2026-10-19T09:00:00+00:00".to_string()),
+                last_error: Some("connection refused".to_string()),
+            },
+        ];
+
+        assert_eq!(
+            ReplicationHealth::from_statuses(statuses.iter()),
+            ReplicationHealth {
+                ok: 1,
+                failed: 1,
+                max_lag: 2,
+                oldest_synced_at: Some("2026-10-19T09:00:00+00:00".to_string()),
+            }
+        );
+        assert_eq!(
+            ReplicationHealth::from_statuses(
+                statuses.iter().chain([ReplicationStatus::default()].iter())
+            )
+            .oldest_synced_at,
+            None
+        );
+    }
+}
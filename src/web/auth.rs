@@ -0,0 +1,443 @@
+use std::{any::Any, fs, io::BufReader, path::Path, sync::Arc};
+
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::{dev::Extensions, rt::net::TcpStream, web, HttpRequest, HttpResponse};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use papaya::LocalGuard;
+use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+use serde_json::{Map, Value as JsonValue};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    core::{
+        error::{
+            HeaderMalformedError, ProtoPackageError, SerfError, UndefinedError, UserNotExistError,
+        },
+        role,
+        serf_proto::{Error, Iss, Request},
+        state::{AppState, User},
+    },
+    web::{
+        proto::{decode_proto, encode_error_proto, ProtoPackageVerifier, RequestKey},
+        util::{
+            check_content_type, extract_headers, get_header_value, get_key_id_header,
+            HttpProtoResponse,
+        },
+    },
+};
+
+/// #### A request verified by an Authenticator
+/// \
+/// The request_key holds the secret the response is signed with and the permissions the request is limited to.
+pub struct Authenticated<'guard> {
+    pub user: &'guard User,
+    pub request: Request,
+    pub request_key: RequestKey,
+}
+
+/// #### Verifies who a request is made by
+/// \
+/// The server runs with a single authenticator, registered as ```web::Data<dyn Authenticator>``` app data.
+/// The HmacAuthenticator is used when none is registered.
+/// \
+/// A rejected request is returned as the response to send, I.E 401 when no user could be found for the request.
+pub trait Authenticator: Send + Sync {
+    fn authenticate<'guard>(
+        &self,
+        req: &HttpRequest,
+        data: &AppState,
+        req_body: &[u8],
+        users_guard: &'guard LocalGuard<'_>,
+    ) -> Result<Authenticated<'guard>, HttpResponse>;
+}
+
+/// #### Authenticates a request with the authenticator of the server
+/// \
+/// __*@returns*__ Result\<Authenticated, HttpResponse\> (the response to send when the request is rejected)
+pub fn authenticate<'guard>(
+    req: &HttpRequest,
+    data: &AppState,
+    req_body: &[u8],
+    users_guard: &'guard LocalGuard<'_>,
+) -> Result<Authenticated<'guard>, HttpResponse> {
+    get_header_value(req.headers().get("content-type"))
+        .and_then(check_content_type)
+        .map_err(|e| HttpResponse::BadRequest().body(e.message))?;
+
+    match req.app_data::<web::Data<dyn Authenticator>>() {
+        Some(authenticator) => authenticator.authenticate(req, data, req_body, users_guard),
+        None => HmacAuthenticator.authenticate(req, data, req_body, users_guard),
+    }
+}
+
+/// #### The username hash (sha256 hex) the users of the app state are keyed by
+fn username_hash(username: &str) -> String {
+    base16ct::lower::encode_string(&Sha256::digest(username.as_bytes()))
+}
+
+/// #### Decodes the request of a user that was verified by its token or certificate
+/// \
+/// The request body isn't signed then, errors are signed with the secret of the request_key.
+fn decode_request<'guard>(
+    user: &'guard User,
+    request_key: RequestKey,
+    req_body: &[u8],
+) -> Result<Authenticated<'guard>, HttpResponse> {
+    let request = ProtoPackageVerifier::builder()
+        .with_issuer(Iss::Client)
+        .build()
+        .verify_claims(req_body)
+        .map_err(|e| {
+            HttpResponse::InternalServerError().protobuf(encode_error_proto(e, &request_key.secret))
+        })?;
+
+    Ok(Authenticated {
+        user,
+        request,
+        request_key,
+    })
+}
+
+/// #### Requests signed with the username_password_hash or an API key of the user
+/// \
+/// Header 0 holds the username hash, header 1 the HMAC-SHA256 signature of the body and header 2 the
+/// name of the API key when the request is signed with one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HmacAuthenticator;
+
+impl Authenticator for HmacAuthenticator {
+    fn authenticate<'guard>(
+        &self,
+        req: &HttpRequest,
+        data: &AppState,
+        req_body: &[u8],
+        users_guard: &'guard LocalGuard<'_>,
+    ) -> Result<Authenticated<'guard>, HttpResponse> {
+        let (_, header_username_hash, header_proto_signature) = extract_headers(req.headers())
+            .map_err(|e| HttpResponse::BadRequest().body(e.message))?;
+
+        let user = data
+            .get_user(header_username_hash, users_guard)
+            .ok_or_else(|| {
+                HttpResponse::Unauthorized().body(UserNotExistError::default().message)
+            })?;
+
+        let key_id = get_key_id_header(req.headers());
+        let (request, request_key) = decode_proto(req_body, user, key_id, header_proto_signature)
+            .map_err(|e| {
+            // signed with the secret the request claims to be signed with, a client holding only an
+            // API key can't verify a response signed with the username_password_hash
+            let secret = match key_id {
+                None => Some(user.username_password_hash.clone()),
+                Some(key_id) => user
+                    .api_keys
+                    .pin()
+                    .get(key_id)
+                    .map(|api_key| api_key.secret.clone()),
+            };
+
+            match secret {
+                Some(secret) => {
+                    HttpResponse::InternalServerError().protobuf(encode_error_proto(e, &secret))
+                }
+                None => HttpResponse::Unauthorized().body(e.message),
+            }
+        })?;
+
+        Ok(Authenticated {
+            user,
+            request,
+            request_key,
+        })
+    }
+}
+
+/// #### Requests carrying a signed JWT as bearer token, verified against the keys of a JWKS file
+/// \
+/// The username is read from the username claim of the token (sub by default), the request body isn't signed
+/// and the response is signed with the token.
+pub struct JwtAuthenticator {
+    jwks: JwkSet,
+    /// the algorithm of the keys that don't set alg
+    algorithm: Option<Algorithm>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    username_claim: String,
+}
+
+impl JwtAuthenticator {
+    pub fn new(jwks: JwkSet) -> Self {
+        JwtAuthenticator {
+            jwks,
+            algorithm: None,
+            issuer: None,
+            audience: None,
+            username_claim: String::from("sub"),
+        }
+    }
+
+    /// #### Reads the keys the tokens are verified with from a JWKS file
+    pub fn from_jwks_file(path: &Path) -> Result<Self, Error> {
+        let jwks = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|jwks| serde_json::from_str(&jwks).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                UndefinedError::with_message(&format!(
+                    "JWKS file {} could not be read: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+        Ok(JwtAuthenticator::new(jwks))
+    }
+
+    pub fn with_algorithm(self, algorithm: Option<Algorithm>) -> Self {
+        JwtAuthenticator { algorithm, ..self }
+    }
+
+    pub fn with_issuer(self, issuer: Option<String>) -> Self {
+        JwtAuthenticator { issuer, ..self }
+    }
+
+    pub fn with_audience(self, audience: Option<String>) -> Self {
+        JwtAuthenticator { audience, ..self }
+    }
+
+    pub fn with_username_claim(self, username_claim: String) -> Self {
+        JwtAuthenticator {
+            username_claim,
+            ..self
+        }
+    }
+
+    /// #### Verifies the token and returns the username it was issued to
+    /// \
+    /// The token is verified with the algorithm of its key (the alg of the JWK, or the configured algorithm
+    /// when the JWK has none), never with the alg the token header claims, which has to match it.
+    pub fn verify_token(&self, token: &str) -> Result<String, Error> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|_| ProtoPackageError::verification_error("malformed token"))?;
+        // a token without a key id can only be verified by the single key of the JWKS
+        let jwk = match &header.kid {
+            Some(kid) => self.jwks.find(kid),
+            None if self.jwks.keys.len() == 1 => self.jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| ProtoPackageError::verification_error("unknown token key"))?;
+        let decoding_key = DecodingKey::from_jwk(jwk)
+            .map_err(|_| ProtoPackageError::verification_error("unsupported token key"))?;
+
+        let algorithm = match jwk.common.key_algorithm {
+            Some(key_algorithm) => key_algorithm.to_string().parse::<Algorithm>().ok(),
+            None => self.algorithm,
+        }
+        .ok_or_else(|| ProtoPackageError::verification_error("unsupported token key algorithm"))?;
+        if header.alg != algorithm {
+            return Err(ProtoPackageError::verification_error(&format!(
+                "token algorithm {:?} doesn't match the key algorithm {:?}",
+                header.alg, algorithm
+            )));
+        }
+
+        let mut validation = Validation::new(algorithm);
+        validation.validate_aud = self.audience.is_some();
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+        }
+
+        let token_data =
+            jsonwebtoken::decode::<Map<String, JsonValue>>(token, &decoding_key, &validation)
+                .map_err(|e| {
+                    ProtoPackageError::verification_error(&format!("invalid token: {}", e))
+                })?;
+
+        token_data
+            .claims
+            .get(&self.username_claim)
+            .and_then(JsonValue::as_str)
+            .filter(|username| !username.is_empty())
+            .map(String::from)
+            .ok_or_else(|| {
+                ProtoPackageError::verification_error(&format!(
+                    "token is missing the {} claim",
+                    self.username_claim
+                ))
+            })
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    fn authenticate<'guard>(
+        &self,
+        req: &HttpRequest,
+        data: &AppState,
+        req_body: &[u8],
+        users_guard: &'guard LocalGuard<'_>,
+    ) -> Result<Authenticated<'guard>, HttpResponse> {
+        let token = get_header_value(req.headers().get("authorization"))
+            .and_then(|authorization| {
+                authorization
+                    .strip_prefix("Bearer ")
+                    .ok_or_else(|| HeaderMalformedError::with_message("expected a Bearer token"))
+            })
+            .map_err(|e| HttpResponse::BadRequest().body(e.message))?;
+
+        let username = self
+            .verify_token(token)
+            .map_err(|e| HttpResponse::Unauthorized().body(e.message))?;
+        let user = data
+            .get_user(&username_hash(&username), users_guard)
+            .ok_or_else(|| {
+                HttpResponse::Unauthorized().body(UserNotExistError::default().message)
+            })?;
+
+        decode_request(
+            user,
+            RequestKey {
+                secret: token.to_string(),
+                scope: role::ALL,
+            },
+            req_body,
+        )
+    }
+}
+
+/// #### The DER of the certificate the client presented on the TLS connection
+#[derive(Debug, Clone)]
+pub struct ClientCertificate(pub Vec<u8>);
+
+/// #### Keeps the client certificate of a TLS connection for the requests made over it
+/// \
+/// Passed to HttpServer::on_connect, the certificate is read with ```req.conn_data::<ClientCertificate>()```.
+pub fn client_certificate_on_connect(connection: &dyn Any, data: &mut Extensions) {
+    if let Some(tls_stream) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+        let (_, session) = tls_stream.get_ref();
+        if let Some(certificate) = session.peer_certificates().and_then(|certs| certs.first()) {
+            data.insert(ClientCertificate(certificate.to_vec()));
+        }
+    }
+}
+
+/// #### Requests made over a TLS connection with a client certificate issued by the client CA
+/// \
+/// The certificate chain is verified during the TLS handshake (see tls_server_config), the common name of
+/// the certificate subject is the username. The request body isn't signed and the response is signed
+/// with the sha256 hex of the certificate DER.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MtlsAuthenticator;
+
+impl MtlsAuthenticator {
+    /// #### The username a client certificate is issued to, the common name of its subject
+    pub fn certificate_username(certificate: &[u8]) -> Result<String, Error> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(certificate)
+            .map_err(|_| ProtoPackageError::verification_error("malformed client certificate"))?;
+
+        let username = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|common_name| common_name.as_str().ok())
+            .filter(|username| !username.is_empty())
+            .map(String::from);
+
+        username.ok_or_else(|| {
+            ProtoPackageError::verification_error("client certificate has no common name")
+        })
+    }
+}
+
+impl Authenticator for MtlsAuthenticator {
+    fn authenticate<'guard>(
+        &self,
+        req: &HttpRequest,
+        data: &AppState,
+        req_body: &[u8],
+        users_guard: &'guard LocalGuard<'_>,
+    ) -> Result<Authenticated<'guard>, HttpResponse> {
+        let certificate = req.conn_data::<ClientCertificate>().ok_or_else(|| {
+            HttpResponse::Unauthorized()
+                .body(ProtoPackageError::verification_error("missing client certificate").message)
+        })?;
+
+        let username = MtlsAuthenticator::certificate_username(&certificate.0)
+            .map_err(|e| HttpResponse::Unauthorized().body(e.message))?;
+        let user = data
+            .get_user(&username_hash(&username), users_guard)
+            .ok_or_else(|| {
+                HttpResponse::Unauthorized().body(UserNotExistError::default().message)
+            })?;
+
+        decode_request(
+            user,
+            RequestKey {
+                secret: base16ct::lower::encode_string(&Sha256::digest(&certificate.0)),
+                scope: role::ALL,
+            },
+            req_body,
+        )
+    }
+}
+
+fn read_pem_file<T>(
+    path: &Path,
+    read: impl FnOnce(&mut BufReader<fs::File>) -> Result<T, std::io::Error>,
+) -> Result<T, Error> {
+    fs::File::open(path)
+        .and_then(|file| read(&mut BufReader::new(file)))
+        .map_err(|e| {
+            UndefinedError::with_message(&format!(
+                "PEM file {} could not be read: {}",
+                path.display(),
+                e
+            ))
+        })
+}
+
+/// #### The TLS config of the server
+/// \
+/// __*@param*__ client_ca: Option\<&Path\> (PEM of the CAs client certificates must be issued by,
+/// None = clients aren't asked for a certificate)
+/// \
+/// __*@returns*__ Result\<ServerConfig, Error\>
+pub fn tls_server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<ServerConfig, Error> {
+    let tls_error = |e: rustls::Error| UndefinedError::with_message(&format!("TLS: {}", e));
+    let certs = read_pem_file(cert, |reader| {
+        rustls_pemfile::certs(reader).collect::<Result<Vec<_>, _>>()
+    })?;
+    let key =
+        read_pem_file(key, |reader| rustls_pemfile::private_key(reader))?.ok_or_else(|| {
+            UndefinedError::with_message(&format!("no private key in {}", key.display()))
+        })?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for ca in read_pem_file(client_ca, |reader| {
+                rustls_pemfile::certs(reader).collect::<Result<Vec<_>, _>>()
+            })? {
+                roots.add(ca).map_err(tls_error)?;
+            }
+
+            let client_verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                    .build()
+                    .map_err(|e| UndefinedError::with_message(&format!("TLS: {}", e)))?;
+            builder.with_client_cert_verifier(client_verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    builder.with_single_cert(certs, key).map_err(tls_error)
+}
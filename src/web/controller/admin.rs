@@ -0,0 +1,479 @@
+use std::path::Path;
+
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    core::{
+        audit::{AuditEntry, AUDIT_SOURCE_ADMIN},
+        error::{
+            DatabaseError, ResourceNotExistError, SerfError, UndefinedError, UserNotAllowedError,
+        },
+        role,
+        serf_proto::{
+            claims::Dat, AdminResponse, BackupRequest, BackupResponse, Claims, Error, ErrorKind,
+            ListDatabasesResponse, ListUsersResponse, RestoreRequest, RestoreResponse, Sub,
+        },
+        state::AppState,
+        util::{
+            backup_db, get_db_users, populate_app_state_users, record_audit_entry, restore_db,
+            with_db_closed,
+        },
+    },
+    web::{
+        auth::{authenticate, Authenticated},
+        proto::{encode_error_proto, encode_proto, ProtoPackage},
+        util::{database_manager, HttpProtoResponse},
+    },
+};
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(handle_admin_backup_post);
+    cfg.service(handle_admin_restore_post);
+    cfg.service(handle_admin_users_create_post);
+    cfg.service(handle_admin_users_delete_post);
+    cfg.service(handle_admin_users_list_post);
+    cfg.service(handle_admin_access_grant_post);
+    cfg.service(handle_admin_access_revoke_post);
+    cfg.service(handle_admin_databases_create_post);
+    cfg.service(handle_admin_databases_drop_post);
+    cfg.service(handle_admin_databases_list_post);
+}
+
+#[post("/admin/backup")]
+async fn handle_admin_backup_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (username, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+    let audit_entry = admin_audit_entry(&username, &claims);
+
+    let proto_package = match (claims.sub(), &claims.dat) {
+        (Sub::Admin, Some(Dat::BackupRequest(dat))) => {
+            backup_db(&data, &backup_database_hash(&dat.database), dat.compress)
+                .await
+                .and_then(|manifest| {
+                    encode_proto(
+                        BackupResponse::as_dat(manifest.file, manifest.size, manifest.sha256),
+                        Sub::Data,
+                        &secret,
+                    )
+                })
+        }
+        _ => Err(UndefinedError::default()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, audit_entry.with_result(&proto_package));
+    }
+
+    admin_response(proto_package, &secret)
+}
+
+#[post("/admin/restore")]
+async fn handle_admin_restore_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (username, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+    let audit_entry = admin_audit_entry(&username, &claims);
+
+    let proto_package = match (claims.sub(), &claims.dat) {
+        (Sub::Admin, Some(Dat::RestoreRequest(dat))) => {
+            restore_db(&data, &backup_database_hash(&dat.database), &dat.file)
+                .await
+                .and_then(|_| encode_proto(RestoreResponse::as_dat(true), Sub::Data, &secret))
+        }
+        _ => Err(UndefinedError::default()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, audit_entry.with_result(&proto_package));
+    }
+
+    admin_response(proto_package, &secret)
+}
+
+#[post("/admin/users/create")]
+async fn handle_admin_users_create_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (username, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+    let audit_entry = admin_audit_entry(&username, &claims);
+
+    let proto_package = match (claims.sub(), claims.dat) {
+        (Sub::Admin, Some(Dat::CreateUserRequest(dat))) => {
+            let database_manager = database_manager(&data);
+            let res = match database_manager
+                .create_user(dat.username.clone(), dat.password)
+                .await
+            {
+                Ok(_) if dat.is_admin => {
+                    database_manager.modify_user_admin(dat.username, true).await
+                }
+                res => res,
+            };
+
+            admin_state_response(res, &data, &secret).await
+        }
+        _ => Err(UndefinedError::default()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, audit_entry.with_result(&proto_package));
+    }
+
+    admin_response(proto_package, &secret)
+}
+
+#[post("/admin/users/delete")]
+async fn handle_admin_users_delete_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (username, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+    let audit_entry = admin_audit_entry(&username, &claims);
+
+    let proto_package = match (claims.sub(), claims.dat) {
+        (Sub::Admin, Some(Dat::DeleteUserRequest(dat))) => {
+            let res = database_manager(&data).delete_user(dat.username).await;
+
+            admin_state_response(res, &data, &secret).await
+        }
+        _ => Err(UndefinedError::default()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, audit_entry.with_result(&proto_package));
+    }
+
+    admin_response(proto_package, &secret)
+}
+
+/// Lists every user, or the user named in the request, with their limits and roles.
+#[post("/admin/users/list")]
+async fn handle_admin_users_list_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (_, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+
+    let proto_package = match (claims.sub(), claims.dat) {
+        (Sub::Admin, Some(Dat::ListUsersRequest(dat))) => {
+            let username = Some(dat.username).filter(|username| !username.is_empty());
+            database_manager(&data)
+                .get_users(username.as_deref())
+                .await
+                .and_then(|users| {
+                    encode_proto(ListUsersResponse::as_dat(users), Sub::Data, &secret)
+                })
+        }
+        _ => Err(UndefinedError::default()),
+    };
+
+    admin_response(proto_package, &secret)
+}
+
+#[post("/admin/access/grant")]
+async fn handle_admin_access_grant_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (username, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+    let audit_entry = admin_audit_entry(&username, &claims);
+
+    let proto_package = match (claims.sub(), claims.dat) {
+        (Sub::Admin, Some(Dat::GrantAccessRequest(dat))) => {
+            let res = if database_exists(&data, &dat.database) {
+                database_manager(&data)
+                    .modify_user_access(dat.username, dat.database, dat.role)
+                    .await
+            } else {
+                Err(ResourceNotExistError::with_message(
+                    "Database doesn't exist",
+                ))
+            };
+
+            admin_state_response(res, &data, &secret).await
+        }
+        _ => Err(UndefinedError::default()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, audit_entry.with_result(&proto_package));
+    }
+
+    admin_response(proto_package, &secret)
+}
+
+#[post("/admin/access/revoke")]
+async fn handle_admin_access_revoke_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (username, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+    let audit_entry = admin_audit_entry(&username, &claims);
+
+    let proto_package = match (claims.sub(), claims.dat) {
+        (Sub::Admin, Some(Dat::RevokeAccessRequest(dat))) => {
+            let res = database_manager(&data)
+                .revoke_user_access(dat.username, dat.database)
+                .await;
+
+            admin_state_response(res, &data, &secret).await
+        }
+        _ => Err(UndefinedError::default()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, audit_entry.with_result(&proto_package));
+    }
+
+    admin_response(proto_package, &secret)
+}
+
+#[post("/admin/databases/create")]
+async fn handle_admin_databases_create_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (username, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+    let audit_entry = admin_audit_entry(&username, &claims);
+
+    let proto_package = match (claims.sub(), claims.dat) {
+        (Sub::Admin, Some(Dat::CreateDatabaseRequest(dat))) => database_manager(&data)
+            .create_consumer_database_from(
+                &dat.database,
+                Some(dat.template.as_str()).filter(|template| !template.is_empty()),
+                dat.schema_only,
+            )
+            .await
+            .and_then(|_| encode_proto(AdminResponse::as_dat(true), Sub::Data, &secret)),
+        _ => Err(UndefinedError::default()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, audit_entry.with_result(&proto_package));
+    }
+
+    admin_response(proto_package, &secret)
+}
+
+/// Drops a db and all access to it, the pools of the db are closed first.
+#[post("/admin/databases/drop")]
+async fn handle_admin_databases_drop_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (username, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+    let audit_entry = admin_audit_entry(&username, &claims);
+
+    let proto_package = match (claims.sub(), claims.dat) {
+        (Sub::Admin, Some(Dat::DropDatabaseRequest(dat))) => {
+            let database_manager = database_manager(&data);
+            let res = with_db_closed(
+                &data,
+                &database_name_hash(&dat.database),
+                database_manager.drop_database(dat.database, true),
+            )
+            .await;
+
+            admin_state_response(res, &data, &secret).await
+        }
+        _ => Err(UndefinedError::default()),
+    };
+
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, audit_entry.with_result(&proto_package));
+    }
+
+    admin_response(proto_package, &secret)
+}
+
+/// Lists every db with its name, hash, size, WAL size and the users that can access it.
+#[post("/admin/databases/list")]
+async fn handle_admin_databases_list_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let (_, secret, claims) = match decode_admin_request(&req, &data, &req_body) {
+        Ok(decoded) => decoded,
+        Err(res) => return res,
+    };
+
+    let proto_package = match (claims.sub(), claims.dat) {
+        (Sub::Admin, Some(Dat::ListDatabasesRequest(_))) => database_manager(&data)
+            .get_databases()
+            .await
+            .and_then(|databases| {
+                encode_proto(ListDatabasesResponse::as_dat(databases), Sub::Data, &secret)
+            }),
+        _ => Err(UndefinedError::default()),
+    };
+
+    admin_response(proto_package, &secret)
+}
+
+fn database_name_hash(database_name: &str) -> String {
+    base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()))
+}
+
+/// The db name hash of a backup or restore request, the empty name of the users db stays empty
+fn backup_database_hash(database_name: &str) -> String {
+    match database_name {
+        "" => String::new(),
+        database_name => database_name_hash(database_name),
+    }
+}
+
+fn database_exists(data: &AppState, database_name: &str) -> bool {
+    let database_name_hash = database_name_hash(database_name);
+
+    Path::new(&data.db_path)
+        .join(&database_name_hash)
+        .join(format!("{}.db", database_name_hash))
+        .exists()
+}
+
+/// #### Reloads the users of the app state after a change to users or access, rather than waiting for the watcher
+/// \
+/// __*@returns*__ Result\<ProtoPackage, Error\> (an AdminResponse when the change was made)
+async fn admin_state_response(
+    res: Result<(), Error>,
+    data: &web::Data<AppState>,
+    secret: &str,
+) -> Result<ProtoPackage, Error> {
+    res?;
+
+    let db_users = get_db_users(&data.user_db_path)
+        .await
+        .map_err(|e| DatabaseError::with_message(&e.to_string()))?;
+    populate_app_state_users(db_users, data);
+
+    encode_proto(AdminResponse::as_dat(true), Sub::Data, secret)
+}
+
+/// #### Verifies that the request is made by a user with the admin permission in its global role
+/// \
+/// __*@returns*__ Result\<(String, String, Claims), HttpResponse\> (the username, the secret of the key the request is signed with and the request claims)
+fn decode_admin_request(
+    req: &HttpRequest,
+    data: &AppState,
+    req_body: &[u8],
+) -> Result<(String, String, Claims), HttpResponse> {
+    let users_guard = data.users_guard();
+    let Authenticated {
+        user,
+        request,
+        request_key,
+    } = authenticate(req, data, req_body, &users_guard)?;
+
+    let claims = request.claims.unwrap();
+    // a role with the admin permission on a single db only allows backup and restore of that db
+    let is_db_admin = match &claims.dat {
+        Some(Dat::BackupRequest(BackupRequest { database, .. }))
+        | Some(Dat::RestoreRequest(RestoreRequest { database, .. })) => {
+            !database.is_empty() && user.has_permission(&database_name_hash(database), role::ADMIN)
+        }
+        _ => false,
+    };
+    let is_admin = user.permissions & role::ADMIN != 0 || is_db_admin;
+
+    if !is_admin || request_key.scope & role::ADMIN == 0 {
+        return Err(HttpResponse::Forbidden().protobuf(encode_error_proto(
+            UserNotAllowedError::default(),
+            &request_key.secret,
+        )));
+    }
+
+    Ok((user.username.clone(), request_key.secret, claims))
+}
+
+/// #### The audit log entry of an admin request, None for requests that don't change anything (listings)
+fn admin_audit_entry(username: &str, claims: &Claims) -> Option<AuditEntry> {
+    let entry = |action: &str| AuditEntry::new(AUDIT_SOURCE_ADMIN, username, action);
+
+    let entry = match &claims.dat {
+        Some(Dat::BackupRequest(dat)) if dat.database.is_empty() => entry("backup users"),
+        Some(Dat::BackupRequest(dat)) => entry("backup database").with_database_name(&dat.database),
+        Some(Dat::RestoreRequest(dat)) => {
+            entry(&format!("restore database from {}", dat.file)).with_database_name(&dat.database)
+        }
+        Some(Dat::CreateUserRequest(dat)) => entry(if dat.is_admin {
+            "create admin user"
+        } else {
+            "create user"
+        })
+        .with_target_user(&dat.username),
+        Some(Dat::DeleteUserRequest(dat)) => entry("delete user").with_target_user(&dat.username),
+        Some(Dat::GrantAccessRequest(dat)) => entry(&format!("grant role {}", dat.role))
+            .with_target_user(&dat.username)
+            .with_database_name(&dat.database),
+        Some(Dat::RevokeAccessRequest(dat)) => entry("revoke access")
+            .with_target_user(&dat.username)
+            .with_database_name(&dat.database),
+        Some(Dat::CreateDatabaseRequest(dat)) if dat.template.is_empty() => {
+            entry("create database").with_database_name(&dat.database)
+        }
+        Some(Dat::CreateDatabaseRequest(dat)) => {
+            entry(&format!("create database from {}", dat.template))
+                .with_database_name(&dat.database)
+        }
+        Some(Dat::DropDatabaseRequest(dat)) => {
+            entry("drop database").with_database_name(&dat.database)
+        }
+        _ => return None,
+    };
+
+    Some(entry)
+}
+
+fn admin_response(proto_package: Result<ProtoPackage, Error>, secret: &str) -> HttpResponse {
+    match proto_package {
+        Ok(proto_package) => HttpResponse::Ok().protobuf(proto_package),
+        Err(e) => match e.source() {
+            ErrorKind::ResourceNotExist | ErrorKind::UserNotExist => {
+                HttpResponse::NotFound().protobuf(encode_error_proto(e, secret))
+            }
+            _ => HttpResponse::InternalServerError().protobuf(encode_error_proto(e, secret)),
+        },
+    }
+}
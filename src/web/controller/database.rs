@@ -1,17 +1,23 @@
+use std::sync::Arc;
+
 use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use sha2::{Digest, Sha256};
 
 use crate::{
     core::{
-        error::{SerfError, UserNotExistError},
-        serf_proto::{ErrorKind, Request},
+        audit::{AuditEntry, AUDIT_SOURCE_PROTOCOL},
+        error::{NotPrimaryError, SerfError, UndefinedError, UserNotAllowedError},
+        role,
+        serf_proto::{claims::Dat, CreateDatabaseResponse, ErrorKind, Sub},
         state::AppState,
-        util::get_or_insert_db_connection,
+        util::{get_or_insert_db_connection, record_audit_entry},
     },
     web::{
-        proto::{decode_proto, encode_error_proto},
+        auth::{authenticate, Authenticated},
+        proto::{encode_error_proto, encode_proto},
         util::{
-            check_content_type, extract_headers, get_proto_package_result, HttpProtoResponse,
-            ProtoPackageResultHandler,
+            database_manager, get_proto_package_result, protocol_audit_entry, with_audit_outcome,
+            HttpProtoResponse, ProtoPackageResultHandler,
         },
     },
 };
@@ -19,6 +25,7 @@ use crate::{
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(handle_db_post);
     cfg.service(handle_db_migration_post);
+    cfg.service(handle_db_create_post);
 }
 
 #[post("/{database}")]
@@ -28,66 +35,74 @@ async fn handle_db_post(
     path: web::Path<String>,
     req_body: web::Bytes,
 ) -> impl Responder {
-    let (header_content_type, header_username_hash, header_proto_signature) =
-        match extract_headers(req.headers()) {
-            Ok((h1, h2, h3)) => (h1, h2, h3),
-            Err(e) => return HttpResponse::BadRequest().body(e.message),
-        };
-
-    if let Err(e) = check_content_type(header_content_type) {
-        HttpResponse::BadRequest().body(e.message);
-    }
-
     let db_name = path.into_inner();
     let users_guard = data.users_guard();
-    let user = match data.get_user(header_username_hash, &users_guard) {
-        Some(val) => val,
-        None => {
-            return HttpResponse::Unauthorized().body(UserNotExistError::default().message);
-        }
+    let Authenticated {
+        user,
+        request,
+        request_key,
+    } = match authenticate(&req, &data, &req_body, &users_guard) {
+        Ok(authenticated) => authenticated,
+        Err(res) => return res,
     };
 
-    let decoded_proto: Request = match decode_proto(
-        req_body.iter().as_slice(),
-        &user.username_password_hash,
-        header_proto_signature,
-    ) {
-        Ok(decoded) => decoded,
-        Err(e) => {
-            return HttpResponse::InternalServerError()
-                .protobuf(encode_error_proto(e, &user.username_password_hash));
-        }
-    };
-
-    let claims = decoded_proto.claims.unwrap();
+    let claims = request.claims.unwrap();
     let db_connections_guard = data.db_connections_guard();
     let db = match get_or_insert_db_connection(&data, &db_name, &db_connections_guard).await {
         Ok(conn) => conn,
         Err(e) => {
-            return HttpResponse::NotFound()
-                .protobuf(encode_error_proto(e, &user.username_password_hash));
+            return HttpResponse::NotFound().protobuf(encode_error_proto(e, &request_key.secret));
         }
     };
 
-    let proto_package = match get_proto_package_result(
+    let audit_entry = protocol_audit_entry(&user.username, &db_name, &claims);
+    let res = get_proto_package_result(
         claims,
         &ProtoPackageResultHandler::new(
-            user.get_access_right(&db_name),
-            &user.username_password_hash,
-            &db,
-        ),
+            request_key.get_permissions(user, &db_name),
+            &request_key.secret,
+            &db.writer,
+        )
+        .with_read_db(&db.reader)
+        .with_query_timeout(user.query_timeout.unwrap_or(data.query_timeout))
+        .with_fetch_limits(
+            user.max_rows.unwrap_or(data.max_rows),
+            user.max_response_bytes.unwrap_or(data.max_response_bytes),
+        )
+        .with_replication(db.replication.as_ref())
+        .with_primary(data.primary.as_deref()),
     )
-    .await
-    {
-        Ok(res) => res,
+    .await;
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, with_audit_outcome(audit_entry, &res));
+    }
+
+    let proto_package = match res {
+        Ok(handled_request) => handled_request.proto_package,
         Err(e) => match e.source() {
             ErrorKind::UserNotAllowed => {
                 return HttpResponse::Forbidden()
-                    .protobuf(encode_error_proto(e, &user.username_password_hash))
+                    .protobuf(encode_error_proto(e, &request_key.secret))
+            }
+            ErrorKind::ResourceNotExist => {
+                return HttpResponse::NotFound()
+                    .protobuf(encode_error_proto(e, &request_key.secret))
+            }
+            ErrorKind::NotPrimary => {
+                return HttpResponse::MisdirectedRequest()
+                    .insert_header((
+                        "Location",
+                        format!(
+                            "{}{}",
+                            data.primary.as_deref().unwrap_or_default(),
+                            req.path()
+                        ),
+                    ))
+                    .protobuf(encode_error_proto(e, &request_key.secret))
             }
             _ => {
                 return HttpResponse::InternalServerError()
-                    .protobuf(encode_error_proto(e, &user.username_password_hash))
+                    .protobuf(encode_error_proto(e, &request_key.secret))
             }
         },
     };
@@ -102,69 +117,182 @@ async fn handle_db_migration_post(
     path: web::Path<String>,
     req_body: web::Bytes,
 ) -> impl Responder {
-    let (header_content_type, header_username_hash, header_proto_signature) =
-        match extract_headers(req.headers()) {
-            Ok((h1, h2, h3)) => (h1, h2, h3),
-            Err(e) => return HttpResponse::BadRequest().body(e.message),
-        };
-
-    if let Err(e) = check_content_type(header_content_type) {
-        HttpResponse::BadRequest().body(e.message);
-    }
-
     let db_name = path.into_inner();
     let users_guard = data.users_guard();
-    let user = match data.get_user(header_username_hash, &users_guard) {
-        Some(val) => val,
-        None => {
-            return HttpResponse::Unauthorized().body(UserNotExistError::default().message);
-        }
+    let Authenticated {
+        user,
+        request,
+        request_key,
+    } = match authenticate(&req, &data, &req_body, &users_guard) {
+        Ok(authenticated) => authenticated,
+        Err(res) => return res,
     };
 
-    let decoded_proto: Request = match decode_proto(
-        req_body.iter().as_slice(),
-        &user.username_password_hash,
-        header_proto_signature,
-    ) {
-        Ok(decoded) => decoded,
-        Err(e) => {
-            return HttpResponse::InternalServerError()
-                .protobuf(encode_error_proto(e, &user.username_password_hash));
-        }
-    };
-
-    let claims = decoded_proto.claims.unwrap();
+    let claims = request.claims.unwrap();
     let db_connections_guard = data.db_connections_guard();
     let db = match get_or_insert_db_connection(&data, &db_name, &db_connections_guard).await {
         Ok(conn) => conn,
         Err(e) => {
-            return HttpResponse::NotFound()
-                .protobuf(encode_error_proto(e, &user.username_password_hash));
+            return HttpResponse::NotFound().protobuf(encode_error_proto(e, &request_key.secret));
         }
     };
 
-    let proto_package = match get_proto_package_result(
+    let audit_entry = protocol_audit_entry(&user.username, &db_name, &claims);
+    let res = get_proto_package_result(
         claims,
         &ProtoPackageResultHandler::new(
-            user.get_access_right(&db_name),
-            &user.username_password_hash,
-            &db,
-        ),
+            request_key.get_permissions(user, &db_name),
+            &request_key.secret,
+            &db.writer,
+        )
+        .with_read_db(&db.reader)
+        .with_query_timeout(user.query_timeout.unwrap_or(data.query_timeout))
+        .with_fetch_limits(
+            user.max_rows.unwrap_or(data.max_rows),
+            user.max_response_bytes.unwrap_or(data.max_response_bytes),
+        )
+        .with_replication(db.replication.as_ref())
+        .with_primary(data.primary.as_deref()),
     )
-    .await
-    {
-        Ok(res) => res,
+    .await;
+    if let Some(audit_entry) = audit_entry {
+        record_audit_entry(&data, with_audit_outcome(audit_entry, &res));
+    }
+
+    let proto_package = match res {
+        Ok(handled_request) => handled_request.proto_package,
         Err(e) => match e.source() {
             ErrorKind::UserNotAllowed => {
                 return HttpResponse::Forbidden()
-                    .protobuf(encode_error_proto(e, &user.username_password_hash));
+                    .protobuf(encode_error_proto(e, &request_key.secret));
+            }
+            ErrorKind::NotPrimary => {
+                return HttpResponse::MisdirectedRequest()
+                    .insert_header((
+                        "Location",
+                        format!(
+                            "{}{}",
+                            data.primary.as_deref().unwrap_or_default(),
+                            req.path()
+                        ),
+                    ))
+                    .protobuf(encode_error_proto(e, &request_key.secret));
             }
             _ => {
                 return HttpResponse::InternalServerError()
-                    .protobuf(encode_error_proto(e, &user.username_password_hash));
+                    .protobuf(encode_error_proto(e, &request_key.secret));
             }
         },
     };
 
     HttpResponse::Ok().protobuf(proto_package)
 }
+
+/// Creates a db for a user with the creator right, who is granted the writer role (fetch and mutate) on it as its owner.
+/// The request names the db (and optionally a template db the user has the fetch permission on), the path holds the hash of the name.
+#[post("/{database}/c")]
+async fn handle_db_create_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let db_name = path.into_inner();
+    let users_guard = data.users_guard();
+    let Authenticated {
+        user,
+        request,
+        request_key,
+    } = match authenticate(&req, &data, &req_body, &users_guard) {
+        Ok(authenticated) => authenticated,
+        Err(res) => return res,
+    };
+
+    let claims = request.claims.unwrap();
+    let create_request = match (claims.sub(), claims.dat) {
+        (Sub::Create, Some(Dat::CreateDatabaseRequest(dat)))
+            if base16ct::lower::encode_string(&Sha256::digest(dat.database.as_bytes()))
+                == db_name =>
+        {
+            dat
+        }
+        _ => {
+            return HttpResponse::BadRequest().protobuf(encode_error_proto(
+                UndefinedError::with_message("Request is not a create request for the db"),
+                &request_key.secret,
+            ));
+        }
+    };
+
+    if let Some(primary) = data.primary.as_deref() {
+        return HttpResponse::MisdirectedRequest()
+            .insert_header(("Location", format!("{}{}", primary, req.path())))
+            .protobuf(encode_error_proto(
+                NotPrimaryError::with_message(&format!(
+                    "Server is a read replica, send writes to the primary at {}",
+                    primary
+                )),
+                &request_key.secret,
+            ));
+    }
+
+    let template = Some(create_request.template.as_str()).filter(|template| !template.is_empty());
+    let can_read_template = template.is_none_or(|template| {
+        request_key.has_permission(
+            user,
+            &base16ct::lower::encode_string(&Sha256::digest(template.as_bytes())),
+            role::FETCH,
+        )
+    });
+    // a key has to be allowed to mutate to create dbs
+    if !user.is_creator || request_key.scope & role::MUTATE == 0 || !can_read_template {
+        return HttpResponse::Forbidden().protobuf(encode_error_proto(
+            UserNotAllowedError::default(),
+            &request_key.secret,
+        ));
+    }
+
+    let audit_action = match template {
+        Some(template) => format!("create database from {}", template),
+        None => String::from("create database"),
+    };
+    let audit_entry = AuditEntry::new(AUDIT_SOURCE_PROTOCOL, &user.username, &audit_action)
+        .with_database_hash(&db_name);
+    let res = database_manager(&data)
+        .create_owned_database(
+            user.username.clone(),
+            &create_request.database,
+            template,
+            create_request.schema_only,
+            user.max_databases.unwrap_or(data.max_databases),
+        )
+        .await;
+    record_audit_entry(&data, audit_entry.with_result(&res));
+    if let Err(e) = res {
+        return match e.source() {
+            ErrorKind::UserNotAllowed => {
+                HttpResponse::Forbidden().protobuf(encode_error_proto(e, &request_key.secret))
+            }
+            ErrorKind::ResourceNotExist => {
+                HttpResponse::NotFound().protobuf(encode_error_proto(e, &request_key.secret))
+            }
+            _ => HttpResponse::BadRequest().protobuf(encode_error_proto(e, &request_key.secret)),
+        };
+    }
+
+    // granted right away, the users watcher catches up with the users db after
+    user.db_permissions
+        .pin()
+        .insert(Arc::from(db_name.as_str()), role::FETCH | role::MUTATE);
+
+    match encode_proto(
+        CreateDatabaseResponse::as_dat(db_name),
+        Sub::Data,
+        &request_key.secret,
+    ) {
+        Ok(proto_package) => HttpResponse::Created().protobuf(proto_package),
+        Err(e) => {
+            HttpResponse::InternalServerError().protobuf(encode_error_proto(e, &request_key.secret))
+        }
+    }
+}
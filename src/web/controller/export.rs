@@ -0,0 +1,136 @@
+use std::io;
+
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use futures::channel::oneshot;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    core::{
+        error::{DatabaseError, SerfError, UndefinedError, UserNotAllowedError},
+        export::{check_export, export},
+        role,
+        serf_proto::{claims::Dat, ErrorKind, ExportFormat, Sub},
+        state::AppState,
+        util::get_or_insert_db_connection,
+    },
+    web::{
+        auth::{authenticate, Authenticated},
+        proto::encode_error_proto,
+        util::HttpProtoResponse,
+    },
+};
+
+// bytes buffered between the export and the response
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(handle_db_export_post);
+}
+
+/// Streams a table of a db as csv, jsonl or sql, or a SQL dump of the whole db, to a user with the fetch permission.
+/// Errors before the export starts are signed error packages as usual, the export itself is the plain
+/// (unsigned) response body. An export that fails halfway is cut off, the response doesn't complete.
+#[post("/{database}/e")]
+async fn handle_db_export_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let db_name = path.into_inner();
+    let users_guard = data.users_guard();
+    let Authenticated {
+        user,
+        request,
+        request_key,
+    } = match authenticate(&req, &data, &req_body, &users_guard) {
+        Ok(authenticated) => authenticated,
+        Err(res) => return res,
+    };
+
+    let claims = request.claims.unwrap();
+    let export_request = match (claims.sub(), claims.dat) {
+        (Sub::Export, Some(Dat::ExportRequest(dat))) => dat,
+        _ => {
+            return HttpResponse::InternalServerError().protobuf(encode_error_proto(
+                UndefinedError::default(),
+                &request_key.secret,
+            ));
+        }
+    };
+
+    if !request_key.has_permission(user, &db_name, role::FETCH) {
+        return HttpResponse::Forbidden().protobuf(encode_error_proto(
+            UserNotAllowedError::default(),
+            &request_key.secret,
+        ));
+    }
+
+    let db_connections_guard = data.db_connections_guard();
+    let db = match get_or_insert_db_connection(&data, &db_name, &db_connections_guard).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return HttpResponse::NotFound().protobuf(encode_error_proto(e, &request_key.secret));
+        }
+    };
+
+    let format = export_request.format();
+    let table = Some(export_request.table).filter(|table| !table.is_empty());
+    let mut conn = match db.reader.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return HttpResponse::InternalServerError().protobuf(encode_error_proto(
+                DatabaseError::with_message(&e.to_string()),
+                &request_key.secret,
+            ));
+        }
+    };
+
+    if let Err(e) = check_export(&mut conn, table.as_deref(), format).await {
+        return match e.source() {
+            ErrorKind::ResourceNotExist => {
+                HttpResponse::NotFound().protobuf(encode_error_proto(e, &request_key.secret))
+            }
+            _ => HttpResponse::BadRequest().protobuf(encode_error_proto(e, &request_key.secret)),
+        };
+    }
+
+    let (mut writer, reader) = tokio::io::duplex(EXPORT_CHUNK_SIZE);
+    let (result_tx, result_rx) = oneshot::channel();
+    actix_web::rt::spawn(async move {
+        // fails with a broken pipe once the client goes away, which ends the export
+        let res = export(&mut conn, table.as_deref(), format, &mut writer).await;
+        drop(writer);
+        let _ = result_tx.send(res);
+    });
+
+    let body = futures::stream::unfold(
+        (reader, Some(result_rx)),
+        |(mut reader, result_rx)| async move {
+            let mut chunk = vec![0; EXPORT_CHUNK_SIZE];
+            match reader.read(&mut chunk).await {
+                Ok(0) => match result_rx?.await {
+                    Ok(Err(e)) => Some((Err(io::Error::other(e.message)), (reader, None))),
+                    _ => None,
+                },
+                Ok(len) => {
+                    chunk.truncate(len);
+                    Some((Ok(web::Bytes::from(chunk)), (reader, result_rx)))
+                }
+                Err(e) => Some((Err(e), (reader, None))),
+            }
+        },
+    );
+
+    HttpResponse::Ok()
+        .insert_header(("Content-Type", content_type(format)))
+        .streaming(body)
+}
+
+fn content_type(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Csv => "text/csv",
+        ExportFormat::Jsonl => "application/x-ndjson",
+        ExportFormat::Sql => "application/sql",
+    }
+}
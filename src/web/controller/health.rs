@@ -1,10 +1,105 @@
+use std::sync::Arc;
+
 use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::core::state::{AppState, BackupStatus, ReplicationStatus};
 
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(handle_health_get);
+    cfg.service(handle_health_backups_get);
+    cfg.service(handle_health_replication_get);
+}
+
+/// The health endpoints are unauthenticated, so they only hold counts and times.
+/// Which db failed and why is in the server log.
+#[derive(Debug, PartialEq, Eq, Default, Serialize)]
+pub struct BackupsHealth {
+    pub ok: usize,
+    pub failed: usize,
+    /// most recent run of any backup schedule
+    pub last_run_at: Option<String>,
+    /// earliest upcoming run of any backup schedule
+    pub next_run_at: Option<String>,
+}
+
+impl BackupsHealth {
+    pub fn from_statuses<'a>(statuses: impl Iterator<Item = &'a BackupStatus>) -> Self {
+        // the times are all RFC 3339 in UTC, so they compare as strings
+        statuses.fold(BackupsHealth::default(), |mut health, status| {
+            match status.last_error {
+                Some(_) => health.failed += 1,
+                None => health.ok += 1,
+            }
+            if status.last_run_at > health.last_run_at {
+                health.last_run_at = status.last_run_at.clone();
+            }
+            if status.next_run_at.is_some()
+                && (health.next_run_at.is_none() || status.next_run_at < health.next_run_at)
+            {
+                health.next_run_at = status.next_run_at.clone();
+            }
+            health
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Default, Serialize)]
+pub struct ReplicationHealth {
+    pub ok: usize,
+    pub failed: usize,
+    /// highest lag of any replicated db
+    pub max_lag: u64,
+    /// least recent sync of any replicated db, unset when one never synced
+    pub oldest_synced_at: Option<String>,
+}
+
+impl ReplicationHealth {
+    pub fn from_statuses<'a>(statuses: impl Iterator<Item = &'a ReplicationStatus>) -> Self {
+        let mut health = ReplicationHealth::default();
+        let mut oldest_synced_at: Option<Option<String>> = None;
+
+        for status in statuses {
+            match status.last_error {
+                Some(_) => health.failed += 1,
+                None => health.ok += 1,
+            }
+            health.max_lag = health.max_lag.max(status.lag);
+            // None orders before every time, a db that never synced is the oldest
+            if oldest_synced_at
+                .as_ref()
+                .is_none_or(|oldest| &status.last_synced_at < oldest)
+            {
+                oldest_synced_at = Some(status.last_synced_at.clone());
+            }
+        }
+
+        health.oldest_synced_at = oldest_synced_at.flatten();
+        health
+    }
 }
 
 #[get("/health")]
 async fn handle_health_get() -> impl Responder {
     HttpResponse::Ok().body("OK")
 }
+
+/// Counts of the scheduled backups that succeeded or failed on their last run, see BackupsHealth.
+#[get("/health/backups")]
+async fn handle_health_backups_get(data: web::Data<AppState>) -> impl Responder {
+    let backup_statuses = Arc::clone(&data.backup_statuses);
+    let backup_statuses = backup_statuses.pin();
+
+    HttpResponse::Ok().json(BackupsHealth::from_statuses(backup_statuses.values()))
+}
+
+/// Counts of the replicated dbs that succeeded or failed on their last sync, see ReplicationHealth.
+#[get("/health/replication")]
+async fn handle_health_replication_get(data: web::Data<AppState>) -> impl Responder {
+    let replication_statuses = Arc::clone(&data.replication_statuses);
+    let replication_statuses = replication_statuses.pin();
+
+    HttpResponse::Ok().json(ReplicationHealth::from_statuses(
+        replication_statuses.values(),
+    ))
+}
@@ -1,5 +1,11 @@
+pub mod admin;
 pub mod database;
+pub mod export;
 pub mod health;
+pub mod replication;
 
+pub use admin::init as init_admin_controller;
 pub use database::init as init_db_controller;
-pub use health::init as init_health_controller; 
+pub use export::init as init_export_controller;
+pub use health::init as init_health_controller;
+pub use replication::init as init_replication_controller;
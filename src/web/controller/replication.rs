@@ -0,0 +1,105 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+
+use crate::{
+    core::{
+        error::{ResourceNotExistError, SerfError, UndefinedError, UserNotAllowedError},
+        replication::{get_changes, snapshot},
+        role,
+        serf_proto::{claims::Dat, ErrorKind, ReplicationResponse, Sub},
+        state::AppState,
+        util::{consumer_db_file, get_or_insert_db_connection},
+    },
+    web::{
+        auth::{authenticate, Authenticated},
+        proto::{encode_error_proto, encode_proto},
+        util::HttpProtoResponse,
+    },
+};
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(handle_db_replication_post);
+}
+
+/// Serves the changes of a replicated db, or a snapshot of it, to a follower.
+/// The follower signs its requests as a user with the fetch permission on the db.
+#[post("/{database}/r")]
+async fn handle_db_replication_post(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    req_body: web::Bytes,
+) -> impl Responder {
+    let db_name = path.into_inner();
+    let users_guard = data.users_guard();
+    let Authenticated {
+        user,
+        request,
+        request_key,
+    } = match authenticate(&req, &data, &req_body, &users_guard) {
+        Ok(authenticated) => authenticated,
+        Err(res) => return res,
+    };
+
+    let claims = request.claims.unwrap();
+    let replication_request = match (claims.sub(), claims.dat) {
+        (Sub::Replicate, Some(Dat::ReplicationRequest(dat))) => dat,
+        _ => {
+            return HttpResponse::InternalServerError().protobuf(encode_error_proto(
+                UndefinedError::default(),
+                &request_key.secret,
+            ));
+        }
+    };
+
+    if !request_key.has_permission(user, &db_name, role::FETCH) {
+        return HttpResponse::Forbidden().protobuf(encode_error_proto(
+            UserNotAllowedError::default(),
+            &request_key.secret,
+        ));
+    }
+
+    let db_connections_guard = data.db_connections_guard();
+    let db = match get_or_insert_db_connection(&data, &db_name, &db_connections_guard).await {
+        Ok(conn) if conn.replication.is_some() => conn,
+        Ok(_) => {
+            return HttpResponse::NotFound().protobuf(encode_error_proto(
+                ResourceNotExistError::with_message("Database is not replicated"),
+                &request_key.secret,
+            ));
+        }
+        Err(e) => {
+            return HttpResponse::NotFound().protobuf(encode_error_proto(e, &request_key.secret));
+        }
+    };
+
+    let res = if replication_request.snapshot {
+        match consumer_db_file(&data, &db_name) {
+            Ok(db_file) => snapshot(&db_file)
+                .await
+                .map(|snapshot| ReplicationResponse::as_dat(vec![], snapshot, 0, false)),
+            Err(e) => Err(e),
+        }
+    } else {
+        get_changes(&db.reader, replication_request.after_id)
+            .await
+            .map(|changes| {
+                ReplicationResponse::as_dat(
+                    changes.changes,
+                    vec![],
+                    changes.last_id,
+                    changes.resync,
+                )
+            })
+    };
+
+    match res.and_then(|dat| encode_proto(dat, Sub::Data, &request_key.secret)) {
+        Ok(proto_package) => HttpResponse::Ok().protobuf(proto_package),
+        Err(e) => match e.source() {
+            ErrorKind::ResourceNotExist => {
+                HttpResponse::NotFound().protobuf(encode_error_proto(e, &request_key.secret))
+            }
+            _ => HttpResponse::InternalServerError()
+                .protobuf(encode_error_proto(e, &request_key.secret)),
+        },
+    }
+}
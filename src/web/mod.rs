@@ -1,3 +1,5 @@
+pub mod auth;
 pub mod controller;
 pub mod proto;
+pub mod replica;
 pub mod util;
@@ -5,10 +5,18 @@ use sha2::Sha256;
 
 use crate::core::{
     error::{ProtoPackageError, SerfError},
+    role,
     serf_proto::{
-        claims::Dat, query_arg, Claims, Error, FetchResponse, Iss, MigrationRequest,
-        MigrationResponse, MutationResponse, QueryArg, QueryRequest, Request, Sub,
+        claims::Dat, query_arg, AdminResponse, BackupRequest, BackupResponse, Claims,
+        CreateDatabaseRequest, CreateDatabaseResponse, CreateUserRequest, DatabaseListing,
+        DeleteUserRequest, DropDatabaseRequest, Error, ExportFormat, ExportRequest, FetchResponse,
+        GrantAccessRequest, ImportFormat, ImportRequest, ImportResponse, ImportRowError, Iss,
+        ListDatabasesRequest, ListDatabasesResponse, ListUsersRequest, ListUsersResponse,
+        MigrationRequest, MigrationResponse, MutationResponse, QueryArg, QueryRequest,
+        ReplicationChange, ReplicationRequest, ReplicationResponse, Request, RestoreRequest,
+        RestoreResponse, RevokeAccessRequest, Sub, UserListing,
     },
+    state::User,
 };
 
 impl QueryArg {
@@ -19,7 +27,11 @@ impl QueryArg {
 
 impl QueryRequest {
     pub fn as_dat(query: String, parts: Vec<QueryArg>) -> Dat {
-        Dat::QueryRequest(QueryRequest { query, parts })
+        Dat::QueryRequest(QueryRequest {
+            query,
+            parts,
+            timeout: 0,
+        })
     }
 }
 
@@ -30,8 +42,8 @@ impl MigrationRequest {
 }
 
 impl FetchResponse {
-    pub fn as_dat(data: Vec<u8>) -> Dat {
-        Dat::FetchResponse(FetchResponse { data })
+    pub fn as_dat(data: Vec<u8>, truncated: bool) -> Dat {
+        Dat::FetchResponse(FetchResponse { data, truncated })
     }
 }
 
@@ -50,6 +62,167 @@ impl MigrationResponse {
     }
 }
 
+impl BackupRequest {
+    pub fn as_dat(database: String, compress: bool) -> Dat {
+        Dat::BackupRequest(BackupRequest { database, compress })
+    }
+}
+
+impl BackupResponse {
+    pub fn as_dat(file: String, size: u64, sha256: String) -> Dat {
+        Dat::BackupResponse(BackupResponse { file, size, sha256 })
+    }
+}
+
+impl RestoreRequest {
+    pub fn as_dat(database: String, file: String) -> Dat {
+        Dat::RestoreRequest(RestoreRequest { database, file })
+    }
+}
+
+impl RestoreResponse {
+    pub fn as_dat(state: bool) -> Dat {
+        Dat::RestoreResponse(RestoreResponse { state })
+    }
+}
+
+impl ReplicationRequest {
+    pub fn as_dat(after_id: u64, snapshot: bool) -> Dat {
+        Dat::ReplicationRequest(ReplicationRequest { after_id, snapshot })
+    }
+}
+
+impl ReplicationResponse {
+    pub fn as_dat(
+        changes: Vec<ReplicationChange>,
+        snapshot: Vec<u8>,
+        last_id: u64,
+        resync: bool,
+    ) -> Dat {
+        Dat::ReplicationResponse(ReplicationResponse {
+            changes,
+            snapshot,
+            last_id,
+            resync,
+        })
+    }
+}
+
+impl ExportRequest {
+    pub fn as_dat(table: String, format: ExportFormat) -> Dat {
+        Dat::ExportRequest(ExportRequest {
+            table,
+            format: format.into(),
+        })
+    }
+}
+
+impl ImportRequest {
+    pub fn as_dat(table: String, format: ImportFormat, data: Vec<u8>, max_errors: u64) -> Dat {
+        Dat::ImportRequest(ImportRequest {
+            table,
+            format: format.into(),
+            data,
+            max_errors,
+        })
+    }
+}
+
+impl ImportResponse {
+    pub fn as_dat(imported: u64, failed: u64, errors: Vec<ImportRowError>, aborted: bool) -> Dat {
+        Dat::ImportResponse(ImportResponse {
+            imported,
+            failed,
+            errors,
+            aborted,
+        })
+    }
+}
+
+impl CreateUserRequest {
+    pub fn as_dat(username: String, password: String, is_admin: bool) -> Dat {
+        Dat::CreateUserRequest(CreateUserRequest {
+            username,
+            password,
+            is_admin,
+        })
+    }
+}
+
+impl DeleteUserRequest {
+    pub fn as_dat(username: String) -> Dat {
+        Dat::DeleteUserRequest(DeleteUserRequest { username })
+    }
+}
+
+impl GrantAccessRequest {
+    pub fn as_dat(username: String, database: String, role: String) -> Dat {
+        Dat::GrantAccessRequest(GrantAccessRequest {
+            username,
+            database,
+            role,
+        })
+    }
+}
+
+impl RevokeAccessRequest {
+    pub fn as_dat(username: String, database: String) -> Dat {
+        Dat::RevokeAccessRequest(RevokeAccessRequest { username, database })
+    }
+}
+
+impl CreateDatabaseRequest {
+    pub fn as_dat(database: String, template: String, schema_only: bool) -> Dat {
+        Dat::CreateDatabaseRequest(CreateDatabaseRequest {
+            database,
+            template,
+            schema_only,
+        })
+    }
+}
+
+impl CreateDatabaseResponse {
+    pub fn as_dat(database: String) -> Dat {
+        Dat::CreateDatabaseResponse(CreateDatabaseResponse { database })
+    }
+}
+
+impl DropDatabaseRequest {
+    pub fn as_dat(database: String) -> Dat {
+        Dat::DropDatabaseRequest(DropDatabaseRequest { database })
+    }
+}
+
+impl ListUsersRequest {
+    pub fn as_dat(username: String) -> Dat {
+        Dat::ListUsersRequest(ListUsersRequest { username })
+    }
+}
+
+impl ListUsersResponse {
+    pub fn as_dat(users: Vec<UserListing>) -> Dat {
+        Dat::ListUsersResponse(ListUsersResponse { users })
+    }
+}
+
+impl ListDatabasesRequest {
+    pub fn as_dat() -> Dat {
+        Dat::ListDatabasesRequest(ListDatabasesRequest {})
+    }
+}
+
+impl ListDatabasesResponse {
+    pub fn as_dat(databases: Vec<DatabaseListing>) -> Dat {
+        Dat::ListDatabasesResponse(ListDatabasesResponse { databases })
+    }
+}
+
+impl AdminResponse {
+    pub fn as_dat(state: bool) -> Dat {
+        Dat::AdminResponse(AdminResponse { state })
+    }
+}
+
 #[derive(Eq, PartialEq, Debug)]
 pub struct ProtoPackage {
     pub data: Vec<u8>,
@@ -69,6 +242,7 @@ impl ProtoPackage {
 pub struct ProtoPackageBuilder {
     data: Option<Dat>,
     subject: Option<Sub>,
+    issuer: Option<Iss>,
     iat: Option<u64>,
     exp: Option<u64>,
     error: Option<Error>,
@@ -79,6 +253,7 @@ impl ProtoPackageBuilder {
         ProtoPackageBuilder {
             data: None,
             subject: None,
+            issuer: None,
             iat: None,
             exp: None,
             error: None,
@@ -99,6 +274,14 @@ impl ProtoPackageBuilder {
         }
     }
 
+    /// Packages are issued by the server unless set, I.E a follower requesting changes from its primary is a client.
+    pub fn with_issuer(self, issuer: Iss) -> Self {
+        ProtoPackageBuilder {
+            issuer: Some(issuer),
+            ..self
+        }
+    }
+
     pub fn with_error(self, error: Error) -> Self {
         ProtoPackageBuilder {
             error: Some(error),
@@ -129,19 +312,20 @@ impl ProtoPackageBuilder {
                 claims: None,
                 error: self.error,
             }
-        } else if self.subject.is_some() {
-            if self.data.is_some() {
+        } else if let Some(subject) = self.subject {
+            if let Some(data) = self.data {
                 let iat = match self.iat {
                     Some(t) => t,
                     _ => chrono::Utc::now().timestamp() as u64,
                 };
-                
+
                 let exp = match self.exp {
                     Some(t) => t,
                     _ => iat + 30,
                 };
 
-                let claims = generate_claims(self.data.unwrap(), self.subject.unwrap(), iat, exp);
+                let issuer = self.issuer.unwrap_or(Iss::Server);
+                let claims = generate_claims(data, subject, issuer, iat, exp);
                 request = Request {
                     claims: Some(claims),
                     error: None,
@@ -153,8 +337,7 @@ impl ProtoPackageBuilder {
             return Err(ProtoPackageError::signing_error("missing subject"));
         }
 
-        let mut buf = Vec::new();
-        buf.reserve(request.encoded_len());
+        let mut buf = Vec::with_capacity(request.encoded_len());
 
         if let Err(e) = request.encode(&mut buf) {
             eprintln!("{e}");
@@ -211,6 +394,13 @@ impl<'a> ProtoPackageVerifier<'a> {
             return Err(ProtoPackageError::verification_error("invalid signature"));
         }
 
+        self.verify_claims(data)
+    }
+
+    /// #### Verifies the claims of a request without checking its signature
+    /// \
+    /// For requests whose sender is verified by other means than the signature, I.E a bearer token or a client certificate.
+    pub fn verify_claims(self, data: &[u8]) -> Result<Request, Error> {
         let decoded = match Request::decode(&mut &data[..]) {
             Ok(d) => d,
             Err(_) => {
@@ -325,21 +515,21 @@ fn verify_signature(data: &[u8], signature: &str, secret: &[u8]) -> bool {
 }
 
 pub fn generate_signature(data: &[u8], secret: &[u8]) -> String {
-    let mut mac = match Hmac::<Sha256>::new_from_slice(&secret) {
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret) {
         Ok(m) => m,
         Err(_) => panic!("ERROR DURING SIGNING"),
     };
 
-    mac.update(&data);
+    mac.update(data);
     let result = mac.finalize();
     let result_bytes = result.into_bytes();
 
     base16ct::lower::encode_string(&result_bytes)
 }
 
-fn generate_claims(data: Dat, subject: Sub, iat: u64, exp: u64) -> Claims {
+fn generate_claims(data: Dat, subject: Sub, issuer: Iss, iat: u64, exp: u64) -> Claims {
     Claims {
-        iss: Iss::Server.into(),
+        iss: issuer.into(),
         sub: subject.into(),
         dat: Some(data),
         iat,
@@ -365,12 +555,68 @@ pub fn encode_error_proto(error: Error, secret: &str) -> ProtoPackage {
         .unwrap()
 }
 
-pub fn decode_proto(proto_bytes: &[u8], secret: &str, signature: &str) -> Result<Request, Error> {
+/// #### The key a request was signed with, the response is signed with the same key
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RequestKey {
+    pub secret: String,
+    /// bits of role::FETCH, MUTATE, MIGRATE and ADMIN the request is limited to
+    pub scope: u8,
+}
+
+impl RequestKey {
+    pub fn get_permissions(&self, user: &User, db_name: &str) -> u8 {
+        user.get_permissions(db_name) & self.scope
+    }
+
+    pub fn has_permission(&self, user: &User, db_name: &str, permission: u8) -> bool {
+        self.get_permissions(user, db_name) & permission == permission
+    }
+}
+
+/// #### Verifies a request signed by the user
+/// \
+/// __*@param*__ key_id: Option\<&str\> (name of the API key of the user the request is signed with,
+/// None = signed with the username_password_hash)
+/// \
+/// __*@returns*__ Result\<(Request, RequestKey), Error\>
+pub fn decode_proto(
+    proto_bytes: &[u8],
+    user: &User,
+    key_id: Option<&str>,
+    signature: &str,
+) -> Result<(Request, RequestKey), Error> {
+    let request_key = match key_id {
+        None => RequestKey {
+            secret: user.username_password_hash.clone(),
+            scope: role::ALL,
+        },
+        Some(key_id) => {
+            let api_keys = user.api_keys.pin();
+            let api_key = api_keys
+                .get(key_id)
+                .ok_or_else(|| ProtoPackageError::verification_error("unknown key"))?;
+
+            if api_key
+                .expires_at
+                .is_some_and(|expires_at| chrono::Utc::now().timestamp() as u64 > expires_at)
+            {
+                return Err(ProtoPackageError::verification_error("key expired"));
+            }
+
+            RequestKey {
+                secret: api_key.secret.clone(),
+                scope: api_key.scope.unwrap_or(role::ALL),
+            }
+        }
+    };
+
     let proto_package_verifier = ProtoPackageVerifier::builder()
         .with_signature(signature)
-        .with_secret(secret)
+        .with_secret(&request_key.secret)
         .with_issuer(Iss::Client)
         .build();
 
-    proto_package_verifier.verify(proto_bytes)
+    proto_package_verifier
+        .verify(proto_bytes)
+        .map(|request| (request, request_key))
 }
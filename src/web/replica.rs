@@ -0,0 +1,232 @@
+use std::{fs, path::Path, sync::Arc, time::Duration};
+
+use actix_web::web;
+use prost::Message;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    core::{
+        backup::swap_db_file,
+        error::{undefined_error, SerfError, UndefinedError},
+        replication::{self, REPLICATION_MAX_CHANGES},
+        serf_proto::{
+            claims::Dat, Error, Iss, ReplicationRequest, ReplicationResponse, Request, Sub,
+        },
+        state::{AppState, DatabaseConnectionLease},
+        util::{get_or_insert_db_connection, with_db_closed},
+    },
+    web::proto::{generate_signature, ProtoPackage, ProtoPackageVerifier},
+};
+
+/// #### A read replica of a primary serf
+/// \
+/// Polls the primary for the changes of the replicated dbs and replays them on the local copies,
+/// a db that doesn't exist locally yet (or fell too far behind) is first replaced by a snapshot of the primary.
+pub struct Follower {
+    primary: String,
+    username_hash: String,
+    username_password_hash: String,
+    databases: Vec<String>,
+    client: reqwest::Client,
+}
+
+impl Follower {
+    /// __*@param*__ primary: &str (I.E http://10.0.0.1:8080) \
+    /// __*@param*__ username: &str (user on the primary with the fetch permission on the replicated dbs) \
+    /// __*@param*__ password: &str \
+    /// __*@param*__ databases: Vec\<String\> (names of the replicated dbs)
+    pub fn new(primary: &str, username: &str, password: &str, databases: Vec<String>) -> Self {
+        Follower {
+            primary: primary.trim_end_matches('/').to_string(),
+            username_hash: base16ct::lower::encode_string(&Sha256::digest(username.as_bytes())),
+            username_password_hash: base16ct::lower::encode_string(&Sha256::digest(
+                format!("{}{}", username, password).as_bytes(),
+            )),
+            databases,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn request(
+        &self,
+        db_name: &str,
+        after_id: u64,
+        snapshot: bool,
+    ) -> Result<ReplicationResponse, Error> {
+        let proto_package = ProtoPackage::builder()
+            .with_data(ReplicationRequest::as_dat(after_id, snapshot))
+            .with_subject(Sub::Replicate)
+            .with_issuer(Iss::Client)
+            .sign(&self.username_password_hash)?;
+
+        let res = self
+            .client
+            .post(format!("{}/{}/r", self.primary, db_name))
+            .header("Content-Type", "application/protobuf")
+            .header("0", &self.username_hash)
+            .header("1", proto_package.signature)
+            .body(proto_package.data)
+            .send()
+            .await
+            .map_err(undefined_error)?;
+        let status = res.status();
+        let signature = res
+            .headers()
+            .get("0")
+            .and_then(|signature| signature.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = res.bytes().await.map_err(undefined_error)?;
+
+        if !status.is_success() {
+            // error packages have no claims to verify, only their signature
+            return Err(match Request::decode(body.as_ref()) {
+                Ok(Request {
+                    error: Some(error), ..
+                }) if signature
+                    == generate_signature(&body, self.username_password_hash.as_bytes()) =>
+                {
+                    error
+                }
+                _ => UndefinedError::with_message(&format!(
+                    "Primary responded with {}: {}",
+                    status,
+                    String::from_utf8_lossy(&body)
+                )),
+            });
+        }
+
+        let decoded = ProtoPackageVerifier::builder()
+            .with_signature(&signature)
+            .with_secret(&self.username_password_hash)
+            .with_issuer(Iss::Server)
+            .build()
+            .verify(&body)?;
+
+        match decoded.claims.and_then(|claims| claims.dat) {
+            Some(Dat::ReplicationResponse(dat)) => Ok(dat),
+            _ => Err(UndefinedError::with_message(
+                "Primary responded without a ReplicationResponse",
+            )),
+        }
+    }
+}
+
+pub async fn async_replicate(app_data: web::Data<AppState>, follower: Follower, interval: u64) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_millis(interval.max(1)));
+
+    loop {
+        interval.tick().await;
+        run_replication(&app_data, &follower).await;
+    }
+}
+
+/// #### Brings every replicated db up to date with the primary
+/// \
+/// The outcome is kept per db name hash in the replication statuses of the app state.
+pub async fn run_replication(app_data: &web::Data<AppState>, follower: &Follower) {
+    let replication_statuses = Arc::clone(&app_data.replication_statuses);
+    let replication_statuses_pin = replication_statuses.pin();
+
+    for database_name in &follower.databases {
+        let db_name = base16ct::lower::encode_string(&Sha256::digest(database_name.as_bytes()));
+        let mut status = replication_statuses_pin
+            .get(db_name.as_str())
+            .cloned()
+            .unwrap_or_default();
+
+        match sync_database(app_data, follower, database_name, &db_name).await {
+            Ok((applied_id, primary_last_id)) => {
+                status.applied_id = applied_id;
+                status.primary_last_id = primary_last_id;
+                status.lag = primary_last_id.saturating_sub(applied_id);
+                status.last_synced_at = Some(chrono::Utc::now().to_rfc3339());
+                status.last_error = None;
+            }
+            Err(e) => {
+                eprintln!("Replication failed for {}: {}", database_name, e.message);
+                status.last_error = Some(e.message);
+            }
+        }
+
+        replication_statuses_pin.insert(Arc::from(db_name.as_str()), status);
+    }
+}
+
+/// __*@returns*__ Result\<(u64, u64), Error\> (the id of the last applied change and of the latest change on the primary)
+async fn sync_database(
+    app_data: &web::Data<AppState>,
+    follower: &Follower,
+    database_name: &str,
+    db_name: &str,
+) -> Result<(u64, u64), Error> {
+    let db_dir = Path::new(&app_data.db_path).join(db_name);
+    let db_file = db_dir.join(format!("{}.db", db_name));
+
+    let mut applied_id = if db_file.exists() {
+        let db = db_connection(app_data, db_name).await?;
+        let mut conn = db.writer.acquire().await.map_err(undefined_error)?;
+        replication::applied_id(&mut conn).await?
+    } else {
+        None
+    };
+    let mut snapshotted = false;
+
+    loop {
+        let after_id = match applied_id {
+            Some(after_id) => after_id,
+            None if snapshotted => {
+                return Err(UndefinedError::with_message(
+                    "Primary asked for a resync right after a snapshot",
+                ));
+            }
+            None => {
+                let response = follower.request(db_name, 0, true).await?;
+                if !db_dir.exists() {
+                    fs::create_dir_all(&db_dir).map_err(undefined_error)?;
+                    // same as serf-cli create database, a reminder of the db name
+                    let _ = fs::write(db_dir.join(database_name), db_name);
+                }
+
+                let (restore_file, snapshot_id) =
+                    replication::unpack_snapshot(response.snapshot, &db_file).await?;
+                with_db_closed(app_data, db_name, async {
+                    swap_db_file(&restore_file, &db_file)
+                })
+                .await?;
+
+                applied_id = Some(snapshot_id);
+                snapshotted = true;
+                continue;
+            }
+        };
+
+        let response = follower.request(db_name, after_id, false).await?;
+        if response.resync {
+            applied_id = None;
+            continue;
+        }
+
+        if !response.changes.is_empty() {
+            let db = db_connection(app_data, db_name).await?;
+            let mut conn = db.writer.acquire().await.map_err(undefined_error)?;
+            if let Some(last_applied_id) =
+                replication::apply_changes(&mut conn, &response.changes).await?
+            {
+                applied_id = Some(last_applied_id);
+            }
+        }
+
+        if (response.changes.len() as u64) < REPLICATION_MAX_CHANGES {
+            return Ok((applied_id.unwrap_or(after_id), response.last_id));
+        }
+    }
+}
+
+async fn db_connection(
+    app_data: &web::Data<AppState>,
+    db_name: &str,
+) -> Result<DatabaseConnectionLease, Error> {
+    let db_connections_guard = app_data.db_connections_guard();
+    get_or_insert_db_connection(app_data, db_name, &db_connections_guard).await
+}
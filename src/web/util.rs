@@ -1,24 +1,34 @@
-use std::future::Future;
+use std::{future::Future, path::Path};
 
 use actix_web::{
     http::header::{HeaderMap, HeaderValue},
     HttpResponse, HttpResponseBuilder,
 };
+use prost::Message;
 use sqlx::SqlitePool;
 
 #[cfg(test)]
 use mockall::automock;
 
-use crate::core::{
-    constants::queries,
-    db::{execute_query, fetch_all_as_json, AppliedQuery},
-    error::{
-        DatabaseError, HeaderMalformedError, HeaderMissingError, SerfError, UndefinedError,
-        UserNotAllowedError,
-    },
-    serf_proto::{
-        claims::Dat, query_arg, Claims, Error, FetchResponse, MigrationRequest, MigrationResponse,
-        MutationResponse, QueryArg, QueryRequest, Sub,
+use crate::{
+    cli::util::DatabaseManager,
+    core::{
+        audit::{self, AuditEntry, AUDIT_SOURCE_PROTOCOL},
+        config::ReplicationConfig,
+        constants::queries,
+        db::{execute_query, fetch_as_json_bytes, AppliedQuery, QueryInterrupt},
+        error::{
+            DatabaseError, HeaderMalformedError, HeaderMissingError, NotPrimaryError, SerfError,
+            TimeoutError, UndefinedError, UserNotAllowedError,
+        },
+        import::{import, IMPORT_BATCH_SIZE},
+        replication::{check_deterministic, log_change},
+        role,
+        serf_proto::{
+            claims::Dat, query_arg, Claims, Error, FetchResponse, ImportRequest, ImportResponse,
+            MigrationRequest, MigrationResponse, MutationResponse, QueryArg, QueryRequest, Sub,
+        },
+        state::AppState,
     },
 };
 
@@ -50,160 +60,416 @@ pub trait RequestHandler<T = ProtoPackage> {
         &self,
         migration: &MigrationRequest,
     ) -> impl Future<Output = Result<T, Error>> + Send;
+    fn handle_import(
+        &self,
+        import_request: &ImportRequest,
+    ) -> impl Future<Output = Result<T, Error>> + Send;
+}
+
+/// #### The response to a request together with what the audit log records of it
+/// \
+/// Handed back by ProtoPackageResultHandler, so the outcome doesn't have to be decoded from the signed response.
+#[derive(Debug, PartialEq)]
+pub struct HandledRequest {
+    pub proto_package: ProtoPackage,
+    /// rows changed by a mutation or inserted by an import
+    pub rows_affected: Option<u64>,
+    /// set when the request was handled without making its change, I.E a failed migration
+    pub outcome: Option<String>,
+}
+
+impl HandledRequest {
+    pub fn new(proto_package: ProtoPackage) -> Self {
+        HandledRequest {
+            proto_package,
+            rows_affected: None,
+            outcome: None,
+        }
+    }
+
+    pub fn with_rows_affected(self, rows_affected: u64) -> Self {
+        HandledRequest {
+            rows_affected: Some(rows_affected),
+            ..self
+        }
+    }
+
+    pub fn with_outcome(self, outcome: &str) -> Self {
+        HandledRequest {
+            outcome: Some(outcome.to_string()),
+            ..self
+        }
+    }
 }
 
 pub struct ProtoPackageResultHandler<'a> {
-    pub user_access: u8,
+    /// bits of role::FETCH, MUTATE and MIGRATE the user has on the db
+    pub permissions: u8,
     pub username_password_hash: &'a str,
     pub db: &'a SqlitePool,
+    pub read_db: Option<&'a SqlitePool>,
+    pub query_timeout: u64,
+    pub max_rows: u64,
+    pub max_response_bytes: u64,
+    pub replication: Option<&'a ReplicationConfig>,
+    pub primary: Option<&'a str>,
 }
 
 impl<'a> ProtoPackageResultHandler<'a> {
-    pub fn new(user_access: u8, username_password_hash: &'a str, db: &'a SqlitePool) -> Self {
+    pub fn new(permissions: u8, username_password_hash: &'a str, db: &'a SqlitePool) -> Self {
         ProtoPackageResultHandler {
-            user_access,
+            permissions,
             username_password_hash,
             db,
+            read_db: None,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            replication: None,
+            primary: None,
+        }
+    }
+
+    /// Serve fetches from a separate (read-only) pool, mutations and migrations still go to __db__.
+    pub fn with_read_db(self, read_db: &'a SqlitePool) -> Self {
+        ProtoPackageResultHandler {
+            read_db: Some(read_db),
+            ..self
+        }
+    }
+
+    /// Max time in milliseconds that a fetch or mutation may run for, 0 = no timeout.
+    /// A request can ask for a lower timeout but never for a higher one.
+    pub fn with_query_timeout(self, query_timeout: u64) -> Self {
+        ProtoPackageResultHandler {
+            query_timeout,
+            ..self
+        }
+    }
+
+    /// Max number of rows and max size of the fetch response JSON, 0 = no limit.
+    /// Fetch responses going over either limit are truncated and flagged as such.
+    pub fn with_fetch_limits(self, max_rows: u64, max_response_bytes: u64) -> Self {
+        ProtoPackageResultHandler {
+            max_rows,
+            max_response_bytes,
+            ..self
+        }
+    }
+
+    /// Record every committed mutation and migration in the replication log of the db, for followers to replay.
+    pub fn with_replication(self, replication: Option<&'a ReplicationConfig>) -> Self {
+        ProtoPackageResultHandler {
+            replication,
+            ..self
+        }
+    }
+
+    /// Serve as a read replica of the primary at the given url, mutations and migrations are rejected.
+    pub fn with_primary(self, primary: Option<&'a str>) -> Self {
+        ProtoPackageResultHandler { primary, ..self }
+    }
+
+    fn check_primary(&self) -> Result<(), Error> {
+        match self.primary {
+            Some(primary) => Err(NotPrimaryError::with_message(&format!(
+                "Server is a read replica, send writes to the primary at {}",
+                primary
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn effective_query_timeout(&self, requested_timeout: u64) -> u64 {
+        match (self.query_timeout, requested_timeout) {
+            (0, requested) => requested,
+            (max, 0) => max,
+            (max, requested) => max.min(requested),
         }
     }
 }
 
-impl<'a> RequestHandler<ProtoPackage> for ProtoPackageResultHandler<'a> {
-    fn handle_fetch(
-        &self,
-        request_query: &QueryRequest,
-    ) -> impl Future<Output = Result<ProtoPackage, Error>> + Send {
-        async move {
-            if self.user_access >= 1 {
-                match fetch_all_as_json(
-                    AppliedQuery::new(&request_query.query).with_args(&request_query.parts),
-                    &self.db,
+fn database_error(e: &sqlx::Error, interrupt: &QueryInterrupt) -> Error {
+    if interrupt.timed_out() {
+        return TimeoutError::default();
+    }
+
+    match e.as_database_error() {
+        Some(db_error) => DatabaseError::with_message(db_error.message()),
+        None => DatabaseError::with_message(&e.to_string()),
+    }
+}
+
+impl<'a> RequestHandler<HandledRequest> for ProtoPackageResultHandler<'a> {
+    async fn handle_fetch(&self, request_query: &QueryRequest) -> Result<HandledRequest, Error> {
+        if self.permissions & role::FETCH != 0 {
+            let mut conn = match self.read_db.unwrap_or(self.db).acquire().await {
+                Ok(conn) => conn,
+                Err(e) => return Err(DatabaseError::with_message(&e.to_string())),
+            };
+            let interrupt = match QueryInterrupt::set(
+                &mut conn,
+                self.effective_query_timeout(request_query.timeout),
+            )
+            .await
+            {
+                Ok(interrupt) => interrupt,
+                Err(e) => return Err(DatabaseError::with_message(&e.to_string())),
+            };
+
+            let res = fetch_as_json_bytes(
+                AppliedQuery::new(&request_query.query).with_args(&request_query.parts),
+                &mut *conn,
+                self.max_rows,
+                self.max_response_bytes,
+            )
+            .await;
+            interrupt.finish();
+
+            match res {
+                Ok((data, truncated)) => encode_proto(
+                    FetchResponse::as_dat(data, truncated),
+                    Sub::Data,
+                    self.username_password_hash,
                 )
-                .await
-                {
-                    Ok(res) => encode_proto(
-                        FetchResponse::as_dat(serde_json::to_vec(&res).unwrap()),
-                        Sub::Data,
-                        self.username_password_hash,
-                    ),
-                    Err(e) => Err(DatabaseError::with_message(
-                        e.as_database_error().unwrap().message(),
-                    )),
-                }
-            } else {
-                Err(UserNotAllowedError::default())
+                .map(HandledRequest::new),
+                Err(e) => Err(database_error(&e, &interrupt)),
             }
+        } else {
+            Err(UserNotAllowedError::default())
         }
     }
 
-    fn handle_mutate(
-        &self,
-        request_query: &QueryRequest,
-    ) -> impl Future<Output = Result<ProtoPackage, Error>> + Send {
-        async move {
-            if self.user_access >= 2 {
-                let mut transaction = self.db.begin().await.unwrap();
-                match execute_query(
-                    AppliedQuery::new(&request_query.query).with_args(&request_query.parts),
-                    &mut *transaction,
-                )
-                .await
-                {
-                    Ok(res) => {
-                        let _ = &mut transaction.commit().await;
-                        encode_proto(
-                            MutationResponse::as_dat(
-                                res.rows_affected(),
-                                res.last_insert_rowid() as u64,
-                            ),
-                            Sub::Data,
-                            self.username_password_hash,
+    async fn handle_mutate(&self, request_query: &QueryRequest) -> Result<HandledRequest, Error> {
+        self.check_primary()?;
+
+        if self.permissions & role::MUTATE != 0 {
+            if self.replication.is_some() {
+                check_deterministic(&request_query.query, &request_query.parts)?;
+            }
+
+            let mut transaction = match self.db.begin().await {
+                Ok(transaction) => transaction,
+                Err(e) => return Err(DatabaseError::with_message(&e.to_string())),
+            };
+            let interrupt = match QueryInterrupt::set(
+                &mut transaction,
+                self.effective_query_timeout(request_query.timeout),
+            )
+            .await
+            {
+                Ok(interrupt) => interrupt,
+                Err(e) => return Err(DatabaseError::with_message(&e.to_string())),
+            };
+
+            match execute_query(
+                AppliedQuery::new(&request_query.query).with_args(&request_query.parts),
+                &mut *transaction,
+            )
+            .await
+            {
+                Ok(res) => {
+                    if let Some(replication) = self.replication {
+                        if let Err(e) = log_change(
+                            &mut transaction,
+                            Sub::Mutate,
+                            request_query.encode_to_vec(),
+                            replication.keep_changes,
                         )
+                        .await
+                        {
+                            let _ = &mut transaction.rollback().await;
+                            interrupt.finish();
+                            return Err(database_error(&e, &interrupt));
+                        }
                     }
-                    Err(e) => {
-                        let _ = &mut transaction.rollback().await;
-                        Err(DatabaseError::with_message(
-                            e.as_database_error().unwrap().message(),
-                        ))
+
+                    // the audit log records the outcome of the response, a failed commit has to be an error
+                    let commit_res = transaction.commit().await;
+                    interrupt.finish();
+                    if let Err(e) = commit_res {
+                        return Err(database_error(&e, &interrupt));
                     }
+
+                    encode_proto(
+                        MutationResponse::as_dat(
+                            res.rows_affected(),
+                            res.last_insert_rowid() as u64,
+                        ),
+                        Sub::Data,
+                        self.username_password_hash,
+                    )
+                    .map(|proto_package| {
+                        HandledRequest::new(proto_package).with_rows_affected(res.rows_affected())
+                    })
+                }
+                Err(e) => {
+                    let _ = &mut transaction.rollback().await;
+                    interrupt.finish();
+                    Err(database_error(&e, &interrupt))
                 }
-            } else {
-                Err(UserNotAllowedError::default())
             }
+        } else {
+            Err(UserNotAllowedError::default())
         }
     }
 
-    fn handle_migrate(
-        &self,
-        migration: &MigrationRequest,
-    ) -> impl Future<Output = Result<ProtoPackage, Error>> + Send {
-        async move {
-            if self.user_access >= 2 {
-                let mut transaction = self.db.begin().await.unwrap();
-
-                // create if not exist, will enter Ok clause even if it exists
-                match execute_query(
-                    AppliedQuery::new(queries::CREATE_MIGRATIONS_TABLE),
-                    &mut *transaction,
-                )
-                .await
-                {
-                    Ok(_) => {
-                        if let Err(e) = execute_query(
-                            AppliedQuery::new(queries::INSERT_MIGRATION).with_args(&vec![
-                                QueryArg::new(query_arg::Value::String(migration.name.clone())),
-                                QueryArg::new(query_arg::Value::String(migration.query.clone())),
-                            ]),
-                            &mut *transaction,
+    async fn handle_migrate(&self, migration: &MigrationRequest) -> Result<HandledRequest, Error> {
+        self.check_primary()?;
+
+        if self.permissions & role::MIGRATE != 0 {
+            if self.replication.is_some() {
+                check_deterministic(&migration.query, &[])?;
+            }
+
+            let mut transaction = match self.db.begin().await {
+                Ok(transaction) => transaction,
+                Err(e) => return Err(DatabaseError::with_message(&e.to_string())),
+            };
+            let interrupt = match QueryInterrupt::set(
+                &mut transaction,
+                self.effective_query_timeout(0),
+            )
+            .await
+            {
+                Ok(interrupt) => interrupt,
+                Err(e) => return Err(DatabaseError::with_message(&e.to_string())),
+            };
+
+            // create if not exist, will enter Ok clause even if it exists
+            match execute_query(
+                AppliedQuery::new(queries::CREATE_MIGRATIONS_TABLE),
+                &mut *transaction,
+            )
+            .await
+            {
+                Ok(_) => {
+                    if let Err(e) = execute_query(
+                        AppliedQuery::new(queries::INSERT_MIGRATION).with_args(&[
+                            QueryArg::new(query_arg::Value::String(migration.name.clone())),
+                            QueryArg::new(query_arg::Value::String(migration.query.clone())),
+                        ]),
+                        &mut *transaction,
+                    )
+                    .await
+                    {
+                        let _ = &mut transaction.rollback().await;
+                        interrupt.finish();
+                        return Err(database_error(&e, &interrupt));
+                    }
+                }
+                Err(e) => {
+                    let _ = &mut transaction.rollback().await;
+                    interrupt.finish();
+                    return Err(database_error(&e, &interrupt));
+                }
+            };
+
+            match execute_query(AppliedQuery::new(&migration.query), &mut *transaction).await {
+                Ok(_) => {
+                    if let Some(replication) = self.replication {
+                        if let Err(e) = log_change(
+                            &mut transaction,
+                            Sub::Migrate,
+                            migration.encode_to_vec(),
+                            replication.keep_changes,
                         )
                         .await
                         {
-                            let _ = &mut transaction.rollback().await;
-                            return Err(DatabaseError::with_message(
-                                e.as_database_error().unwrap().message(),
-                            ));
+                            let _ = transaction.rollback().await;
+                            interrupt.finish();
+                            return Err(database_error(&e, &interrupt));
                         }
                     }
-                    Err(e) => {
-                        let _ = &mut transaction.rollback().await;
-                        return Err(DatabaseError::with_message(
-                            e.as_database_error().unwrap().message(),
-                        ));
-                    }
-                };
-
-                match execute_query(AppliedQuery::new(&migration.query), &mut *transaction).await {
-                    Ok(_) => {
-                        let _ = transaction.commit().await;
-                        encode_proto(
-                            MigrationResponse::as_dat(true),
-                            Sub::Data,
-                            self.username_password_hash,
-                        )
+
+                    let commit_res = transaction.commit().await;
+                    interrupt.finish();
+                    if let Err(e) = commit_res {
+                        return Err(database_error(&e, &interrupt));
                     }
-                    Err(e) => {
-                        let _ = transaction.rollback().await;
-                        eprintln!("{e}");
-                        encode_proto(
-                            MigrationResponse::as_dat(false),
-                            Sub::Data,
-                            self.username_password_hash,
-                        )
+
+                    encode_proto(
+                        MigrationResponse::as_dat(true),
+                        Sub::Data,
+                        self.username_password_hash,
+                    )
+                    .map(HandledRequest::new)
+                }
+                Err(e) => {
+                    let _ = transaction.rollback().await;
+                    interrupt.finish();
+                    // a migration cut off by the query timeout did not fail on its own
+                    if interrupt.timed_out() {
+                        return Err(TimeoutError::default());
                     }
+
+                    eprintln!("{e}");
+                    encode_proto(
+                        MigrationResponse::as_dat(false),
+                        Sub::Data,
+                        self.username_password_hash,
+                    )
+                    .map(|proto_package| {
+                        HandledRequest::new(proto_package).with_outcome("migration failed")
+                    })
                 }
-            } else {
-                Err(UserNotAllowedError::default())
             }
+        } else {
+            Err(UserNotAllowedError::default())
+        }
+    }
+
+    async fn handle_import(&self, import_request: &ImportRequest) -> Result<HandledRequest, Error> {
+        self.check_primary()?;
+
+        if self.permissions & role::MUTATE != 0 {
+            let mut conn = match self.db.acquire().await {
+                Ok(conn) => conn,
+                Err(e) => return Err(DatabaseError::with_message(&e.to_string())),
+            };
+
+            let report = import(
+                &mut conn,
+                &import_request.table,
+                import_request.format(),
+                import_request.data.as_slice(),
+                IMPORT_BATCH_SIZE,
+                import_request.max_errors,
+                self.replication,
+            )
+            .await?;
+
+            let outcome = report
+                .aborted
+                .then(|| format!("aborted after {} failed rows", report.failed));
+            let imported = report.imported;
+            encode_proto(
+                ImportResponse::as_dat(
+                    report.imported,
+                    report.failed,
+                    report.errors,
+                    report.aborted,
+                ),
+                Sub::Data,
+                self.username_password_hash,
+            )
+            .map(|proto_package| {
+                let handled_request =
+                    HandledRequest::new(proto_package).with_rows_affected(imported);
+                match &outcome {
+                    Some(outcome) => handled_request.with_outcome(outcome),
+                    None => handled_request,
+                }
+            })
+        } else {
+            Err(UserNotAllowedError::default())
         }
     }
 }
 
-pub async fn get_proto_package_result<'a, T>(
-    claims: Claims,
-    handler: &'a T,
-) -> Result<ProtoPackage, Error>
+pub async fn get_proto_package_result<T, H>(claims: Claims, handler: &H) -> Result<T, Error>
 where
-    T: RequestHandler,
+    H: RequestHandler<T>,
 {
     match &claims.dat {
         Some(Dat::MigrationRequest(dat)) => match claims.sub() {
@@ -215,10 +481,60 @@ where
             Sub::Fetch => handler.handle_fetch(dat).await,
             _ => Err(UndefinedError::default()),
         },
+        Some(Dat::ImportRequest(dat)) => match claims.sub() {
+            Sub::Import => handler.handle_import(dat).await,
+            _ => Err(UndefinedError::default()),
+        },
         _ => Err(UndefinedError::default()),
     }
 }
 
+/// #### The audit log entry of a request to a db, None for requests that don't change anything (fetches)
+/// \
+/// __*@param*__ database: &str (db name hash)
+pub fn protocol_audit_entry(username: &str, database: &str, claims: &Claims) -> Option<AuditEntry> {
+    let entry = |action: &str| {
+        AuditEntry::new(AUDIT_SOURCE_PROTOCOL, username, action).with_database_hash(database)
+    };
+
+    match (claims.sub(), &claims.dat) {
+        (Sub::Mutate, Some(Dat::QueryRequest(dat))) => {
+            Some(entry("mutate").with_query(&dat.query, audit::args_digest(&dat.parts)))
+        }
+        (Sub::Migrate, Some(Dat::MigrationRequest(dat))) => {
+            Some(entry(&format!("migrate {}", dat.name)).with_query(&dat.query, None))
+        }
+        // the digest of the imported data takes the place of the query args
+        (Sub::Import, Some(Dat::ImportRequest(dat))) => Some(AuditEntry {
+            args_digest: audit::args_digest(&[QueryArg::new(query_arg::Value::Blob(
+                dat.data.clone(),
+            ))]),
+            ..entry(&format!("import into {}", dat.table))
+        }),
+        _ => None,
+    }
+}
+
+/// #### Completes an audit log entry with the rows affected and the outcome of the request
+pub fn with_audit_outcome(entry: AuditEntry, res: &Result<HandledRequest, Error>) -> AuditEntry {
+    let handled_request = match res {
+        Ok(handled_request) => handled_request,
+        Err(e) => return entry.with_error(e),
+    };
+
+    let entry = match handled_request.rows_affected {
+        Some(rows_affected) => entry.with_rows_affected(rows_affected),
+        None => entry,
+    };
+    match &handled_request.outcome {
+        Some(outcome) => AuditEntry {
+            outcome: outcome.clone(),
+            ..entry
+        },
+        None => entry,
+    }
+}
+
 pub fn get_header_value(header: Option<&HeaderValue>) -> Result<&str, Error> {
     match header {
         Some(hdr) => match hdr.to_str() {
@@ -231,16 +547,37 @@ pub fn get_header_value(header: Option<&HeaderValue>) -> Result<&str, Error> {
 
 pub fn check_content_type(content_type: &str) -> Result<(), Error> {
     if content_type != "application/protobuf" {
-        return Err(HeaderMalformedError::with_message("Content-Type not supported"))
+        return Err(HeaderMalformedError::with_message(
+            "Content-Type not supported",
+        ));
     }
 
     Ok(())
 }
 
-pub fn extract_headers<'a>(headers: &'a HeaderMap) -> Result<(&'a str, &'a str, &'a str), Error> {
+pub fn extract_headers(headers: &HeaderMap) -> Result<(&str, &str, &str), Error> {
     let header_content_type = get_header_value(headers.get("content-type"))?;
     let header_username_hash = get_header_value(headers.get("0"))?;
     let header_proto_signature = get_header_value(headers.get("1"))?;
 
-    Ok((header_content_type, header_username_hash, header_proto_signature))
+    Ok((
+        header_content_type,
+        header_username_hash,
+        header_proto_signature,
+    ))
+}
+
+/// #### Name of the API key the request is signed with, None when signed with the username_password_hash
+pub fn get_key_id_header(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("2")
+        .and_then(|header| header.to_str().ok())
+        .filter(|key_id| !key_id.is_empty())
+}
+
+/// #### The DatabaseManager of the root dir the server runs in
+/// \
+/// The paths of the app state come from a DatabaseManager of the root dir, db_path being \<root_dir\>/db.
+pub fn database_manager(data: &AppState) -> DatabaseManager {
+    DatabaseManager::new(Path::new(&data.db_path).parent().unwrap_or(Path::new("")))
 }
@@ -4,9 +4,9 @@
 #[cfg(test)]
 pub mod db {
     use serf::core::db::{fetch_all_as_json, AppliedQuery};
-    
+
     use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
-    
+
     async fn setup_test_db() -> SqlitePool {
         let db = SqlitePoolOptions::new()
             .max_connections(1)
@@ -73,3 +73,1991 @@ pub mod db {
         });
     }
 }
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+pub mod cli {
+    use std::{fs, path::PathBuf};
+
+    use chrono::{DateTime, Utc};
+    use serde_json::json;
+    use serf::{
+        cli::util::DatabaseManager,
+        core::{
+            audit::{
+                self, AuditEntry, AUDIT_SOURCE_ADMIN, AUDIT_SOURCE_CLI, AUDIT_SOURCE_PROTOCOL,
+            },
+            constants::queries,
+            role,
+            serf_proto::{Error, ErrorKind},
+            util::get_db_users,
+        },
+    };
+    use sha2::{Digest, Sha256};
+    use sqlx::SqlitePool;
+
+    fn test_root_dir(test_name: &str) -> PathBuf {
+        let root_dir =
+            std::env::temp_dir().join(format!("serf_{}_{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&root_dir);
+
+        root_dir
+    }
+
+    async fn count_rows(database_manager: &DatabaseManager, query: &str) -> i64 {
+        let pool = SqlitePool::connect(&format!(
+            "sqlite:{}",
+            database_manager.user_db_full_path_string
+        ))
+        .await
+        .unwrap();
+        let count = sqlx::query_scalar::<_, i64>(query)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        pool.close().await;
+
+        count
+    }
+
+    #[tokio::test]
+    async fn test_database_manager_init__uses_given_root_dir() {
+        let root_dir = test_root_dir("init");
+        let database_manager = DatabaseManager::new(&root_dir);
+
+        assert!(database_manager.init().await.is_ok());
+        assert!(database_manager.user_db_base_path.starts_with(&root_dir));
+        assert!(database_manager.consumer_db_base_path.exists());
+        assert_eq!(
+            count_rows(&database_manager, queries::GET_USER_DB_VERSION).await,
+            queries::USER_DB_MIGRATIONS.len() as i64
+        );
+        // admins are the users with the admin global role, there is no separate flag
+        assert_eq!(
+            count_rows(
+                &database_manager,
+                "SELECT COUNT(*) FROM pragma_table_info('users') WHERE name = 'is_admin';"
+            )
+            .await,
+            0
+        );
+        assert_eq!(
+            count_rows(
+                &database_manager,
+                "SELECT COUNT(*) FROM pragma_table_info('api_keys') WHERE name = 'secret';"
+            )
+            .await,
+            1
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__user_lifecycle() {
+        let root_dir = test_root_dir("user_lifecycle");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        database_manager
+            .create_consumer_database("test_db")
+            .await
+            .unwrap();
+        database_manager
+            .create_user("test_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .modify_user_access(
+                "test_user".to_string(),
+                "test_db".to_string(),
+                "writer".to_string(),
+            )
+            .await
+            .unwrap();
+
+        let wrong_password = database_manager
+            .modify_user_username(
+                "test_user".to_string(),
+                "wrong_password".to_string(),
+                "renamed_user".to_string(),
+            )
+            .await;
+        assert_eq!(
+            wrong_password
+                .expect_err("Should be password error")
+                .message,
+            "Password is incorrect"
+        );
+
+        database_manager
+            .modify_user_username(
+                "test_user".to_string(),
+                "test_password".to_string(),
+                "renamed_user".to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            count_rows(
+                &database_manager,
+                "SELECT COUNT(*) FROM users u INNER JOIN users_database_access USING(username_hash) WHERE u.username = 'renamed_user';"
+            )
+            .await,
+            1
+        );
+
+        database_manager
+            .revoke_user_access("renamed_user".to_string(), "test_db".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            count_rows(
+                &database_manager,
+                "SELECT COUNT(*) FROM users_database_access;"
+            )
+            .await,
+            0
+        );
+
+        database_manager
+            .delete_user("renamed_user".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            count_rows(&database_manager, "SELECT COUNT(*) FROM users;").await,
+            0
+        );
+        assert!(database_manager
+            .delete_user("renamed_user".to_string())
+            .await
+            .is_err());
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__rename_and_drop_database_moves_access() {
+        let root_dir = test_root_dir("database_lifecycle");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        database_manager
+            .create_consumer_database("test_db")
+            .await
+            .unwrap();
+        database_manager
+            .create_user("test_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .modify_user_access(
+                "test_user".to_string(),
+                "test_db".to_string(),
+                "reader".to_string(),
+            )
+            .await
+            .unwrap();
+
+        database_manager
+            .rename_database("test_db".to_string(), "renamed_db".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            count_rows(
+                &database_manager,
+                "SELECT COUNT(*) FROM users_database_access WHERE database = 'renamed_db';"
+            )
+            .await,
+            1
+        );
+        assert!(database_manager
+            .database_info("test_db".to_string())
+            .await
+            .is_err());
+        assert!(database_manager
+            .database_info("renamed_db".to_string())
+            .await
+            .is_ok());
+
+        database_manager
+            .drop_database("renamed_db".to_string(), true)
+            .await
+            .unwrap();
+        assert_eq!(
+            count_rows(
+                &database_manager,
+                "SELECT COUNT(*) FROM users_database_access;"
+            )
+            .await,
+            0
+        );
+        assert_eq!(
+            fs::read_dir(&database_manager.consumer_db_base_path)
+                .unwrap()
+                .count(),
+            0
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__failed_rename_database_is_rolled_back() {
+        let root_dir = test_root_dir("database_rename_rollback");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        database_manager
+            .create_consumer_database("test_db")
+            .await
+            .unwrap();
+        database_manager
+            .create_user("test_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .modify_user_access(
+                "test_user".to_string(),
+                "test_db".to_string(),
+                "reader".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // a directory in the way of the renamed db file makes the rename of the file fail
+        let test_db_hash = base16ct::lower::encode_string(&Sha256::digest(b"test_db"));
+        let renamed_db_hash = base16ct::lower::encode_string(&Sha256::digest(b"renamed_db"));
+        let test_db_dir = database_manager.consumer_db_base_path.join(&test_db_hash);
+        fs::create_dir_all(
+            test_db_dir
+                .join(format!("{}.db", renamed_db_hash))
+                .join("blocker"),
+        )
+        .unwrap();
+
+        assert!(database_manager
+            .rename_database("test_db".to_string(), "renamed_db".to_string())
+            .await
+            .is_err());
+        assert!(test_db_dir.join(format!("{}.db", test_db_hash)).exists());
+        assert!(!database_manager
+            .consumer_db_base_path
+            .join(&renamed_db_hash)
+            .exists());
+        assert_eq!(
+            count_rows(
+                &database_manager,
+                "SELECT COUNT(*) FROM users_database_access WHERE database = 'test_db';"
+            )
+            .await,
+            1
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__modify_user_limits_unlimited_and_reset() {
+        let root_dir = test_root_dir("fetch_limits");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+        database_manager
+            .create_user("test_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+
+        database_manager
+            .modify_user_limits(
+                "test_user".to_string(),
+                None,
+                Some(Some(0)),
+                Some(Some(1024)),
+                None,
+            )
+            .await
+            .unwrap();
+        let users = database_manager.get_users(Some("test_user")).await.unwrap();
+        assert_eq!(users[0].max_rows, Some(0));
+        assert_eq!(users[0].max_response_bytes, Some(1024));
+
+        database_manager
+            .modify_user_limits("test_user".to_string(), None, None, Some(None), None)
+            .await
+            .unwrap();
+        let users = database_manager.get_users(Some("test_user")).await.unwrap();
+        assert_eq!(users[0].max_rows, Some(0));
+        assert_eq!(users[0].max_response_bytes, None);
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__get_users_and_databases() {
+        let root_dir = test_root_dir("listings");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        for db_name in ["test_db", "test_db_too"] {
+            database_manager
+                .create_consumer_database(db_name)
+                .await
+                .unwrap();
+        }
+        for username in ["test_admin", "test_user"] {
+            database_manager
+                .create_user(username.to_string(), "test_password".to_string())
+                .await
+                .unwrap();
+        }
+        database_manager
+            .modify_user_admin("test_admin".to_string(), true)
+            .await
+            .unwrap();
+        database_manager
+            .modify_user_limits("test_user".to_string(), Some(5000), None, None, None)
+            .await
+            .unwrap();
+        for (db_name, role) in [("test_db", "writer"), ("test_db_too", "reader")] {
+            database_manager
+                .modify_user_access(
+                    "test_user".to_string(),
+                    db_name.to_string(),
+                    role.to_string(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let users = database_manager.get_users(None).await.unwrap();
+        assert_eq!(users.len(), 2);
+        assert!(users[0].is_admin);
+        assert_eq!(users[0].role, "admin");
+        assert!(users[0].access.is_empty());
+        assert_eq!(users[1].username, "test_user");
+        assert_eq!(users[1].query_timeout, 5000);
+        assert_eq!(users[1].max_rows, None);
+        assert_eq!(
+            users[1]
+                .access
+                .iter()
+                .map(|access| (
+                    access.database.as_str(),
+                    access.role.as_str(),
+                    access.permissions
+                ))
+                .collect::<Vec<_>>(),
+            vec![("test_db", "writer", 3), ("test_db_too", "reader", 1)]
+        );
+        assert_eq!(
+            database_manager.get_users(Some("test_user")).await.unwrap(),
+            users[1..]
+        );
+        assert!(database_manager.get_users(Some("no_user")).await.is_err());
+
+        let databases = database_manager.get_databases().await.unwrap();
+        assert_eq!(databases.len(), 2);
+        assert_eq!(databases[0].name, "test_db");
+        assert_eq!(databases[0].hash, users[1].access[0].database_hash);
+        assert!(databases[0].size > 0);
+        assert_eq!(databases[1].users.len(), 1);
+        assert_eq!(databases[1].users[0].username, "test_user");
+        assert_eq!(databases[1].users[0].role, "reader");
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__roles() {
+        let root_dir = test_root_dir("roles");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        database_manager
+            .create_consumer_database("test_db")
+            .await
+            .unwrap();
+        database_manager
+            .create_user("test_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .create_role(
+                "deployer".to_string(),
+                &["fetch".to_string(), "migrate".to_string()],
+            )
+            .await
+            .unwrap();
+
+        let unknown_role = database_manager
+            .modify_user_access(
+                "test_user".to_string(),
+                "test_db".to_string(),
+                "no_role".to_string(),
+            )
+            .await;
+        assert_eq!(
+            unknown_role.expect_err("Should be unknown role").source(),
+            ErrorKind::ResourceNotExist
+        );
+
+        database_manager
+            .modify_user_access(
+                "test_user".to_string(),
+                "test_db".to_string(),
+                "deployer".to_string(),
+            )
+            .await
+            .unwrap();
+        database_manager
+            .modify_user_role("test_user".to_string(), Some(role::READER.to_string()))
+            .await
+            .unwrap();
+
+        let db_users = get_db_users(&database_manager.user_db_full_path_string)
+            .await
+            .unwrap();
+        let test_db_hash = base16ct::lower::encode_string(&Sha256::digest(b"test_db"));
+        assert_eq!(db_users[0]["permissions"], role::FETCH);
+        assert_eq!(
+            db_users[0]["databases"][&test_db_hash],
+            role::FETCH | role::MIGRATE
+        );
+
+        let roles = database_manager.get_roles().await.unwrap();
+        assert_eq!(roles.len(), role::BUILTIN_ROLES.len() + 1);
+        assert!(roles
+            .iter()
+            .any(|role| role.name == "deployer" && !role.is_builtin));
+
+        for (role_name, expected_error) in [
+            ("deployer", "Role deployer is still given to users 1 times"),
+            (role::READER, "Built-in roles can't be deleted"),
+        ] {
+            let in_use = database_manager.delete_role(role_name.to_string()).await;
+            assert_eq!(
+                in_use.expect_err("Should not be deleted").message,
+                expected_error
+            );
+        }
+
+        database_manager
+            .revoke_user_access("test_user".to_string(), "test_db".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .modify_user_role("test_user".to_string(), None)
+            .await
+            .unwrap();
+        database_manager
+            .delete_role("deployer".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            database_manager.get_roles().await.unwrap().len(),
+            role::BUILTIN_ROLES.len()
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__groups() {
+        let root_dir = test_root_dir("groups");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        for db_name in ["test_db", "test_db_too"] {
+            database_manager
+                .create_consumer_database(db_name)
+                .await
+                .unwrap();
+        }
+        database_manager
+            .create_user("test_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .create_group("test_group".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .modify_group_member("test_group".to_string(), "test_user".to_string(), true)
+            .await
+            .unwrap();
+        for (db_name, role) in [("test_db", role::MIGRATOR), ("test_db_too", role::WRITER)] {
+            database_manager
+                .modify_group_access(
+                    "test_group".to_string(),
+                    db_name.to_string(),
+                    role.to_string(),
+                )
+                .await
+                .unwrap();
+        }
+        database_manager
+            .modify_user_access(
+                "test_user".to_string(),
+                "test_db".to_string(),
+                role::READER.to_string(),
+            )
+            .await
+            .unwrap();
+
+        let missing_user = database_manager
+            .modify_group_member("test_group".to_string(), "no_user".to_string(), true)
+            .await;
+        assert_eq!(
+            missing_user.expect_err("Should be missing user").source(),
+            ErrorKind::UserNotExist
+        );
+
+        // the direct and group grants on test_db are combined
+        let db_users = get_db_users(&database_manager.user_db_full_path_string)
+            .await
+            .unwrap();
+        let test_db_hash = base16ct::lower::encode_string(&Sha256::digest(b"test_db"));
+        let test_db_too_hash = base16ct::lower::encode_string(&Sha256::digest(b"test_db_too"));
+        assert_eq!(db_users.as_array().unwrap().len(), 1);
+        assert_eq!(
+            db_users[0]["databases"],
+            json!({
+                &test_db_hash: role::FETCH | role::MIGRATE,
+                &test_db_too_hash: role::FETCH | role::MUTATE
+            })
+        );
+
+        let users = database_manager.get_users(Some("test_user")).await.unwrap();
+        assert_eq!(users[0].groups, vec!["test_group"]);
+
+        let groups = database_manager.get_groups().await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].members, vec!["test_user"]);
+        assert_eq!(
+            groups[0].access,
+            vec![
+                ("test_db".to_string(), role::MIGRATOR.to_string()),
+                ("test_db_too".to_string(), role::WRITER.to_string())
+            ]
+        );
+
+        database_manager
+            .rename_database("test_db_too".to_string(), "renamed_db".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .revoke_group_access("test_group".to_string(), "test_db".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            database_manager.get_groups().await.unwrap()[0].access,
+            vec![("renamed_db".to_string(), role::WRITER.to_string())]
+        );
+
+        database_manager
+            .delete_group("test_group".to_string())
+            .await
+            .unwrap();
+        assert!(database_manager.get_groups().await.unwrap().is_empty());
+        assert!(
+            database_manager.get_users(Some("test_user")).await.unwrap()[0]
+                .groups
+                .is_empty()
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_get_db_users__users_with_zero_one_and_many_grants() {
+        let root_dir = test_root_dir("zero_one_and_many_grants");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        for db_name in ["test_db", "test_db_too"] {
+            database_manager
+                .create_consumer_database(db_name)
+                .await
+                .unwrap();
+        }
+        for username in ["no_grants_user", "one_grant_user", "many_grants_user"] {
+            database_manager
+                .create_user(username.to_string(), "test_password".to_string())
+                .await
+                .unwrap();
+        }
+        for (username, db_name, role) in [
+            ("one_grant_user", "test_db", role::READER),
+            ("many_grants_user", "test_db", role::READER),
+            ("many_grants_user", "test_db_too", role::WRITER),
+        ] {
+            database_manager
+                .modify_user_access(username.to_string(), db_name.to_string(), role.to_string())
+                .await
+                .unwrap();
+        }
+
+        let db_users = get_db_users(&database_manager.user_db_full_path_string)
+            .await
+            .unwrap();
+        let databases = |username: &str| {
+            db_users
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|db_user| db_user["username"] == username)
+                .map(|db_user| db_user["databases"].clone())
+        };
+        let test_db_hash = base16ct::lower::encode_string(&Sha256::digest(b"test_db"));
+        let test_db_too_hash = base16ct::lower::encode_string(&Sha256::digest(b"test_db_too"));
+
+        assert_eq!(db_users.as_array().unwrap().len(), 3);
+        assert_eq!(databases("no_grants_user"), Some(json!({})));
+        assert_eq!(
+            databases("one_grant_user"),
+            Some(json!({ &test_db_hash: role::FETCH }))
+        );
+        assert_eq!(
+            databases("many_grants_user"),
+            Some(json!({
+                &test_db_hash: role::FETCH,
+                &test_db_too_hash: role::FETCH | role::MUTATE
+            }))
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__api_keys() {
+        let root_dir = test_root_dir("api_keys");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        database_manager
+            .create_consumer_database("test_db")
+            .await
+            .unwrap();
+        database_manager
+            .create_user("test_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+        database_manager
+            .modify_user_access(
+                "test_user".to_string(),
+                "test_db".to_string(),
+                role::WRITER.to_string(),
+            )
+            .await
+            .unwrap();
+
+        let expires_at = DateTime::from_timestamp(Utc::now().timestamp() + 3600, 0).unwrap();
+        let api_key = database_manager
+            .create_api_key(
+                "test_user".to_string(),
+                "old_key".to_string(),
+                None,
+                &["fetch".to_string()],
+            )
+            .await
+            .unwrap();
+        database_manager
+            .create_api_key("test_user".to_string(), "new_key".to_string(), None, &[])
+            .await
+            .unwrap();
+        let missing_user = database_manager
+            .create_api_key("no_user".to_string(), "key".to_string(), None, &[])
+            .await;
+        assert_eq!(
+            missing_user.expect_err("Should be missing user").source(),
+            ErrorKind::UserNotExist
+        );
+
+        // rotation, the old key keeps working until it expires
+        database_manager
+            .revoke_api_key(
+                "test_user".to_string(),
+                "old_key".to_string(),
+                Some(expires_at),
+            )
+            .await
+            .unwrap();
+
+        let db_users = get_db_users(&database_manager.user_db_full_path_string)
+            .await
+            .unwrap();
+        assert_eq!(
+            db_users[0]["api_keys"]["old_key"],
+            json!({
+                "secret": base16ct::lower::encode_string(&Sha256::digest(api_key.as_bytes())),
+                "expires_at": expires_at.timestamp(),
+                "scope": role::FETCH,
+            })
+        );
+        assert_eq!(db_users[0]["api_keys"]["new_key"]["scope"], json!(null));
+
+        let api_keys = database_manager
+            .get_api_keys("test_user".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            api_keys
+                .iter()
+                .map(|api_key| (api_key.name.as_str(), api_key.expires_at, api_key.scope))
+                .collect::<Vec<_>>(),
+            vec![
+                ("new_key", None, None),
+                ("old_key", Some(expires_at), Some(role::FETCH))
+            ]
+        );
+
+        database_manager
+            .revoke_api_key("test_user".to_string(), "old_key".to_string(), None)
+            .await
+            .unwrap();
+        let revoked = database_manager
+            .revoke_api_key("test_user".to_string(), "old_key".to_string(), None)
+            .await;
+        assert_eq!(
+            revoked.expect_err("Should be revoked").source(),
+            ErrorKind::ResourceNotExist
+        );
+        assert_eq!(
+            database_manager
+                .get_api_keys("test_user".to_string())
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__create_owned_database_from_template() {
+        let root_dir = test_root_dir("owned_database");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        database_manager
+            .create_consumer_database("template_db")
+            .await
+            .unwrap();
+        let template_db = connect_consumer_db(&database_manager, "template_db").await;
+        sqlx::query("CREATE TABLE test_data_table (id INTEGER PRIMARY KEY, im_data TEXT);")
+            .execute(&template_db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO test_data_table(im_data) VALUES('from_template');")
+            .execute(&template_db)
+            .await
+            .unwrap();
+        database_manager
+            .create_user("test_creator".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+
+        database_manager
+            .create_owned_database(
+                "test_creator".to_string(),
+                "owned_db",
+                Some("template_db"),
+                false,
+                1,
+            )
+            .await
+            .unwrap();
+        template_db.close().await;
+
+        let owned_db = connect_consumer_db(&database_manager, "owned_db").await;
+        let im_data = sqlx::query_scalar::<_, String>("SELECT im_data FROM test_data_table;")
+            .fetch_one(&owned_db)
+            .await;
+        owned_db.close().await;
+
+        assert_eq!(im_data.unwrap(), "from_template");
+        assert_eq!(
+            count_rows(
+                &database_manager,
+                "SELECT COUNT(*) FROM users_database_access WHERE database = 'owned_db' AND role = 'writer' AND is_owner = 1;"
+            )
+            .await,
+            1
+        );
+
+        let over_quota = database_manager
+            .create_owned_database("test_creator".to_string(), "owned_db_too", None, false, 1)
+            .await;
+        assert_eq!(
+            over_quota.expect_err("Should be over the quota").source(),
+            ErrorKind::UserNotAllowed
+        );
+
+        let missing_template = database_manager
+            .create_owned_database(
+                "test_creator".to_string(),
+                "owned_db_too",
+                Some("no_db"),
+                false,
+                0,
+            )
+            .await;
+        assert_eq!(
+            missing_template
+                .expect_err("Should be missing template")
+                .source(),
+            ErrorKind::ResourceNotExist
+        );
+        assert_eq!(
+            fs::read_dir(&database_manager.consumer_db_base_path)
+                .unwrap()
+                .count(),
+            2
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    async fn connect_consumer_db(database_manager: &DatabaseManager, db_name: &str) -> SqlitePool {
+        let db_name_hash = base16ct::lower::encode_string(&Sha256::digest(db_name.as_bytes()));
+
+        SqlitePool::connect(&format!(
+            "sqlite:{}",
+            database_manager
+                .consumer_db_base_path
+                .join(&db_name_hash)
+                .join(format!("{}.db", db_name_hash))
+                .display()
+        ))
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__create_database_from_migrated_template() {
+        let root_dir = test_root_dir("cloned_database");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        database_manager
+            .create_consumer_database("template_db")
+            .await
+            .unwrap();
+        let template_db = connect_consumer_db(&database_manager, "template_db").await;
+        for query in [
+            queries::CREATE_MIGRATIONS_TABLE,
+            queries::CREATE_REPLICATION_LOG_TABLE,
+            "CREATE TABLE test_data_table (id INTEGER PRIMARY KEY AUTOINCREMENT, im_data TEXT);",
+            "INSERT INTO __migrations_tracker_t__(name, query) VALUES('create_test_data_table', '');",
+            "INSERT INTO test_data_table(im_data) VALUES('from_template');",
+            "INSERT INTO __replication_log_t__(sub, dat) VALUES(3, X'00');",
+        ] {
+            sqlx::raw_sql(query).execute(&template_db).await.unwrap();
+        }
+
+        database_manager
+            .create_consumer_database_from("full_db", Some("template_db"), false)
+            .await
+            .unwrap();
+        database_manager
+            .create_consumer_database_from("schema_db", Some("template_db"), true)
+            .await
+            .unwrap();
+        template_db.close().await;
+
+        for (db_name, expected_rows) in [("full_db", 1), ("schema_db", 0)] {
+            let db = connect_consumer_db(&database_manager, db_name).await;
+            let count = |query| sqlx::query_scalar::<_, i64>(query).fetch_one(&db);
+
+            assert_eq!(
+                count("SELECT COUNT(*) FROM __migrations_tracker_t__;")
+                    .await
+                    .unwrap(),
+                1,
+                "{} keeps the tracked migrations",
+                db_name
+            );
+            assert_eq!(
+                count("SELECT COUNT(*) FROM test_data_table;")
+                    .await
+                    .unwrap(),
+                expected_rows
+            );
+            assert_eq!(
+                count(queries::HAS_REPLICATION_LOG_TABLE).await.unwrap(),
+                0,
+                "{} has no replication log",
+                db_name
+            );
+            db.close().await;
+        }
+
+        assert!(database_manager
+            .create_consumer_database_from("full_db", Some("template_db"), false)
+            .await
+            .is_err());
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_database_manager__audit_log() {
+        let root_dir = test_root_dir("audit_log");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+
+        let db_hash = base16ct::lower::encode_string(&Sha256::digest("test_db".as_bytes()));
+        let now = Utc::now();
+        let old_entry = AuditEntry {
+            created_at: now - chrono::Duration::days(10),
+            ..AuditEntry::new(AUDIT_SOURCE_CLI, "operator", "create user")
+                .with_target_user("test_user")
+        };
+        let mutation = AuditEntry::new(AUDIT_SOURCE_PROTOCOL, "test_user", "mutate")
+            .with_database_name("test_db")
+            .with_query("INSERT INTO t(a) VALUES(?);", Some("0123abcd".to_string()))
+            .with_rows_affected(2);
+        let failed_drop = AuditEntry::new(AUDIT_SOURCE_ADMIN, "admin_user", "drop database")
+            .with_database_name("other_db")
+            .with_error(&Error {
+                message: "Database doesn't exist".to_string(),
+                source: ErrorKind::ResourceNotExist.into(),
+            });
+        for entry in [&old_entry, &mutation, &failed_drop] {
+            database_manager.record_audit_entry(entry).await.unwrap();
+        }
+
+        let all_entries = database_manager
+            .get_audit_entries(None, None, None, None, 0)
+            .await
+            .unwrap();
+        assert_eq!(all_entries.len(), 3);
+        assert_eq!(all_entries[1].database, Some(db_hash));
+        assert_eq!(all_entries[1].rows_affected, Some(2));
+        assert_eq!(all_entries[1].outcome, "ok");
+        assert_eq!(all_entries[2].outcome, "Database doesn't exist");
+        assert_eq!(all_entries[2].query, None);
+
+        // by the user that made the change or the user it was made to
+        let user_entries = database_manager
+            .get_audit_entries(Some("test_user".to_string()), None, None, None, 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            user_entries
+                .iter()
+                .map(|entry| entry.action.as_str())
+                .collect::<Vec<_>>(),
+            vec!["create user", "mutate"]
+        );
+
+        let db_entries = database_manager
+            .get_audit_entries(None, Some("test_db".to_string()), None, None, 0)
+            .await
+            .unwrap();
+        assert_eq!(db_entries.len(), 1);
+        assert_eq!(
+            db_entries[0].query.as_deref(),
+            Some("INSERT INTO t(a) VALUES(?);")
+        );
+        assert_eq!(db_entries[0].args_digest.as_deref(), Some("0123abcd"));
+
+        let recent_entries = database_manager
+            .get_audit_entries(
+                None,
+                None,
+                Some(now - chrono::Duration::days(1)),
+                Some(now + chrono::Duration::days(1)),
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(recent_entries.len(), 1);
+        assert_eq!(recent_entries[0].action, "mutate");
+
+        let pool = database_manager.connect_audit_db().await.unwrap();
+        // append only
+        assert!(sqlx::query("UPDATE audit_log SET outcome = 'ok';")
+            .execute(&pool)
+            .await
+            .is_err());
+
+        assert_eq!(audit::prune(&pool, 0).await.unwrap(), 0);
+        assert_eq!(audit::prune(&pool, 5).await.unwrap(), 1);
+        pool.close().await;
+
+        let remaining_entries = database_manager
+            .get_audit_entries(None, None, None, None, 0)
+            .await
+            .unwrap();
+        assert_eq!(remaining_entries.len(), 2);
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+pub mod backup {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use actix_web::web;
+    use chrono::Utc;
+    use papaya::{HashMap, HashSet};
+    use serf::{
+        cli::util::DatabaseManager,
+        core::{
+            backup::{
+                backup_database, manifest_path, prune_backups, restore_database,
+                validate_backup_file_name,
+            },
+            config::{BackupScheduleConfig, DatabaseConfig, WalArchiveConfig},
+            replication::{create_log, get_changes, log_change},
+            serf_proto::Sub,
+            state::AppState,
+            util::{
+                async_watch, get_or_insert_db_connection, restore_db, run_scheduled_backups,
+                with_db_closed,
+            },
+            wal_archive::{archive_wal, restore_wal_archive},
+        },
+    };
+    use sqlx::{
+        sqlite::{SqliteConnectOptions, SqliteJournalMode},
+        SqlitePool,
+    };
+
+    const TEST_DB_NAME: &str = "0123abcd";
+
+    fn test_root_dir(test_name: &str) -> PathBuf {
+        let root_dir =
+            std::env::temp_dir().join(format!("serf_backup_{}_{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&root_dir);
+
+        root_dir
+    }
+
+    /// creates <root_dir>/db/<TEST_DB_NAME>/<TEST_DB_NAME>.db holding one row and
+    /// returns the pool, which keeps the row in the WAL as long as it is open
+    async fn setup_test_db(root_dir: &Path) -> (PathBuf, SqlitePool) {
+        let db_dir = root_dir.join("db").join(TEST_DB_NAME);
+        fs::create_dir_all(&db_dir).unwrap();
+        let db_file = db_dir.join(format!("{}.db", TEST_DB_NAME));
+
+        let db = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(&db_file)
+                .journal_mode(SqliteJournalMode::Wal)
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+        sqlx::query("CREATE TABLE test_data_table (id INTEGER PRIMARY KEY, im_data TEXT);")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO test_data_table(im_data) VALUES('backed_up');")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        (db_file, db)
+    }
+
+    fn setup_test_app_state(root_dir: &Path) -> web::Data<AppState> {
+        web::Data::new(AppState {
+            db_connections: Arc::new(HashMap::new()),
+            users: Arc::new(HashMap::new()),
+            db_max_connections: 1,
+            db_max_idle_time: 3600,
+            db_max_lifetime: 86400,
+            db_max_open: 10,
+            db_pool_max_idle_time: 3600,
+            query_timeout: 0,
+            max_rows: 0,
+            max_response_bytes: 0,
+            max_databases: 0,
+            db_path: root_dir.join("db").to_string_lossy().to_string(),
+            backup_path: root_dir.join("backup").to_string_lossy().to_string(),
+            archive_path: root_dir.join("archive").to_string_lossy().to_string(),
+            user_db_path: String::from("testing_user_db_path"),
+            restoring_dbs: Arc::new(HashSet::new()),
+            backup_statuses: Arc::new(HashMap::new()),
+            primary: None,
+            replication_statuses: Arc::new(HashMap::new()),
+            audit_db: None,
+        })
+    }
+
+    async fn count_rows(db_file: &Path) -> i64 {
+        let db = SqlitePool::connect_with(SqliteConnectOptions::new().filename(db_file))
+            .await
+            .unwrap();
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM test_data_table;")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        db.close().await;
+
+        count
+    }
+
+    #[tokio::test]
+    async fn test_backup_database__backup_and_restore() {
+        for compress in [false, true] {
+            let root_dir = test_root_dir(&format!("roundtrip_{}", compress));
+            let (db_file, db) = setup_test_db(&root_dir).await;
+            let backup_file = root_dir.join("backup").join("test.db");
+
+            let manifest = backup_database(&db_file, &backup_file, TEST_DB_NAME, compress)
+                .await
+                .unwrap();
+            assert!(manifest_path(&backup_file).exists());
+            assert_eq!(manifest.database, TEST_DB_NAME);
+            assert_eq!(manifest.compressed, compress);
+            assert_eq!(manifest.size, fs::metadata(&backup_file).unwrap().len());
+
+            sqlx::query("INSERT INTO test_data_table(im_data) VALUES('not_backed_up');")
+                .execute(&db)
+                .await
+                .unwrap();
+            db.close().await;
+            assert_eq!(count_rows(&db_file).await, 2);
+
+            let restored_manifest = restore_database(&backup_file, &db_file).await.unwrap();
+            assert_eq!(restored_manifest, manifest);
+            assert_eq!(count_rows(&db_file).await, 1);
+
+            let _ = fs::remove_dir_all(&root_dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restore_database__followers_of_replicated_db_resync() {
+        let root_dir = test_root_dir("replicated");
+        let (db_file, db) = setup_test_db(&root_dir).await;
+        let mut conn = db.acquire().await.unwrap();
+        create_log(&mut conn).await.unwrap();
+        log_change(&mut conn, Sub::Mutate, vec![], 10)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let backed_up_id = get_changes(&db, 0).await.unwrap().last_id;
+        assert!(
+            backed_up_id > Utc::now().timestamp_micros() as u64 - 60_000_000,
+            "ids of a new log start at the current time"
+        );
+
+        let backup_file = root_dir.join("backup").join("test.db");
+        backup_database(&db_file, &backup_file, TEST_DB_NAME, false)
+            .await
+            .unwrap();
+        let mut conn = db.acquire().await.unwrap();
+        log_change(&mut conn, Sub::Mutate, vec![], 10)
+            .await
+            .unwrap();
+        drop(conn);
+        db.close().await;
+
+        restore_database(&backup_file, &db_file).await.unwrap();
+        let db = SqlitePool::connect_with(SqliteConnectOptions::new().filename(&db_file))
+            .await
+            .unwrap();
+
+        // followers that were at the backed up or the replaced change can't catch up from the log
+        for after_id in [backed_up_id, backed_up_id + 1] {
+            let changes = get_changes(&db, after_id).await.unwrap();
+            assert!(changes.resync);
+            assert!(changes.last_id > backed_up_id + 1);
+        }
+        let last_id = get_changes(&db, 0).await.unwrap().last_id;
+        assert!(!get_changes(&db, last_id).await.unwrap().resync);
+        db.close().await;
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    async fn wait_for_user(app_state: &AppState, username: &str) -> bool {
+        for _ in 0..50 {
+            if app_state
+                .users
+                .pin()
+                .values()
+                .any(|user| user.username == username)
+            {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        false
+    }
+
+    #[tokio::test]
+    async fn test_async_watch__users_reloaded_after_users_db_restore() {
+        let root_dir = test_root_dir("watch_restore");
+        let database_manager = DatabaseManager::new(&root_dir);
+        database_manager.init().await.unwrap();
+        database_manager
+            .create_user("test_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+
+        // a users db holding another user, backed up to restore over the watched one
+        let other_root_dir = test_root_dir("watch_restore_other");
+        let other_database_manager = DatabaseManager::new(&other_root_dir);
+        other_database_manager.init().await.unwrap();
+        other_database_manager
+            .create_user("restored_user".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+        let backup_file = other_root_dir.join("users.db");
+        backup_database(
+            Path::new(&other_database_manager.user_db_full_path_string),
+            &backup_file,
+            "cfg",
+            false,
+        )
+        .await
+        .unwrap();
+
+        let app_state = setup_test_app_state(&root_dir);
+        let watch = tokio::spawn(async_watch(
+            database_manager.user_db_full_path_string.clone(),
+            app_state.clone(),
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        database_manager
+            .create_user("test_user_too".to_string(), "test_password".to_string())
+            .await
+            .unwrap();
+        assert!(wait_for_user(&app_state, "test_user_too").await);
+
+        restore_database(
+            &backup_file,
+            Path::new(&database_manager.user_db_full_path_string),
+        )
+        .await
+        .unwrap();
+        assert!(wait_for_user(&app_state, "restored_user").await);
+
+        // the watcher follows the restored file, not the one it replaced
+        database_manager
+            .create_user(
+                "created_after_restore".to_string(),
+                "test_password".to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(wait_for_user(&app_state, "created_after_restore").await);
+
+        watch.abort();
+        let _ = fs::remove_dir_all(&root_dir);
+        let _ = fs::remove_dir_all(&other_root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_backup_database__existing_backup_file_fail() {
+        let root_dir = test_root_dir("existing_file");
+        let (db_file, db) = setup_test_db(&root_dir).await;
+        let backup_file = root_dir.join("test.db");
+        fs::write(&backup_file, "").unwrap();
+
+        assert!(backup_database(&db_file, &backup_file, TEST_DB_NAME, false)
+            .await
+            .is_err());
+
+        db.close().await;
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_restore_database__checksum_mismatch_keeps_db() {
+        let root_dir = test_root_dir("checksum_mismatch");
+        let (db_file, db) = setup_test_db(&root_dir).await;
+        let backup_file = root_dir.join("test.db");
+
+        backup_database(&db_file, &backup_file, TEST_DB_NAME, false)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO test_data_table(im_data) VALUES('not_backed_up');")
+            .execute(&db)
+            .await
+            .unwrap();
+        db.close().await;
+
+        let mut backup_bytes = fs::read(&backup_file).unwrap();
+        backup_bytes.push(0);
+        fs::write(&backup_file, backup_bytes).unwrap();
+
+        let res = restore_database(&backup_file, &db_file).await;
+        assert_eq!(
+            res.expect_err("Should be checksum error").message,
+            "Backup checksum does not match its manifest"
+        );
+        assert_eq!(count_rows(&db_file).await, 2);
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[test]
+    fn test_validate_backup_file_name__rejects_paths() {
+        assert!(validate_backup_file_name("20250101T120000000Z.db.gz").is_ok());
+        assert!(validate_backup_file_name("").is_err());
+        assert!(validate_backup_file_name("../20250101T120000000Z.db").is_err());
+        assert!(validate_backup_file_name("/etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_restore_db__closes_open_db_connection() {
+        let root_dir = test_root_dir("restore_db");
+        let (db_file, db) = setup_test_db(&root_dir).await;
+        db.close().await;
+
+        let backup_dir = root_dir.join("backup").join(TEST_DB_NAME);
+        backup_database(&db_file, &backup_dir.join("test.db"), TEST_DB_NAME, true)
+            .await
+            .unwrap();
+
+        let app_data = setup_test_app_state(&root_dir);
+
+        {
+            let db_connections_guard = app_data.db_connections_guard();
+            let db_connection =
+                get_or_insert_db_connection(&app_data, TEST_DB_NAME, &db_connections_guard)
+                    .await
+                    .unwrap();
+            sqlx::query("DELETE FROM test_data_table;")
+                .execute(&db_connection.writer)
+                .await
+                .unwrap();
+        }
+
+        assert!(restore_db(&app_data, TEST_DB_NAME, "../test.db")
+            .await
+            .is_err());
+        restore_db(&app_data, TEST_DB_NAME, "test.db")
+            .await
+            .unwrap();
+
+        assert!(app_data.db_connections.pin().is_empty());
+        assert!(!app_data.is_restoring_db(TEST_DB_NAME));
+        assert_eq!(count_rows(&db_file).await, 1);
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_with_db_closed__cancelled_restore_unmarks_db() {
+        let root_dir = test_root_dir("restore_db_cancelled");
+        let (_, db) = setup_test_db(&root_dir).await;
+        db.close().await;
+
+        let app_data = setup_test_app_state(&root_dir);
+
+        // the restore future is dropped by the timeout before the replace is done
+        let cancelled = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            with_db_closed(&app_data, TEST_DB_NAME, async {
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                Ok(())
+            }),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        assert!(!app_data.is_restoring_db(TEST_DB_NAME));
+
+        let db_connections_guard = app_data.db_connections_guard();
+        assert!(
+            get_or_insert_db_connection(&app_data, TEST_DB_NAME, &db_connections_guard)
+                .await
+                .is_ok()
+        );
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[test]
+    fn test_prune_backups__keeps_latest_per_hour_and_day() {
+        let backup_dir = test_root_dir("prune");
+        fs::create_dir_all(&backup_dir).unwrap();
+        let backups = [
+            "20250103T120500000Z.db.gz",
+            "20250103T120000000Z.db.gz",
+            "20250103T110000000Z.db.gz",
+            "20250103T100000000Z.db.gz",
+            "20250102T230000000Z.db",
+            "20250102T220000000Z.db",
+            "20250101T230000000Z.db.gz",
+        ];
+        for backup in backups {
+            fs::write(backup_dir.join(backup), "").unwrap();
+            fs::write(manifest_path(&backup_dir.join(backup)), "").unwrap();
+        }
+        fs::write(backup_dir.join("manual_backup.db"), "").unwrap();
+
+        let mut removed = prune_backups(&backup_dir, 2, 2).unwrap();
+        removed.sort();
+
+        // kept: the latest of hour 12 and 11 of the 3rd, and the latest of the 3rd and the 2nd
+        assert_eq!(
+            removed,
+            vec![
+                "20250101T230000000Z.db.gz",
+                "20250102T220000000Z.db",
+                "20250103T100000000Z.db.gz",
+                "20250103T120000000Z.db.gz",
+            ]
+        );
+        assert!(backup_dir.join("20250102T230000000Z.db").exists());
+        assert!(!manifest_path(&backup_dir.join("20250101T230000000Z.db.gz")).exists());
+        assert!(backup_dir.join("manual_backup.db").exists());
+
+        let _ = fs::remove_dir_all(&backup_dir);
+    }
+
+    #[tokio::test]
+    async fn test_run_scheduled_backups__backs_up_when_due() {
+        let root_dir = test_root_dir("scheduled");
+        let (_, db) = setup_test_db(&root_dir).await;
+        db.close().await;
+        DatabaseConfig {
+            backup: Some(BackupScheduleConfig {
+                schedule: String::from("*/5 * * * *"),
+                compress: false,
+                keep_hourly: 1,
+                keep_daily: 0,
+            }),
+            ..DatabaseConfig::default()
+        }
+        .save(&root_dir.join("db").join(TEST_DB_NAME))
+        .unwrap();
+
+        let app_data = setup_test_app_state(&root_dir);
+        let mut next_runs = std::collections::HashMap::new();
+        let now = chrono::DateTime::parse_from_rfc3339("2025-01-01T12:01:00Z")
+            .unwrap()
+            .to_utc();
+
+        // the first run only schedules the next backup
+        run_scheduled_backups(&app_data, &mut next_runs, now).await;
+        let status = app_data
+            .backup_statuses
+            .pin()
+            .get(TEST_DB_NAME)
+            .cloned()
+            .unwrap();
+        assert_eq!(status.last_run_at, None);
+        assert_eq!(
+            status.next_run_at.as_deref(),
+            Some("2025-01-01T12:05:00+00:00")
+        );
+
+        run_scheduled_backups(
+            &app_data,
+            &mut next_runs,
+            now + chrono::Duration::minutes(3),
+        )
+        .await;
+        assert!(app_data
+            .backup_statuses
+            .pin()
+            .get(TEST_DB_NAME)
+            .unwrap()
+            .last_run_at
+            .is_none());
+
+        run_scheduled_backups(
+            &app_data,
+            &mut next_runs,
+            now + chrono::Duration::minutes(4),
+        )
+        .await;
+        let status = app_data
+            .backup_statuses
+            .pin()
+            .get(TEST_DB_NAME)
+            .cloned()
+            .unwrap();
+        assert_eq!(status.last_error, None);
+        assert_eq!(
+            status.next_run_at.as_deref(),
+            Some("2025-01-01T12:10:00+00:00")
+        );
+        let backup_file = root_dir
+            .join("backup")
+            .join(TEST_DB_NAME)
+            .join(status.last_file.unwrap());
+        assert!(backup_file.exists());
+        assert!(manifest_path(&backup_file).exists());
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_wal_archive__restore_to_a_point_in_time() {
+        let root_dir = test_root_dir("wal_archive");
+        let (db_file, db) = setup_test_db(&root_dir).await;
+        let archive_dir = root_dir.join("archive").join(TEST_DB_NAME);
+        let wal_archive_config = WalArchiveConfig::default();
+        let now = chrono::DateTime::parse_from_rfc3339("2025-01-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+
+        let mut conn = db.acquire().await.unwrap();
+        let state = archive_wal(
+            &mut conn,
+            &db_file,
+            &archive_dir,
+            None,
+            &wal_archive_config,
+            now,
+        )
+        .await
+        .unwrap();
+        assert!(state.is_some());
+
+        let mut state = state;
+        for (i, im_data) in ["archived_1", "archived_2"].into_iter().enumerate() {
+            sqlx::query("INSERT INTO test_data_table(im_data) VALUES(?);")
+                .bind(im_data)
+                .execute(&mut *conn)
+                .await
+                .unwrap();
+            state = archive_wal(
+                &mut conn,
+                &db_file,
+                &archive_dir,
+                state,
+                &wal_archive_config,
+                now + chrono::Duration::seconds(10 * (i as i64 + 1)),
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(state.unwrap().salt, None);
+        drop(conn);
+        db.close().await;
+
+        assert!(
+            restore_wal_archive(&archive_dir, now - chrono::Duration::seconds(1), &db_file)
+                .await
+                .is_err()
+        );
+        assert_eq!(count_rows(&db_file).await, 3);
+
+        for (to_secs, rows) in [(5, 1), (15, 2), (25, 3)] {
+            let restored_to = restore_wal_archive(
+                &archive_dir,
+                now + chrono::Duration::seconds(to_secs),
+                &db_file,
+            )
+            .await
+            .unwrap();
+            assert!(restored_to <= now + chrono::Duration::seconds(to_secs));
+            assert_eq!(count_rows(&db_file).await, rows);
+        }
+
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+pub mod export {
+    use serf::core::{
+        error::{ResourceNotExistError, SerfError},
+        export::export,
+        serf_proto::ExportFormat,
+    };
+
+    use sqlx::{Connection, Row, SqliteConnection};
+
+    async fn setup_test_db() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:")
+            .await
+            .expect("Failed to create in-memory SQLite connection");
+
+        sqlx::raw_sql(
+            r#"
+            CREATE TABLE test_data_table (
+                id INTEGER PRIMARY KEY NOT NULL,
+                im_text_data TEXT,
+                im_float_data REAL,
+                im_blob_data BLOB,
+                im_any_data
+            );
+            CREATE INDEX test_data_table_text_idx ON test_data_table(im_text_data);
+            INSERT INTO test_data_table VALUES(1, 'it''s, "quoted"', 1.5, X'0102ff', 42);
+            INSERT INTO test_data_table VALUES(2, NULL, NULL, NULL, 'text');
+        "#,
+        )
+        .execute(&mut conn)
+        .await
+        .expect("Failed to create test data table");
+
+        conn
+    }
+
+    async fn export_to_string(
+        conn: &mut SqliteConnection,
+        table: Option<&str>,
+        format: ExportFormat,
+    ) -> String {
+        let mut output = vec![];
+        let rows = export(conn, table, format, &mut output).await.unwrap();
+        assert_eq!(rows, 2);
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export__csv() {
+        let mut conn = setup_test_db().await;
+
+        let csv = export_to_string(&mut conn, Some("test_data_table"), ExportFormat::Csv).await;
+
+        assert_eq!(
+            csv,
+            "id,im_text_data,im_float_data,im_blob_data,im_any_data\n\
+            1,\"it's, \"\"quoted\"\"\",1.5,0102ff,42\n\
+            2,,,,text\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export__jsonl() {
+        let mut conn = setup_test_db().await;
+
+        let jsonl = export_to_string(&mut conn, Some("test_data_table"), ExportFormat::Jsonl).await;
+        let rows = jsonl
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rows,
+            vec![
+                serde_json::json!({"id": 1, "im_text_data": "it's, \"quoted\"", "im_float_data": 1.5, "im_blob_data": [1, 2, 255], "im_any_data": 42}),
+                serde_json::json!({"id": 2, "im_text_data": null, "im_float_data": null, "im_blob_data": null, "im_any_data": "text"}),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export__sql_dump_restores_into_new_db() {
+        let mut conn = setup_test_db().await;
+        let mut restored_conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+
+        let dump = export_to_string(&mut conn, None, ExportFormat::Sql).await;
+        sqlx::raw_sql(&dump)
+            .execute(&mut restored_conn)
+            .await
+            .expect("Failed to run the dump");
+
+        let query = "SELECT id, im_text_data, im_float_data, hex(im_blob_data) im_blob_data, typeof(im_any_data) im_any_type FROM test_data_table ORDER BY id;";
+        let rows = sqlx::query(query).fetch_all(&mut conn).await.unwrap();
+        let restored_rows = sqlx::query(query)
+            .fetch_all(&mut restored_conn)
+            .await
+            .unwrap();
+        let restored_index = sqlx::query("SELECT name FROM sqlite_schema WHERE type = 'index';")
+            .fetch_one(&mut restored_conn)
+            .await
+            .unwrap();
+
+        assert_eq!(rows.len(), restored_rows.len());
+        for (row, restored_row) in rows.iter().zip(restored_rows.iter()) {
+            assert_eq!(row.get::<i64, _>("id"), restored_row.get::<i64, _>("id"));
+            assert_eq!(
+                row.get::<Option<String>, _>("im_text_data"),
+                restored_row.get::<Option<String>, _>("im_text_data")
+            );
+            assert_eq!(
+                row.get::<Option<f64>, _>("im_float_data"),
+                restored_row.get::<Option<f64>, _>("im_float_data")
+            );
+            assert_eq!(
+                row.get::<String, _>("im_blob_data"),
+                restored_row.get::<String, _>("im_blob_data")
+            );
+            assert_eq!(
+                row.get::<String, _>("im_any_type"),
+                restored_row.get::<String, _>("im_any_type")
+            );
+        }
+        assert_eq!(
+            restored_index.get::<String, _>("name"),
+            "test_data_table_text_idx"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export__invalid_table_or_format_fail() {
+        let mut conn = setup_test_db().await;
+        let mut output = vec![];
+
+        let missing_table = export(
+            &mut conn,
+            Some("missing_table"),
+            ExportFormat::Csv,
+            &mut output,
+        )
+        .await;
+        let no_table = export(&mut conn, None, ExportFormat::Jsonl, &mut output).await;
+
+        assert_eq!(
+            missing_table.expect_err("Should be ResourceNotExistError"),
+            ResourceNotExistError::with_message("Table doesn't exist")
+        );
+        assert!(no_table.is_err());
+        assert!(output.is_empty());
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+pub mod import {
+    use serf::core::{
+        config::ReplicationConfig,
+        constants::queries,
+        error::{ResourceNotExistError, SerfError},
+        export::export,
+        import::import,
+        serf_proto::{ExportFormat, ImportFormat, ImportRowError},
+    };
+
+    use sqlx::{Connection, Row, SqliteConnection};
+
+    async fn setup_test_db() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:")
+            .await
+            .expect("Failed to create in-memory SQLite connection");
+
+        sqlx::raw_sql(
+            r#"
+            CREATE TABLE test_data_table (
+                id INTEGER PRIMARY KEY NOT NULL,
+                im_text_data TEXT,
+                im_int_data INTEGER NOT NULL,
+                im_blob_data BLOB
+            ) STRICT;
+        "#,
+        )
+        .execute(&mut conn)
+        .await
+        .expect("Failed to create test data table");
+
+        conn
+    }
+
+    async fn count_rows(conn: &mut SqliteConnection, query: &str) -> i64 {
+        sqlx::query(query)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+            .get::<i64, _>(0)
+    }
+
+    #[tokio::test]
+    async fn test_import__csv_round_trip_of_export() {
+        let mut conn = setup_test_db().await;
+        let mut imported_conn = setup_test_db().await;
+        sqlx::raw_sql(
+            r#"
+            INSERT INTO test_data_table VALUES(1, 'multi
+line, "quoted"', 1, X'00ff');
+            INSERT INTO test_data_table VALUES(2, NULL, 2, NULL);
+            INSERT INTO test_data_table VALUES(3, '', 3, X'');
+        "#,
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        sqlx::query(queries::CREATE_REPLICATION_LOG_TABLE)
+            .execute(&mut imported_conn)
+            .await
+            .unwrap();
+
+        let mut csv = vec![];
+        export(
+            &mut conn,
+            Some("test_data_table"),
+            ExportFormat::Csv,
+            &mut csv,
+        )
+        .await
+        .unwrap();
+        let report = import(
+            &mut imported_conn,
+            "test_data_table",
+            ImportFormat::Csv,
+            csv.as_slice(),
+            1,
+            0,
+            Some(&ReplicationConfig::default()),
+        )
+        .await
+        .unwrap();
+
+        let query =
+            "SELECT id, im_text_data, im_int_data, im_blob_data FROM test_data_table ORDER BY id;";
+        let rows = sqlx::query(query).fetch_all(&mut conn).await.unwrap();
+        let imported_rows = sqlx::query(query)
+            .fetch_all(&mut imported_conn)
+            .await
+            .unwrap();
+
+        assert_eq!(report.imported, 3);
+        assert_eq!(report.failed, 0);
+        assert!(!report.aborted);
+        assert_eq!(rows.len(), imported_rows.len());
+        assert_eq!(
+            imported_rows[2].get::<Option<String>, _>(1),
+            Some(String::new())
+        );
+        assert_eq!(imported_rows[2].get::<Option<Vec<u8>>, _>(3), Some(vec![]));
+        for (row, imported_row) in rows.iter().zip(imported_rows.iter()) {
+            assert_eq!(row.get::<i64, _>(0), imported_row.get::<i64, _>(0));
+            assert_eq!(
+                row.get::<Option<String>, _>(1),
+                imported_row.get::<Option<String>, _>(1)
+            );
+            assert_eq!(row.get::<i64, _>(2), imported_row.get::<i64, _>(2));
+            assert_eq!(
+                row.get::<Option<Vec<u8>>, _>(3),
+                imported_row.get::<Option<Vec<u8>>, _>(3)
+            );
+        }
+        assert_eq!(
+            count_rows(
+                &mut imported_conn,
+                "SELECT COUNT(*) FROM __replication_log_t__;"
+            )
+            .await,
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import__jsonl_row_errors_reported() {
+        let mut conn = setup_test_db().await;
+        let jsonl = r#"{"im_text_data": "value1", "im_int_data": 1}
+
+{"im_text_data": "value2"}
+not json
+{"im_int_data": 3, "im_unknown_data": 3}
+{"im_text_data": "value4", "im_int_data": 4, "im_blob_data": [1, 2]}
+"#;
+
+        let report = import(
+            &mut conn,
+            "test_data_table",
+            ImportFormat::Jsonl,
+            jsonl.as_bytes(),
+            1000,
+            3,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.failed, 3);
+        assert!(!report.aborted);
+        assert_eq!(
+            report
+                .errors
+                .iter()
+                .map(|error| error.line)
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(
+            report.errors[0],
+            ImportRowError {
+                line: 3,
+                message: String::from("NOT NULL constraint failed: test_data_table.im_int_data"),
+            }
+        );
+        assert_eq!(
+            count_rows(
+                &mut conn,
+                "SELECT COUNT(*) FROM test_data_table WHERE im_blob_data = X'0102';"
+            )
+            .await,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import__aborted_after_max_errors_keeps_committed_batches() {
+        let mut conn = setup_test_db().await;
+        // 1 and 2 are committed as a batch, 3 is rolled back with the batch in progress
+        let csv = "im_int_data\n1\n2\n3\nnot_an_int\n\nnot_an_int\n4\n";
+
+        let report = import(
+            &mut conn,
+            "test_data_table",
+            ImportFormat::Csv,
+            csv.as_bytes(),
+            2,
+            1,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(report.aborted);
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.failed, 2);
+        assert_eq!(
+            count_rows(&mut conn, "SELECT COUNT(*) FROM test_data_table;").await,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import__unknown_table_or_column_fail() {
+        let mut conn = setup_test_db().await;
+
+        let unknown_table = import(
+            &mut conn,
+            "missing_table",
+            ImportFormat::Csv,
+            "id\n1\n".as_bytes(),
+            1000,
+            0,
+            None,
+        )
+        .await;
+        let unknown_column = import(
+            &mut conn,
+            "test_data_table",
+            ImportFormat::Csv,
+            "im_int_data,im_unknown_data\n1,2\n".as_bytes(),
+            1000,
+            0,
+            None,
+        )
+        .await;
+
+        assert_eq!(
+            unknown_table.expect_err("Should be ResourceNotExistError"),
+            ResourceNotExistError::with_message("Table doesn't exist")
+        );
+        assert!(unknown_column.is_err());
+        assert_eq!(
+            count_rows(&mut conn, "SELECT COUNT(*) FROM test_data_table;").await,
+            0
+        );
+    }
+}
@@ -5,11 +5,18 @@
 pub mod util {
     use serf::{
         core::{
-            error::{DatabaseError, SerfError, UserNotAllowedError},
+            config::{DatabaseConfig, ReplicationConfig},
+            constants::queries,
+            db::QueryInterrupt,
+            error::{DatabaseError, NotPrimaryError, SerfError, TimeoutError, UserNotAllowedError},
+            replication::{apply_changes, get_changes},
+            role,
             serf_proto::{
-                query_arg, Claims, FetchResponse, Iss, MigrationRequest, MigrationResponse,
+                claims::Dat, query_arg, Claims, FetchResponse, ImportFormat, ImportRequest,
+                ImportResponse, ImportRowError, Iss, MigrationRequest, MigrationResponse,
                 MutationResponse, QueryArg, QueryRequest, Sub,
             },
+            util::create_db_connection,
         },
         web::{
             proto::{encode_proto, ProtoPackage},
@@ -17,6 +24,8 @@ pub mod util {
         },
     };
 
+    use std::{fs, time::Duration};
+
     use serde_json::json;
     use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
 
@@ -74,7 +83,7 @@ pub mod util {
     #[tokio::test]
     async fn test_handle_mutate__user_access_too_low() {
         let db = setup_test_db().await;
-        let result_handler = ProtoPackageResultHandler::new(1, "test_hash", &db);
+        let result_handler = ProtoPackageResultHandler::new(role::FETCH, "test_hash", &db);
 
         let query_request_dat = QueryRequest::as_dat(
             "INSERT INTO test_data_table(im_data, im_data_too) VALUES(?, ?);".to_string(),
@@ -133,7 +142,8 @@ pub mod util {
     #[tokio::test]
     async fn test_handle_migrate__user_access_too_low() {
         let db = setup_test_db().await;
-        let result_handler = ProtoPackageResultHandler::new(1, "test_hash", &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH | role::MUTATE, "test_hash", &db);
 
         let migration_request_dat = MigrationRequest::as_dat(
             "1__add_test_col".to_string(),
@@ -156,6 +166,31 @@ pub mod util {
             UserNotAllowedError::default()
         );
     }
+
+    #[tokio::test]
+    async fn test_handle_mutate__migrator_not_allowed() {
+        let db = setup_test_db().await;
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH | role::MIGRATE, "test_hash", &db);
+
+        let query_request_dat =
+            QueryRequest::as_dat("DELETE FROM test_data_table;".to_string(), vec![]);
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: chrono::Utc::now().timestamp() as u64,
+            exp: (chrono::Utc::now() + chrono::Duration::seconds(30)).timestamp() as u64,
+            sub: Sub::Mutate.into(),
+            dat: Some(query_request_dat),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+
+        assert_eq!(
+            result.expect_err("Should be UserNotAllowedError"),
+            UserNotAllowedError::default()
+        );
+    }
     // USER ACCESS LEVEL END
 
     // MIGRATE
@@ -163,7 +198,8 @@ pub mod util {
     async fn test_handle_migrate__migration_success() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(2, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MIGRATE, username_password_hash, &db);
         let expected_result_proto_package = encode_proto(
             MigrationResponse::as_dat(true),
             Sub::Data,
@@ -192,7 +228,10 @@ pub mod util {
             .await;
 
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), expected_result_proto_package.unwrap());
+        assert_eq!(
+            result.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
         assert!(db_content.is_ok());
         assert!(migration_table_content.is_ok());
         assert_eq!(migration_table_content.unwrap().len(), 1);
@@ -202,7 +241,8 @@ pub mod util {
     async fn test_handle_migrate__migration_fail_migration_already_exists() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(2, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MIGRATE, username_password_hash, &db);
         let now = chrono::Utc::now().timestamp() as u64;
 
         let expected_result_proto_package = ProtoPackage::builder()
@@ -254,7 +294,10 @@ pub mod util {
             .await;
 
         assert!(result_1.is_ok());
-        assert_eq!(result_1.unwrap(), expected_result_proto_package.unwrap());
+        assert_eq!(
+            result_1.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
         assert!(db_content_1.is_ok());
         assert!(migration_table_content_1.is_ok());
         assert_eq!(migration_table_content_1.unwrap().len(), 1);
@@ -273,7 +316,8 @@ pub mod util {
     async fn test_handle_migrate__migration_fail_column_already_exists() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(2, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MIGRATE, username_password_hash, &db);
         let now = chrono::Utc::now().timestamp() as u64;
 
         let expected_result_proto_package_1 = ProtoPackage::builder()
@@ -331,17 +375,62 @@ pub mod util {
             .await;
 
         assert!(result_1.is_ok());
-        assert_eq!(result_1.unwrap(), expected_result_proto_package_1.unwrap());
+        assert_eq!(
+            result_1.unwrap().proto_package,
+            expected_result_proto_package_1.unwrap()
+        );
         assert!(db_content_1.is_ok());
         assert!(migration_table_content_1.is_ok());
         assert_eq!(migration_table_content_1.unwrap().len(), 1);
 
         assert!(result_2.is_ok());
-        assert_eq!(result_2.unwrap(), expected_result_proto_package_2.unwrap());
+        let handled_request_2 = result_2.unwrap();
+        assert_eq!(
+            handled_request_2.outcome.as_deref(),
+            Some("migration failed")
+        );
+        assert_eq!(
+            handled_request_2.proto_package,
+            expected_result_proto_package_2.unwrap()
+        );
         assert!(db_content_2.is_ok());
         assert!(migration_table_content_2.is_ok());
         assert_eq!(migration_table_content_2.unwrap().len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_handle_migrate__query_timeout() {
+        let db = setup_test_db().await;
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MIGRATE, "test_hash", &db).with_query_timeout(50);
+
+        let migration_request_dat = MigrationRequest::as_dat(
+            "1__slow_migration".to_string(),
+            "CREATE TABLE slow_table AS WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 1000000000) SELECT count(*) AS n FROM c;"
+                .to_string(),
+        );
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: chrono::Utc::now().timestamp() as u64,
+            exp: (chrono::Utc::now() + chrono::Duration::seconds(30)).timestamp() as u64,
+            sub: Sub::Migrate.into(),
+            dat: Some(migration_request_dat),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+        let migration_table_content = sqlx::query("SELECT * FROM __migrations_tracker_t__;")
+            .fetch_all(&db)
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            result.expect_err("Should be TimeoutError"),
+            TimeoutError::default()
+        );
+        // the migration is rolled back together with its tracker row
+        assert!(migration_table_content.is_err());
+    }
     // MIGRATE
 
     // FETCH
@@ -349,7 +438,8 @@ pub mod util {
     async fn test_handle_fetch__fetch_data_success() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(1, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH, username_password_hash, &db);
         let now = chrono::Utc::now().timestamp() as u64;
         let expected_result_json = json!([
             {
@@ -363,6 +453,7 @@ pub mod util {
         let expected_result_proto_package = ProtoPackage::builder()
             .with_data(FetchResponse::as_dat(
                 serde_json::to_vec(&expected_result_json).unwrap(),
+                false,
             ))
             .with_subject(Sub::Data)
             .with_iat(now)
@@ -384,14 +475,18 @@ pub mod util {
         let result = get_proto_package_result(claims, &result_handler).await;
 
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), expected_result_proto_package.unwrap());
+        assert_eq!(
+            result.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
     }
 
     #[tokio::test]
     async fn test_handle_fetch__fetch_data_fail() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(1, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH, username_password_hash, &db);
 
         let query_request_dat = QueryRequest::as_dat(
             "SELECT * FROM test_data_table WHERE non_existing_col = ?;".to_string(),
@@ -414,6 +509,303 @@ pub mod util {
             DatabaseError::with_message("no such column: non_existing_col")
         );
     }
+
+    #[tokio::test]
+    async fn test_handle_fetch__fetch_data_from_read_db() {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Failed to create in-memory SQLite pool");
+        let read_db = setup_test_db().await;
+        let username_password_hash = "test_hash";
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH, username_password_hash, &db)
+                .with_read_db(&read_db);
+        let now = chrono::Utc::now().timestamp() as u64;
+        let expected_result_json = json!([{ "im_data": "test_value1" }]);
+
+        let expected_result_proto_package = ProtoPackage::builder()
+            .with_data(FetchResponse::as_dat(
+                serde_json::to_vec(&expected_result_json).unwrap(),
+                false,
+            ))
+            .with_subject(Sub::Data)
+            .with_iat(now)
+            .sign(username_password_hash);
+
+        let query_request_dat = QueryRequest::as_dat(
+            "SELECT im_data FROM test_data_table WHERE id = ?;".to_string(),
+            vec![QueryArg::new(query_arg::Value::Int(1))],
+        );
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: now,
+            exp: now + 30,
+            sub: Sub::Fetch.into(),
+            dat: Some(query_request_dat),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+
+        assert!(result.is_ok());
+        assert_eq!(
+            result.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_fetch__goes_through_read_only_pool() {
+        let root_dir =
+            std::env::temp_dir().join(format!("serf_read_only_pool_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root_dir);
+        fs::create_dir_all(&root_dir).unwrap();
+        let db = create_db_connection(
+            &format!("sqlite:{}/test.db?mode=rwc", root_dir.to_str().unwrap()),
+            &DatabaseConfig::default(),
+            2,
+            3600,
+            86400,
+        )
+        .await
+        .unwrap();
+        sqlx::query(
+            "CREATE TABLE test_data_table (id INTEGER PRIMARY KEY NOT NULL, im_data TEXT);",
+        )
+        .execute(&db.writer)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO test_data_table(im_data) VALUES('test_value1');")
+            .execute(&db.writer)
+            .await
+            .unwrap();
+
+        // the reader is opened read-only, SQLite rejects writes on it with SQLITE_READONLY (8)
+        let write_error = sqlx::query("DELETE FROM test_data_table;")
+            .execute(&db.reader)
+            .await
+            .expect_err("Should be a read-only error");
+        assert_eq!(
+            write_error.as_database_error().unwrap().code().as_deref(),
+            Some("8")
+        );
+
+        // so a fetch that tries to write fails, which it only does when it runs on the reader
+        let username_password_hash = "test_hash";
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH, username_password_hash, &db.writer)
+                .with_read_db(&db.reader);
+        let now = chrono::Utc::now().timestamp() as u64;
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: now,
+            exp: now + 30,
+            sub: Sub::Fetch.into(),
+            dat: Some(QueryRequest::as_dat(
+                "DELETE FROM test_data_table RETURNING id;".to_string(),
+                vec![],
+            )),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+        assert_eq!(
+            result.expect_err("Should be DatabaseError"),
+            DatabaseError::with_message("attempt to write a readonly database")
+        );
+        assert_eq!(
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM test_data_table;")
+                .fetch_one(&db.writer)
+                .await
+                .unwrap(),
+            1
+        );
+
+        db.close().await;
+        let _ = fs::remove_dir_all(&root_dir);
+    }
+
+    #[tokio::test]
+    async fn test_handle_fetch__truncated_by_max_rows() {
+        let db = setup_test_db().await;
+        let username_password_hash = "test_hash";
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH, username_password_hash, &db)
+                .with_fetch_limits(2, 0);
+        let now = chrono::Utc::now().timestamp() as u64;
+        let expected_result_json = json!([{ "x": 1 }, { "x": 2 }]);
+
+        let expected_result_proto_package = ProtoPackage::builder()
+            .with_data(FetchResponse::as_dat(
+                serde_json::to_vec(&expected_result_json).unwrap(),
+                true,
+            ))
+            .with_subject(Sub::Data)
+            .with_iat(now)
+            .sign(username_password_hash);
+
+        let query_request_dat = QueryRequest::as_dat(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 5) SELECT x FROM c;"
+                .to_string(),
+            vec![],
+        );
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: now,
+            exp: now + 30,
+            sub: Sub::Fetch.into(),
+            dat: Some(query_request_dat),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+
+        assert!(result.is_ok());
+        assert_eq!(
+            result.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_fetch__truncated_by_max_response_bytes() {
+        let db = setup_test_db().await;
+        let username_password_hash = "test_hash";
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH, username_password_hash, &db)
+                .with_fetch_limits(0, 20);
+        let now = chrono::Utc::now().timestamp() as u64;
+        let expected_result_json = json!([{ "x": 1 }, { "x": 2 }]);
+
+        let expected_result_proto_package = ProtoPackage::builder()
+            .with_data(FetchResponse::as_dat(
+                serde_json::to_vec(&expected_result_json).unwrap(),
+                true,
+            ))
+            .with_subject(Sub::Data)
+            .with_iat(now)
+            .sign(username_password_hash);
+
+        let query_request_dat = QueryRequest::as_dat(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 5) SELECT x FROM c;"
+                .to_string(),
+            vec![],
+        );
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: now,
+            exp: now + 30,
+            sub: Sub::Fetch.into(),
+            dat: Some(query_request_dat),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+
+        assert!(result.is_ok());
+        assert_eq!(
+            result.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_fetch__query_timeout() {
+        let db = setup_test_db().await;
+        let result_handler =
+            ProtoPackageResultHandler::new(role::FETCH, "test_hash", &db).with_query_timeout(50);
+
+        let query_request_dat = QueryRequest::as_dat(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c;"
+                .to_string(),
+            vec![],
+        );
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: chrono::Utc::now().timestamp() as u64,
+            exp: (chrono::Utc::now() + chrono::Duration::seconds(30)).timestamp() as u64,
+            sub: Sub::Fetch.into(),
+            dat: Some(query_request_dat),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            result.expect_err("Should be TimeoutError"),
+            TimeoutError::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_fetch__request_timeout_lower_than_query_timeout() {
+        let db = setup_test_db().await;
+        let result_handler = ProtoPackageResultHandler::new(role::FETCH, "test_hash", &db)
+            .with_query_timeout(60 * 1000);
+
+        let query_request_dat = Dat::QueryRequest(QueryRequest {
+            query: "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c;"
+                .to_string(),
+            parts: vec![],
+            timeout: 50,
+        });
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: chrono::Utc::now().timestamp() as u64,
+            exp: (chrono::Utc::now() + chrono::Duration::seconds(30)).timestamp() as u64,
+            sub: Sub::Fetch.into(),
+            dat: Some(query_request_dat),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            result.expect_err("Should be TimeoutError"),
+            TimeoutError::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_fetch__query_interrupted_when_request_dropped() {
+        let db = setup_test_db().await;
+        let result_handler = ProtoPackageResultHandler::new(role::FETCH, "test_hash", &db);
+
+        let query_request_dat = QueryRequest::as_dat(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c;"
+                .to_string(),
+            vec![],
+        );
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: chrono::Utc::now().timestamp() as u64,
+            exp: (chrono::Utc::now() + chrono::Duration::seconds(30)).timestamp() as u64,
+            sub: Sub::Fetch.into(),
+            dat: Some(query_request_dat),
+        };
+
+        // the request is dropped after 100ms, I.E the client went away
+        let result = actix_web::rt::time::timeout(
+            Duration::from_millis(100),
+            get_proto_package_result(claims, &result_handler),
+        )
+        .await;
+        assert!(result.is_err());
+
+        // the single pool connection is only given back once the running query is interrupted
+        let db_content = actix_web::rt::time::timeout(
+            Duration::from_secs(5),
+            sqlx::query("SELECT * FROM test_data_table;").fetch_all(&db),
+        )
+        .await;
+
+        assert!(db_content.is_ok());
+        assert_eq!(db_content.unwrap().unwrap().len(), 1);
+    }
     // FETCH END
 
     // MUTATE
@@ -421,7 +813,8 @@ pub mod util {
     async fn test_handle_mutate__mutate_data_update_entry_success() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(2, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MUTATE, username_password_hash, &db);
         let now = chrono::Utc::now().timestamp() as u64;
 
         let expected_result_proto_package = ProtoPackage::builder()
@@ -453,7 +846,13 @@ pub mod util {
             .await;
 
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), expected_result_proto_package.unwrap());
+        let handled_request = result.unwrap();
+        assert_eq!(handled_request.rows_affected, Some(1));
+        assert_eq!(handled_request.outcome, None);
+        assert_eq!(
+            handled_request.proto_package,
+            expected_result_proto_package.unwrap()
+        );
         assert!(db_content.is_ok());
         assert_eq!(db_content.unwrap().len(), 1);
     }
@@ -462,7 +861,8 @@ pub mod util {
     async fn test_handle_mutate__mutate_data_insert_entry_success() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(2, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MUTATE, username_password_hash, &db);
         let now = chrono::Utc::now().timestamp() as u64;
 
         let expected_result_proto_package = ProtoPackage::builder()
@@ -495,7 +895,10 @@ pub mod util {
             .await;
 
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), expected_result_proto_package.unwrap());
+        assert_eq!(
+            result.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
         assert!(db_content.is_ok());
         assert_eq!(db_content.unwrap().len(), 2);
     }
@@ -504,7 +907,8 @@ pub mod util {
     async fn test_handle_mutate__mutate_data_non_strict_table_insert_entry_success() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(2, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MUTATE, username_password_hash, &db);
         let now = chrono::Utc::now().timestamp() as u64;
 
         let expected_result_proto_package = ProtoPackage::builder()
@@ -537,7 +941,10 @@ pub mod util {
             .await;
 
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), expected_result_proto_package.unwrap());
+        assert_eq!(
+            result.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
         assert!(db_content.is_ok());
         assert_eq!(db_content.unwrap().len(), 2);
     }
@@ -546,7 +953,8 @@ pub mod util {
     async fn test_handle_mutate__mutate_data_insert_entry_unknown_col_fail() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(2, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MUTATE, username_password_hash, &db);
 
         let query_request_dat = QueryRequest::as_dat(
             "INSERT INTO test_data_table(im_data, im_data_too, im_data_yo) VALUES(?, ?, ?);"
@@ -584,7 +992,8 @@ pub mod util {
     async fn test_handle_mutate__mutate_data_strict_table_insert_entry_incorrect_type_fail() {
         let db = setup_test_db().await;
         let username_password_hash = "test_hash";
-        let result_handler = ProtoPackageResultHandler::new(2, username_password_hash, &db);
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MUTATE, username_password_hash, &db);
 
         let query_request_dat = QueryRequest::as_dat(
             "INSERT INTO strict_test_data_table(im_data, im_data_too, im_data_aswell) VALUES(?, ?, ?);"
@@ -619,5 +1028,296 @@ pub mod util {
         assert!(db_content.is_ok());
         assert_eq!(db_content.unwrap().len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_handle_mutate__failed_commit_fail() {
+        let db = setup_test_db().await;
+        // the foreign key is only checked on commit
+        sqlx::query(
+            r#"
+            CREATE TABLE deferred_test_data_table (
+                id INTEGER PRIMARY KEY,
+                test_data_id INTEGER REFERENCES test_data_table (id) DEFERRABLE INITIALLY DEFERRED
+            );
+            "#,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let username_password_hash = "test_hash";
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MUTATE, username_password_hash, &db);
+
+        let claims = Claims {
+            iss: Iss::Client.into(),
+            iat: chrono::Utc::now().timestamp() as u64,
+            exp: (chrono::Utc::now() + chrono::Duration::seconds(30)).timestamp() as u64,
+            sub: Sub::Mutate.into(),
+            dat: Some(QueryRequest::as_dat(
+                "INSERT INTO deferred_test_data_table(test_data_id) VALUES(?);".to_string(),
+                vec![QueryArg::new(query_arg::Value::Int(100))],
+            )),
+        };
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+        let db_content = sqlx::query("SELECT * FROM deferred_test_data_table;")
+            .fetch_all(&db)
+            .await;
+
+        assert_eq!(
+            result.expect_err("Should be DatabaseError"),
+            DatabaseError::with_message("FOREIGN KEY constraint failed")
+        );
+        assert_eq!(db_content.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_handle_mutate__dropped_during_commit_leaves_pool_usable() {
+        let root_dir =
+            std::env::temp_dir().join(format!("serf_dropped_commit_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root_dir);
+        fs::create_dir_all(&root_dir).unwrap();
+        let db = create_db_connection(
+            &format!("sqlite:{}/test.db?mode=rwc", root_dir.to_str().unwrap()),
+            &DatabaseConfig::default(),
+            2,
+            3600,
+            86400,
+        )
+        .await
+        .unwrap();
+
+        // what handle_mutate leaves behind when its future is dropped while the commit is awaited:
+        // the statement is done and committed, but the interrupt is dropped without being finished
+        let mut transaction = db.writer.begin().await.unwrap();
+        let interrupt = QueryInterrupt::set(&mut transaction, 0).await.unwrap();
+        sqlx::query("CREATE TABLE test_data_table (id INTEGER PRIMARY KEY NOT NULL);")
+            .execute(&mut *transaction)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        drop(interrupt);
+
+        // a statement of far more than QUERY_INTERRUPT_CHECK_OPS ops, without an interrupt of its own
+        let count = sqlx::query_scalar::<_, i64>(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 100000) SELECT COUNT(*) FROM c;",
+        )
+        .fetch_one(&db.writer)
+        .await;
+
+        assert_eq!(count.unwrap(), 100000);
+
+        db.close().await;
+        let _ = fs::remove_dir_all(&root_dir);
+    }
     // MUTATE END
+
+    // IMPORT
+    fn import_claims(data: &str, max_errors: u64) -> Claims {
+        Claims {
+            iss: Iss::Client.into(),
+            iat: chrono::Utc::now().timestamp() as u64,
+            exp: (chrono::Utc::now() + chrono::Duration::seconds(30)).timestamp() as u64,
+            sub: Sub::Import.into(),
+            dat: Some(ImportRequest::as_dat(
+                "test_data_table".to_string(),
+                ImportFormat::Csv,
+                data.as_bytes().to_vec(),
+                max_errors,
+            )),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_import__user_access_too_low() {
+        let db = setup_test_db().await;
+        let result_handler = ProtoPackageResultHandler::new(role::FETCH, "test_hash", &db);
+
+        let result = get_proto_package_result(
+            import_claims("im_data,im_data_aswell\nvalue1,1\n", 0),
+            &result_handler,
+        )
+        .await;
+
+        assert_eq!(
+            result.expect_err("Should be UserNotAllowedError"),
+            UserNotAllowedError::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_import__import_data_success() {
+        let db = setup_test_db().await;
+        let username_password_hash = "test_hash";
+        let result_handler =
+            ProtoPackageResultHandler::new(role::MUTATE, username_password_hash, &db);
+        let now = chrono::Utc::now().timestamp() as u64;
+
+        let expected_result_proto_package = ProtoPackage::builder()
+            .with_data(ImportResponse::as_dat(
+                2,
+                1,
+                vec![ImportRowError {
+                    line: 3,
+                    message: "NOT NULL constraint failed: test_data_table.im_data_aswell"
+                        .to_string(),
+                }],
+                false,
+            ))
+            .with_subject(Sub::Data)
+            .with_iat(now)
+            .sign(username_password_hash);
+
+        let result = get_proto_package_result(
+            import_claims("im_data,im_data_aswell\nvalue1,1\nvalue2,\nvalue3,3\n", 1),
+            &result_handler,
+        )
+        .await;
+        let db_content = sqlx::query("SELECT * FROM test_data_table;")
+            .fetch_all(&db)
+            .await;
+
+        assert_eq!(
+            result.unwrap().proto_package,
+            expected_result_proto_package.unwrap()
+        );
+        assert_eq!(db_content.unwrap().len(), 3);
+    }
+    // IMPORT END
+
+    // REPLICATION
+    fn insert_claims(im_data: &str) -> Claims {
+        Claims {
+            iss: Iss::Client.into(),
+            iat: chrono::Utc::now().timestamp() as u64,
+            exp: (chrono::Utc::now() + chrono::Duration::seconds(30)).timestamp() as u64,
+            sub: Sub::Mutate.into(),
+            dat: Some(QueryRequest::as_dat(
+                "INSERT INTO test_data_table(im_data, im_data_too, im_data_aswell) VALUES(?, ?, ?);"
+                    .to_string(),
+                vec![
+                    QueryArg::new(query_arg::Value::String(im_data.to_string())),
+                    QueryArg::new(query_arg::Value::String("value2".to_string())),
+                    QueryArg::new(query_arg::Value::Int(3)),
+                ],
+            )),
+        }
+    }
+
+    async fn setup_test_replicated_db() -> SqlitePool {
+        let db = setup_test_db().await;
+        sqlx::query(queries::CREATE_REPLICATION_LOG_TABLE)
+            .execute(&db)
+            .await
+            .expect("Failed to create replication log table");
+
+        db
+    }
+
+    #[tokio::test]
+    async fn test_handle_mutate__rejected_on_replica() {
+        let db = setup_test_db().await;
+        let result_handler = ProtoPackageResultHandler::new(role::MUTATE, "test_hash", &db)
+            .with_primary(Some("http://10.0.0.1:8080"));
+
+        let result = get_proto_package_result(insert_claims("value1"), &result_handler).await;
+        let db_content = sqlx::query("SELECT * FROM test_data_table;")
+            .fetch_all(&db)
+            .await;
+
+        assert_eq!(
+            result.expect_err("Should be NotPrimaryError"),
+            NotPrimaryError::with_message(
+                "Server is a read replica, send writes to the primary at http://10.0.0.1:8080"
+            )
+        );
+        assert_eq!(db_content.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replication__changes_replayed_on_follower() {
+        let primary = setup_test_replicated_db().await;
+        let follower = setup_test_replicated_db().await;
+        let replication = ReplicationConfig::default();
+        let result_handler = ProtoPackageResultHandler::new(role::MUTATE, "test_hash", &primary)
+            .with_replication(Some(&replication));
+
+        for im_data in ["value1", "value2"] {
+            let result = get_proto_package_result(insert_claims(im_data), &result_handler).await;
+            assert!(result.is_ok());
+        }
+
+        let changes = get_changes(&primary, 0).await.unwrap();
+        assert_eq!(changes.last_id, 2);
+        assert!(!changes.resync);
+        assert_eq!(changes.changes.len(), 2);
+
+        let mut conn = follower.acquire().await.unwrap();
+        let applied_id = apply_changes(&mut conn, &changes.changes).await.unwrap();
+        drop(conn);
+        let follower_content = sqlx::query("SELECT im_data FROM test_data_table WHERE id > 1;")
+            .fetch_all(&follower)
+            .await;
+
+        assert_eq!(applied_id, Some(2));
+        assert_eq!(follower_content.unwrap().len(), 2);
+        assert_eq!(
+            get_changes(&primary, 2).await.unwrap().changes.len(),
+            0,
+            "follower is up to date"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replication__resync_when_changes_pruned() {
+        let primary = setup_test_replicated_db().await;
+        let replication = ReplicationConfig { keep_changes: 1 };
+        let result_handler = ProtoPackageResultHandler::new(role::MUTATE, "test_hash", &primary)
+            .with_replication(Some(&replication));
+
+        for im_data in ["value1", "value2", "value3"] {
+            let result = get_proto_package_result(insert_claims(im_data), &result_handler).await;
+            assert!(result.is_ok());
+        }
+
+        let behind = get_changes(&primary, 0).await.unwrap();
+        let caught_up = get_changes(&primary, 2).await.unwrap();
+        let ahead = get_changes(&primary, 5).await.unwrap();
+
+        assert!(behind.resync);
+        assert!(behind.changes.is_empty());
+        assert!(!caught_up.resync);
+        assert_eq!(caught_up.changes.len(), 1);
+        assert_eq!(caught_up.changes[0].id, 3);
+        assert!(ahead.resync);
+    }
+
+    #[tokio::test]
+    async fn test_handle_mutate__non_deterministic_rejected_when_replicated() {
+        let primary = setup_test_replicated_db().await;
+        let replication = ReplicationConfig::default();
+        let result_handler = ProtoPackageResultHandler::new(role::MUTATE, "test_hash", &primary)
+            .with_replication(Some(&replication));
+        let mut claims = insert_claims("value1");
+        claims.dat = Some(QueryRequest::as_dat(
+            "INSERT INTO test_data_table(im_data, im_data_too, im_data_aswell) VALUES(datetime('now'), ?, ?);"
+                .to_string(),
+            vec![
+                QueryArg::new(query_arg::Value::String("value2".to_string())),
+                QueryArg::new(query_arg::Value::Int(3)),
+            ],
+        ));
+
+        let result = get_proto_package_result(claims, &result_handler).await;
+        let changes = get_changes(&primary, 0).await.unwrap();
+
+        assert_eq!(
+            result.expect_err("Should be DatabaseError"),
+            DatabaseError::with_message(
+                "datetime('now') gives a different result on the followers of a replicated db, pass its value as an arg instead"
+            )
+        );
+        assert!(changes.changes.is_empty());
+    }
+    // REPLICATION END
 }